@@ -82,6 +82,7 @@ mkdir -p %{buildroot}%{_libexecdir}/%{pkgname}
 install -Dpm0755 target/release/greenboot %{buildroot}%{_libexecdir}/%{pkgname}/%{pkgname}
 install -Dpm0644 -t %{buildroot}%{_unitdir} usr/lib/systemd/system/*.service
 install -Dpm0644 -t %{buildroot}%{_unitdir} usr/lib/systemd/system/*.target
+install -Dpm0644 -t %{buildroot}%{_journalcatalogdir} usr/lib/systemd/catalog/greenboot.catalog
 mkdir -p %{buildroot}%{_exec_prefix}/lib/motd.d/
 mkdir -p %{buildroot}%{_libexecdir}/%{pkgname}
 install -Dpm0644 -t %{buildroot}%{_sysconfdir}/%{pkgname} etc/greenboot/greenboot.conf
@@ -90,10 +91,14 @@ mkdir -p %{buildroot}%{_sysconfdir}/%{pkgname}/check/required.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/check/wanted.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/green.d
 mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/red.d
+mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/pre-reboot.d
+mkdir    %{buildroot}%{_sysconfdir}/%{pkgname}/greenboot.conf.d
 mkdir -p %{buildroot}%{_prefix}/lib/%{pkgname}/check/required.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/check/wanted.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/green.d
 mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/red.d
+mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/pre-reboot.d
+mkdir    %{buildroot}%{_prefix}/lib/%{pkgname}/greenboot.conf.d
 mkdir -p %{buildroot}%{_unitdir}
 mkdir -p %{buildroot}%{_tmpfilesdir}
 install -DpZm 0755 usr/lib/greenboot/check/required.d/* %{buildroot}%{_prefix}/lib/%{pkgname}/check/required.d
@@ -103,16 +108,19 @@ install -DpZm 0644 usr/lib/systemd/system/greenboot-healthcheck.service.d/10-net
 %post -n %{pkgname}
 %systemd_post greenboot-healthcheck.service
 %systemd_post greenboot-set-rollback-trigger.service
+%systemd_post greenboot-monitor.service
 %systemd_post greenboot-success.target
 
 %preun -n %{pkgname}
 %systemd_preun greenboot-healthcheck.service
 %systemd_preun greenboot-set-rollback-trigger.service
+%systemd_preun greenboot-monitor.service
 %systemd_preun greenboot-success.target
 
 %postun -n %{pkgname}
 %systemd_postun greenboot-healthcheck.service
 %systemd_postun greenboot-set-rollback-trigger.service
+%systemd_postun greenboot-monitor.service
 %systemd_postun greenboot-success.target
 
 %files -n %{pkgname}
@@ -122,7 +130,9 @@ install -DpZm 0644 usr/lib/systemd/system/greenboot-healthcheck.service.d/10-net
 %{_libexecdir}/%{pkgname}/%{pkgname}
 %{_unitdir}/greenboot-healthcheck.service
 %{_unitdir}/greenboot-set-rollback-trigger.service
+%{_unitdir}/greenboot-monitor.service
 %{_unitdir}/greenboot-success.target
+%{_journalcatalogdir}/greenboot.catalog
 %config(noreplace) %{_sysconfdir}/%{pkgname}/greenboot.conf
 %{_prefix}/lib/bootupd/grub2-static/configs.d/08_greenboot.cfg
 %dir %{_prefix}/lib/%{pkgname}
@@ -131,12 +141,16 @@ install -DpZm 0644 usr/lib/systemd/system/greenboot-healthcheck.service.d/10-net
 %dir %{_prefix}/lib/%{pkgname}/check/wanted.d
 %dir %{_prefix}/lib/%{pkgname}/green.d
 %dir %{_prefix}/lib/%{pkgname}/red.d
+%dir %{_prefix}/lib/%{pkgname}/pre-reboot.d
+%dir %{_prefix}/lib/%{pkgname}/greenboot.conf.d
 %dir %{_sysconfdir}/%{pkgname}
 %dir %{_sysconfdir}/%{pkgname}/check
 %dir %{_sysconfdir}/%{pkgname}/check/required.d
 %dir %{_sysconfdir}/%{pkgname}/check/wanted.d
 %dir %{_sysconfdir}/%{pkgname}/green.d
 %dir %{_sysconfdir}/%{pkgname}/red.d
+%dir %{_sysconfdir}/%{pkgname}/pre-reboot.d
+%dir %{_sysconfdir}/%{pkgname}/greenboot.conf.d
 
 %files -n %{pkgname}-default-health-checks
 %dir %{_unitdir}/greenboot-healthcheck.service.d
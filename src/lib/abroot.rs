@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+
+/// GPT attribute telling systemd's partition discovery to skip a partition
+static GPT_NO_AUTO: &str = "63";
+
+/// how the rollback switches to the previous root once the counter is used up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RollbackHandler {
+    /// `bootc rollback` or `rpm-ostree rollback`
+    #[default]
+    Deployment,
+    /// flips a bootloader variable naming the root slot, a or b
+    BootloaderEnv,
+    /// moves the no-auto GPT attribute bit from the other root to the booted one
+    Gpt,
+}
+
+impl fmt::Display for RollbackHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RollbackHandler::Deployment => write!(f, "deployment"),
+            RollbackHandler::BootloaderEnv => write!(f, "bootloader-env"),
+            RollbackHandler::Gpt => write!(f, "gpt"),
+        }
+    }
+}
+
+impl FromStr for RollbackHandler {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "deployment" => Ok(RollbackHandler::Deployment),
+            "bootloader-env" => Ok(RollbackHandler::BootloaderEnv),
+            "gpt" => Ok(RollbackHandler::Gpt),
+            other => bail!("unknown rollback handler '{other}'"),
+        }
+    }
+}
+
+/// Rollback of image based systems with two root partitions and no
+/// deployment manager. The bootloader either picks the root named by one of
+/// its variables, or leaves it to systemd's partition discovery, which skips
+/// a root carrying the no-auto GPT attribute.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AbRoot {
+    pub handler: RollbackHandler,
+    /// bootloader variable holding the slot, a or b
+    pub env_var: String,
+    /// partition labels of the a and b roots
+    pub partlabels: Vec<String>,
+}
+
+impl AbRoot {
+    pub fn new(handler: RollbackHandler, env_var: String, partlabels: Vec<String>) -> Self {
+        Self {
+            handler,
+            env_var,
+            partlabels,
+        }
+    }
+
+    /// switches to the other root, false if the rollback is left to the
+    /// deployment manager
+    pub fn rollback(&self, paths: &Paths, backend: &dyn BootloaderBackend) -> Result<bool> {
+        match self.handler {
+            RollbackHandler::Deployment => return Ok(false),
+            RollbackHandler::BootloaderEnv => {
+                let slot = backend.env_var(&self.env_var)?;
+                let other = match slot.as_deref().map(str::trim) {
+                    Some("a") | None => "b",
+                    Some("b") => "a",
+                    Some(other) => bail!("{} has invalid value: {other}", self.env_var),
+                };
+                backend.set_env_var(&self.env_var, other)?;
+                tracing::info!("switched {} to slot {other}", self.env_var);
+            }
+            RollbackHandler::Gpt => {
+                let [a, b] = self.partlabels.as_slice() else {
+                    bail!("two root partition labels are needed for the gpt rollback");
+                };
+                let roots = [
+                    GptPartition::resolve(paths, a)?,
+                    GptPartition::resolve(paths, b)?,
+                ];
+                let booted = booted_root(paths, &roots)?;
+                let (booted, other) = (&roots[booted], &roots[1 - booted]);
+                booted.set_no_auto(true)?;
+                other.set_no_auto(false)?;
+                tracing::info!(
+                    "switched the root partition from {} to {}",
+                    booted.label,
+                    other.label
+                );
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// a root partition, addressed as sfdisk expects it
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct GptPartition {
+    label: String,
+    device: PathBuf,
+    disk: PathBuf,
+    number: u32,
+}
+
+impl GptPartition {
+    /// follows the by-partlabel link, the disk and the partition number come
+    /// from the partition's sysfs entry
+    fn resolve(paths: &Paths, label: &str) -> Result<Self> {
+        let link = paths.partlabel_dir.join(label);
+        let device =
+            fs::canonicalize(&link).with_context(|| format!("no partition labelled {label}"))?;
+        let name = device.file_name().context("invalid partition device")?;
+        let sysfs = paths.sys_class_block.join(name);
+        let number = fs::read_to_string(sysfs.join("partition"))
+            .with_context(|| format!("{} is not a partition", device.display()))?
+            .trim()
+            .parse()
+            .with_context(|| format!("invalid partition number of {}", device.display()))?;
+        let disk_name = fs::canonicalize(&sysfs)?
+            .parent()
+            .and_then(Path::file_name)
+            .map(ToOwned::to_owned)
+            .with_context(|| format!("no disk found for {}", device.display()))?;
+        let disk = device.with_file_name(disk_name);
+        Ok(Self {
+            label: label.to_string(),
+            device,
+            disk,
+            number,
+        })
+    }
+
+    fn sfdisk(&self, attrs: Option<&str>) -> Result<String> {
+        let mut command = Command::new("sfdisk");
+        command
+            .arg("--part-attrs")
+            .arg(&self.disk)
+            .arg(self.number.to_string());
+        command.args(attrs);
+        let output = command.output().context("Unable to run sfdisk")?;
+        if !output.status.success() {
+            bail!(
+                "sfdisk --part-attrs failed on {}: {}",
+                self.device.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn attrs(&self) -> Result<Vec<String>> {
+        Ok(parse_attrs(&self.sfdisk(None)?))
+    }
+
+    fn set_no_auto(&self, no_auto: bool) -> Result<()> {
+        let mut attrs = self.attrs()?;
+        attrs.retain(|attr| attr != GPT_NO_AUTO);
+        if no_auto {
+            attrs.push(GPT_NO_AUTO.to_string());
+        }
+        self.sfdisk(Some(&attrs.join(",")))?;
+        Ok(())
+    }
+}
+
+/// attributes as printed by `sfdisk --part-attrs`, e.g. `RequiredPartition
+/// GUID:60,63`, in the form it accepts them back
+fn parse_attrs(output: &str) -> Vec<String> {
+    output
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|attr| attr.strip_prefix("GUID:").unwrap_or(attr))
+        .filter(|attr| !attr.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// index of the booted root: the one mounted on /, or else the only one
+/// systemd's partition discovery may pick
+fn booted_root(paths: &Paths, roots: &[GptPartition; 2]) -> Result<usize> {
+    let mounted = fs::read_to_string(&paths.mount_info)
+        .ok()
+        .and_then(|mounts| root_source(&mounts).map(PathBuf::from))
+        .and_then(|source| fs::canonicalize(source).ok());
+    if let Some(booted) = roots
+        .iter()
+        .position(|root| Some(&root.device) == mounted.as_ref())
+    {
+        return Ok(booted);
+    }
+    let no_auto = [
+        roots[0].attrs()?.iter().any(|attr| attr == GPT_NO_AUTO),
+        roots[1].attrs()?.iter().any(|attr| attr == GPT_NO_AUTO),
+    ];
+    match no_auto {
+        [false, true] => Ok(0),
+        [true, false] => Ok(1),
+        _ => bail!(
+            "cannot tell whether {} or {} is booted",
+            roots[0].label,
+            roots[1].label
+        ),
+    }
+}
+
+/// source of the last mount on /, the visible one
+fn root_source(mounts: &str) -> Option<&str> {
+    mounts.lines().rev().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [source, "/", ..] => Some(*source),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::statefile::StateFileBackend;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_bootloader_env_rollback() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let backend = StateFileBackend::new(paths.state_file.clone());
+        let ab_root = AbRoot::new(
+            RollbackHandler::BootloaderEnv,
+            "root_slot".to_string(),
+            vec![],
+        );
+
+        assert!(ab_root.rollback(&paths, &backend).unwrap());
+        assert_eq!(backend.env_var("root_slot").unwrap().as_deref(), Some("b"));
+        assert!(ab_root.rollback(&paths, &backend).unwrap());
+        assert_eq!(backend.env_var("root_slot").unwrap().as_deref(), Some("a"));
+
+        let deployment = AbRoot::default();
+        assert!(!deployment.rollback(&paths, &backend).unwrap());
+    }
+
+    #[test]
+    fn test_gpt_partition_resolution() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let dev = root.path().join("dev");
+        let sys_disk = root.path().join("sys/devices/pci0/block/sda");
+        fs::create_dir_all(sys_disk.join("sda3")).unwrap();
+        fs::write(sys_disk.join("sda3/partition"), "3\n").unwrap();
+        fs::create_dir_all(&paths.sys_class_block).unwrap();
+        symlink(sys_disk.join("sda3"), paths.sys_class_block.join("sda3")).unwrap();
+        fs::create_dir_all(&paths.partlabel_dir).unwrap();
+        fs::write(dev.join("sda3"), "").unwrap();
+        symlink(dev.join("sda3"), paths.partlabel_dir.join("root_b")).unwrap();
+
+        let partition = GptPartition::resolve(&paths, "root_b").unwrap();
+        assert_eq!(partition.disk, dev.join("sda"));
+        assert_eq!(partition.number, 3);
+        assert!(GptPartition::resolve(&paths, "root_a").is_err());
+
+        assert_eq!(
+            parse_attrs("RequiredPartition GUID:60,63\n"),
+            vec!["RequiredPartition", "60", "63"]
+        );
+        assert_eq!(
+            root_source("/dev/sda2 / ext4 rw 0 0\n/dev/sda3 / ext4 ro 0 0\n"),
+            Some("/dev/sda3")
+        );
+    }
+}
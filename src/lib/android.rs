@@ -0,0 +1,309 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+
+/// offset of the boot control block in the misc partition, right after the
+/// 2 KiB bootloader_message of the recovery
+const BOOT_CONTROL_OFFSET: u64 = 2048;
+/// size of AOSP's struct bootloader_control
+const BOOT_CONTROL_SIZE: usize = 32;
+/// "BCAB" little endian
+const BOOT_CONTROL_MAGIC: u32 = 0x4241_4342;
+/// offset of slot_info[0], each slot_metadata takes 2 bytes
+const SLOT_INFO_OFFSET: usize = 12;
+/// tries_remaining is a 3 bit field
+const MAX_TRIES: u8 = 7;
+/// flag file in the boot dir, the boot control block has no room for it
+static ROLLBACK_TRIGGER_FILE: &str = "greenboot-rollback-trigger";
+
+/// Android style A/B bootloaders (aboot, ABL) pick the slot to boot from the
+/// boot control block in the misc partition: each slot has a priority, the
+/// tries it has left and whether it booted successfully. The bootloader
+/// decrements the tries of an unsuccessful slot and falls back to the other
+/// slot once they are exhausted, the same block boot_control HALs edit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AndroidAbBackend {
+    misc: PathBuf,
+    cmdline: PathBuf,
+    rollback_trigger: PathBuf,
+}
+
+impl AndroidAbBackend {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            misc: paths.android_misc.clone(),
+            cmdline: paths.cmdline.clone(),
+            rollback_trigger: paths.boot_dir.join(ROLLBACK_TRIGGER_FILE),
+        }
+    }
+
+    /// index of the booted slot, 0 for _a
+    fn current_slot(&self) -> Result<usize> {
+        let cmdline = fs::read_to_string(&self.cmdline)
+            .with_context(|| format!("Unable to read {}", self.cmdline.display()))?;
+        let suffix = booted_slot_suffix(&cmdline).context("no androidboot.slot_suffix")?;
+        match suffix.strip_prefix('_').unwrap_or(suffix) {
+            "a" => Ok(0),
+            "b" => Ok(1),
+            other => bail!("unsupported slot {other}"),
+        }
+    }
+
+    fn read_control(&self) -> Result<BootControl> {
+        let mut misc = fs::File::open(&self.misc)
+            .with_context(|| format!("Unable to open {}", self.misc.display()))?;
+        let mut raw = [0; BOOT_CONTROL_SIZE];
+        misc.seek(SeekFrom::Start(BOOT_CONTROL_OFFSET))?;
+        misc.read_exact(&mut raw).with_context(|| {
+            format!("Unable to read the boot control of {}", self.misc.display())
+        })?;
+        BootControl::parse(raw)
+    }
+
+    fn write_control(&self, control: &BootControl) -> Result<()> {
+        let mut misc = OpenOptions::new()
+            .write(true)
+            .open(&self.misc)
+            .with_context(|| format!("Unable to open {}", self.misc.display()))?;
+        misc.seek(SeekFrom::Start(BOOT_CONTROL_OFFSET))?;
+        misc.write_all(&control.serialize())?;
+        misc.sync_all().with_context(|| {
+            format!(
+                "Unable to write the boot control of {}",
+                self.misc.display()
+            )
+        })
+    }
+
+    fn update_slot(&self, change: impl FnOnce(&mut SlotMetadata)) -> Result<()> {
+        let slot = self.current_slot()?;
+        let mut control = self.read_control()?;
+        let mut metadata = control.slot(slot);
+        change(&mut metadata);
+        control.set_slot(slot, metadata);
+        self.write_control(&control)
+    }
+}
+
+impl BootloaderBackend for AndroidAbBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        let metadata = self.read_control()?.slot(self.current_slot()?);
+        Ok((!metadata.successful_boot).then_some(metadata.tries_remaining as i32))
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        if let Some(counter) = self.boot_counter()? {
+            bail!("already set boot_counter={counter}");
+        }
+        let tries = attempts.min(MAX_TRIES as u16) as u8;
+        if tries as u16 != attempts {
+            tracing::warn!(
+                "the boot control block allows at most {MAX_TRIES} tries, using {tries}"
+            );
+        }
+        self.update_slot(|metadata| {
+            metadata.successful_boot = false;
+            metadata.tries_remaining = tries;
+        })
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.update_slot(|metadata| metadata.successful_boot = true)
+    }
+
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        // like markBootSuccessful, a failure leaves the tries to the bootloader
+        if success {
+            self.unset_boot_counter()?;
+        }
+        Ok(())
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        Ok(self.rollback_trigger.exists())
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        fs::write(&self.rollback_trigger, "1\n")
+            .with_context(|| format!("Unable to write {}", self.rollback_trigger.display()))
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        match fs::remove_file(&self.rollback_trigger) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)
+                .with_context(|| format!("Unable to remove {}", self.rollback_trigger.display())),
+            _ => Ok(()),
+        }
+    }
+
+    fn store(&self) -> String {
+        format!("boot control block of {}", self.misc.display())
+    }
+}
+
+/// slot suffix the bootloader passed on the kernel command line, e.g. `_a`
+pub fn booted_slot_suffix(cmdline: &str) -> Option<&str> {
+    cmdline.split_whitespace().find_map(|param| {
+        param
+            .strip_prefix("androidboot.slot_suffix=")
+            .or_else(|| param.strip_prefix("androidboot.slot="))
+    })
+}
+
+/// true if the running kernel was started by an A/B Android bootloader
+pub fn booted_by_android_ab(paths: &Paths) -> bool {
+    fs::read_to_string(&paths.cmdline).is_ok_and(|cmdline| booted_slot_suffix(&cmdline).is_some())
+}
+
+/// struct slot_metadata of AOSP's boot_control, bit fields packed LSB first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SlotMetadata {
+    priority: u8,
+    tries_remaining: u8,
+    successful_boot: bool,
+}
+
+/// struct bootloader_control, only the slot metadata is edited, every
+/// other byte is written back as read
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BootControl([u8; BOOT_CONTROL_SIZE]);
+
+impl BootControl {
+    fn parse(raw: [u8; BOOT_CONTROL_SIZE]) -> Result<Self> {
+        let magic = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+        if magic != BOOT_CONTROL_MAGIC {
+            bail!("no boot control block found, magic is {magic:#x}");
+        }
+        let crc = u32::from_le_bytes([raw[28], raw[29], raw[30], raw[31]]);
+        if crc != crc32(&raw[..28]) {
+            bail!("boot control block has an invalid checksum");
+        }
+        Ok(Self(raw))
+    }
+
+    fn slot(&self, slot: usize) -> SlotMetadata {
+        let bits = self.0[SLOT_INFO_OFFSET + 2 * slot];
+        SlotMetadata {
+            priority: bits & 0x0f,
+            tries_remaining: (bits >> 4) & 0x07,
+            successful_boot: bits & 0x80 != 0,
+        }
+    }
+
+    fn set_slot(&mut self, slot: usize, metadata: SlotMetadata) {
+        self.0[SLOT_INFO_OFFSET + 2 * slot] = (metadata.priority & 0x0f)
+            | ((metadata.tries_remaining & 0x07) << 4)
+            | (u8::from(metadata.successful_boot) << 7);
+    }
+
+    fn serialize(&self) -> [u8; BOOT_CONTROL_SIZE] {
+        let mut raw = self.0;
+        let crc = crc32(&raw[..28]);
+        raw[28..].copy_from_slice(&crc.to_le_bytes());
+        raw
+    }
+}
+
+/// CRC-32/ISO-HDLC, the checksum of the boot control block
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::Path;
+
+    /// misc partitions are block devices, tests use a plain file
+    fn write_test_misc(path: &Path, slots: [(u8, u8, bool); 2]) {
+        let mut raw = [0; BOOT_CONTROL_SIZE];
+        raw[..3].copy_from_slice(b"_a\0");
+        raw[4..8].copy_from_slice(&BOOT_CONTROL_MAGIC.to_le_bytes());
+        raw[8] = 1;
+        raw[9] = 2;
+        let mut control = BootControl(raw);
+        for (slot, (priority, tries_remaining, successful_boot)) in slots.into_iter().enumerate() {
+            control.set_slot(
+                slot,
+                SlotMetadata {
+                    priority,
+                    tries_remaining,
+                    successful_boot,
+                },
+            );
+        }
+        let mut misc = vec![0; BOOT_CONTROL_OFFSET as usize];
+        misc.extend(control.serialize());
+        misc.resize(4096, 0);
+        fs::write(path, misc).unwrap();
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_android_ab_backend() {
+        let root = tempfile::tempdir().unwrap();
+        let mut paths = Paths::with_root(root.path());
+        paths.android_misc = root.path().join("misc");
+        fs::create_dir_all(paths.cmdline.parent().unwrap()).unwrap();
+        fs::create_dir_all(&paths.boot_dir).unwrap();
+        fs::write(
+            &paths.cmdline,
+            "root=/dev/sda1 androidboot.slot_suffix=_b\n",
+        )
+        .unwrap();
+        write_test_misc(&paths.android_misc, [(14, 0, true), (15, 7, true)]);
+        assert!(booted_by_android_ab(&paths));
+
+        let backend = AndroidAbBackend::new(&paths);
+        assert_eq!(backend.boot_counter().unwrap(), None);
+        backend.set_boot_counter(3).unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), Some(3));
+        assert!(backend.set_boot_counter(3).is_err());
+
+        let control = backend.read_control().unwrap();
+        assert_eq!(
+            control.slot(1),
+            SlotMetadata {
+                priority: 15,
+                tries_remaining: 3,
+                successful_boot: false
+            }
+        );
+        // the other slot stays untouched
+        assert_eq!(control.slot(0).priority, 14);
+
+        backend.set_boot_status(true).unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupted_boot_control() {
+        let root = tempfile::tempdir().unwrap();
+        let misc = root.path().join("misc");
+        write_test_misc(&misc, [(15, 7, true), (14, 7, true)]);
+        let mut raw = fs::read(&misc).unwrap();
+        raw[BOOT_CONTROL_OFFSET as usize + SLOT_INFO_OFFSET] ^= 1;
+        fs::write(&misc, raw).unwrap();
+
+        let backend = AndroidAbBackend {
+            misc,
+            cmdline: root.path().join("cmdline"),
+            rollback_trigger: root.path().join(ROLLBACK_TRIGGER_FILE),
+        };
+        assert!(backend.read_control().is_err());
+    }
+}
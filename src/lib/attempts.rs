@@ -0,0 +1,104 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::cmdline::booted_deployment;
+use crate::paths::Paths;
+
+/// Failed boots of each deployment, by commit checksum. Unlike the boot
+/// counter of the bootloader it survives a grubenv reset, and re-staging an
+/// image that already used up its attempts does not give it new ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BootAttempts(BTreeMap<String, u16>);
+
+impl BootAttempts {
+    /// the attempts so far, none when never recorded or unreadable
+    pub fn read(paths: &Paths) -> Self {
+        fs::read_to_string(&paths.boot_attempts)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write(&self, paths: &Paths) -> Result<()> {
+        let path = &paths.boot_attempts;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    /// failed boots of `checksum`
+    pub fn get(&self, checksum: &str) -> u16 {
+        self.0.get(checksum).copied().unwrap_or(0)
+    }
+
+    /// counts a failed boot of `checksum`, returns its failed boots
+    pub fn failed(&mut self, checksum: &str) -> u16 {
+        let attempts = self.0.entry(checksum.to_string()).or_default();
+        *attempts = attempts.saturating_add(1);
+        *attempts
+    }
+
+    /// a healthy boot forgets the failures of `checksum`
+    pub fn passed(&mut self, checksum: &str) {
+        self.0.remove(checksum);
+    }
+}
+
+/// commit checksum of the booted deployment, resolved from the `ostree=`
+/// boot link which points at `/ostree/deploy/<os>/deploy/<checksum>.<serial>`
+pub fn booted_checksum(paths: &Paths) -> Option<String> {
+    deployment_checksum(Path::new(&booted_deployment(paths)?))
+}
+
+fn deployment_checksum(boot_link: &Path) -> Option<String> {
+    let deployment = fs::canonicalize(boot_link).ok()?;
+    let name = deployment.file_name()?.to_str()?;
+    let (checksum, _serial) = name.rsplit_once('.')?;
+    Some(checksum.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_attempts_per_checksum() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let mut attempts = BootAttempts::read(&paths);
+        assert_eq!(attempts.failed("3e5f"), 1);
+        assert_eq!(attempts.failed("3e5f"), 2);
+        assert_eq!(attempts.failed("9a7b"), 1);
+        attempts.write(&paths).unwrap();
+
+        let mut attempts = BootAttempts::read(&paths);
+        assert_eq!(attempts.get("3e5f"), 2);
+        attempts.passed("3e5f");
+        assert_eq!(attempts.get("3e5f"), 0);
+        assert_eq!(attempts.get("9a7b"), 1);
+    }
+
+    #[test]
+    fn test_deployment_checksum() {
+        let root = tempfile::tempdir().unwrap();
+        let deploy = root
+            .path()
+            .join("ostree/deploy/fedora-iot/deploy/3e5f12ab.1");
+        fs::create_dir_all(&deploy).unwrap();
+        let boot = root.path().join("ostree/boot.1/fedora-iot/c0ffee/0");
+        fs::create_dir_all(boot.parent().unwrap()).unwrap();
+        symlink(&deploy, &boot).unwrap();
+
+        assert_eq!(deployment_checksum(&boot).as_deref(), Some("3e5f12ab"));
+        assert_eq!(deployment_checksum(&root.path().join("missing")), None);
+    }
+}
@@ -0,0 +1,196 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::status::StatusRecord;
+
+/// snapshots kept at most while offline, the oldest are dropped first
+const SPOOL_LIMIT: usize = 1000;
+
+/// how long a single upload attempt may take
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// spools monitor snapshots on disk and uploads them in batches, for devices
+/// that are only online a few minutes per day
+#[derive(Debug, Clone)]
+pub struct BatchUploader {
+    /// JSON lines file of the snapshots not uploaded yet
+    pub spool: PathBuf,
+    /// endpoint the batches are POSTed to as a JSON array
+    pub url: String,
+    /// upload once this many snapshots are pending
+    pub batch_size: usize,
+    /// upload once the oldest pending snapshot is this old, even if the batch is not full
+    pub max_age: Duration,
+}
+
+impl BatchUploader {
+    /// appends a snapshot to the spool
+    pub fn record(&self, snapshot: &StatusRecord) -> Result<()> {
+        let mut pending = self.pending()?;
+        pending.push(snapshot.clone());
+        if pending.len() > SPOOL_LIMIT {
+            let dropped = pending.len() - SPOOL_LIMIT;
+            tracing::warn!("monitor spool is full, dropping the {dropped} oldest snapshot(s)");
+            pending.drain(..dropped);
+            return self.rewrite(&pending);
+        }
+
+        if let Some(parent) = self.spool.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Unable to create {}", parent.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool)
+            .with_context(|| format!("Unable to open {}", self.spool.display()))?;
+        writeln!(file, "{}", serde_json::to_string(snapshot)?)
+            .with_context(|| format!("Unable to write {}", self.spool.display()))
+    }
+
+    /// uploads the pending snapshots with curl if a batch is due, returns how
+    /// many were uploaded; a failed upload keeps them for the next attempt
+    pub fn flush_if_due(&self) -> Result<usize> {
+        self.flush_if_due_with(|body| post_json(&self.url, body, UPLOAD_TIMEOUT))
+    }
+
+    fn flush_if_due_with(&self, send: impl FnOnce(&str) -> Result<()>) -> Result<usize> {
+        let pending = self.pending()?;
+        if !self.is_due(&pending) {
+            return Ok(0);
+        }
+        send(&serde_json::to_string(&pending)?)?;
+        self.rewrite(&[])?;
+        Ok(pending.len())
+    }
+
+    fn is_due(&self, pending: &[StatusRecord]) -> bool {
+        let Some(oldest) = pending.first() else {
+            return false;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        pending.len() >= self.batch_size
+            || now.saturating_sub(oldest.timestamp) >= self.max_age.as_secs()
+    }
+
+    fn pending(&self) -> Result<Vec<StatusRecord>> {
+        let content = match fs::read_to_string(&self.spool) {
+            Ok(content) => content,
+            Err(_) => return Ok(vec![]),
+        };
+        // a line torn by a power cut must not block every later upload
+        Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    fn rewrite(&self, pending: &[StatusRecord]) -> Result<()> {
+        let mut content = String::new();
+        for snapshot in pending {
+            content.push_str(&serde_json::to_string(snapshot)?);
+            content.push('\n');
+        }
+        fs::write(&self.spool, content)
+            .with_context(|| format!("Unable to write {}", self.spool.display()))
+    }
+}
+
+/// POSTs `body` with curl, giving up after `timeout`
+pub fn post_json(url: &str, body: &str, timeout: Duration) -> Result<()> {
+    let mut child = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(timeout.as_secs().to_string())
+        .args(["-X", "POST", "-H", "Content-Type: application/json"])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Unable to run curl")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "upload to {url} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::HealthStatus;
+
+    fn uploader(dir: &tempfile::TempDir) -> BatchUploader {
+        BatchUploader {
+            spool: dir.path().join("spool.jsonl"),
+            url: "http://localhost/".to_string(),
+            batch_size: 3,
+            max_age: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn test_batch_uploaded_when_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = uploader(&dir);
+        let snapshot = StatusRecord::new(HealthStatus::Green, vec![]);
+
+        uploader.record(&snapshot).unwrap();
+        uploader.record(&snapshot).unwrap();
+        assert_eq!(
+            uploader
+                .flush_if_due_with(|_| bail!("batch is not due yet"))
+                .unwrap(),
+            0
+        );
+
+        uploader.record(&snapshot).unwrap();
+        // offline, the snapshots stay spooled
+        assert!(
+            uploader
+                .flush_if_due_with(|_| bail!("network is unreachable"))
+                .is_err()
+        );
+        let mut sent = String::new();
+        let uploaded = uploader
+            .flush_if_due_with(|body| {
+                sent = body.to_string();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(uploaded, 3);
+        assert_eq!(
+            serde_json::from_str::<Vec<StatusRecord>>(&sent)
+                .unwrap()
+                .len(),
+            3
+        );
+        assert!(uploader.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_old_snapshot_makes_batch_due() {
+        let dir = tempfile::tempdir().unwrap();
+        let uploader = uploader(&dir);
+        let mut snapshot = StatusRecord::new(HealthStatus::Degraded, vec!["a.sh".to_string()]);
+        snapshot.timestamp -= 7200;
+
+        uploader.record(&snapshot).unwrap();
+        assert_eq!(uploader.flush_if_due_with(|_| Ok(())).unwrap(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::android::{AndroidAbBackend, booted_by_android_ab};
+use crate::grub::{GrubenvBackend, grubenv_is_writable};
+use crate::paths::Paths;
+use crate::rauc::{RaucBackend, managed_by_rauc};
+use crate::sdboot::{SdBootBackend, booted_by_sdboot};
+use crate::statefile::StateFileBackend;
+use crate::uboot::UBootBackend;
+use crate::uefi::UefiAbBackend;
+use crate::zipl::zipl_backend;
+
+/// Storage of the boot counter, the boot status and the rollback trigger.
+/// The health-check only goes through this trait, each supported bootloader
+/// provides one implementation of it.
+pub trait BootloaderBackend: fmt::Debug {
+    /// remaining boot attempts, none if the counter is not armed
+    fn boot_counter(&self) -> Result<Option<i32>>;
+    /// arms the counter with the given attempts, fails if it is already armed
+    fn set_boot_counter(&self, attempts: u16) -> Result<()>;
+    fn unset_boot_counter(&self) -> Result<()>;
+    /// records the verdict of the current boot, a success also disarms the counter
+    fn set_boot_status(&self, success: bool) -> Result<()>;
+    /// true if a rollback may happen once the counter is exhausted
+    fn rollback_trigger(&self) -> Result<bool>;
+    fn set_rollback_trigger(&self) -> Result<()>;
+    fn unset_rollback_trigger(&self) -> Result<()>;
+    /// accounts for the current boot when the bootloader cannot do it at
+    /// boot time, called once at the start of the health-check
+    fn count_boot(&self) -> Result<()> {
+        Ok(())
+    }
+    /// switches to the other image itself on A/B systems not managed by an
+    /// OS deployment manager, false if the rollback is left to bootc or
+    /// rpm-ostree
+    fn rollback(&self) -> Result<bool> {
+        Ok(false)
+    }
+    /// whether the image switched to by `rollback` exists, none if the
+    /// rollback is left to bootc or rpm-ostree
+    fn has_rollback_target(&self) -> Result<Option<bool>> {
+        Ok(None)
+    }
+    /// variable of the bootloader environment, for
+    /// `GREENBOOT_ROLLBACK_HANDLER=bootloader-env`
+    fn env_var(&self, key: &str) -> Result<Option<String>> {
+        bail!("the {} cannot hold {key}", self.store())
+    }
+    fn set_env_var(&self, key: &str, value: &str) -> Result<()> {
+        bail!("the {} cannot hold {key}={value}", self.store())
+    }
+    /// where the state is kept, for the logs and `greenboot status`
+    fn store(&self) -> String;
+}
+
+/// backend keeping the state of `bootloader`. Without a writable grubenv,
+/// e.g. on netboot or with a read-only ESP, GRUB systems keep it in the
+/// state file rather than losing the rollback protection.
+pub fn backend_for(bootloader: Bootloader, paths: &Paths) -> Option<Box<dyn BootloaderBackend>> {
+    match bootloader {
+        Bootloader::GrubEfi | Bootloader::GrubBios if !grubenv_is_writable(&paths.grubenv) => {
+            tracing::warn!(
+                "no writable grubenv at {}, keeping the boot counter in {}",
+                paths.grubenv.display(),
+                paths.state_file.display()
+            );
+            Some(Box::new(StateFileBackend::new(paths.state_file.clone())))
+        }
+        Bootloader::GrubEfi | Bootloader::GrubBios => Some(Box::new(GrubenvBackend::new(paths))),
+        Bootloader::SystemdBoot => Some(Box::new(SdBootBackend::new(paths))),
+        Bootloader::UBoot => Some(Box::new(UBootBackend::new(paths))),
+        Bootloader::Zipl => Some(Box::new(zipl_backend(paths))),
+        Bootloader::AndroidAb => Some(Box::new(AndroidAbBackend::new(paths))),
+        Bootloader::UefiAb => Some(Box::new(UefiAbBackend::new(paths))),
+        Bootloader::Rauc => Some(Box::new(RaucBackend::new(paths))),
+    }
+}
+
+/// bootloader family, decides which backend keeps the boot counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bootloader {
+    /// GRUB booted by UEFI firmware
+    GrubEfi,
+    /// GRUB booted by legacy BIOS firmware
+    GrubBios,
+    /// systemd-boot, UEFI only
+    SystemdBoot,
+    /// U-Boot, common on ARM boards
+    UBoot,
+    /// zipl, the s390x IPL loader
+    Zipl,
+    /// Android style A/B bootloaders (aboot, ABL) driven by the misc partition
+    AndroidAb,
+    /// two images behind their own UEFI boot entries, switched through
+    /// BootNext and BootOrder, never detected
+    UefiAb,
+    /// slots managed by RAUC, over whichever bootloader it drives
+    Rauc,
+}
+
+impl Bootloader {
+    /// true for the GRUB variants, which share the grubenv backend
+    pub fn is_grub(&self) -> bool {
+        matches!(self, Bootloader::GrubEfi | Bootloader::GrubBios)
+    }
+}
+
+impl fmt::Display for Bootloader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Bootloader::GrubEfi => write!(f, "grub-efi"),
+            Bootloader::GrubBios => write!(f, "grub-bios"),
+            Bootloader::SystemdBoot => write!(f, "systemd-boot"),
+            Bootloader::UBoot => write!(f, "u-boot"),
+            Bootloader::Zipl => write!(f, "zipl"),
+            Bootloader::AndroidAb => write!(f, "android-ab"),
+            Bootloader::UefiAb => write!(f, "uefi-ab"),
+            Bootloader::Rauc => write!(f, "rauc"),
+        }
+    }
+}
+
+impl FromStr for Bootloader {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "grub-efi" => Ok(Bootloader::GrubEfi),
+            "grub-bios" => Ok(Bootloader::GrubBios),
+            "systemd-boot" => Ok(Bootloader::SystemdBoot),
+            "u-boot" => Ok(Bootloader::UBoot),
+            "zipl" => Ok(Bootloader::Zipl),
+            "android-ab" => Ok(Bootloader::AndroidAb),
+            "uefi-ab" => Ok(Bootloader::UefiAb),
+            "rauc" => Ok(Bootloader::Rauc),
+            other => bail!("unknown bootloader '{other}'"),
+        }
+    }
+}
+
+/// probes the firmware and the filesystem for the bootloader in use,
+/// none if nothing known was found
+pub fn detect_bootloader(paths: &Paths) -> Option<Bootloader> {
+    // RAUC owns the environment of the bootloader below it
+    if managed_by_rauc(paths) {
+        return Some(Bootloader::Rauc);
+    }
+    // zipl only exists on s390x, where there is no GRUB
+    if std::env::consts::ARCH == "s390x" || paths.zipl_config.exists() {
+        return Some(Bootloader::Zipl);
+    }
+    // only an A/B bootloader passes a slot on the kernel command line
+    if booted_by_android_ab(paths) {
+        return Some(Bootloader::AndroidAb);
+    }
+    // a grub2 dir can be left over on an image switched to systemd-boot
+    if booted_by_sdboot(paths) {
+        return Some(Bootloader::SystemdBoot);
+    }
+    if paths.boot_dir.join("grub2").is_dir() {
+        return if paths.efi_firmware.is_dir() {
+            Some(Bootloader::GrubEfi)
+        } else {
+            Some(Bootloader::GrubBios)
+        };
+    }
+    let uboot_files = ["boot.scr", "uEnv.txt"];
+    if paths.uboot_env_config.exists()
+        || uboot_files
+            .iter()
+            .any(|file| paths.boot_dir.join(file).exists())
+    {
+        return Some(Bootloader::UBoot);
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_detect_bootloader() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        if std::env::consts::ARCH == "s390x" {
+            assert_eq!(detect_bootloader(&paths), Some(Bootloader::Zipl));
+            return;
+        }
+        assert_eq!(detect_bootloader(&paths), None);
+
+        fs::create_dir_all(&paths.boot_dir).unwrap();
+        fs::write(paths.boot_dir.join("boot.scr"), "").unwrap();
+        assert_eq!(detect_bootloader(&paths), Some(Bootloader::UBoot));
+
+        fs::create_dir_all(paths.boot_dir.join("grub2")).unwrap();
+        assert_eq!(detect_bootloader(&paths), Some(Bootloader::GrubBios));
+
+        fs::create_dir_all(&paths.efi_firmware).unwrap();
+        assert_eq!(detect_bootloader(&paths), Some(Bootloader::GrubEfi));
+
+        fs::create_dir_all(paths.cmdline.parent().unwrap()).unwrap();
+        fs::write(&paths.cmdline, "androidboot.slot_suffix=_a quiet\n").unwrap();
+        assert_eq!(detect_bootloader(&paths), Some(Bootloader::AndroidAb));
+    }
+
+    #[test]
+    fn test_backend_for() {
+        let paths = Paths::default();
+        assert!(backend_for(Bootloader::GrubEfi, &paths).is_some());
+        assert!(backend_for(Bootloader::GrubBios, &paths).is_some());
+        assert!(backend_for(Bootloader::SystemdBoot, &paths).is_some());
+        assert!(backend_for(Bootloader::UBoot, &paths).is_some());
+        assert!(backend_for(Bootloader::Zipl, &paths).is_some());
+        assert!(backend_for(Bootloader::AndroidAb, &paths).is_some());
+        assert!(backend_for(Bootloader::UefiAb, &paths).is_some());
+        assert!(backend_for(Bootloader::Rauc, &paths).is_some());
+    }
+
+    #[test]
+    fn test_grub_without_grubenv_uses_state_file() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let backend = backend_for(Bootloader::GrubEfi, &paths).unwrap();
+        assert_eq!(
+            backend.store(),
+            format!("state file {}", paths.state_file.display())
+        );
+
+        fs::create_dir_all(paths.grubenv.parent().unwrap()).unwrap();
+        fs::copy("testing_assets/grubenv", &paths.grubenv).unwrap();
+        let backend = backend_for(Bootloader::GrubEfi, &paths).unwrap();
+        assert_eq!(
+            backend.store(),
+            format!("grubenv {}", paths.grubenv.display())
+        );
+    }
+
+    #[test]
+    fn test_bootloader_round_trip() {
+        for bootloader in [
+            Bootloader::GrubEfi,
+            Bootloader::GrubBios,
+            Bootloader::SystemdBoot,
+            Bootloader::UBoot,
+            Bootloader::Zipl,
+            Bootloader::AndroidAb,
+            Bootloader::UefiAb,
+            Bootloader::Rauc,
+        ] {
+            assert_eq!(
+                bootloader.to_string().parse::<Bootloader>().unwrap(),
+                bootloader
+            );
+        }
+        assert!("lilo".parse::<Bootloader>().is_err());
+    }
+}
@@ -0,0 +1,166 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::greenboot::{Diagnostics, DiagnosticsError, FailedCheck};
+
+/// name the boot time budget check is reported under
+pub static BOOT_TIME_CHECK: &str = "boot-time-budget";
+/// seconds since boot, including the time suspended, see proc_uptime(5)
+static UPTIME_PATH: &str = "/proc/uptime";
+
+/// how a boot that took longer than its budget is judged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BudgetCheck {
+    /// only logged, the boot stays green
+    #[default]
+    Warn,
+    /// the boot is degraded
+    Wanted,
+    /// the boot is red
+    Required,
+}
+
+impl fmt::Display for BudgetCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetCheck::Warn => write!(f, "warn"),
+            BudgetCheck::Wanted => write!(f, "wanted"),
+            BudgetCheck::Required => write!(f, "required"),
+        }
+    }
+}
+
+impl FromStr for BudgetCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "warn" => Ok(BudgetCheck::Warn),
+            "wanted" => Ok(BudgetCheck::Wanted),
+            "required" => Ok(BudgetCheck::Required),
+            other => bail!("unknown boot time check '{other}'"),
+        }
+    }
+}
+
+/// time since the kernel started
+pub fn time_since_boot() -> Result<Duration> {
+    let uptime =
+        fs::read_to_string(UPTIME_PATH).with_context(|| format!("Unable to read {UPTIME_PATH}"))?;
+    let secs: f64 = uptime
+        .split_whitespace()
+        .next()
+        .and_then(|secs| secs.parse().ok())
+        .with_context(|| format!("Invalid {UPTIME_PATH}"))?;
+    Ok(Duration::from_secs_f64(secs))
+}
+
+/// Built-in check run once the scripts passed: the time from boot to green,
+/// as regressions in boot time are a health issue of their own for kiosks
+/// and industrial controllers. Without a budget the check is not run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootTimeBudget {
+    pub budget: Option<Duration>,
+    pub check: BudgetCheck,
+}
+
+impl BootTimeBudget {
+    pub fn new(budget: Option<Duration>, check: BudgetCheck) -> Self {
+        Self { budget, check }
+    }
+
+    /// adds the outcome of the check to the one of the scripts, a red boot
+    /// stays red and the check is not run
+    pub fn apply(&self, result: Result<Diagnostics>) -> Result<Diagnostics> {
+        let (budget, diagnostics) = match (self.budget, result) {
+            (None, result) | (_, result @ Err(_)) => return result,
+            (Some(budget), Ok(diagnostics)) => (budget, diagnostics),
+        };
+        match time_since_boot() {
+            Ok(elapsed) => self.judge(diagnostics, budget, elapsed),
+            Err(e) => {
+                tracing::warn!(
+                    "cannot tell how long the boot took, skipping {BOOT_TIME_CHECK}: {e}"
+                );
+                Ok(diagnostics)
+            }
+        }
+    }
+
+    fn judge(
+        &self,
+        mut diagnostics: Diagnostics,
+        budget: Duration,
+        elapsed: Duration,
+    ) -> Result<Diagnostics> {
+        if elapsed <= budget {
+            diagnostics.passed += 1;
+            return Ok(diagnostics);
+        }
+        let message = format!(
+            "boot took {}s, over its budget of {}s",
+            elapsed.as_secs(),
+            budget.as_secs()
+        );
+        tracing::warn!("{message}");
+        let failed = FailedCheck::new(BOOT_TIME_CHECK, None, &message, SystemTime::now());
+        match self.check {
+            BudgetCheck::Warn => Ok(diagnostics),
+            BudgetCheck::Wanted => {
+                diagnostics.failed_wanted.push(BOOT_TIME_CHECK.to_string());
+                diagnostics.wanted_details.push(failed);
+                Ok(diagnostics)
+            }
+            BudgetCheck::Required => Err(DiagnosticsError::RequiredFailed {
+                failed: vec![BOOT_TIME_CHECK.to_string()],
+                passed: diagnostics.passed,
+                details: vec![failed],
+            }
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::status::HealthStatus;
+
+    #[test]
+    fn test_boot_time_budget() {
+        let budget = Duration::from_secs(90);
+        let check = |check| {
+            BootTimeBudget::new(Some(budget), check).judge(
+                Diagnostics::default(),
+                budget,
+                Duration::from_secs(95),
+            )
+        };
+        assert_eq!(
+            check(BudgetCheck::Warn).unwrap().status(),
+            HealthStatus::Green
+        );
+        let degraded = check(BudgetCheck::Wanted).unwrap();
+        assert_eq!(degraded.failed_wanted, [BOOT_TIME_CHECK]);
+        assert_eq!(
+            degraded.wanted_details[0].output,
+            "boot took 95s, over its budget of 90s"
+        );
+        let red = check(BudgetCheck::Required).unwrap_err();
+        assert!(matches!(
+            red.downcast_ref::<DiagnosticsError>(),
+            Some(DiagnosticsError::RequiredFailed { failed, .. }) if failed == &[BOOT_TIME_CHECK]
+        ));
+
+        let within = BootTimeBudget::new(Some(budget), BudgetCheck::Required)
+            .judge(Diagnostics::default(), budget, Duration::from_secs(42))
+            .unwrap();
+        assert_eq!(within.passed, 1);
+        assert!(time_since_boot().unwrap() > Duration::ZERO);
+    }
+}
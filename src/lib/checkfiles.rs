@@ -0,0 +1,217 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use glob::Pattern;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::container::is_container;
+
+/// the kernel does not read interpreter lines longer than this either
+const SHEBANG_READ_LIMIT: u64 = 256;
+
+/// file names never run as checks unless GREENBOOT_IGNORE_PATTERNS says otherwise
+pub static DEFAULT_IGNORE_PATTERNS: [&str; 2] = ["README*", "*.conf"];
+
+/// what to do about a file in a check dir that is neither a .sh script,
+/// executable nor a container check, e.g. documentation shipped alongside
+/// the checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownFiles {
+    /// skip it silently
+    Ignore,
+    /// skip it with a warning
+    #[default]
+    Warn,
+    /// skip it with a warning, and make validate-image fail
+    Fail,
+}
+
+impl fmt::Display for UnknownFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnknownFiles::Ignore => write!(f, "ignore"),
+            UnknownFiles::Warn => write!(f, "warn"),
+            UnknownFiles::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+impl FromStr for UnknownFiles {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "ignore" => Ok(UnknownFiles::Ignore),
+            "warn" => Ok(UnknownFiles::Warn),
+            "fail" => Ok(UnknownFiles::Fail),
+            other => bail!("unknown policy '{other}'"),
+        }
+    }
+}
+
+/// Which files of the check dirs are not checks: those matching an ignore
+/// pattern are never run, even when executable, and the other files greenboot
+/// cannot run are reported according to `unknown`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckFiles {
+    pub unknown: UnknownFiles,
+    /// globs matched against the file name
+    pub ignore: Vec<Pattern>,
+}
+
+impl Default for CheckFiles {
+    fn default() -> Self {
+        Self::new(UnknownFiles::default(), &DEFAULT_IGNORE_PATTERNS)
+    }
+}
+
+impl CheckFiles {
+    /// invalid patterns are logged and left out
+    pub fn new<S: AsRef<str>>(unknown: UnknownFiles, patterns: &[S]) -> Self {
+        let ignore = patterns
+            .iter()
+            .filter_map(|pattern| match Pattern::new(pattern.as_ref()) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    tracing::warn!("ignoring invalid pattern '{}': {e}", pattern.as_ref());
+                    None
+                }
+            })
+            .collect();
+        Self { unknown, ignore }
+    }
+
+    /// true if the file name matches one of the ignore patterns
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        path.file_name()
+            .map(|name| name.to_string_lossy())
+            .is_some_and(|name| self.ignore.iter().any(|pattern| pattern.matches(&name)))
+    }
+
+    /// regular files of `dir` that are not ignored but cannot be run either
+    pub fn unknown_files(&self, dir: &Path) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return vec![];
+        };
+        let mut unknown: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.is_file() && !is_script(path) && !is_container(path) && !self.is_ignored(path)
+            })
+            .collect();
+        unknown.sort();
+        unknown
+    }
+
+    /// logs the unknown files of `dir` unless the policy is to ignore them
+    pub fn report_unknown(&self, dir: &Path) {
+        if self.unknown == UnknownFiles::Ignore {
+            return;
+        }
+        for path in self.unknown_files(dir) {
+            tracing::warn!(
+                "{} is neither a .sh script nor executable and is ignored",
+                path.display()
+            );
+        }
+    }
+}
+
+/// regular file that is either a .sh script or executable
+pub fn is_script(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| {
+        metadata.is_file()
+            && (path.extension().and_then(|ext| ext.to_str()) == Some("sh")
+                || metadata.permissions().mode() & 0o111 != 0)
+    })
+}
+
+/// interpreter line of a script, e.g. `["/usr/bin/env", "python3"]` for
+/// `#!/usr/bin/env python3`, none without one. Like for the kernel,
+/// everything after the interpreter is a single argument.
+pub fn shebang(path: &Path) -> Option<Vec<String>> {
+    let mut head = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(SHEBANG_READ_LIMIT)
+        .read_to_end(&mut head)
+        .ok()?;
+    let line = head.strip_prefix(b"#!")?.split(|b| *b == b'\n').next()?;
+    let line = String::from_utf8_lossy(line);
+    let (interpreter, argument) = match line.trim().split_once(char::is_whitespace) {
+        Some((interpreter, argument)) => (interpreter, Some(argument.trim())),
+        None => (line.trim(), None),
+    };
+    if interpreter.is_empty() {
+        return None;
+    }
+    Some(
+        std::iter::once(interpreter)
+            .chain(argument)
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+/// a check symlinked to /dev/null is masked, like a systemd unit
+pub fn is_masked(path: &Path) -> bool {
+    fs::read_link(path).is_ok_and(|target| target == Path::new("/dev/null"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_shebang() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = dir.path().join("01_check.sh");
+        for (content, expected) in [
+            ("#!/bin/sh\nexit 0\n", Some(vec!["/bin/sh"])),
+            (
+                "#! /usr/bin/env python3 -u\n",
+                Some(vec!["/usr/bin/env", "python3 -u"]),
+            ),
+            ("exit 0\n", None),
+            ("#!\n", None),
+        ] {
+            fs::write(&script, content).unwrap();
+            assert_eq!(
+                shebang(&script),
+                expected.map(|args| args.into_iter().map(str::to_string).collect())
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["01_disk.sh", "README.md", "checks.conf", "notes.txt"] {
+            fs::write(dir.path().join(name), "exit 0\n").unwrap();
+        }
+        let binary = dir.path().join("02_binary");
+        fs::write(&binary, "").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let files = CheckFiles::default();
+        assert!(files.is_ignored(&dir.path().join("README.md")));
+        assert!(!files.is_ignored(&binary));
+        assert_eq!(
+            files.unknown_files(dir.path()),
+            vec![dir.path().join("notes.txt")]
+        );
+
+        let files = CheckFiles::new(UnknownFiles::Fail, &["*.txt", "[invalid"]);
+        assert_eq!(files.ignore.len(), 1);
+        assert_eq!(
+            files.unknown_files(dir.path()),
+            vec![dir.path().join("README.md"), dir.path().join("checks.conf")]
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{BufRead, BufReader, ErrorKind, Read};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::journal::{self, JournalStream, PRIORITY_INFO, PRIORITY_WARNING};
+use crate::redact::redact;
+
+/// longer lines are split, a check writing without newlines cannot make one
+/// grow unbounded
+const LINE_LIMIT: u64 = 4096;
+/// end of the output of a script kept by default, where the error usually is
+pub const DEFAULT_OUTPUT_LIMIT: usize = 64 * 1024;
+/// how long the rest of the output is waited for once the script is done
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// stream of a check, stderr lines are logged as warnings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputStream::Stdout => write!(f, "stdout"),
+            OutputStream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+impl OutputStream {
+    fn priority(self) -> u8 {
+        match self {
+            OutputStream::Stdout => PRIORITY_INFO,
+            OutputStream::Stderr => PRIORITY_WARNING,
+        }
+    }
+
+    fn log(self, check: &str, line: &str) {
+        match self {
+            OutputStream::Stdout => tracing::info!("{check}: {line}"),
+            OutputStream::Stderr => tracing::warn!("{check}: {line}"),
+        }
+    }
+}
+
+/// end of the output, counting what was dropped before it
+#[derive(Debug, Default)]
+struct Tail {
+    bytes: VecDeque<u8>,
+    limit: usize,
+    dropped: usize,
+}
+
+impl Tail {
+    fn push(&mut self, line: &[u8]) {
+        self.bytes.extend(line);
+        if self.bytes.len() > self.limit {
+            let mut excess = self.bytes.len() - self.limit;
+            // the cut moves to the next char rather than split one
+            while self
+                .bytes
+                .get(excess)
+                .is_some_and(|byte| byte & 0b1100_0000 == 0b1000_0000)
+            {
+                excess += 1;
+            }
+            self.bytes.drain(..excess);
+            self.dropped += excess;
+        }
+    }
+}
+
+/// Captures the output of a script while forwarding it to the journal line
+/// by line as it is written, with the name of the script as
+/// SYSLOG_IDENTIFIER and the GREENBOOT_CHECK and GREENBOOT_PHASE fields, so
+/// it can be followed live with `journalctl -t <script>`. Secrets are
+/// redacted from each line first. Only the end of stdout and stderr,
+/// interleaved as written, is kept within the limit, however much the
+/// script writes. Without journald the lines are logged.
+#[derive(Debug)]
+pub struct OutputCapture {
+    stage: String,
+    check: String,
+    tail: Arc<Mutex<Tail>>,
+    forwarders: Vec<(OutputStream, JoinHandle<()>)>,
+}
+
+impl OutputCapture {
+    /// output of the `check` of `stage`, keeping its last `limit` bytes
+    pub fn new(stage: &str, check: &str, limit: usize) -> Self {
+        Self {
+            stage: stage.to_string(),
+            check: check.to_string(),
+            tail: Arc::new(Mutex::new(Tail {
+                limit,
+                ..Tail::default()
+            })),
+            forwarders: Vec::new(),
+        }
+    }
+
+    /// reads `reader` until it is closed
+    pub fn forward(&mut self, reader: impl Read + Send + 'static, stream: OutputStream) {
+        let tail = Arc::clone(&self.tail);
+        let (stage, name) = (self.stage.clone(), self.check.clone());
+        let handle = thread::spawn(move || {
+            let journal = journal::is_available()
+                .then(|| {
+                    JournalStream::open(
+                        &name,
+                        &[("GREENBOOT_CHECK", &name), ("GREENBOOT_PHASE", &stage)],
+                    )
+                    .inspect_err(|e| {
+                        tracing::debug!("cannot open a journal stream for {name}: {e}")
+                    })
+                    .ok()
+                })
+                .flatten();
+            let mut reader = BufReader::new(reader);
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                match (&mut reader).take(LINE_LIMIT).read_until(b'\n', &mut line) {
+                    Ok(0) => break,
+                    Ok(_) => {}
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        tracing::debug!("cannot read the {stream} of {name}: {e}");
+                        break;
+                    }
+                }
+                let line = String::from_utf8_lossy(&line);
+                let line = redact(&line);
+                forward(journal.as_ref(), stream, &name, &line);
+                tail.lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .push(line.as_bytes());
+            }
+        });
+        self.forwarders.push((stream, handle));
+    }
+
+    /// the end of the output once every stream is closed, or after
+    /// DRAIN_TIMEOUT as a process that left the process group of the script
+    /// may hold one open
+    pub fn finish(self) -> String {
+        let deadline = Instant::now() + DRAIN_TIMEOUT;
+        for (stream, handle) in self.forwarders {
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(DRAIN_POLL_INTERVAL);
+            }
+            if handle.is_finished() {
+                let _ = handle.join();
+            } else {
+                tracing::warn!(
+                    "the {stream} of {} is still held open, not waiting for the rest of it",
+                    self.check
+                );
+            }
+        }
+        let tail = self.tail.lock().unwrap_or_else(PoisonError::into_inner);
+        let bytes: Vec<u8> = tail.bytes.iter().copied().collect();
+        let output = String::from_utf8_lossy(&bytes);
+        match tail.dropped {
+            0 => output.into_owned(),
+            dropped => format!("[{dropped} bytes truncated]\n{output}"),
+        }
+    }
+}
+
+fn forward(journal: Option<&JournalStream>, stream: OutputStream, check: &str, line: &str) {
+    let line = line.trim_end_matches(['\n', '\r']);
+    if line.trim().is_empty() {
+        return;
+    }
+    let sent = journal.map(|journal| journal.send(stream.priority(), line));
+    if !matches!(sent, Some(Ok(()))) {
+        stream.log(check, line);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_only_the_end_is_kept() {
+        let line = format!("{}\n", "x".repeat(99));
+        let output = line.repeat(1000);
+        let mut capture = OutputCapture::new("wanted", "01_chatty.sh", 1000);
+        capture.forward(
+            Cursor::new(output.clone().into_bytes()),
+            OutputStream::Stdout,
+        );
+        let captured = capture.finish();
+        let kept = captured.strip_prefix("[99000 bytes truncated]\n").unwrap();
+        assert_eq!(kept, line.repeat(10));
+
+        let mut capture = OutputCapture::new("wanted", "01_chatty.sh", DEFAULT_OUTPUT_LIMIT);
+        capture.forward(Cursor::new(b"checking\nok".to_vec()), OutputStream::Stderr);
+        assert_eq!(capture.finish(), "checking\nok");
+
+        // "é" is 2 bytes, a limit of 5 would keep half of the first one
+        let mut capture = OutputCapture::new("wanted", "01_accents.sh", 5);
+        capture.forward(
+            Cursor::new("éééé".as_bytes().to_vec()),
+            OutputStream::Stdout,
+        );
+        assert_eq!(capture.finish(), "[4 bytes truncated]\néé");
+    }
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use nix::fcntl::{FcntlArg, FdFlag, fcntl};
+use nix::unistd::dup2_raw;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use crate::redact::redact;
+
+/// file descriptor a check writes its report to
+pub const RESULT_FD: RawFd = 3;
+
+/// a report is a few lines of JSON, anything longer is not one
+const RESULT_READ_LIMIT: u64 = 4096;
+
+/// how bad a check considers what it found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckSeverity {
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl fmt::Display for CheckSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckSeverity::Info => write!(f, "info"),
+            CheckSeverity::Warning => write!(f, "warning"),
+            CheckSeverity::Error => write!(f, "error"),
+            CheckSeverity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Result a check may write as a JSON object to RESULT_FD, e.g.
+/// `{"message": "disk 95% full", "severity": "error", "metrics": {"used": 95}}`,
+/// so a failure is explained by a line rather than by the end of its output.
+/// Every field is optional.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckReport {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<CheckSeverity>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metrics: BTreeMap<String, serde_json::Number>,
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(severity) = self.severity {
+            write!(f, "[{severity}] ")?;
+        }
+        write!(f, "{}", self.message.as_deref().unwrap_or("no message"))?;
+        for (name, value) in &self.metrics {
+            write!(f, " {name}={value}")?;
+        }
+        Ok(())
+    }
+}
+
+impl CheckReport {
+    /// none when nothing was written, an invalid report is only logged as
+    /// the check result does not depend on it. Secrets are redacted from the
+    /// message.
+    pub fn parse(content: &str, check: &str) -> Option<Self> {
+        if content.trim().is_empty() {
+            return None;
+        }
+        let mut report: Self = serde_json::from_str(content)
+            .inspect_err(|e| {
+                tracing::warn!("ignoring invalid result of {check} on fd {RESULT_FD}: {e}")
+            })
+            .ok()?;
+        report.message = report.message.map(|message| redact(&message).into_owned());
+        Some(report)
+    }
+
+    /// the report written to `file` by a check run with with_result_fd
+    pub fn read(mut file: &File, check: &str) -> Option<Self> {
+        let mut content = String::new();
+        let read = file
+            .rewind()
+            .and_then(|_| file.take(RESULT_READ_LIMIT).read_to_string(&mut content));
+        match read {
+            Ok(_) => Self::parse(&content, check),
+            Err(e) => {
+                tracing::warn!("cannot read the result of {check}: {e}");
+                None
+            }
+        }
+    }
+}
+
+/// opens `file` as RESULT_FD in the process spawned by `command`
+pub fn with_result_fd(command: &mut Command, file: &File) {
+    let fd = file.as_raw_fd();
+    // SAFETY: only dup2 and fcntl run between fork and exec, both are
+    // async-signal-safe, and `file` outlives the spawn
+    unsafe {
+        command.pre_exec(move || {
+            let file = BorrowedFd::borrow_raw(fd);
+            if fd == RESULT_FD {
+                // already in place, it only has to survive the exec
+                fcntl(file, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            } else {
+                // the duplicate is RESULT_FD, it must stay open for the exec
+                std::mem::forget(dup2_raw(file, RESULT_FD)?);
+            }
+            Ok(())
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_report() {
+        let file = tempfile::tempfile().unwrap();
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            r#"echo '{"message": "disk 95% full", "severity": "error", "metrics": {"used": 95}}' >&3"#,
+        ]);
+        with_result_fd(&mut command, &file);
+        assert!(command.status().unwrap().success());
+
+        let report = CheckReport::read(&file, "01_disk.sh").unwrap();
+        assert_eq!(report.message.as_deref(), Some("disk 95% full"));
+        assert_eq!(report.severity, Some(CheckSeverity::Error));
+        assert_eq!(report.to_string(), "[error] disk 95% full used=95");
+
+        assert_eq!(CheckReport::parse("\n", "01_disk.sh"), None);
+        assert_eq!(CheckReport::parse("disk full", "01_disk.sh"), None);
+        assert_eq!(
+            CheckReport::parse(r#"{"severity": "fatal"}"#, "01_disk.sh"),
+            None
+        );
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result};
+use std::fs;
+
+use crate::paths::Paths;
+
+/// prefix of the kernel parameters greenboot honors
+static PARAM_PREFIX: &str = "greenboot.";
+
+/// `greenboot.*` kernel parameters, they override the config file for a single
+/// boot, e.g. when added from the GRUB prompt
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KernelOverrides {
+    /// greenboot.disable=1, skip the health-check and leave the boot counter alone
+    pub disable: bool,
+    /// greenboot.max_boot_attempts=N
+    pub max_boot_attempts: Option<u16>,
+    /// greenboot.skip=<check>[,<check>...], may be repeated
+    pub skip: Vec<String>,
+    /// greenboot.profile=<profile>
+    pub profile: Option<String>,
+}
+
+impl KernelOverrides {
+    /// parses the content of /proc/cmdline, unknown or invalid parameters are ignored
+    pub fn parse(cmdline: &str) -> Self {
+        let mut overrides = Self::default();
+        for param in cmdline.split_whitespace() {
+            let Some(param) = param.strip_prefix(PARAM_PREFIX) else {
+                continue;
+            };
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            match key {
+                "disable" => overrides.disable = matches!(value, "" | "1" | "true" | "yes"),
+                "max_boot_attempts" => match value.parse() {
+                    Ok(attempts) => overrides.max_boot_attempts = Some(attempts),
+                    Err(_) => {
+                        tracing::warn!("ignoring invalid greenboot.max_boot_attempts={value}")
+                    }
+                },
+                "skip" => overrides.skip.extend(
+                    value
+                        .split(',')
+                        .filter(|check| !check.is_empty())
+                        .map(str::to_string),
+                ),
+                "profile" => overrides.profile = Some(value.to_string()),
+                _ => tracing::warn!("ignoring unknown kernel parameter greenboot.{key}"),
+            }
+        }
+        overrides
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// ostree= parameter of the running kernel, identifying the booted
+/// deployment on ostree based systems
+pub fn booted_deployment(paths: &Paths) -> Option<String> {
+    fs::read_to_string(&paths.cmdline)
+        .ok()?
+        .split_whitespace()
+        .find_map(|param| param.strip_prefix("ostree="))
+        .map(str::to_string)
+}
+
+/// reads the greenboot overrides of the running kernel's command line
+pub fn read_kernel_overrides(paths: &Paths) -> Result<KernelOverrides> {
+    let cmdline = fs::read_to_string(&paths.cmdline)
+        .with_context(|| format!("Unable to read {}", paths.cmdline.display()))?;
+    Ok(KernelOverrides::parse(&cmdline))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_booted_deployment() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        fs::create_dir_all(paths.cmdline.parent().unwrap()).unwrap();
+        fs::write(&paths.cmdline, "root=UUID=abc ro quiet\n").unwrap();
+        assert_eq!(booted_deployment(&paths), None);
+        fs::write(
+            &paths.cmdline,
+            "BOOT_IMAGE=(hd0,gpt3)/boot/ostree/fedora-iot-3e5f/vmlinuz ostree=/ostree/boot.1/fedora-iot/3e5f/0 ro\n",
+        )
+        .unwrap();
+        assert_eq!(
+            booted_deployment(&paths).as_deref(),
+            Some("/ostree/boot.1/fedora-iot/3e5f/0")
+        );
+    }
+
+    #[test]
+    fn test_parse_greenboot_params() {
+        let overrides = KernelOverrides::parse(
+            "BOOT_IMAGE=(hd0,gpt2)/vmlinuz root=UUID=abc ro greenboot.max_boot_attempts=5 greenboot.skip=01_dns.sh,02_watchdog.sh greenboot.skip=03_net.sh greenboot.profile=factory quiet\n",
+        );
+        assert_eq!(
+            overrides,
+            KernelOverrides {
+                disable: false,
+                max_boot_attempts: Some(5),
+                skip: vec![
+                    "01_dns.sh".to_string(),
+                    "02_watchdog.sh".to_string(),
+                    "03_net.sh".to_string()
+                ],
+                profile: Some("factory".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_disable() {
+        assert!(KernelOverrides::parse("ro greenboot.disable=1").disable);
+        assert!(KernelOverrides::parse("ro greenboot.disable").disable);
+        assert!(!KernelOverrides::parse("ro greenboot.disable=0").disable);
+        assert!(KernelOverrides::parse("ro greenboot.max_boot_attempts=many").is_empty());
+    }
+}
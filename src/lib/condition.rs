@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// what a condition tests, named after the systemd unit setting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConditionKind {
+    PathExists,
+    Virtualization,
+    Architecture,
+}
+
+impl fmt::Display for ConditionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConditionKind::PathExists => write!(f, "ConditionPathExists"),
+            ConditionKind::Virtualization => write!(f, "ConditionVirtualization"),
+            ConditionKind::Architecture => write!(f, "ConditionArchitecture"),
+        }
+    }
+}
+
+/// A condition a check declares like a systemd unit, e.g.
+/// `ConditionVirtualization=!container`. A check whose conditions do not all
+/// hold is skipped, so images can ship checks that only apply to some of
+/// the systems they are installed on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    pub kind: ConditionKind,
+    pub value: String,
+    /// a leading `!` inverts the condition
+    pub negate: bool,
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let negate = if self.negate { "!" } else { "" };
+        write!(f, "{}={negate}{}", self.kind, self.value)
+    }
+}
+
+impl Condition {
+    /// none if `key` is not a condition
+    pub fn parse(key: &str, value: &str) -> Result<Option<Self>> {
+        let kind = match key {
+            "ConditionPathExists" => ConditionKind::PathExists,
+            "ConditionVirtualization" => ConditionKind::Virtualization,
+            "ConditionArchitecture" => ConditionKind::Architecture,
+            _ => return Ok(None),
+        };
+        let (negate, value) = match value.strip_prefix('!') {
+            Some(value) => (true, value),
+            None => (false, value),
+        };
+        if value.is_empty() {
+            bail!("{kind} needs a value");
+        }
+        if kind == ConditionKind::PathExists && !Path::new(value).is_absolute() {
+            bail!("{kind} needs an absolute path, not '{value}'");
+        }
+        Ok(Some(Self {
+            kind,
+            value: value.to_string(),
+            negate,
+        }))
+    }
+
+    pub fn holds(&self) -> bool {
+        let holds = match self.kind {
+            ConditionKind::PathExists => Path::new(&self.value).exists(),
+            ConditionKind::Virtualization => virtualization_matches(&self.value),
+            ConditionKind::Architecture => self.value == "native" || self.value == architecture(),
+        };
+        holds != self.negate
+    }
+}
+
+/// systemd name of the architecture greenboot was built for
+fn architecture() -> &'static str {
+    match std::env::consts::ARCH {
+        "x86_64" => "x86-64",
+        "aarch64" => "arm64",
+        "powerpc64" if cfg!(target_endian = "little") => "ppc64-le",
+        "powerpc64" => "ppc64",
+        "loongarch64" => "loongarch64",
+        arch => arch,
+    }
+}
+
+/// `value` is yes, no, vm, container or a technology systemd-detect-virt
+/// reports, e.g. kvm or podman
+fn virtualization_matches(value: &str) -> bool {
+    static DETECTED: OnceLock<[String; 3]> = OnceLock::new();
+    let [any, vm, container] = DETECTED.get_or_init(|| {
+        [
+            detect_virt(&[]),
+            detect_virt(&["--vm"]),
+            detect_virt(&["--container"]),
+        ]
+    });
+    match value {
+        "yes" | "true" => any != "none",
+        "no" | "false" => any == "none",
+        "vm" => vm != "none",
+        "container" => container != "none",
+        technology => any == technology,
+    }
+}
+
+/// none when not virtualized or the detection is not available
+fn detect_virt(args: &[&str]) -> String {
+    match Command::new("systemd-detect-virt").args(args).output() {
+        Ok(output) => match String::from_utf8_lossy(&output.stdout).trim() {
+            "" => "none".to_string(),
+            technology => technology.to_string(),
+        },
+        Err(e) => {
+            tracing::debug!("cannot run systemd-detect-virt: {e}");
+            "none".to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_conditions() {
+        let root = tempfile::tempdir().unwrap();
+        let modem = root.path().join("dev/ttyUSB0");
+        let exists = Condition::parse("ConditionPathExists", &modem.to_string_lossy())
+            .unwrap()
+            .unwrap();
+        assert!(!exists.holds());
+        std::fs::create_dir_all(&modem).unwrap();
+        assert!(exists.holds());
+
+        let other = if architecture() == "s390x" {
+            "arm64"
+        } else {
+            "s390x"
+        };
+        let arch = Condition::parse("ConditionArchitecture", &format!("!{other}"))
+            .unwrap()
+            .unwrap();
+        assert!(arch.holds());
+        assert_eq!(arch.to_string(), format!("ConditionArchitecture=!{other}"));
+        assert!(
+            Condition::parse("ConditionArchitecture", "native")
+                .unwrap()
+                .unwrap()
+                .holds()
+        );
+
+        assert_eq!(Condition::parse("Description", "modem").unwrap(), None);
+        assert!(Condition::parse("ConditionPathExists", "dev/ttyUSB0").is_err());
+        assert!(Condition::parse("ConditionVirtualization", "!").is_err());
+    }
+}
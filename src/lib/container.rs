@@ -0,0 +1,182 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// extension of the files declaring a container check
+static CONTAINER_EXTENSION: &str = "container";
+
+/// when podman pulls the image of a container check, see podman-run(1)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PullPolicy {
+    Always,
+    /// only when it is not in local storage, e.g. not preloaded in the image
+    #[default]
+    Missing,
+    Never,
+    Newer,
+}
+
+impl fmt::Display for PullPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PullPolicy::Always => write!(f, "always"),
+            PullPolicy::Missing => write!(f, "missing"),
+            PullPolicy::Never => write!(f, "never"),
+            PullPolicy::Newer => write!(f, "newer"),
+        }
+    }
+}
+
+impl FromStr for PullPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "always" => Ok(PullPolicy::Always),
+            "missing" => Ok(PullPolicy::Missing),
+            "never" => Ok(PullPolicy::Never),
+            "newer" => Ok(PullPolicy::Newer),
+            other => bail!("unknown pull policy '{other}'"),
+        }
+    }
+}
+
+/// A check shipped as a container image, declared by a `<check>.container`
+/// file in a check dir so application teams can ship health checks with
+/// their workload images rather than into the host's /etc. The file holds
+/// `key=value` lines: the `image` to run, the `args` passed to it, split on
+/// whitespace, and the `pull` policy. The container is run with podman and
+/// judged by its exit code like a script.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContainerCheck {
+    pub image: String,
+    pub args: Vec<String>,
+    pub pull: PullPolicy,
+}
+
+/// regular file declaring a container check
+pub fn is_container(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(CONTAINER_EXTENSION) && path.is_file()
+}
+
+impl ContainerCheck {
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid {}", path.display()))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut check = Self::default();
+        let lines = content
+            .lines()
+            .map(str::trim)
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        for (index, line) in lines {
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("line {}: expected key=value", index + 1);
+            };
+            let value = value.trim();
+            match key.trim() {
+                "image" => check.image = value.to_string(),
+                "args" => check.args = value.split_whitespace().map(str::to_string).collect(),
+                "pull" => {
+                    check.pull = value
+                        .parse()
+                        .with_context(|| format!("line {}", index + 1))?
+                }
+                key => tracing::warn!(
+                    "line {}: ignoring unknown container setting {key}",
+                    index + 1
+                ),
+            }
+        }
+        if check.image.is_empty() {
+            bail!("no image");
+        }
+        Ok(check)
+    }
+
+    /// podman running the container once, removed afterwards, with the
+    /// variables `env` of the podman process passed into it. It shares the
+    /// network of the host, as checks usually probe the host's services, and
+    /// gets the result file descriptor. conmon rather than podman supervises
+    /// the container, `timeout` makes it kill the container too.
+    pub fn command<'a>(
+        &self,
+        env: impl IntoIterator<Item = &'a str>,
+        timeout: Option<Duration>,
+    ) -> Command {
+        let mut command = Command::new("podman");
+        command.args([
+            "run",
+            "--rm",
+            "--network=host",
+            "--preserve-fds=1",
+            &format!("--pull={}", self.pull),
+        ]);
+        if let Some(timeout) = timeout {
+            command.arg(format!("--timeout={}", timeout.as_secs()));
+        }
+        for name in env {
+            command.arg(format!("--env={name}"));
+        }
+        command.arg(&self.image).args(&self.args);
+        command
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_container_check() {
+        let check = ContainerCheck::parse(
+            "# greenboot: description=\"app health\"\nimage=quay.io/example/app-health:1.4\nargs=--endpoint http://localhost:8080/health\npull=never\n",
+        )
+        .unwrap();
+        assert_eq!(
+            check,
+            ContainerCheck {
+                image: "quay.io/example/app-health:1.4".to_string(),
+                args: vec![
+                    "--endpoint".to_string(),
+                    "http://localhost:8080/health".to_string()
+                ],
+                pull: PullPolicy::Never,
+            }
+        );
+        let command = check.command(["GREENBOOT_PHASE"], Some(Duration::from_secs(30)));
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_str().unwrap())
+            .collect();
+        assert_eq!(
+            args,
+            [
+                "run",
+                "--rm",
+                "--network=host",
+                "--preserve-fds=1",
+                "--pull=never",
+                "--timeout=30",
+                "--env=GREENBOOT_PHASE",
+                "quay.io/example/app-health:1.4",
+                "--endpoint",
+                "http://localhost:8080/health"
+            ]
+        );
+
+        assert!(ContainerCheck::parse("args=--verbose\n").is_err());
+        assert!(ContainerCheck::parse("image=app-health\npull=sometimes\n").is_err());
+        assert!(ContainerCheck::parse("quay.io/example/app-health\n").is_err());
+    }
+}
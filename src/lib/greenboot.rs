@@ -1,58 +1,342 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
-use anyhow::{Result, bail};
+use anyhow::Result;
 use glob::glob;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::error::Error;
+use std::fmt;
 use std::fs;
 use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::checkfiles::{CheckFiles, is_masked, is_script, shebang};
+use crate::checkoutput::{DEFAULT_OUTPUT_LIMIT, OutputCapture, OutputStream};
+use crate::checkreport::{CheckReport, with_result_fd};
+use crate::container::{ContainerCheck, is_container};
+use crate::inject::Injections;
+use crate::journal;
+use crate::metadata::{CheckSettings, apply_sidecar, read_metadata};
+use crate::network::NetworkWait;
+use crate::paths::Paths;
+use crate::procgroup::ProcessGroup;
+use crate::progress::progress;
+use crate::selinux::{process_context, selinux_enabled};
+use crate::status::HealthStatus;
+use crate::systemd::{CheckUnit, ResourceLimits, SYSTEMD_RUN};
+use crate::webhook::{CheckOutcome, ResultWebhook};
+
+/// exit code (EX_TEMPFAIL) a red.d script returns to ask greenboot to defer the
+/// failure reboot, e.g. while a remediation agent still has work in progress
+pub const RED_DEFER_REBOOT_EXIT_CODE: i32 = 75;
+/// exit code a check returns when it does not apply to the system, e.g. to
+/// hardware it is not running on, as in the automake test convention
+pub const CHECK_SKIP_EXIT_CODE: i32 = 77;
+
+/// how often running action scripts are polled for completion
+const ACTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// how a single green.d/red.d action script ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActionStatus {
+    Success,
+    /// red.d script exited with RED_DEFER_REBOOT_EXIT_CODE
+    DeferReboot,
+    /// non-zero exit, `code` is none when killed by a signal
+    Failed {
+        code: Option<i32>,
+        output: String,
+    },
+    /// killed after running longer than the action timeout
+    TimedOut,
+    /// the script could not be executed at all
+    Unable(String),
+}
 
-/// dir that greenboot looks for the health check and other scripts
-static GREENBOOT_INSTALL_PATHS: [&str; 2] = ["/usr/lib/greenboot", "/etc/greenboot"];
+/// outcome of one action script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionOutcome {
+    pub script: PathBuf,
+    pub status: ActionStatus,
+}
 
-/// run required.d and wanted.d scripts.
-/// If a required script fails, log the error, and skip remaining checks.
-pub fn run_diagnostics(skipped: Vec<String>) -> Result<Vec<String>> {
-    let mut path_exists = false;
-    let mut all_skipped = HashSet::new();
+impl ActionOutcome {
+    /// false for scripts that failed, timed out or could not be run
+    pub fn is_success(&self) -> bool {
+        matches!(
+            self.status,
+            ActionStatus::Success | ActionStatus::DeferReboot
+        )
+    }
+}
 
-    // Convert input skipped Vec to HashSet for efficient lookups
-    let disabled_scripts: HashSet<String> = skipped.clone().into_iter().collect();
+impl fmt::Display for ActionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let script = self.script.display();
+        match &self.status {
+            ActionStatus::Success => write!(f, "{script} succeeded"),
+            ActionStatus::DeferReboot => write!(f, "{script} requested to defer the reboot"),
+            ActionStatus::Failed {
+                code: Some(code),
+                output,
+            } => write!(f, "{script} failed with exit code {code}\n{output}"),
+            ActionStatus::Failed { code: None, output } => {
+                write!(f, "{script} was terminated by a signal\n{output}")
+            }
+            ActionStatus::TimedOut => write!(f, "{script} timed out"),
+            ActionStatus::Unable(reason) => write!(f, "unable to execute {script}: {reason}"),
+        }
+    }
+}
 
-    // Run required checks
-    for path in GREENBOOT_INSTALL_PATHS {
-        let greenboot_required_path = format!("{path}/check/required.d/");
-        if !Path::new(&greenboot_required_path).is_dir() {
-            log::warn!("skipping test as {greenboot_required_path} is not a dir");
-            continue;
+/// aggregated outcome of a green.d/red.d run, one entry per script in directory order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActionReport {
+    pub outcomes: Vec<ActionOutcome>,
+}
+
+impl ActionReport {
+    /// scripts that did not succeed
+    pub fn failures(&self) -> impl Iterator<Item = &ActionOutcome> {
+        self.outcomes.iter().filter(|o| !o.is_success())
+    }
+
+    /// true if a red.d script asked to defer the failure reboot
+    pub fn defer_reboot(&self) -> bool {
+        self.outcomes
+            .iter()
+            .any(|o| o.status == ActionStatus::DeferReboot)
+    }
+}
+
+/// stage of a health-check run a script belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// a failure makes the boot red and stops the remaining required checks
+    Required,
+    /// a failure makes the boot degraded
+    Wanted,
+    /// runs once the boot is green
+    Green,
+    /// runs once the boot is red
+    Red,
+    /// runs right before a failure reboot
+    PreReboot,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Required => write!(f, "required"),
+            Stage::Wanted => write!(f, "wanted"),
+            Stage::Green => write!(f, "green"),
+            Stage::Red => write!(f, "red"),
+            Stage::PreReboot => write!(f, "pre-reboot"),
         }
-        path_exists = true;
-        let result = run_scripts("required", &greenboot_required_path, Some(&skipped));
-        all_skipped.extend(result.skipped);
+    }
+}
+
+/// script a health-check run would execute, as resolved by plan_run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedScript {
+    pub stage: Stage,
+    pub script: PathBuf,
+    /// why the script would not run, none if it runs
+    pub skip_reason: Option<String>,
+    /// upper bound spent waiting for the network before running it
+    pub network_wait: Option<Duration>,
+    /// the script is killed after running this long
+    pub timeout: Option<Duration>,
+    /// from the metadata header of a check
+    pub description: Option<String>,
+}
 
-        if !result.errors.is_empty() {
-            log::error!("required script error:");
-            result.errors.iter().for_each(|e| log::error!("{e}"));
-            bail!("required health-check failed, skipping remaining scripts");
+impl fmt::Display for PlannedScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<10} {}",
+            self.stage.to_string(),
+            self.script.display()
+        )?;
+        if let Some(reason) = &self.skip_reason {
+            write!(f, " (skipped: {reason})")?;
+        } else {
+            if let Some(wait) = self.network_wait {
+                write!(f, " (waits up to {}s for the network)", wait.as_secs())?;
+            }
+            match self.timeout {
+                Some(timeout) => write!(f, " (timeout {}s)", timeout.as_secs())?,
+                None => write!(f, " (no timeout)")?,
+            }
         }
+        match &self.description {
+            Some(description) => write!(f, ": {description}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// a check that failed, with what it takes to triage it once the boot that
+/// ran it is gone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailedCheck {
+    pub check: String,
+    /// none when the outcome was injected or the check was killed by a signal
+    pub exit_code: Option<i32>,
+    /// stdout and stderr as written, only the end of it when longer than
+    /// the output limit of the run
+    pub output: String,
+    /// seconds since the epoch when the check started
+    pub started_at: u64,
+    /// seconds since the epoch when the check ended
+    pub finished_at: u64,
+    /// what the check wrote to RESULT_FD
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<CheckReport>,
+    /// SELinux context the check ran in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selinux_context: Option<String>,
+}
+
+impl FailedCheck {
+    pub fn new(check: &str, exit_code: Option<i32>, output: &str, started: SystemTime) -> Self {
+        let epoch_secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0)
+        };
+        Self {
+            check: check.to_string(),
+            exit_code,
+            output: output.to_string(),
+            started_at: epoch_secs(started),
+            finished_at: epoch_secs(SystemTime::now()),
+            report: None,
+            selinux_context: None,
+        }
+    }
+
+    pub fn with_report(mut self, report: Option<CheckReport>) -> Self {
+        self.report = report;
+        self
+    }
+
+    pub fn with_selinux_context(mut self, context: Option<String>) -> Self {
+        self.selinux_context = context;
+        self
+    }
+
+    /// `<check>: <message>` when the check reported a message
+    pub fn message(&self) -> Option<String> {
+        let message = self.report.as_ref()?.message.as_deref()?;
+        Some(format!("{}: {message}", self.check))
+    }
+}
+
+/// reasons for run_diagnostics to fail, telling real health failures
+/// apart from greenboot being unable to run the checks at all
+#[derive(Debug, Error)]
+pub enum DiagnosticsError {
+    #[error("required health-check failed, skipping remaining scripts")]
+    RequiredFailed {
+        failed: Vec<String>,
+        /// required checks that passed before the failure
+        passed: usize,
+        /// exit code and output of the failed checks
+        details: Vec<FailedCheck>,
+    },
+    #[error("cannot find any required.d folder")]
+    MissingRequiredDir,
+    #[error("unable to run required health-check: {0}")]
+    Infrastructure(String),
+}
+
+impl DiagnosticsError {
+    /// true when greenboot itself failed rather than a check
+    pub fn is_infrastructure(&self) -> bool {
+        !matches!(self, DiagnosticsError::RequiredFailed { .. })
     }
+}
 
-    if !path_exists {
-        bail!("cannot find any required.d folder");
+/// outcome of run_diagnostics when all the required checks passed
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    /// required and wanted checks that passed
+    pub passed: usize,
+    /// disabled scripts that were not found in any directory
+    pub missing_disabled: Vec<String>,
+    /// wanted checks that failed, the boot is degraded if this is not empty
+    pub failed_wanted: Vec<String>,
+    /// exit code, output and report of the failed wanted checks that ran
+    pub wanted_details: Vec<FailedCheck>,
+}
+
+impl Diagnostics {
+    /// GREEN when every wanted check passed, DEGRADED otherwise
+    pub fn status(&self) -> HealthStatus {
+        if self.failed_wanted.is_empty() {
+            HealthStatus::Green
+        } else {
+            HealthStatus::Degraded
+        }
     }
+}
+
+/// run required.d and wanted.d scripts.
+/// If a required script fails, log the error, and skip remaining checks.
+/// Checks listed in `network` wait for connectivity before being executed,
+/// checks listed in `injections` get their forced outcome instead of running,
+/// `files` decides which files of the check dirs are not checks and each
+/// result is streamed to `webhook` as soon as the check completes.
+pub fn run_diagnostics(
+    paths: &Paths,
+    skipped: Vec<String>,
+    network: &NetworkWait,
+    injections: &Injections,
+    files: &CheckFiles,
+    webhook: Option<&ResultWebhook>,
+    env: &ScriptEnv,
+) -> Result<Diagnostics> {
+    let mut all_skipped = HashSet::new();
 
-    // Run wanted checks
-    for path in GREENBOOT_INSTALL_PATHS {
-        let greenboot_wanted_path = format!("{path}/check/wanted.d/");
-        let result = run_scripts("wanted", &greenboot_wanted_path, Some(&skipped));
-        all_skipped.extend(result.skipped);
+    // Convert input skipped Vec to HashSet for efficient lookups
+    let disabled_scripts: HashSet<String> = skipped.clone().into_iter().collect();
 
-        if !result.errors.is_empty() {
-            log::warn!("wanted script runner error:");
-            result.errors.iter().for_each(|e| log::error!("{e}"));
+    let run = CheckRun {
+        skipped: &skipped,
+        network,
+        injections,
+        files,
+        webhook,
+        env,
+    };
+    let required = run_required_checks(paths, &run, &mut all_skipped)?;
+    let required_passed = required.passed;
+
+    let wanted = run_wanted_checks(paths, &run);
+    all_skipped.extend(wanted.skipped);
+    if !wanted.promoted.is_empty() {
+        wanted.errors.iter().for_each(|e| tracing::error!("{e}"));
+        let details = wanted
+            .details
+            .into_iter()
+            .filter(|failed| wanted.promoted.contains(&failed.check))
+            .collect();
+        return Err(DiagnosticsError::RequiredFailed {
+            failed: wanted.promoted,
+            passed: required_passed + wanted.passed,
+            details,
         }
+        .into());
+    }
+    if !wanted.errors.is_empty() {
+        tracing::warn!("wanted script runner error:");
+        wanted.errors.iter().for_each(|e| tracing::error!("{e}"));
     }
 
     // Check for disabled scripts that weren't found
@@ -62,133 +346,995 @@ pub fn run_diagnostics(skipped: Vec<String>) -> Result<Vec<String>> {
         .collect();
 
     if !missing_disabled.is_empty() {
-        log::warn!(
+        tracing::warn!(
             "The following disabled scripts were not found in any directory: {missing_disabled:?}"
         );
     }
 
-    Ok(missing_disabled)
+    Ok(Diagnostics {
+        passed: required_passed + wanted.passed,
+        missing_disabled,
+        failed_wanted: required.demoted.into_iter().chain(wanted.failed).collect(),
+        // the details of the required checks are those of the demoted ones
+        wanted_details: required.details.into_iter().chain(wanted.details).collect(),
+    })
+}
+
+/// runs only the required.d scripts, failing on the first broken check
+pub fn run_required(
+    paths: &Paths,
+    skipped: &[String],
+    network: &NetworkWait,
+    injections: &Injections,
+    files: &CheckFiles,
+    webhook: Option<&ResultWebhook>,
+    env: &ScriptEnv,
+) -> Result<()> {
+    let run = CheckRun {
+        skipped,
+        network,
+        injections,
+        files,
+        webhook,
+        env,
+    };
+    run_required_checks(paths, &run, &mut HashSet::new()).map(|_| ())
+}
+
+/// failures of the wanted.d scripts
+#[derive(Debug, Default)]
+pub struct WantedFailures {
+    /// names of the checks that failed or could not be run
+    pub failed: Vec<String>,
+    /// exit code, output and report of the failed checks that ran
+    pub details: Vec<FailedCheck>,
+    pub errors: Vec<Box<dyn Error>>,
+}
+
+/// runs only the wanted.d scripts and returns every failure instead of logging it
+pub fn run_wanted(
+    paths: &Paths,
+    skipped: &[String],
+    network: &NetworkWait,
+    injections: &Injections,
+    files: &CheckFiles,
+    webhook: Option<&ResultWebhook>,
+    env: &ScriptEnv,
+) -> WantedFailures {
+    let run = CheckRun {
+        skipped,
+        network,
+        injections,
+        files,
+        webhook,
+        env,
+    };
+    let result = run_wanted_checks(paths, &run);
+    WantedFailures {
+        failed: result.failed.into_iter().chain(result.promoted).collect(),
+        details: result.details,
+        errors: result.errors,
+    }
+}
+
+/// what the required.d and wanted.d runners share for a health-check
+struct CheckRun<'a> {
+    skipped: &'a [String],
+    network: &'a NetworkWait,
+    injections: &'a Injections,
+    files: &'a CheckFiles,
+    webhook: Option<&'a ResultWebhook>,
+    env: &'a ScriptEnv,
+}
+
+/// result of the required checks when none failed, apart from those their
+/// sidecar makes wanted
+fn run_required_checks(
+    paths: &Paths,
+    run: &CheckRun,
+    all_skipped: &mut HashSet<String>,
+) -> Result<ScriptRunResult> {
+    let required_dirs = paths.required_dirs();
+    for dir in required_dirs.iter().filter(|dir| !dir.is_dir()) {
+        tracing::warn!("skipping test as {} is not a dir", dir.display());
+    }
+    if !required_dirs.iter().any(|dir| dir.is_dir()) {
+        return Err(DiagnosticsError::MissingRequiredDir.into());
+    }
+
+    let profile = run.env.profile.as_deref();
+    report_unknown(run.files, &required_dirs, profile);
+    let checks = resolve_checks(&required_dirs, profile, run.files)
+        .map_err(|e| DiagnosticsError::Infrastructure(e.to_string()))?;
+    let mut result = run_scripts("required", &checks, run);
+    all_skipped.extend(std::mem::take(&mut result.skipped));
+
+    if !result.errors.is_empty() {
+        tracing::error!("required script error:");
+        result.errors.iter().for_each(|e| tracing::error!("{e}"));
+        // errors without any failed check mean the scripts could not even be run
+        if result.failed.is_empty() {
+            let reason = result
+                .errors
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ");
+            return Err(DiagnosticsError::Infrastructure(reason).into());
+        }
+        return Err(DiagnosticsError::RequiredFailed {
+            failed: result.failed,
+            passed: result.passed,
+            details: result.details,
+        }
+        .into());
+    }
+    Ok(result)
+}
+
+fn run_wanted_checks(paths: &Paths, run: &CheckRun) -> ScriptRunResult {
+    let wanted_dirs = paths.wanted_dirs();
+    let profile = run.env.profile.as_deref();
+    report_unknown(run.files, &wanted_dirs, profile);
+    let checks = match resolve_checks(&wanted_dirs, profile, run.files) {
+        Ok(checks) => checks,
+        Err(e) => {
+            let mut result = ScriptRunResult::default();
+            result.errors.push(Box::new(e));
+            return result;
+        }
+    };
+    let mut result = run_scripts("wanted", &checks, run);
+    // wanted scripts that could not be spawned still degrade the boot
+    let broken = std::mem::take(&mut result.broken);
+    result.failed.extend(broken);
+    result
+}
+
+/// Environment every check and action script is run with, so a script can
+/// adapt to the run without knowing greenboot internals
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScriptEnv {
+    pub max_boot_attempts: u16,
+    /// boot of the booted deployment this is, 1 unless earlier boots of it
+    /// failed
+    pub boot_attempt: u16,
+    /// the outcome of some checks is forced by the testing mode, the verdict
+    /// does not reflect the system
+    pub dry_run: bool,
+    /// where scripts may leave files for the triage of the run
+    pub results_dir: PathBuf,
+    /// check profile selected for the run, see collect_checks
+    pub profile: Option<String>,
+    /// transient unit the checks run in
+    pub check_unit: CheckUnit,
+    /// limits of the checks that do not set their own
+    pub check_limits: ResourceLimits,
+    /// wanted.d checks run sandboxed unless their sidecar says otherwise
+    pub sandbox_wanted: bool,
+    /// SELinux context of the checks that do not set their own, none to
+    /// leave the transition to the policy
+    pub selinux_context: Option<String>,
+    /// bytes of output kept of each script, DEFAULT_OUTPUT_LIMIT if none
+    pub output_limit: Option<usize>,
+}
+
+impl ScriptEnv {
+    fn output_limit(&self) -> usize {
+        self.output_limit.unwrap_or(DEFAULT_OUTPUT_LIMIT)
+    }
+
+    /// variables of a script of `phase` found in `dir`
+    fn vars(&self, phase: &str, dir: &Path) -> Vec<(&'static str, String)> {
+        // checks sit in check/<phase>.d, actions in <phase>.d
+        let install_path = match phase {
+            "required" | "wanted" => dir.parent().and_then(Path::parent),
+            _ => dir.parent(),
+        };
+        vec![
+            ("GREENBOOT_PHASE", phase.to_string()),
+            (
+                "GREENBOOT_INSTALL_PATH",
+                install_path
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "GREENBOOT_MAX_BOOT_ATTEMPTS",
+                self.max_boot_attempts.to_string(),
+            ),
+            ("GREENBOOT_BOOT_ATTEMPT", self.boot_attempt.to_string()),
+            (
+                "GREENBOOT_DRY_RUN",
+                if self.dry_run { "1" } else { "0" }.to_string(),
+            ),
+            (
+                "GREENBOOT_RESULTS_DIR",
+                self.results_dir.display().to_string(),
+            ),
+            (
+                "GREENBOOT_PROFILE",
+                self.profile.clone().unwrap_or_default(),
+            ),
+        ]
+    }
 }
 
-// runs all the scripts in red.d when health-check fails
-pub fn run_red() -> Vec<Box<dyn Error>> {
-    let mut errors = Vec::new();
+/// the failure red.d scripts run for, so a remediation can act on it
+/// instead of diagnosing the system again
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FailureContext {
+    pub failed: Vec<FailedCheck>,
+}
 
-    for path in GREENBOOT_INSTALL_PATHS {
-        let red_path = format!("{path}/red.d/");
-        let result = run_scripts("red", &red_path, None); // Pass None for disabled scripts
-        errors.extend(result.errors);
+impl FailureContext {
+    /// GREENBOOT_FAILED_CHECKS and GREENBOOT_FAILED_EXIT_CODES list the
+    /// checks and their exit codes in the same order, separated by spaces,
+    /// with `-` for an unknown code. GREENBOOT_FAILED_MESSAGES has a line
+    /// for each check that reported a message.
+    fn vars(&self) -> Vec<(&'static str, String)> {
+        let checks = self.failed.iter().map(|failed| failed.check.as_str());
+        let codes = self.failed.iter().map(|failed| match failed.exit_code {
+            Some(code) => code.to_string(),
+            None => "-".to_string(),
+        });
+        vec![
+            (
+                "GREENBOOT_FAILED_CHECKS",
+                checks.collect::<Vec<_>>().join(" "),
+            ),
+            (
+                "GREENBOOT_FAILED_EXIT_CODES",
+                codes.collect::<Vec<_>>().join(" "),
+            ),
+            (
+                "GREENBOOT_FAILED_MESSAGES",
+                self.failed
+                    .iter()
+                    .filter_map(FailedCheck::message)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        ]
     }
+}
+
+/// runs all the scripts in red.d concurrently when health-check fails, a script
+/// exiting with RED_DEFER_REBOOT_EXIT_CODE is not an error but requests the
+/// failure reboot to be deferred. Scripts still running after `timeout` are
+/// killed. The scripts also get `failure` in their environment.
+pub fn run_red(
+    paths: &Paths,
+    timeout: Option<Duration>,
+    env: &ScriptEnv,
+    failure: &FailureContext,
+) -> ActionReport {
+    run_actions("red", &paths.red_dirs(), timeout, env, &failure.vars())
+}
+
+/// runs all the scripts in green.d concurrently when health-check passes
+pub fn run_green(paths: &Paths, timeout: Option<Duration>, env: &ScriptEnv) -> ActionReport {
+    run_actions("green", &paths.green_dirs(), timeout, env, &[])
+}
 
-    errors
+/// runs all the scripts in pre-reboot.d concurrently right before greenboot
+/// reboots a failed boot, e.g. to flush logs or notify a server
+pub fn run_pre_reboot(paths: &Paths, timeout: Option<Duration>, env: &ScriptEnv) -> ActionReport {
+    run_actions("pre-reboot", &paths.pre_reboot_dirs(), timeout, env, &[])
 }
 
-/// runs all the scripts green.d when health-check passes
-pub fn run_green() -> Vec<Box<dyn Error>> {
-    let mut errors = Vec::new();
+/// remediation advice declared by the given failed checks in their metadata
+/// header, formatted as `<check>: <advice>`
+pub fn remediation_hints(paths: &Paths, failed_checks: &[String]) -> Vec<String> {
+    let check_dirs: Vec<PathBuf> = paths
+        .required_dirs()
+        .into_iter()
+        .chain(paths.wanted_dirs())
+        .collect();
+    failed_checks
+        .iter()
+        .filter_map(|check| {
+            // the check of /etc masks the one of /usr/lib
+            let script = check_dirs
+                .iter()
+                .rev()
+                .map(|dir| dir.join(check))
+                .find(|path| path.is_file())?;
+            let remediation = read_metadata(&script).remediation?;
+            Some(format!("{check}: {remediation}"))
+        })
+        .collect()
+}
+
+/// every script a health-check run would consider, in execution order,
+/// without running anything: the required.d and wanted.d checks, then the
+/// green.d, red.d and pre-reboot.d actions that run depending on the verdict
+pub fn plan_run(
+    paths: &Paths,
+    skipped: &[String],
+    network: &NetworkWait,
+    injections: &Injections,
+    files: &CheckFiles,
+    action_timeout: Option<Duration>,
+    profile: Option<&str>,
+) -> Vec<PlannedScript> {
+    let stages = [
+        (Stage::Required, paths.required_dirs()),
+        (Stage::Wanted, paths.wanted_dirs()),
+        (Stage::Green, paths.green_dirs()),
+        (Stage::Red, paths.red_dirs()),
+        (Stage::PreReboot, paths.pre_reboot_dirs()),
+    ];
+    let mut plan = Vec::new();
+    for (stage, dirs) in stages {
+        let is_check = matches!(stage, Stage::Required | Stage::Wanted);
+        let scripts: Vec<(PathBuf, Option<PathBuf>)> = if is_check {
+            resolve_checks(&dirs, profile, files)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|check| (check.path, check.overridden_by))
+                .collect()
+        } else {
+            dirs.iter()
+                .filter_map(|dir| collect_scripts(dir).ok())
+                .flatten()
+                .map(|script| (script, None))
+                .collect()
+        };
+        for (script, overridden_by) in scripts {
+            let name = script
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let (settings, description) = if is_check {
+                let metadata = read_metadata(&script);
+                let settings = apply_sidecar(&script, metadata.settings())
+                    .unwrap_or_else(|_| metadata.settings());
+                (settings, metadata.description)
+            } else {
+                let settings = CheckSettings {
+                    timeout: action_timeout,
+                    ..CheckSettings::default()
+                };
+                (settings, None)
+            };
+            let skip_reason = if !is_check {
+                None
+            } else if let Some(other) = overridden_by {
+                Some(format!("overridden by {}", other.display()))
+            } else if is_masked(&script) {
+                Some("masked".to_string())
+            } else if skipped.contains(&name) {
+                Some("disabled".to_string())
+            } else if let Some(reason) = skip_reason(&settings, profile) {
+                Some(reason)
+            } else {
+                injections
+                    .outcome(&name)
+                    .map(|outcome| format!("injected outcome {outcome}"))
+            };
+            plan.push(PlannedScript {
+                stage,
+                network_wait: (is_check && network.applies_to(&name)).then_some(network.timeout),
+                timeout: settings.timeout,
+                description,
+                script,
+                skip_reason,
+            });
+        }
+    }
+    plan
+}
 
-    for path in GREENBOOT_INSTALL_PATHS {
-        let green_path = format!("{path}/green.d/");
-        let result = run_scripts("green", &green_path, None); // Pass None for disabled scripts
-        errors.extend(result.errors);
+fn run_actions(
+    name: &str,
+    dirs: &[PathBuf],
+    timeout: Option<Duration>,
+    env: &ScriptEnv,
+    extra_vars: &[(&str, String)],
+) -> ActionReport {
+    let mut scripts = Vec::new();
+    let mut outcomes = Vec::new();
+    for dir in dirs {
+        let mut vars = env.vars(name, dir);
+        vars.extend(extra_vars.iter().cloned());
+        match collect_scripts(dir) {
+            Ok(found) => scripts.extend(found.into_iter().map(|script| (script, vars.clone()))),
+            Err(e) => outcomes.push(ActionOutcome {
+                script: dir.clone(),
+                status: ActionStatus::Unable(e.to_string()),
+            }),
+        }
     }
 
-    errors
+    let deadline = timeout.map(|t| Instant::now() + t);
+    let output_limit = env.output_limit();
+    thread::scope(|s| {
+        let handles: Vec<_> = scripts
+            .iter()
+            .map(|(script, vars)| {
+                tracing::info!("running {} script {}", name, script.display());
+                s.spawn(move || ActionOutcome {
+                    script: script.clone(),
+                    status: run_action(name, script, deadline, vars, output_limit),
+                })
+            })
+            .collect();
+        for handle in handles {
+            match handle.join() {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(_) => tracing::error!("{name} script runner thread panicked"),
+            }
+        }
+    });
+
+    for outcome in &outcomes {
+        if outcome.is_success() {
+            tracing::info!("{name} script {outcome}");
+        } else {
+            tracing::error!("{name} script {outcome}");
+        }
+    }
+
+    ActionReport { outcomes }
+}
+
+/// Command running `script` with the interpreter of its shebang, so e.g. a
+/// Python or POSIX sh check runs with the semantics its author intended. An
+/// executable without one is run directly, which is how binaries run, and
+/// any other script with /bin/sh.
+fn script_command(script: &Path) -> Command {
+    match shebang(script) {
+        Some(interpreter) => {
+            let mut command = Command::new(&interpreter[0]);
+            command.args(&interpreter[1..]).arg(script);
+            command
+        }
+        None if fs::metadata(script).is_ok_and(|m| m.permissions().mode() & 0o111 != 0) => {
+            Command::new(script)
+        }
+        None => {
+            let mut command = Command::new("/bin/sh");
+            command.arg(script);
+            command
+        }
+    }
 }
 
+/// the output is captured like the one of a check, a killed script's
+/// leftover children are killed with the process group of the script so
+/// they cannot keep the runner blocked on reading it
+fn run_action(
+    name: &str,
+    script: &Path,
+    deadline: Option<Instant>,
+    env: &[(&str, String)],
+    output_limit: usize,
+) -> ActionStatus {
+    let file_name = script
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let mut capture = OutputCapture::new(name, &file_name, output_limit);
+    let (mut child, group) = match ProcessGroup::spawn(
+        script_command(script)
+            .envs(env.iter().map(|(key, value)| (key, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    ) {
+        Ok(spawned) => spawned,
+        Err(e) => return ActionStatus::Unable(e.to_string()),
+    };
+    if let Some(stdout) = child.stdout.take() {
+        capture.forward(stdout, OutputStream::Stdout);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        capture.forward(stderr, OutputStream::Stderr);
+    }
+
+    let exit = loop {
+        match child.try_wait() {
+            Ok(Some(exit)) => break exit,
+            Ok(None) if deadline.is_some_and(|d| Instant::now() >= d) => {
+                tracing::warn!("{} script {} timed out, killing it", name, script.display());
+                group.kill();
+                let _ = child.wait();
+                return ActionStatus::TimedOut;
+            }
+            Ok(None) => thread::sleep(ACTION_POLL_INTERVAL),
+            Err(e) => return ActionStatus::Unable(e.to_string()),
+        }
+    };
+    drop(group);
+    let text = capture.finish().trim_end().to_string();
+
+    match exit.code() {
+        Some(0) => ActionStatus::Success,
+        Some(RED_DEFER_REBOOT_EXIT_CODE) if name == "red" => ActionStatus::DeferReboot,
+        code => ActionStatus::Failed { code, output: text },
+    }
+}
+
+#[derive(Default)]
 struct ScriptRunResult {
     errors: Vec<Box<dyn Error>>,
     skipped: Vec<String>,
+    failed: Vec<String>,
+    /// exit code and output of the failed scripts
+    details: Vec<FailedCheck>,
+    /// required.d scripts that failed but are wanted by their sidecar
+    demoted: Vec<String>,
+    /// wanted.d scripts that failed but are required by their sidecar
+    promoted: Vec<String>,
+    /// scripts that could not be executed at all
+    broken: Vec<String>,
+    /// scripts that ran successfully
+    passed: usize,
+}
+
+/// how a check ended, after its retries
+enum CheckExit {
+    /// its output was forwarded while it ran
+    Passed,
+    /// exited with CHECK_SKIP_EXIT_CODE, with its output
+    Skipped(String),
+    Failed {
+        code: Option<i32>,
+        output: String,
+    },
 }
 
-fn run_scripts(name: &str, path: &str, disabled_scripts: Option<&[String]>) -> ScriptRunResult {
-    let mut result = ScriptRunResult {
-        errors: Vec::new(),
-        skipped: Vec::new(),
+/// runs a check of `stage` with the settings of its sidecar, in the unit of
+/// `env`, along with the report of its last attempt and the SELinux context
+/// it ran in, an error means it could not be spawned
+fn run_check(
+    stage: &str,
+    entry: &Path,
+    vars: &[(&str, String)],
+    settings: &CheckSettings,
+    env: &ScriptEnv,
+) -> std::io::Result<(CheckExit, Option<CheckReport>, Option<String>)> {
+    let check = entry
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let container = if is_container(entry) {
+        Some(ContainerCheck::read(entry).map_err(std::io::Error::other)?)
+    } else {
+        None
     };
+    let mut attempt = 0;
+    loop {
+        let result_file = tempfile::tempfile()?;
+        let mut command = match &container {
+            // podman passes the variables it got on to the container
+            Some(container) => container.command(
+                vars.iter()
+                    .map(|(key, _)| *key)
+                    .chain(settings.env.iter().map(|(key, _)| key.as_str())),
+                settings.timeout,
+            ),
+            None => script_command(entry),
+        };
+        command
+            .envs(vars.iter().map(|(key, value)| (key, value)))
+            .envs(settings.env.iter().map(|(key, value)| (key, value)));
+        let mut command =
+            env.check_unit
+                .wrap(command, stage, &check, settings, &env.results_dir)?;
+        with_result_fd(&mut command, &result_file);
+        // systemd-run is not the check, only the context it was given is known
+        let direct = command.get_program() != SYSTEMD_RUN;
+        let mut context = settings.selinux_context.clone();
+        let capture = OutputCapture::new(stage, &check, env.output_limit());
+        let exit = output_within(&mut command, settings.timeout, capture, |child| {
+            if direct && selinux_enabled() {
+                context = process_context(child.id());
+            }
+        })?;
+        if let Some(context) = &context {
+            tracing::debug!("{check} ran in SELinux context {context}");
+        }
+        let report = CheckReport::read(&result_file, &entry.display().to_string());
+        let (code, output) = match exit {
+            Some((status, _)) if status.success() => {
+                return Ok((CheckExit::Passed, report, context));
+            }
+            Some((status, output)) if status.code() == Some(CHECK_SKIP_EXIT_CODE) => {
+                return Ok((CheckExit::Skipped(output), report, context));
+            }
+            Some((status, output)) => (status.code(), output),
+            None => (
+                None,
+                format!(
+                    "timed out after {}s",
+                    settings.timeout.unwrap_or_default().as_secs()
+                ),
+            ),
+        };
+        if attempt >= settings.retries {
+            return Ok((CheckExit::Failed { code, output }, report, context));
+        }
+        attempt += 1;
+        tracing::warn!(
+            "{} failed, retrying {attempt}/{}",
+            entry.display(),
+            settings.retries
+        );
+    }
+}
 
-    let entries = match glob(&format!("{path}*")) {
-        Ok(e) => {
-            let valid: Vec<_> = e
-                .filter_map(Result::ok)
-                .filter(|entry| {
-                    if let Ok(metadata) = fs::metadata(entry) {
-                        let mode = metadata.permissions().mode();
-                        metadata.is_file()
-                            && (entry.extension().and_then(|ext| ext.to_str()) == Some("sh")
-                                || (mode & 0o001 != 0 || mode & 0o010 != 0 || mode & 0o100 != 0))
-                    } else {
-                        false
+/// exit status and output of `command`, none if it was killed for running
+/// longer than `timeout`. The output is captured and forwarded to the
+/// journal as it is written, only its end is returned. The process group of
+/// the command is killed once it exits, closing the output it left open.
+/// `spawned` can look at the process before it is waited for.
+fn output_within(
+    command: &mut Command,
+    timeout: Option<Duration>,
+    mut capture: OutputCapture,
+    spawned: impl FnOnce(&Child),
+) -> std::io::Result<Option<(ExitStatus, String)>> {
+    let (mut child, group) = ProcessGroup::spawn(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    spawned(&child);
+    if let Some(stdout) = child.stdout.take() {
+        capture.forward(stdout, OutputStream::Stdout);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        capture.forward(stderr, OutputStream::Stderr);
+    }
+    let status = match timeout {
+        None => Some(child.wait()?),
+        Some(timeout) => {
+            let deadline = Instant::now() + timeout;
+            loop {
+                match child.try_wait()? {
+                    Some(status) => break Some(status),
+                    None if Instant::now() >= deadline => {
+                        group.kill();
+                        let _ = child.wait();
+                        break None;
                     }
-                })
-                .collect();
-            Some(valid).into_iter()
+                    None => thread::sleep(ACTION_POLL_INTERVAL),
+                }
+            }
         }
-        Err(e) => {
-            result.errors.push(Box::new(e));
-            return result;
+    };
+    drop(group);
+    let output = capture.finish();
+    Ok(status.map(|status| (status, output)))
+}
+
+/// why a check does not run in `profile` or on this system, none if it runs
+fn skip_reason(settings: &CheckSettings, profile: Option<&str>) -> Option<String> {
+    if !settings.profiles.is_empty()
+        && !profile.is_some_and(|profile| settings.profiles.iter().any(|p| p == profile))
+    {
+        return Some(match profile {
+            Some(profile) => format!("not in profile {profile}"),
+            None => "no profile selected".to_string(),
+        });
+    }
+    settings
+        .conditions
+        .iter()
+        .find(|condition| !condition.holds())
+        .map(|unmet| format!("{unmet} not met"))
+}
+
+/// a check of the check dirs of a stage, as resolved by resolve_checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ResolvedCheck {
+    /// check dir of the install path it was found in
+    dir: PathBuf,
+    path: PathBuf,
+    /// the check of a later install path with the same file name, which
+    /// runs instead of this one
+    overridden_by: Option<PathBuf>,
+}
+
+/// The checks of the `dirs` of a stage as a single sequence sorted by file
+/// name, whichever install path or profile subdir they come from, so the
+/// numeric prefixes order them across directories. Like systemd drop-ins, a
+/// check of a later install path replaces the one with the same file name
+/// of an earlier one, e.g. /etc over /usr/lib; the replaced check is kept
+/// right before its replacement so it can be reported, but is not run.
+fn resolve_checks(
+    dirs: &[PathBuf],
+    profile: Option<&str>,
+    files: &CheckFiles,
+) -> Result<Vec<ResolvedCheck>, glob::PatternError> {
+    let mut checks = Vec::new();
+    for dir in dirs {
+        for path in collect_checks(dir, profile)? {
+            if files.is_ignored(&path) {
+                tracing::debug!("Skipping ignored file: {}", path.display());
+                continue;
+            }
+            checks.push(ResolvedCheck {
+                dir: dir.clone(),
+                path,
+                overridden_by: None,
+            });
+        }
+    }
+    // stable, same-named checks stay in install path order
+    checks.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+    for index in (1..checks.len()).rev() {
+        if checks[index - 1].path.file_name() == checks[index].path.file_name() {
+            let winner = checks[index]
+                .overridden_by
+                .clone()
+                .unwrap_or_else(|| checks[index].path.clone());
+            checks[index - 1].overridden_by = Some(winner);
+        }
+    }
+    Ok(checks)
+}
+
+/// scripts, container checks and masked checks of a check dir and, when a
+/// profile is selected, of its `<profile>/` subdir
+fn collect_checks(path: &Path, profile: Option<&str>) -> Result<Vec<PathBuf>, glob::PatternError> {
+    let is_check = |entry: &Path| is_script(entry) || is_container(entry) || is_masked(entry);
+    let mut checks = collect(path, is_check)?;
+    if let Some(profile) = profile {
+        checks.extend(collect(&path.join(profile), is_check)?);
+    }
+    Ok(checks)
+}
+
+/// logs the unknown files of the check dirs and their profile subdir
+fn report_unknown(files: &CheckFiles, dirs: &[PathBuf], profile: Option<&str>) {
+    for dir in dirs {
+        files.report_unknown(dir);
+        if let Some(profile) = profile {
+            files.report_unknown(&dir.join(profile));
+        }
+    }
+}
+
+/// regular files in `path` that are either .sh scripts or executables
+fn collect_scripts(path: &Path) -> Result<Vec<PathBuf>, glob::PatternError> {
+    collect(path, is_script)
+}
+
+fn collect(path: &Path, keep: fn(&Path) -> bool) -> Result<Vec<PathBuf>, glob::PatternError> {
+    Ok(glob(&format!("{}/*", path.display()))?
+        .filter_map(Result::ok)
+        .filter(|entry| keep(entry))
+        .collect())
+}
+
+/// tags the result of a check in the journal, for `greenboot logs`
+fn log_outcome(
+    stage: &str,
+    check: &str,
+    outcome: CheckOutcome,
+    duration: Duration,
+    check_report: Option<&CheckReport>,
+) {
+    if !journal::is_available() {
+        return;
+    }
+    let priority = match outcome {
+        CheckOutcome::Passed | CheckOutcome::Skipped => journal::PRIORITY_INFO,
+        CheckOutcome::Failed | CheckOutcome::Error => journal::PRIORITY_WARNING,
+    };
+    let outcome_field = outcome.to_string();
+    let duration_field = duration.as_millis().to_string();
+    let mut fields = vec![
+        ("GREENBOOT_CHECK", check),
+        ("GREENBOOT_PHASE", stage),
+        ("GREENBOOT_OUTCOME", outcome_field.as_str()),
+        ("GREENBOOT_DURATION_MS", duration_field.as_str()),
+    ];
+    if let Some(message) = check_report.and_then(|report| report.message.as_deref()) {
+        fields.push(("GREENBOOT_CHECK_MESSAGE", message));
+    }
+    journal::send(
+        priority,
+        &format!("{stage} check {check} {outcome}"),
+        &fields,
+    )
+    .unwrap_or_else(|e| tracing::debug!("cannot send the result of {check} to journald: {e}"));
+}
+
+fn run_scripts(name: &str, checks: &[ResolvedCheck], run: &CheckRun) -> ScriptRunResult {
+    let mut result = ScriptRunResult::default();
+    let report = |check: &str,
+                  outcome: CheckOutcome,
+                  duration: Duration,
+                  check_report: Option<&CheckReport>| {
+        if let Some(webhook) = run.webhook {
+            webhook.send(name, check, outcome, duration, check_report);
+        }
+        if let Some(progress) = progress() {
+            progress.finished(name, check, outcome, duration);
         }
+        log_outcome(name, check, outcome, duration, check_report);
     };
+    let profile = run.env.profile.as_deref();
 
-    for entry in entries.flatten() {
+    for check in checks {
+        let entry = &check.path;
         // Process script/binary name
         let file_name = match entry.file_name().and_then(|n| n.to_str()) {
             Some(name) => name,
             None => continue,
         };
+        // what is logged while the check runs carries its name and phase
+        let _span = tracing::info_span!("check", phase = name, check = file_name).entered();
+
+        if let Some(other) = &check.overridden_by {
+            tracing::info!(
+                "Skipping {}, overridden by {}",
+                entry.display(),
+                other.display()
+            );
+            continue;
+        }
+        let vars = run.env.vars(name, &check.dir);
 
-        // Check if script/binary should be skipped
-        if let Some(disabled) = disabled_scripts
-            && disabled.contains(&file_name.to_string())
-        {
-            log::info!("Skipping disabled script: {file_name}");
+        if is_masked(entry) {
+            tracing::info!("Skipping masked {name} check {}", entry.display());
             result.skipped.push(file_name.to_string());
+            report(file_name, CheckOutcome::Skipped, Duration::ZERO, None);
             continue;
         }
 
-        log::info!("running {} check {}", name, entry.to_string_lossy());
+        // Check if script/binary should be skipped
+        if run.skipped.contains(&file_name.to_string()) {
+            tracing::info!("Skipping disabled script: {file_name}");
+            result.skipped.push(file_name.to_string());
+            report(file_name, CheckOutcome::Skipped, Duration::ZERO, None);
+            continue;
+        }
 
-        // Sort between scripts and binaries since they require different commands to execute properly.
-        let output = if entry.extension().and_then(|ext| ext.to_str()) == Some("sh") {
-            Command::new("bash").arg("-C").arg(&entry).output()
-        } else {
-            Command::new(&entry).output()
+        // the metadata header is the default, the sidecar overrides it
+        let metadata = read_metadata(entry);
+        let mut settings = apply_sidecar(entry, metadata.settings()).unwrap_or_else(|e| {
+            tracing::warn!("{e:#}, running {file_name} with the settings of its header");
+            metadata.settings()
+        });
+        settings.limits = settings.limits.or(run.env.check_limits);
+        settings.selinux_context = settings
+            .selinux_context
+            .or_else(|| run.env.selinux_context.clone())
+            .filter(|_| selinux_enabled());
+        settings.sandbox = Some(
+            settings
+                .sandbox
+                .unwrap_or(name == "wanted" && run.env.sandbox_wanted),
+        );
+        if let Some(reason) = skip_reason(&settings, profile) {
+            tracing::info!("Skipping {name} script {file_name}: {reason}");
+            result.skipped.push(file_name.to_string());
+            report(file_name, CheckOutcome::Skipped, Duration::ZERO, None);
+            continue;
+        }
+        let described = match &metadata.description {
+            Some(description) => format!("{} ({description})", entry.display()),
+            None => entry.display().to_string(),
         };
+        // the sidecar may move the check to the other stage
+        let required = settings.required.unwrap_or(name == "required");
 
-        match output {
-            Ok(o) if o.status.success() => {
-                log::info!("{} script {} success!", name, entry.to_string_lossy());
-                let stdout = String::from_utf8_lossy(&o.stdout);
-                let stderr = String::from_utf8_lossy(&o.stderr);
-                if !stdout.trim().is_empty() {
-                    log::info!("{}", stdout.trim_end());
-                }
-                if !stderr.trim().is_empty() {
-                    log::warn!("{}", stderr.trim_end());
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        if let Some(progress) = progress() {
+            progress.started(name, file_name);
+        }
+        let (exit_code, output, error_msg, check_report, context) = match run
+            .injections
+            .outcome(file_name)
+        {
+            Some(outcome) => {
+                tracing::warn!("injecting outcome for {name} check {file_name}: {outcome}");
+                let error_msg = format!("{name} script {described} {outcome} (injected)");
+                (None, format!("{outcome} (injected)"), error_msg, None, None)
+            }
+            None => {
+                run.network.wait_for(file_name);
+                tracing::info!("running {name} check {described}");
+                match run_check(name, entry, &vars, &settings, run.env) {
+                    Ok((CheckExit::Passed, check_report, _)) => {
+                        match &check_report {
+                            Some(check_report) => {
+                                tracing::info!("{name} script {described} success: {check_report}")
+                            }
+                            None => tracing::info!("{name} script {described} success!"),
+                        }
+                        result.passed += 1;
+                        report(
+                            file_name,
+                            CheckOutcome::Passed,
+                            started.elapsed(),
+                            check_report.as_ref(),
+                        );
+                        continue;
+                    }
+                    Ok((CheckExit::Skipped(output), check_report, _)) => {
+                        let reason = match &check_report {
+                            Some(check_report) => check_report.to_string(),
+                            None => output.trim_end().to_string(),
+                        };
+                        tracing::info!("{name} script {described} skipped itself: {reason}");
+                        result.skipped.push(file_name.to_string());
+                        report(
+                            file_name,
+                            CheckOutcome::Skipped,
+                            started.elapsed(),
+                            check_report.as_ref(),
+                        );
+                        continue;
+                    }
+                    Ok((CheckExit::Failed { code, output }, check_report, context)) => {
+                        let error_msg = match &check_report {
+                            Some(check_report) => format!(
+                                "{name} script {described} failed: {check_report}\n{output}"
+                            ),
+                            None => format!("{name} script {described} failed!\n{output}"),
+                        };
+                        (code, output, error_msg, check_report, context)
+                    }
+                    Err(e) => {
+                        // the check could not be spawned, this is not a check failure
+                        result.errors.push(Box::new(std::io::Error::other(format!(
+                            "unable to execute {} script {}: {e}",
+                            name,
+                            entry.to_string_lossy()
+                        ))));
+                        result.broken.push(file_name.to_string());
+                        report(file_name, CheckOutcome::Error, started.elapsed(), None);
+                        if name == "required" {
+                            break;
+                        }
+                        continue;
+                    }
                 }
             }
-            Ok(o) => {
-                let error_msg = format!(
-                    "{} script {} failed!\n{}\n{}",
-                    name,
-                    entry.to_string_lossy(),
-                    String::from_utf8_lossy(&o.stdout),
-                    String::from_utf8_lossy(&o.stderr)
+        };
+
+        report(
+            file_name,
+            CheckOutcome::Failed,
+            started.elapsed(),
+            check_report.as_ref(),
+        );
+        result.details.push(
+            FailedCheck::new(file_name, exit_code, &output, started_at)
+                .with_report(check_report)
+                .with_selinux_context(context),
+        );
+        match (name == "required", required) {
+            (true, false) => {
+                tracing::warn!(
+                    "{error_msg}\n{file_name} is wanted by its sidecar, the boot is only degraded"
                 );
+                result.demoted.push(file_name.to_string());
+            }
+            (false, true) => {
                 result
                     .errors
                     .push(Box::new(std::io::Error::other(error_msg)));
-                if name == "required" {
-                    break;
-                }
+                result.promoted.push(file_name.to_string());
             }
-            Err(e) => {
-                result.errors.push(Box::new(e));
-                if name == "required" {
-                    break;
-                }
+            _ => {
+                result
+                    .errors
+                    .push(Box::new(std::io::Error::other(error_msg)));
+                result.failed.push(file_name.to_string());
             }
         }
+        if name == "required" && required {
+            break;
+        }
     }
 
     result
@@ -197,55 +1343,79 @@ fn run_scripts(name: &str, path: &str, disabled_scripts: Option<&[String]>) -> S
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::checkfiles::UnknownFiles;
     use anyhow::{Context, Result};
     use std::fs::File;
     use std::io::Write;
     use std::sync::Once;
     use std::{fs, os::unix::fs::PermissionsExt};
+    use tempfile::{TempDir, tempdir};
 
     static INIT: Once = Once::new();
 
     fn init_logger() {
         INIT.call_once(|| {
-            env_logger::builder().is_test(true).try_init().ok();
+            tracing_subscriber::fmt().with_test_writer().try_init().ok();
         });
     }
 
-    static GREENBOOT_INSTALL_PATHS: [&str; 2] = ["/usr/lib/greenboot", "/etc/greenboot"];
-
     /// validate when the required folder is not found
     #[test]
     fn test_missing_required_folder() {
-        for path in GREENBOOT_INSTALL_PATHS {
-            let required_path = format!("{path}/check/required.d");
-            if Path::new(&required_path).exists() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        for required_path in paths.required_dirs() {
+            if required_path.exists() {
                 fs::remove_dir_all(&required_path).unwrap();
             }
-            assert_eq!(
-                run_diagnostics(vec![]).unwrap_err().to_string(),
-                String::from("cannot find any required.d folder")
-            );
         }
+        let err = run_diagnostics(
+            &paths,
+            vec![],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            String::from("cannot find any required.d folder")
+        );
+        assert!(
+            err.downcast_ref::<DiagnosticsError>()
+                .is_some_and(DiagnosticsError::is_infrastructure)
+        );
     }
 
     #[test]
     fn test_passed_diagnostics() {
-        setup_folder_structure(true)
+        let (_temp_dir, paths) = setup_folder_structure(true)
             .context("Test setup failed")
             .unwrap();
-        let state = run_diagnostics(vec![]);
+        let state = run_diagnostics(
+            &paths,
+            vec![],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
         assert!(state.is_ok());
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     #[test]
     fn test_required_script_failure_exit_early() {
         init_logger();
-        setup_folder_structure(false)
+        let (_temp_dir, paths) = setup_folder_structure(false)
             .context("Test setup failed")
             .unwrap();
 
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        // /etc first, its checks mask those of /usr/lib
+        for base_path in paths.install_paths.iter().rev().map(|p| p.display()) {
             // Causes errors if these are not removed since they cause an excess amount
             // of failures.
             let _ = std::fs::remove_file(format!("{base_path}/01_failing_binary"));
@@ -261,21 +1431,41 @@ mod test {
                 let mut script = File::create(&path).unwrap();
                 writeln!(
                     script,
-                    "#!/bin/bash\nCOUNTER_FILE=\"{counter_file}\"\ncount=$(cat $COUNTER_FILE)\necho $((count + 1)) >| $COUNTER_FILE\nexit 1"
+                    "#!/bin/bash\nCOUNTER_FILE=\"{counter_file}\"\ncount=$(cat $COUNTER_FILE)\necho $((count + 1)) >| $COUNTER_FILE\necho {name} failed >&2\nexit 1"
                 ).unwrap();
                 std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
             }
 
-            let result = run_diagnostics(vec![]);
-            log::debug!("Diagnostics result: {result:?}");
+            let result = run_diagnostics(
+                &paths,
+                vec![],
+                &NetworkWait::default(),
+                &Injections::default(),
+                &CheckFiles::default(),
+                None,
+                &ScriptEnv::default(),
+            );
+            tracing::debug!("Diagnostics result: {result:?}");
 
             assert!(result.is_err());
+            let err = result.unwrap_err();
             assert_eq!(
-                result.unwrap_err().to_string(),
+                err.to_string(),
                 "required health-check failed, skipping remaining scripts"
             );
+            assert!(matches!(
+                err.downcast_ref::<DiagnosticsError>(),
+                Some(DiagnosticsError::RequiredFailed { failed, .. }) if failed.len() == 1
+            ));
+            if let Some(DiagnosticsError::RequiredFailed { details, .. }) = err.downcast_ref() {
+                assert_eq!(details[0].exit_code, Some(1));
+                assert_eq!(
+                    details[0].output.trim(),
+                    format!("{} failed", details[0].check.trim_end_matches(".sh"))
+                );
+            }
 
-            log::info!("Health check failed as expected.");
+            tracing::info!("Health check failed as expected.");
 
             let fail_script_count = fs::read_to_string(counter_file)
                 .unwrap()
@@ -294,36 +1484,43 @@ mod test {
                     .expect("Failed to remove script file");
             }
         }
-
-        tear_down().expect("teardown failed");
     }
 
     #[test]
     fn test_skip_nonexistent_script() {
         let nonexistent_script_name = "nonexistent_script.sh".to_string();
-        setup_folder_structure(true)
+        let (_temp_dir, paths) = setup_folder_structure(true)
             .context("Test setup failed")
             .unwrap();
 
         // Try to run a script that doesn't exist
-        let state = run_diagnostics(vec![nonexistent_script_name.clone()]);
+        let state = run_diagnostics(
+            &paths,
+            vec![nonexistent_script_name.clone()],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
         assert!(
-            state.unwrap().contains(&nonexistent_script_name),
+            state
+                .unwrap()
+                .missing_disabled
+                .contains(&nonexistent_script_name),
             "non existent script names did not match"
         );
-
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     #[test]
     fn test_skip_disabled_script() {
-        setup_folder_structure(false)
+        let (_temp_dir, paths) = setup_folder_structure(false)
             .context("Test setup failed")
             .unwrap();
 
         // Removing extra failing binaries because this can cause a
         // failure if not added to the skips or removed as done below.
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        for base_path in paths.install_paths.iter().map(|p| p.display()) {
             let required_path = format!("{base_path}/check/required.d");
             let _ = std::fs::remove_file(format!("{required_path}/01_failing_binary"));
             let _ = std::fs::remove_file(format!("{required_path}/02_failing_binary"));
@@ -331,29 +1528,35 @@ mod test {
 
         // Skip the disabled script in required.d ,since there are two
         // failing- scripts passing them both so that this test passes.
-        let state = run_diagnostics(vec![
-            "01_failing_script.sh".to_string(),
-            "02_failing_script.sh".to_string(),
-        ]);
+        let state = run_diagnostics(
+            &paths,
+            vec![
+                "01_failing_script.sh".to_string(),
+                "02_failing_script.sh".to_string(),
+            ],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
         assert!(
             state.is_ok(),
             "Should pass when skipping disabled required script"
         );
-
-        tear_down().context("Test teardown failed").unwrap();
     }
 
     // Since binaries are a separate and later added feature compared to
     // scripts, there should be a separate test to ensure they both work.
     #[test]
     fn test_skip_disabled_binary() {
-        setup_folder_structure(false)
+        let (_temp_dir, paths) = setup_folder_structure(false)
             .context("Test setup failed")
             .unwrap();
 
         // Removing extra failing scripts because this can cause a
         // failure if not added to the skips or removed as done below
-        for base_path in GREENBOOT_INSTALL_PATHS {
+        for base_path in paths.install_paths.iter().map(|p| p.display()) {
             let required_path = format!("{base_path}/check/required.d");
             let _ = std::fs::remove_file(format!("{required_path}/01_failing_script.sh"));
             let _ = std::fs::remove_file(format!("{required_path}/02_failing_script.sh"));
@@ -361,25 +1564,657 @@ mod test {
 
         // Skip the disabled script in required.d ,since there are two
         // failing- scripts passing them both so that this test passes.
-        let state = run_diagnostics(vec![
-            "01_failing_binary".to_string(),
-            "02_failing_binary".to_string(),
-        ]);
+        let state = run_diagnostics(
+            &paths,
+            vec![
+                "01_failing_binary".to_string(),
+                "02_failing_binary".to_string(),
+            ],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
         assert!(
             state.is_ok(),
             "Should pass when skipping disabled required binary"
         );
+    }
+
+    #[test]
+    fn test_failed_wanted_marks_degraded() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+
+        let diagnostics = run_diagnostics(
+            &paths,
+            vec![],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        )
+        .unwrap();
+        assert_eq!(diagnostics.status(), HealthStatus::Degraded);
+        assert!(
+            diagnostics
+                .failed_wanted
+                .contains(&"failing_script.sh".to_string())
+        );
+
+        let diagnostics = run_diagnostics(
+            &paths,
+            vec![
+                "failing_script.sh".to_string(),
+                "failing_binary".to_string(),
+            ],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        )
+        .unwrap();
+        assert_eq!(diagnostics.status(), HealthStatus::Green);
+    }
+
+    #[test]
+    fn test_injected_outcomes() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let skipped = vec![
+            "failing_script.sh".to_string(),
+            "failing_binary".to_string(),
+        ];
+
+        assert!(
+            run_diagnostics(
+                &paths,
+                skipped.clone(),
+                &NetworkWait::default(),
+                &Injections::default(),
+                &CheckFiles::default(),
+                None,
+                &ScriptEnv::default()
+            )
+            .is_ok()
+        );
+
+        for spec in ["fail:passing_script.sh", "timeout:passing_script.sh"] {
+            let injections = Injections::parse(spec).unwrap();
+            let err = run_diagnostics(
+                &paths,
+                skipped.clone(),
+                &NetworkWait::default(),
+                &injections,
+                &CheckFiles::default(),
+                None,
+                &ScriptEnv::default(),
+            )
+            .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<DiagnosticsError>(),
+                Some(DiagnosticsError::RequiredFailed { failed, .. }) if failed == &vec!["passing_script.sh".to_string()]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_run_wanted_reports_failures() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+
+        // each install path ships one failing script and one failing binary in
+        // wanted.d, those of /etc mask those of /usr/lib
+        let failures = run_wanted(
+            &paths,
+            &[],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
+        assert_eq!(failures.errors.len(), 2);
+        assert_eq!(failures.failed.len(), 2);
+        // the details carry the exit code of each failed check
+        assert_eq!(failures.details.len(), 2);
+        assert!(
+            failures
+                .details
+                .iter()
+                .all(|failed| failures.failed.contains(&failed.check) && failed.exit_code.is_some())
+        );
+
+        let failures = run_wanted(
+            &paths,
+            &["failing_script.sh".to_string()],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
+        assert_eq!(failures.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_checks_run_with_their_shebang() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        // cat only prints the check, a shell would fail
+        fs::write(
+            paths.required_dirs()[1].join("05_cat.sh"),
+            "#!/bin/cat\nexit 1\n",
+        )
+        .unwrap();
+        let result = run_required(
+            &paths,
+            &[],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        );
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    #[test]
+    fn test_check_exiting_77_is_skipped() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let run = CheckRun {
+            skipped: &[],
+            network: &NetworkWait::default(),
+            injections: &Injections::default(),
+            files: &CheckFiles::default(),
+            webhook: None,
+            env: &ScriptEnv::default(),
+        };
+        let before = run_required_checks(&paths, &run, &mut HashSet::new()).unwrap();
+        // does not apply, not a failure
+        fs::write(
+            paths.required_dirs()[1].join("06_not_here.sh"),
+            "#!/bin/sh\necho no modem\nexit 77\n",
+        )
+        .unwrap();
+        let mut skipped = HashSet::new();
+        let result = run_required_checks(&paths, &run, &mut skipped).unwrap();
+        assert_eq!(skipped, HashSet::from(["06_not_here.sh".to_string()]));
+        assert!(result.failed.is_empty());
+        assert!(result.details.is_empty());
+        assert_eq!(result.passed, before.passed);
+    }
+
+    #[test]
+    fn test_failed_check_reports_on_fd_3() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let wanted_path = &paths.wanted_dirs()[1];
+        fs::create_dir_all(wanted_path).unwrap();
+        fs::write(
+            wanted_path.join("10_disk.sh"),
+            "echo '{\"message\": \"/var 95% full\", \"metrics\": {\"used\": 95}}' >&3\nexit 1\n",
+        )
+        .unwrap();
+        let diagnostics = run_diagnostics(
+            &paths,
+            vec![],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        )
+        .unwrap();
+        // the failing checks of the setup do not report anything
+        assert_eq!(diagnostics.wanted_details.len(), 3);
+        let failed = &diagnostics.wanted_details[0];
+        assert_eq!(failed.check, "10_disk.sh");
+        assert_eq!(failed.exit_code, Some(1));
+        assert_eq!(
+            failed.message().as_deref(),
+            Some("10_disk.sh: /var 95% full")
+        );
+        assert_eq!(failed.report.as_ref().unwrap().metrics["used"], 95.into());
+        assert!(diagnostics.wanted_details[1].report.is_none());
+    }
+
+    #[test]
+    fn test_checks_run_in_global_order() {
+        let (temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let (usr_lib, etc) = (&paths.required_dirs()[0], &paths.required_dirs()[1]);
+        let order = temp_dir.path().join("order");
+        for (dir, name) in [
+            (usr_lib, "30_late.sh"),
+            (etc, "20_mid.sh"),
+            (usr_lib, "10_early.sh"),
+        ] {
+            fs::write(
+                dir.join(name),
+                format!("echo {name} >> {}\n", order.display()),
+            )
+            .unwrap();
+        }
+        run_required(
+            &paths,
+            &[],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &ScriptEnv::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&order).unwrap(),
+            "10_early.sh\n20_mid.sh\n30_late.sh\n"
+        );
+    }
+
+    #[test]
+    fn test_etc_overrides_usr_lib() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let (usr_lib, etc) = (&paths.required_dirs()[0], &paths.required_dirs()[1]);
+        fs::write(usr_lib.join("10_vendor.sh"), "exit 1\n").unwrap();
+        fs::write(etc.join("10_vendor.sh"), "exit 0\n").unwrap();
+        let run = || {
+            run_required(
+                &paths,
+                &[],
+                &NetworkWait::default(),
+                &Injections::default(),
+                &CheckFiles::default(),
+                None,
+                &ScriptEnv::default(),
+            )
+        };
+        assert!(run().is_ok());
+
+        // masking the check in /etc disables it
+        fs::remove_file(etc.join("10_vendor.sh")).unwrap();
+        std::os::unix::fs::symlink("/dev/null", etc.join("10_vendor.sh")).unwrap();
+        assert!(run().is_ok());
+
+        fs::remove_file(etc.join("10_vendor.sh")).unwrap();
+        assert!(run().is_err());
+    }
+
+    #[test]
+    fn test_sidecar_settings() {
+        let (temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let run = |paths: &Paths| {
+            run_diagnostics(
+                paths,
+                vec!["failing_binary".to_string()],
+                &NetworkWait::default(),
+                &Injections::default(),
+                &CheckFiles::default(),
+                None,
+                &ScriptEnv::default(),
+            )
+        };
+        let required_path = &paths.required_dirs()[0];
+        let wanted_path = &paths.wanted_dirs()[1];
+
+        // fails on its first run only
+        let flag = temp_dir.path().join("flaky-ran");
+        fs::write(
+            required_path.join("10_flaky.sh"),
+            format!("[ -e {0} ] && exit 0\ntouch {0}\nexit 1\n", flag.display()),
+        )
+        .unwrap();
+        fs::write(required_path.join("10_flaky.sh.conf"), "retries=1\n").unwrap();
+        // killed after a second, only degrading the boot
+        fs::write(required_path.join("20_slow.sh"), "sleep 5\n").unwrap();
+        fs::write(
+            required_path.join("20_slow.sh.conf"),
+            "timeout=1\nrequired=false\n",
+        )
+        .unwrap();
+        // skipped as the modem it checks is missing
+        fs::write(
+            required_path.join("30_modem.sh"),
+            format!(
+                "# greenboot: ConditionPathExists={}\nexit 1\n",
+                temp_dir.path().join("dev/ttyUSB0").display()
+            ),
+        )
+        .unwrap();
+        let diagnostics = run(&paths).unwrap();
+        assert!(
+            diagnostics
+                .failed_wanted
+                .starts_with(&["20_slow.sh".to_string()])
+        );
+
+        // a wanted check made required fails the boot
+        fs::write(
+            wanted_path.join("failing_script.sh.conf"),
+            "required=true\nenv.REASON=\"sidecar env\"\n",
+        )
+        .unwrap();
+        fs::write(
+            wanted_path.join("failing_script.sh"),
+            "echo $REASON\nexit 1\n",
+        )
+        .unwrap();
+        let err = run(&paths).unwrap_err();
+        match err.downcast_ref::<DiagnosticsError>() {
+            Some(DiagnosticsError::RequiredFailed {
+                failed, details, ..
+            }) => {
+                assert_eq!(failed, &vec!["failing_script.sh".to_string()]);
+                assert_eq!(details[0].output.trim(), "sidecar env");
+            }
+            other => panic!("unexpected {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_scripts_get_standard_env() {
+        let (temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let wanted_path = &paths.wanted_dirs()[1];
+        fs::write(
+            wanted_path.join("50_env.sh"),
+            format!(
+                "[ \"$GREENBOOT_PHASE:$GREENBOOT_INSTALL_PATH\" = \"wanted:{}\" ] || exit 1\n\
+                 [ \"$GREENBOOT_BOOT_ATTEMPT/$GREENBOOT_MAX_BOOT_ATTEMPTS\" = 2/3 ] || exit 2\n\
+                 [ \"$GREENBOOT_DRY_RUN\" = 1 ] || exit 3\n\
+                 [ \"$GREENBOOT_PROFILE\" = field ] || exit 4\n\
+                 touch \"$GREENBOOT_RESULTS_DIR/env-ok\"\n",
+                paths.install_paths[1].display()
+            ),
+        )
+        .unwrap();
+
+        let env = ScriptEnv {
+            max_boot_attempts: 3,
+            boot_attempt: 2,
+            dry_run: true,
+            results_dir: temp_dir.path().to_path_buf(),
+            profile: Some("field".to_string()),
+            ..Default::default()
+        };
+        let failures = run_wanted(
+            &paths,
+            &[
+                "failing_script.sh".to_string(),
+                "failing_binary".to_string(),
+            ],
+            &NetworkWait::default(),
+            &Injections::default(),
+            &CheckFiles::default(),
+            None,
+            &env,
+        );
+        assert!(failures.failed.is_empty(), "{:?}", failures.errors);
+        assert!(temp_dir.path().join("env-ok").exists());
+    }
+
+    #[test]
+    fn test_ignored_files_are_not_run() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let readme = paths.required_dirs()[0].join("README");
+        fs::write(&readme, "exit 1\n").unwrap();
+        fs::set_permissions(&readme, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let run = |files: &CheckFiles| {
+            run_diagnostics(
+                &paths,
+                vec![],
+                &NetworkWait::default(),
+                &Injections::default(),
+                files,
+                None,
+                &ScriptEnv::default(),
+            )
+        };
+        assert!(run(&CheckFiles::default()).is_ok());
+        assert!(run(&CheckFiles::new(UnknownFiles::Warn, &[] as &[&str])).is_err());
+    }
 
-        tear_down().context("Test teardown failed").unwrap();
+    #[test]
+    fn test_red_script_defers_reboot() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+
+        let report = run_red(
+            &paths,
+            None,
+            &ScriptEnv::default(),
+            &FailureContext::default(),
+        );
+        assert_eq!(report.failures().count(), 0);
+        assert!(!report.defer_reboot());
+
+        let red_path = &paths.red_dirs()[1];
+        fs::create_dir_all(red_path).unwrap();
+        fs::write(red_path.join("50_defer.sh"), "exit 75\n").unwrap();
+
+        let report = run_red(
+            &paths,
+            None,
+            &ScriptEnv::default(),
+            &FailureContext::default(),
+        );
+        assert_eq!(report.failures().count(), 0);
+        assert!(report.defer_reboot());
     }
 
-    fn setup_folder_structure(passing: bool) -> Result<()> {
+    #[test]
+    fn test_red_scripts_get_failure_context() {
+        let (_temp_dir, paths) = setup_folder_structure(true)
+            .context("Test setup failed")
+            .unwrap();
+        let red_path = &paths.red_dirs()[1];
+        fs::create_dir_all(red_path).unwrap();
+        fs::write(
+            red_path.join("50_context.sh"),
+            "[ \"$GREENBOOT_FAILED_CHECKS\" = \"01_dns.sh 02_ntp.sh\" ] || exit 1\n\
+             [ \"$GREENBOOT_FAILED_EXIT_CODES\" = \"2 -\" ] || exit 2\n\
+             [ \"$GREENBOOT_FAILED_MESSAGES\" = \"01_dns.sh: no nameserver\" ] || exit 4\n\
+             [ \"$GREENBOOT_PHASE:$GREENBOOT_BOOT_ATTEMPT/$GREENBOOT_MAX_BOOT_ATTEMPTS\" = red:2/3 ] || exit 3\n",
+        )
+        .unwrap();
+
+        let started = SystemTime::now();
+        let failure = FailureContext {
+            failed: vec![
+                FailedCheck::new("01_dns.sh", Some(2), "", started).with_report(Some(
+                    CheckReport {
+                        message: Some("no nameserver".to_string()),
+                        ..Default::default()
+                    },
+                )),
+                FailedCheck::new("02_ntp.sh", None, "", started),
+            ],
+        };
+        let env = ScriptEnv {
+            max_boot_attempts: 3,
+            boot_attempt: 2,
+            ..Default::default()
+        };
+        let report = run_red(&paths, None, &env, &failure);
+        assert_eq!(report.failures().count(), 0);
+        let report = run_red(&paths, None, &env, &FailureContext::default());
+        assert_eq!(report.failures().count(), 1);
+    }
+
+    #[test]
+    fn test_actions_run_concurrently_with_timeout() {
+        let temp_dir = tempdir().unwrap();
+        let paths = Paths::with_root(temp_dir.path());
+        let green_path = &paths.green_dirs()[0];
+        fs::create_dir_all(green_path).unwrap();
+        fs::write(green_path.join("01_slow.sh"), "sleep 30\n").unwrap();
+        fs::write(green_path.join("02_slow.sh"), "sleep 30\n").unwrap();
+        fs::write(green_path.join("03_failing.sh"), "echo broken\nexit 2\n").unwrap();
+        fs::write(green_path.join("04_passing.sh"), "exit 0\n").unwrap();
+
+        let started = std::time::Instant::now();
+        let report = run_green(&paths, Some(Duration::from_secs(1)), &ScriptEnv::default());
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let statuses: Vec<_> = report.outcomes.iter().map(|o| &o.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                &ActionStatus::TimedOut,
+                &ActionStatus::TimedOut,
+                &ActionStatus::Failed {
+                    code: Some(2),
+                    output: "broken".to_string()
+                },
+                &ActionStatus::Success,
+            ]
+        );
+        assert_eq!(report.failures().count(), 3);
+    }
+
+    #[test]
+    fn test_pre_reboot_scripts_run() {
+        let temp_dir = tempdir().unwrap();
+        let paths = Paths::with_root(temp_dir.path());
+        let marker = temp_dir.path().join("flushed");
+        for dir in paths.pre_reboot_dirs() {
+            fs::create_dir_all(&dir).unwrap();
+        }
+        fs::write(
+            paths.pre_reboot_dirs()[1].join("10_flush.sh"),
+            format!("touch {}\n", marker.display()),
+        )
+        .unwrap();
+
+        let report = run_pre_reboot(&paths, None, &ScriptEnv::default());
+        assert_eq!(report.outcomes.len(), 1);
+        assert_eq!(report.failures().count(), 0);
+        assert!(marker.exists());
+    }
+
+    #[test]
+    fn test_remediation_hints_from_metadata() {
+        let temp_dir = tempdir().unwrap();
+        let paths = Paths::with_root(temp_dir.path());
+        let wanted_path = &paths.wanted_dirs()[1];
+        fs::create_dir_all(wanted_path).unwrap();
+        fs::write(
+            wanted_path.join("10_modem.sh"),
+            "#!/bin/bash\n# greenboot: remediation=\"run mmcli -m 0 -r\"\nexit 1\n",
+        )
+        .unwrap();
+        fs::write(wanted_path.join("20_plain.sh"), "exit 1\n").unwrap();
+
+        let hints = remediation_hints(
+            &paths,
+            &["10_modem.sh".to_string(), "20_plain.sh".to_string()],
+        );
+        assert_eq!(hints, vec!["10_modem.sh: run mmcli -m 0 -r".to_string()]);
+    }
+
+    #[test]
+    fn test_plan_run_lists_scripts_in_order() {
+        let temp_dir = tempdir().unwrap();
+        let paths = Paths::with_root(temp_dir.path());
+        let (required, wanted, red) = (
+            &paths.required_dirs()[1],
+            &paths.wanted_dirs()[0],
+            &paths.red_dirs()[1],
+        );
+        for dir in [required, wanted, red] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(
+            required.join("02_dns.sh"),
+            "# greenboot: timeout=30 description=\"DNS check\"\nexit 0\n",
+        )
+        .unwrap();
+        fs::write(required.join("01_disk.sh"), "exit 0\n").unwrap();
+        fs::write(required.join("README"), "").unwrap();
+        fs::create_dir_all(required.join("field")).unwrap();
+        fs::write(required.join("field/03_radio.sh"), "exit 0\n").unwrap();
+        fs::write(
+            required.join("04_burn_in.sh"),
+            "# greenboot: profiles=factory\nexit 0\n",
+        )
+        .unwrap();
+        fs::write(wanted.join("01_updates.sh"), "exit 0\n").unwrap();
+        fs::write(red.join("01_notify.sh"), "exit 0\n").unwrap();
+
+        let network = NetworkWait::new(vec!["02_dns.sh".to_string()], Duration::from_secs(60));
+        let plan: Vec<String> = plan_run(
+            &paths,
+            &["01_updates.sh".to_string()],
+            &network,
+            &Injections::parse("fail:01_disk.sh").unwrap(),
+            &CheckFiles::default(),
+            Some(Duration::from_secs(300)),
+            Some("field"),
+        )
+        .iter()
+        .map(|planned| planned.to_string())
+        .collect();
+        assert_eq!(
+            plan,
+            vec![
+                format!(
+                    "required   {} (skipped: injected outcome failed)",
+                    required.join("01_disk.sh").display()
+                ),
+                format!(
+                    "required   {} (waits up to 60s for the network) (timeout 30s): DNS check",
+                    required.join("02_dns.sh").display()
+                ),
+                format!(
+                    "required   {} (no timeout)",
+                    required.join("field/03_radio.sh").display()
+                ),
+                format!(
+                    "required   {} (skipped: not in profile field)",
+                    required.join("04_burn_in.sh").display()
+                ),
+                format!(
+                    "wanted     {} (skipped: disabled)",
+                    wanted.join("01_updates.sh").display()
+                ),
+                format!(
+                    "red        {} (timeout 300s)",
+                    red.join("01_notify.sh").display()
+                ),
+            ]
+        );
+    }
+
+    /// lays out both install paths under a temp root, removed when the TempDir is dropped
+    fn setup_folder_structure(passing: bool) -> Result<(TempDir, Paths)> {
+        let temp_dir = tempdir().context("unable to create temp dir")?;
+        let paths = Paths::with_root(temp_dir.path());
         let passing_test_scripts = "testing_assets/passing_script.sh";
         let failing_test_scripts = "testing_assets/failing_script.sh";
         let passing_test_binary = "testing_assets/passing_binary";
         let failing_test_binary = "testing_assets/failing_binary";
 
-        for install_path in GREENBOOT_INSTALL_PATHS {
+        for install_path in paths.install_paths.iter().map(|p| p.display()) {
             let required_path = format!("{install_path}/check/required.d");
             let wanted_path = format!("{install_path}/check/wanted.d");
             fs::create_dir_all(&required_path).expect("cannot create folder");
@@ -451,13 +2286,6 @@ mod test {
                 .context("unable to copy another failing binary to required.d")?;
             }
         }
-        Ok(())
-    }
-
-    fn tear_down() -> Result<()> {
-        for path in GREENBOOT_INSTALL_PATHS {
-            fs::remove_dir_all(path).expect("Unable to delete folder");
-        }
-        Ok(())
+        Ok((temp_dir, paths))
     }
 }
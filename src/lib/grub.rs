@@ -1,236 +1,823 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
 use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::str;
+use std::str::{self, FromStr};
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+
+/// grubenv locations relative to the boot dir, in probing order: x86 BIOS and
+/// current UEFI layouts, the legacy Fedora UEFI layout, then upstream GRUB
+static GRUBENV_CANDIDATES: [&str; 3] = ["grub2/grubenv", "efi/EFI/fedora/grubenv", "grub/grubenv"];
+/// first line of a valid GRUB environment block
+static GRUBENV_HEADER: &[u8] = b"# GRUB Environment Block";
+/// size grub2-editenv pads the environment block to
+const GRUBENV_BLOCK_SIZE: usize = 1024;
+/// GRUB's own tool, used when the block cannot be written directly
+static GRUB_EDITENV: &str = "grub2-editenv";
+
+/// greenboot variables of a GRUB environment block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GrubenvState {
+    pub boot_counter: Option<i32>,
+    pub boot_success: bool,
+    pub rollback_trigger: bool,
+}
 
-/// Shared GRUB environment path used by default helpers
-static GRUB_PATH: &str = "/boot/grub2/grubenv";
+/// reads the greenboot variables straight from the block into a stack buffer,
+/// without building a Grubenv, for callers polling the boot state
+pub fn read_grubenv_state(grubenv: &Path) -> Result<GrubenvState> {
+    let mut block = [0; GRUBENV_BLOCK_SIZE + 1];
+    let len = read_block(grubenv, &mut block)?;
+    parse_grubenv_state(&block[..len])
+}
 
-/// fetches boot_counter value, none if not set
-pub fn get_boot_counter() -> Result<Option<i32>> {
-    get_boot_counter_at(GRUB_PATH)
-}
-
-fn get_boot_counter_at(grub_path: &str) -> Result<Option<i32>> {
-    let grub_vars = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("list")
-        .output()?;
-    let grub_vars = str::from_utf8(&grub_vars.stdout[..])?;
-    for var in grub_vars.lines() {
-        let (k, v) = if let Some(kv) = var.split_once('=') {
-            kv
-        } else {
+fn parse_grubenv_state(block: &[u8]) -> Result<GrubenvState> {
+    let mut state = GrubenvState::default();
+    for line in split_block(block)? {
+        let Some((key, value)) = split_var(line) else {
             continue;
         };
-        if k != "boot_counter" {
-            continue;
+        match key {
+            b"boot_counter" => state.boot_counter = Some(parse_counter(value)?),
+            b"boot_success" => state.boot_success = value == b"1",
+            b"greenboot_rollback_trigger" => state.rollback_trigger = value == b"1",
+            _ => {}
         }
+    }
+    Ok(state)
+}
 
-        return match v.parse::<i32>() {
-            Ok(n) => Ok(Some(n)),
-            Err(_) => Err(anyhow::anyhow!("boot_counter has invalid value: {}", v)),
-        };
+/// fills `buf`, one byte larger than a block so an oversized file shows
+fn read_block(grubenv: &Path, buf: &mut [u8]) -> Result<usize> {
+    let mut file =
+        fs::File::open(grubenv).with_context(|| format!("Unable to open {}", grubenv.display()))?;
+    let mut len = 0;
+    while len < buf.len() {
+        match file
+            .read(&mut buf[len..])
+            .with_context(|| format!("Unable to read {}", grubenv.display()))?
+        {
+            0 => break,
+            read => len += read,
+        }
     }
-    Ok(None)
+    Ok(len)
 }
 
-/// sets grub variable boot_counter if not set
-pub fn set_boot_counter(reboot_count: u16) -> Result<()> {
-    set_boot_counter_at(reboot_count, GRUB_PATH)
+/// validates a whole environment block and returns its lines between the
+/// header and the padding, variables and comments alike. A newline escaped
+/// with a backslash belongs to the value, as grub2-editenv writes it.
+fn split_block(block: &[u8]) -> Result<Vec<&[u8]>> {
+    if block.len() != GRUBENV_BLOCK_SIZE {
+        bail!(
+            "grubenv is {} bytes instead of {GRUBENV_BLOCK_SIZE}",
+            block.len()
+        );
+    }
+    let body = block
+        .strip_prefix(GRUBENV_HEADER)
+        .and_then(|rest| rest.strip_prefix(b"\n"))
+        .context("grubenv does not start with the environment block header")?;
+
+    let mut lines = Vec::new();
+    let (mut start, mut escaped) = (0, false);
+    for (i, &b) in body.iter().enumerate() {
+        match b {
+            b'\\' => escaped = !escaped,
+            b'\n' if !escaped => {
+                lines.push(&body[start..i]);
+                start = i + 1;
+            }
+            _ => escaped = false,
+        }
+    }
+    // what follows the last line is padding
+    if body[start..].iter().any(|&b| b != b'#') {
+        bail!("grubenv has an unterminated line or invalid padding");
+    }
+    if let Some(line) = lines
+        .iter()
+        .find(|line| !line.starts_with(b"#") && split_var(line).is_none())
+    {
+        bail!(
+            "grubenv has an invalid line: {}",
+            String::from_utf8_lossy(line)
+        );
+    }
+    Ok(lines)
+}
+
+/// `name=value` line, none for comments and lines without a name
+fn split_var(line: &[u8]) -> Option<(&[u8], &[u8])> {
+    if line.starts_with(b"#") {
+        return None;
+    }
+    let eq = line.iter().position(|&b| b == b'=')?;
+    (eq > 0).then(|| (&line[..eq], &line[eq + 1..]))
+}
+
+fn parse_counter(value: &[u8]) -> Result<i32> {
+    str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .with_context(|| {
+            format!(
+                "boot_counter has invalid value: {}",
+                String::from_utf8_lossy(value)
+            )
+        })
+}
+
+/// A GRUB environment block read and written natively: every line other
+/// than the greenboot variables set or unset, comments and unknown variables
+/// included, is written back byte for byte, and the block is padded to
+/// exactly GRUBENV_BLOCK_SIZE bytes as GRUB expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grubenv {
+    lines: Vec<Vec<u8>>,
 }
 
-fn set_boot_counter_at(reboot_count: u16, grub_path: &str) -> Result<()> {
-    match get_boot_counter_at(grub_path) {
-        Ok(Some(i)) => {
-            bail!("already set boot_counter={i}");
+impl Grubenv {
+    /// reads and validates the block, refusing truncated or oversized files
+    pub fn read(grubenv: &Path) -> Result<Self> {
+        let mut block = [0; GRUBENV_BLOCK_SIZE + 1];
+        let len = read_block(grubenv, &mut block)?;
+        Self::parse(&block[..len]).with_context(|| format!("Invalid {}", grubenv.display()))
+    }
+
+    pub fn parse(block: &[u8]) -> Result<Self> {
+        Ok(Self {
+            lines: split_block(block)?
+                .into_iter()
+                .map(<[u8]>::to_vec)
+                .collect(),
+        })
+    }
+
+    /// raw value of `key`, escapes included
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.lines
+            .iter()
+            .filter_map(|line| split_var(line))
+            .find(|(name, _)| *name == key.as_bytes())
+            .map(|(_, value)| value)
+    }
+
+    /// replaces the value of `key` in place, or appends it
+    pub fn set(&mut self, key: &str, value: &str) {
+        let line = format!("{key}={}", escape(value)).into_bytes();
+        match self.position(key) {
+            Some(i) => self.lines[i] = line,
+            None => self.lines.push(line),
+        }
+    }
+
+    pub fn unset(&mut self, key: &str) {
+        if let Some(i) = self.position(key) {
+            self.lines.remove(i);
         }
-        Ok(None) => {
-            log::info!("boot_counter does not exists");
+    }
+
+    /// the padded block, fails if the variables do not fit
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        let mut block = GRUBENV_HEADER.to_vec();
+        block.push(b'\n');
+        for line in &self.lines {
+            block.extend_from_slice(line);
+            block.push(b'\n');
         }
-        Err(_) => {
-            // Counter exists but has invalid value - overwrite it
-            log::warn!("boot_counter exists with invalid value - overwriting");
+        if block.len() > GRUBENV_BLOCK_SIZE {
+            bail!("grubenv variables exceed {GRUBENV_BLOCK_SIZE} bytes");
         }
+        block.resize(GRUBENV_BLOCK_SIZE, b'#');
+        Ok(block)
     }
 
-    log::info!("setting boot counter");
-    set_grub_var("boot_counter", reboot_count, grub_path)?;
-    Ok(())
+    /// replaces the block atomically: the new block is written and synced to
+    /// a temp file in the same dir, renamed over the old one and the dir is
+    /// synced, so a power cut leaves either the old or the new block. The
+    /// block is then read back and compared.
+    pub fn write(&self, grubenv: &Path) -> Result<()> {
+        let block = self.serialize()?;
+        // write the block a symlink points to, not the link
+        let target = fs::canonicalize(grubenv)
+            .with_context(|| format!("Unable to resolve {}", grubenv.display()))?;
+        let dir = target.parent().unwrap_or(Path::new("/"));
+        let mut staged = tempfile::Builder::new()
+            .prefix(".grubenv")
+            .tempfile_in(dir)
+            .with_context(|| format!("Unable to create a temp file in {}", dir.display()))?;
+        let permissions = fs::metadata(&target)?.permissions();
+        staged
+            .write_all(&block)
+            .and_then(|_| staged.as_file().set_permissions(permissions))
+            .and_then(|_| staged.as_file().sync_all())
+            .with_context(|| format!("Unable to write {}", staged.path().display()))?;
+        staged
+            .persist(&target)
+            .with_context(|| format!("Unable to replace {}", target.display()))?;
+        fs::File::open(dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Unable to sync {}", dir.display()))?;
+
+        if Self::read(&target)? != *self {
+            bail!("{} does not hold the written values", target.display());
+        }
+        Ok(())
+    }
+
+    /// `name=value` lines, values escaped
+    fn vars(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.lines.iter().filter_map(|line| split_var(line))
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.lines
+            .iter()
+            .position(|line| split_var(line).is_some_and(|(name, _)| name == key.as_bytes()))
+    }
 }
-/// sets grub variable boot_success
-pub fn set_boot_status(success: bool) -> Result<()> {
-    set_boot_status_at(success, GRUB_PATH)
+
+/// grub2-editenv escapes backslashes and newlines of values with a backslash
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\\n")
 }
 
-fn set_boot_status_at(success: bool, grub_path: &str) -> Result<()> {
-    if success {
-        set_grub_var("boot_success", 1, grub_path)?;
-        unset_boot_counter_at(grub_path)?;
-        return Ok(());
+fn unescape(value: &[u8]) -> String {
+    let mut unescaped = Vec::with_capacity(value.len());
+    let mut bytes = value.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'\\' => unescaped.extend(bytes.next()),
+            _ => unescaped.push(b),
+        }
     }
-    set_grub_var("boot_success", 0, grub_path)
+    String::from_utf8_lossy(&unescaped).into_owned()
 }
 
-/// unset boot_counter
-pub fn unset_boot_counter() -> Result<()> {
-    unset_boot_counter_at(GRUB_PATH)
+/// how a grubenv update was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrubenvWriter {
+    /// greenboot replaced the block itself
+    Native,
+    /// the direct write failed and grub2-editenv did the update
+    Editenv,
 }
 
-fn unset_boot_counter_at(grub_path: &str) -> Result<()> {
-    unset_grub_var("boot_counter", grub_path)
+impl fmt::Display for GrubenvWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrubenvWriter::Native => write!(f, "native"),
+            GrubenvWriter::Editenv => write!(f, "{GRUB_EDITENV}"),
+        }
+    }
 }
 
-/// sets greenboot_rollback_trigger=1
-pub fn set_rollback_trigger() -> Result<()> {
-    set_rollback_trigger_at(GRUB_PATH)
+impl FromStr for GrubenvWriter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "native" => Ok(GrubenvWriter::Native),
+            other if other == GRUB_EDITENV => Ok(GrubenvWriter::Editenv),
+            other => bail!("unknown grubenv writer '{other}'"),
+        }
+    }
 }
 
-fn set_rollback_trigger_at(grub_path: &str) -> Result<()> {
-    set_grub_var("greenboot_rollback_trigger", 1, grub_path)
+/// how the last grubenv update of this boot was written, none before the first
+pub fn read_grubenv_writer(paths: &Paths) -> Option<GrubenvWriter> {
+    fs::read_to_string(&paths.grubenv_writer)
+        .ok()
+        .and_then(|writer| writer.parse().ok())
 }
 
-/// unsets greenboot_rollback_trigger
-pub fn unset_rollback_trigger() -> Result<()> {
-    unset_rollback_trigger_at(GRUB_PATH)
+/// arguments of the `grub2-editenv set` and `unset` calls turning `old` into
+/// `new`, values unescaped as the tool escapes them itself
+fn editenv_args(old: &Grubenv, new: &Grubenv) -> (Vec<String>, Vec<String>) {
+    let set = new
+        .vars()
+        .filter(|var| !old.vars().any(|old_var| old_var == *var))
+        .map(|(key, value)| format!("{}={}", String::from_utf8_lossy(key), unescape(value)))
+        .collect();
+    let unset = old
+        .vars()
+        .filter(|(key, _)| !new.vars().any(|(new_key, _)| new_key == *key))
+        .map(|(key, _)| String::from_utf8_lossy(key).into_owned())
+        .collect();
+    (set, unset)
 }
 
-fn unset_rollback_trigger_at(grub_path: &str) -> Result<()> {
-    unset_grub_var("greenboot_rollback_trigger", grub_path)
+/// applies the changes from `old` to `new` with grub2-editenv
+fn editenv(grubenv: &Path, old: &Grubenv, new: &Grubenv) -> Result<()> {
+    let (set, unset) = editenv_args(old, new);
+    for (command, args) in [("set", set), ("unset", unset)] {
+        if args.is_empty() {
+            continue;
+        }
+        let status = Command::new(GRUB_EDITENV)
+            .arg(grubenv)
+            .arg(command)
+            .args(&args)
+            .status()
+            .with_context(|| format!("Unable to run {GRUB_EDITENV}"))?;
+        if !status.success() {
+            bail!("{GRUB_EDITENV} {command} {} failed", args.join(" "));
+        }
+    }
+    Ok(())
 }
 
-/// gets greenboot_rollback_trigger value, returns true if set to 1
-pub fn get_rollback_trigger() -> Result<bool> {
-    get_rollback_trigger_at(GRUB_PATH)
-}
-
-fn get_rollback_trigger_at(grub_path: &str) -> Result<bool> {
-    let grub_vars = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("list")
-        .output()
-        .context("Unable to list grubenv variables")?;
-
-    let output = String::from_utf8_lossy(&grub_vars.stdout);
-    for line in output.lines() {
-        if line.starts_with("greenboot_rollback_trigger=") {
-            let value = line.split('=').nth(1).unwrap_or("0");
-            return Ok(value == "1");
+/// first GRUB environment block found under `boot_dir`, with symlinks
+/// resolved so the block itself gets written rather than a link to it
+pub fn discover_grubenv(boot_dir: &Path) -> Option<PathBuf> {
+    GRUBENV_CANDIDATES.iter().find_map(|candidate| {
+        let path = boot_dir.join(candidate);
+        let resolved = fs::canonicalize(&path).ok()?;
+        if !is_grubenv_block(&resolved) {
+            tracing::debug!("{} is not a usable grubenv", path.display());
+            return None;
         }
+        tracing::debug!("found grubenv at {}", resolved.display());
+        Some(resolved)
+    })
+}
+
+/// true if `path` is an environment block greenboot can update
+pub fn grubenv_is_writable(path: &Path) -> bool {
+    fs::canonicalize(path).is_ok_and(|resolved| is_grubenv_block(&resolved))
+}
+
+/// a writable regular file starting with the environment block header
+fn is_grubenv_block(path: &Path) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    if !metadata.is_file() || metadata.permissions().readonly() {
+        return false;
     }
-    Ok(false) // Not set means false
+    let mut header = vec![0; GRUBENV_HEADER.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut header))
+        .is_ok_and(|_| header == GRUBENV_HEADER)
 }
 
-fn unset_grub_var(key: &str, grub_path: &str) -> Result<()> {
-    // Execute GRUB command and capture result
-    let grub_result = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("unset")
-        .arg(key)
-        .status()
-        .context("Unable to clear boot_counter")?;
+/// GRUB keeps greenboot's state as variables of its environment block,
+/// read by the 08_greenboot.cfg snippet and the boot counting logic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrubenvBackend {
+    grubenv: PathBuf,
+    /// flocked around every read-modify-write of the block
+    lock: PathBuf,
+    /// records the GrubenvWriter of the last update
+    writer: PathBuf,
+}
 
-    if !grub_result.success() {
-        bail!("Failed to unset grubenv key: {key}");
+impl GrubenvBackend {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            grubenv: paths.grubenv.clone(),
+            lock: paths.grubenv_lock.clone(),
+            writer: paths.grubenv_writer.clone(),
+        }
     }
 
-    log::info!("Clear grubenv: {key}");
-    Ok(())
+    fn read(&self) -> Result<Grubenv> {
+        Grubenv::read(&self.grubenv)
+    }
+
+    /// reads, changes and writes the block while holding the lock, so
+    /// concurrent updates cannot drop each other's variables. When the block
+    /// cannot be written directly, e.g. on a filesystem not supporting the
+    /// rename or under an SELinux denial, grub2-editenv applies the change.
+    fn update(&self, change: impl FnOnce(&mut Grubenv) -> Result<()>) -> Result<()> {
+        let _lock = lock_file(&self.lock);
+        let current = self.read()?;
+        let mut grubenv = current.clone();
+        change(&mut grubenv)?;
+        let writer = match grubenv.write(&self.grubenv) {
+            Ok(()) => GrubenvWriter::Native,
+            Err(e) => {
+                tracing::warn!(
+                    "cannot write {} directly, retrying with {GRUB_EDITENV}: {e:#}",
+                    self.grubenv.display()
+                );
+                editenv(&self.grubenv, &current, &grubenv).with_context(|| {
+                    format!("{GRUB_EDITENV} fallback failed after direct write error: {e:#}")
+                })?;
+                tracing::info!("{} updated with {GRUB_EDITENV}", self.grubenv.display());
+                GrubenvWriter::Editenv
+            }
+        };
+        self.record_writer(writer);
+        Ok(())
+    }
+
+    /// for `greenboot status`, a failure only loses that information
+    fn record_writer(&self, writer: GrubenvWriter) {
+        if let Err(e) = self
+            .writer
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&self.writer, format!("{writer}\n")))
+        {
+            tracing::debug!("cannot record the grubenv writer: {e}");
+        }
+    }
 }
 
-fn set_grub_var(key: &str, val: u16, grub_path: &str) -> Result<()> {
-    // Execute GRUB command and capture result
-    let grub_result = Command::new("grub2-editenv")
-        .arg(grub_path)
-        .arg("set")
-        .arg(format!("{key}={val}"))
-        .status()
-        .context("Unable to set grubenv")?;
+/// takes an exclusive flock on `path`, waiting for the current holder. Not
+/// being able to lock is only logged, the update then runs unprotected.
+fn lock_file(path: &Path) -> Option<fs::File> {
+    let locked = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| {
+            fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(path)
+        })
+        .and_then(|file| file.lock().map(|_| file));
+    match locked {
+        Ok(file) => Some(file),
+        Err(e) => {
+            tracing::warn!(
+                "cannot lock {}, updating grubenv anyway: {e}",
+                path.display()
+            );
+            None
+        }
+    }
+}
+
+fn set_var(grubenv: &mut Grubenv, key: &str, val: u16) {
+    grubenv.set(key, &val.to_string());
+    tracing::info!("Set grubenv: {key}={val}");
+}
+
+fn unset_var(grubenv: &mut Grubenv, key: &str) {
+    grubenv.unset(key);
+    tracing::info!("Clear grubenv: {key}");
+}
 
-    if !grub_result.success() {
-        bail!("Failed to set grubenv key: {key}");
+impl BootloaderBackend for GrubenvBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        self.read()?
+            .get("boot_counter")
+            .map(parse_counter)
+            .transpose()
     }
 
-    log::info!("Set grubenv: {key}={val}");
-    Ok(())
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        self.update(|grubenv| {
+            match grubenv.get("boot_counter").map(parse_counter).transpose() {
+                Ok(Some(i)) => {
+                    bail!("already set boot_counter={i}");
+                }
+                Ok(None) => {
+                    tracing::info!("boot_counter does not exists");
+                }
+                Err(_) => {
+                    // Counter exists but has invalid value - overwrite it
+                    tracing::warn!("boot_counter exists with invalid value - overwriting");
+                }
+            }
+            tracing::info!("setting boot counter");
+            set_var(grubenv, "boot_counter", attempts);
+            Ok(())
+        })
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.update(|grubenv| {
+            unset_var(grubenv, "boot_counter");
+            Ok(())
+        })
+    }
+
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        self.update(|grubenv| {
+            set_var(grubenv, "boot_success", u16::from(success));
+            if success {
+                unset_var(grubenv, "boot_counter");
+            }
+            Ok(())
+        })
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        // Not set means false
+        Ok(self.read()?.get("greenboot_rollback_trigger") == Some(b"1"))
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.update(|grubenv| {
+            set_var(grubenv, "greenboot_rollback_trigger", 1);
+            Ok(())
+        })
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.update(|grubenv| {
+            unset_var(grubenv, "greenboot_rollback_trigger");
+            Ok(())
+        })
+    }
+
+    fn env_var(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.read()?.get(key).map(unescape))
+    }
+
+    fn set_env_var(&self, key: &str, value: &str) -> Result<()> {
+        self.update(|grubenv| {
+            grubenv.set(key, value);
+            tracing::info!("Set grubenv: {key}={value}");
+            Ok(())
+        })
+    }
+
+    fn store(&self) -> String {
+        format!("grubenv {}", self.grubenv.display())
+    }
+}
+
+/// fetches boot_counter value, none if not set
+pub fn get_boot_counter(paths: &Paths) -> Result<Option<i32>> {
+    GrubenvBackend::new(paths).boot_counter()
+}
+
+/// sets grub variable boot_counter if not set
+pub fn set_boot_counter(paths: &Paths, reboot_count: u16) -> Result<()> {
+    GrubenvBackend::new(paths).set_boot_counter(reboot_count)
+}
+
+/// sets grub variable boot_success
+pub fn set_boot_status(paths: &Paths, success: bool) -> Result<()> {
+    GrubenvBackend::new(paths).set_boot_status(success)
+}
+
+/// unset boot_counter
+pub fn unset_boot_counter(paths: &Paths) -> Result<()> {
+    GrubenvBackend::new(paths).unset_boot_counter()
+}
+
+/// sets greenboot_rollback_trigger=1
+pub fn set_rollback_trigger(paths: &Paths) -> Result<()> {
+    GrubenvBackend::new(paths).set_rollback_trigger()
+}
+
+/// unsets greenboot_rollback_trigger
+pub fn unset_rollback_trigger(paths: &Paths) -> Result<()> {
+    GrubenvBackend::new(paths).unset_rollback_trigger()
+}
+
+/// gets greenboot_rollback_trigger value, returns true if set to 1
+pub fn get_rollback_trigger(paths: &Paths) -> Result<bool> {
+    GrubenvBackend::new(paths).rollback_trigger()
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        get_boot_counter_at, get_rollback_trigger_at, set_boot_counter_at, set_rollback_trigger_at,
-        unset_boot_counter_at, unset_rollback_trigger_at,
+        GRUBENV_BLOCK_SIZE, Grubenv, GrubenvBackend, GrubenvState, GrubenvWriter, discover_grubenv,
+        editenv_args, parse_grubenv_state,
     };
-    use anyhow::Context;
+    use crate::bootloader::BootloaderBackend;
     use std::fs;
-    use std::process::Command;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn test_parse_grubenv_state() {
+        let mut block = b"# GRUB Environment Block\nboot_success=0\nboot_counter=2\n\
+            greenboot_rollback_trigger=1\n"
+            .to_vec();
+        block.resize(GRUBENV_BLOCK_SIZE, b'#');
+        assert_eq!(
+            parse_grubenv_state(&block).unwrap(),
+            GrubenvState {
+                boot_counter: Some(2),
+                boot_success: false,
+                rollback_trigger: true,
+            }
+        );
+        let mut empty = b"# GRUB Environment Block\n".to_vec();
+        empty.resize(GRUBENV_BLOCK_SIZE, b'#');
+        assert_eq!(
+            parse_grubenv_state(&empty).unwrap(),
+            GrubenvState::default()
+        );
+        assert!(parse_grubenv_state(b"# GRUB Environment Block\n").is_err());
+        assert!(parse_grubenv_state(b"boot_counter=two\n").is_err());
+    }
+
+    #[test]
+    fn test_grubenv_validation() {
+        let mut block = b"# GRUB Environment Block\nsaved_entry=a\\\nb\n".to_vec();
+        block.resize(GRUBENV_BLOCK_SIZE, b'#');
+        let grubenv = Grubenv::parse(&block).unwrap();
+        assert_eq!(grubenv.get("saved_entry"), Some(&b"a\\\nb"[..]));
+        assert_eq!(grubenv.serialize().unwrap(), block);
+
+        let mut oversized = block.clone();
+        oversized.push(b'#');
+        let mut bad_header = block.clone();
+        bad_header[2] = b'g';
+        let mut unterminated = b"# GRUB Environment Block\nboot_counter=1".to_vec();
+        unterminated.resize(GRUBENV_BLOCK_SIZE, b'#');
+        let mut no_name = b"# GRUB Environment Block\n=1\n".to_vec();
+        no_name.resize(GRUBENV_BLOCK_SIZE, b'#');
+        for invalid in [
+            &block[..512],
+            &oversized[..],
+            &bad_header[..],
+            &unterminated[..],
+            &no_name[..],
+        ] {
+            assert!(Grubenv::parse(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn test_grubenv_preserves_other_lines() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let original = fs::read(&grubenv).unwrap();
+        backend(&grubenv).set_boot_counter(3).unwrap();
+        backend(&grubenv).unset_boot_counter().unwrap();
+        assert_eq!(fs::read(&grubenv).unwrap(), original);
+
+        let mut env = Grubenv::read(&grubenv).unwrap();
+        env.set("huge", &"x".repeat(GRUBENV_BLOCK_SIZE));
+        assert!(env.serialize().is_err());
+    }
+
+    #[test]
+    fn test_grubenv_write_replaces_link_target() {
+        let (temp_dir, grubenv) = setup_test_paths();
+        fs::set_permissions(&grubenv, fs::Permissions::from_mode(0o600)).unwrap();
+        let link = temp_dir.path().join("link");
+        std::os::unix::fs::symlink(&grubenv, &link).unwrap();
+
+        backend(&link).set_boot_counter(2).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(2));
+        assert_eq!(
+            fs::metadata(&grubenv).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        // no temp file is left behind, next to the lock dir
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_editenv_fallback_args() {
+        let mut block = b"# GRUB Environment Block\nsaved_entry=a\\\\b\nboot_counter=2\n".to_vec();
+        block.resize(GRUBENV_BLOCK_SIZE, b'#');
+        let old = Grubenv::parse(&block).unwrap();
+        let mut new = old.clone();
+        new.unset("boot_counter");
+        new.set("boot_success", "1");
+        new.set("saved_entry", "c\\d");
+        assert_eq!(
+            editenv_args(&old, &new),
+            (
+                vec!["saved_entry=c\\d".to_string(), "boot_success=1".to_string()],
+                vec!["boot_counter".to_string()]
+            )
+        );
+        assert_eq!(editenv_args(&old, &old), (vec![], vec![]));
+    }
+
+    #[test]
+    fn test_update_records_the_writer() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let backend = backend(&grubenv);
+        backend.set_rollback_trigger().unwrap();
+        assert_eq!(
+            fs::read_to_string(&backend.writer)
+                .unwrap()
+                .parse::<GrubenvWriter>()
+                .unwrap(),
+            GrubenvWriter::Native
+        );
+        assert_eq!(
+            "grub2-editenv".parse::<GrubenvWriter>().unwrap(),
+            GrubenvWriter::Editenv
+        );
+    }
+
     use tempfile::TempDir;
     use tempfile::tempdir;
 
-    fn setup_test_paths() -> (TempDir, String) {
+    fn setup_test_paths() -> (TempDir, PathBuf) {
         let temp_dir = tempdir().unwrap();
         let temp_grubenv = temp_dir.path().join("grubenv");
         fs::copy("testing_assets/grubenv", &temp_grubenv).unwrap();
-        (temp_dir, temp_grubenv.to_str().unwrap().to_string())
+        (temp_dir, temp_grubenv)
+    }
+
+    fn backend(grubenv: &Path) -> GrubenvBackend {
+        GrubenvBackend {
+            grubenv: grubenv.to_path_buf(),
+            lock: grubenv.with_file_name("lock/grubenv.lock"),
+            writer: grubenv.with_file_name("lock/grubenv-writer"),
+        }
+    }
+
+    fn set_var(grubenv: &Path, key: &str, value: &str) {
+        let mut env = Grubenv::read(grubenv).unwrap();
+        env.set(key, value);
+        env.write(grubenv).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_updates_keep_every_variable() {
+        let (_temp_dir, grubenv) = setup_test_paths();
+        let backend = backend(&grubenv);
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                let backend = &backend;
+                s.spawn(move || {
+                    backend
+                        .update(|env| {
+                            env.set(&format!("var{i}"), "1");
+                            Ok(())
+                        })
+                        .unwrap()
+                });
+            }
+        });
+        let env = Grubenv::read(&grubenv).unwrap();
+        assert!((0..8).all(|i| env.get(&format!("var{i}")) == Some(b"1")));
+    }
+
+    #[test]
+    fn test_discover_grubenv_follows_symlinks() {
+        let temp_dir = tempdir().unwrap();
+        let boot = temp_dir.path();
+        assert_eq!(discover_grubenv(boot), None);
+
+        // a dangling link and a file that is not an environment block are skipped
+        fs::create_dir_all(boot.join("grub2")).unwrap();
+        std::os::unix::fs::symlink("../efi/EFI/fedora/grubenv", boot.join("grub2/grubenv"))
+            .unwrap();
+        fs::create_dir_all(boot.join("grub")).unwrap();
+        fs::write(boot.join("grub/grubenv"), "garbage").unwrap();
+        assert_eq!(discover_grubenv(boot), None);
+
+        let efi = boot.join("efi/EFI/fedora");
+        fs::create_dir_all(&efi).unwrap();
+        fs::copy("testing_assets/grubenv", efi.join("grubenv")).unwrap();
+        assert_eq!(
+            discover_grubenv(boot),
+            Some(fs::canonicalize(efi.join("grubenv")).unwrap())
+        );
     }
 
     #[test]
     fn test_boot_counter_set() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        set_boot_counter_at(10, &grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(10));
+        backend(&grubenv).set_boot_counter(10).unwrap();
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(10));
     }
 
     #[test]
     fn test_boot_counter_re_set() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=99")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        set_boot_counter_at(20, &grubenv).ok();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(99));
+        set_var(&grubenv, "boot_counter", "99");
+        backend(&grubenv).set_boot_counter(20).ok();
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(99));
     }
 
     #[test]
     fn test_boot_counter_having_invalid_value() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=foo")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        set_boot_counter_at(13, &grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(13));
+        set_var(&grubenv, "boot_counter", "foo");
+        backend(&grubenv).set_boot_counter(13).unwrap();
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(13));
     }
 
     #[test]
     fn test_unset_boot_counter() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=199")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        unset_boot_counter_at(&grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), None);
+        set_var(&grubenv, "boot_counter", "199");
+        backend(&grubenv).unset_boot_counter().unwrap();
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), None);
     }
 
     #[test]
     fn test_get_boot_counter() {
         let (_temp_dir, grubenv) = setup_test_paths();
-        let _ = Command::new("grub2-editenv")
-            .arg(&grubenv)
-            .arg("set")
-            .arg("boot_counter=99")
-            .status()
-            .context("Cannot create grub variable boot_counter");
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(99));
+        set_var(&grubenv, "boot_counter", "99");
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(99));
     }
 
     #[test]
@@ -238,15 +825,15 @@ mod tests {
         let (_temp_dir, grubenv) = setup_test_paths();
 
         // Test when rollback trigger is not set
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        assert!(!backend(&grubenv).rollback_trigger().unwrap());
 
         // Test setting rollback trigger
-        set_rollback_trigger_at(&grubenv).unwrap();
-        assert!(get_rollback_trigger_at(&grubenv).unwrap());
+        backend(&grubenv).set_rollback_trigger().unwrap();
+        assert!(backend(&grubenv).rollback_trigger().unwrap());
 
         // Test unsetting rollback trigger
-        unset_rollback_trigger_at(&grubenv).unwrap();
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        backend(&grubenv).unset_rollback_trigger().unwrap();
+        assert!(!backend(&grubenv).rollback_trigger().unwrap());
     }
 
     #[test]
@@ -254,18 +841,18 @@ mod tests {
         let (_temp_dir, grubenv) = setup_test_paths();
 
         // Set boot counter
-        set_boot_counter_at(3, &grubenv).unwrap();
+        backend(&grubenv).set_boot_counter(3).unwrap();
 
         // Set rollback trigger
-        set_rollback_trigger_at(&grubenv).unwrap();
+        backend(&grubenv).set_rollback_trigger().unwrap();
 
         // Both should coexist
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(3));
-        assert!(get_rollback_trigger_at(&grubenv).unwrap());
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(3));
+        assert!(backend(&grubenv).rollback_trigger().unwrap());
 
         // Unset rollback trigger, boot_counter should remain
-        unset_rollback_trigger_at(&grubenv).unwrap();
-        assert_eq!(get_boot_counter_at(&grubenv).unwrap(), Some(3));
-        assert!(!get_rollback_trigger_at(&grubenv).unwrap());
+        backend(&grubenv).unset_rollback_trigger().unwrap();
+        assert_eq!(backend(&grubenv).boot_counter().unwrap(), Some(3));
+        assert!(!backend(&grubenv).rollback_trigger().unwrap());
     }
 }
@@ -1,19 +1,23 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
-use anyhow::{Context, Result, anyhow, bail};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
 use serde_json::Value;
-use std::path::Path;
+use std::cmp::Ordering;
 use std::process::Command;
 use std::str;
 
-use crate::grub::get_boot_counter;
+use crate::abroot::{AbRoot, RollbackHandler};
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
 
 /// Detects if the system is managed by bootc or is a rpm-ostree system.
 /// First checks for `/run/ostree-booted`, then inspects `status.booted.image`
 /// from `bootc status --booted --json` to distinguish between the two.
-pub fn detect_os_deployment() -> Option<&'static str> {
-    if !Path::new("/run/ostree-booted").exists() {
-        log::info!("'/run/ostree-booted' not found, not an ostree-based system");
+/// Older installs without a working bootc are classic rpm-ostree systems.
+pub fn detect_os_deployment(paths: &Paths) -> Option<&'static str> {
+    if !paths.ostree_booted.exists() {
+        tracing::info!("'/run/ostree-booted' not found, not an ostree-based system");
         return None;
     }
 
@@ -21,20 +25,32 @@ pub fn detect_os_deployment() -> Option<&'static str> {
         .args(["status", "--booted", "--json"])
         .output()
     {
-        Ok(output) => output,
-        Err(_) => return None,
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            tracing::warn!(
+                "'bootc status --booted --json' exited with {}, falling back to rpm-ostree",
+                output.status
+            );
+            return Some("rpm-ostree");
+        }
+        Err(e) => {
+            tracing::info!("bootc not available ({e}), falling back to rpm-ostree");
+            return Some("rpm-ostree");
+        }
     };
+    Some(deployment_manager(&output.stdout))
+}
 
-    if !output.status.success() {
-        log::error!("'bootc status --booted --json' exited with non-zero status");
-        return None;
-    }
-
-    let json: Value = match serde_json::from_slice::<Value>(&output.stdout) {
+/// bootc when `bootc status --booted --json` shows a booted image,
+/// rpm-ostree otherwise
+fn deployment_manager(bootc_status: &[u8]) -> &'static str {
+    let json: Value = match serde_json::from_slice::<Value>(bootc_status) {
         Ok(json) => json,
         Err(_) => {
-            log::error!("Failed to parse JSON from 'bootc status --booted --json'");
-            return None;
+            tracing::error!(
+                "Failed to parse JSON from 'bootc status --booted --json', falling back to rpm-ostree"
+            );
+            return "rpm-ostree";
         }
     };
 
@@ -44,30 +60,155 @@ pub fn detect_os_deployment() -> Option<&'static str> {
         .and_then(|b| b.get("image"))
         .filter(|v| !v.is_null())
     {
-        log::info!("System detected as bootc (status.booted.image: {image_type})");
-        Some("bootc")
+        tracing::info!("System detected as bootc (status.booted.image: {image_type})");
+        "bootc"
     } else {
-        log::info!("System detected as rpm-ostree (status.booted.image is null or absent)");
-        Some("rpm-ostree")
+        tracing::info!("System detected as rpm-ostree (status.booted.image is null or absent)");
+        "rpm-ostree"
+    }
+}
+
+/// Checks that a rollback has somewhere to go before greenboot starts
+/// rebooting for it. The A/B backends know their other image, the dual-root
+/// handlers always have one, and bootc or rpm-ostree are asked for a
+/// deployment other than the booted one.
+pub fn has_rollback_target(
+    paths: &Paths,
+    backend: &dyn BootloaderBackend,
+    policy: &RollbackPolicy,
+) -> Result<bool> {
+    if let Some(target) = backend.has_rollback_target()? {
+        return Ok(target);
+    }
+    if policy.ab_root.handler != RollbackHandler::Deployment {
+        return Ok(true);
+    }
+    let Some(deployment_cmd) = detect_os_deployment(paths) else {
+        return Ok(false);
+    };
+    if let Some(floor) = &policy.min_version {
+        return Ok(rollback_deployment(&list_deployments()?)
+            .is_some_and(|deployment| check_version_floor(deployment, floor).is_ok()));
     }
+    let output = Command::new(deployment_cmd)
+        .args(["status", "--json"])
+        .output()
+        .with_context(|| format!("Failed to execute '{deployment_cmd} status --json'"))?;
+    if !output.status.success() {
+        bail!(
+            "'{deployment_cmd} status --json' failed with status: {}",
+            output.status
+        );
+    }
+    let status: Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Invalid JSON from '{deployment_cmd} status --json'"))?;
+    Ok(match deployment_cmd {
+        "bootc" => bootc_has_rollback(&status),
+        _ => rpm_ostree_has_rollback(&status),
+    })
+}
+
+/// Describes what `rollback_now` would do, without doing it, or why it
+/// would fail: the checks of [`has_rollback_target`] are the preconditions.
+pub fn describe_rollback(
+    paths: &Paths,
+    backend: &dyn BootloaderBackend,
+    policy: &RollbackPolicy,
+) -> Result<String> {
+    if !has_rollback_target(paths, backend, policy)? {
+        bail!("there is no deployment to roll back to");
+    }
+    if backend.has_rollback_target()?.is_some() {
+        return Ok(format!("switch to the other image in {}", backend.store()));
+    }
+    if policy.ab_root.handler != RollbackHandler::Deployment {
+        return Ok(format!(
+            "switch to the other root with the {} handler",
+            policy.ab_root.handler
+        ));
+    }
+    match detect_os_deployment(paths) {
+        Some(deployment_cmd) => Ok(format!("run '{deployment_cmd} rollback'")),
+        None => bail!("Rollback only supported in bootc or rpm-ostree environment."),
+    }
+}
+
+/// bootc reports the deployment `bootc rollback` switches to as status.rollback
+fn bootc_has_rollback(status: &Value) -> bool {
+    status
+        .get("status")
+        .and_then(|s| s.get("rollback"))
+        .is_some_and(|rollback| !rollback.is_null())
+}
+
+/// rpm-ostree rolls back to a deployment other than the booted one, a
+/// staged deployment is the pending update rather than a rollback target
+fn rpm_ostree_has_rollback(status: &Value) -> bool {
+    let flag = |deployment: &Value, key| deployment.get(key).and_then(Value::as_bool);
+    status
+        .get("deployments")
+        .and_then(Value::as_array)
+        .is_some_and(|deployments| {
+            deployments.iter().any(|deployment| {
+                flag(deployment, "booted") == Some(false)
+                    && flag(deployment, "staged") != Some(true)
+            })
+        })
 }
 
 /// reboots the system if boot_counter is greater than 0 or can be forced too
-pub fn handle_reboot(force: bool) -> Result<()> {
+pub fn handle_reboot(backend: &dyn BootloaderBackend, force: bool) -> Result<()> {
     if !force {
-        let boot_counter = get_boot_counter()?;
+        let boot_counter = backend.boot_counter()?;
         if boot_counter <= Some(0) {
             bail!("countdown ended, check greenboot-rollback status")
         };
     }
-    log::info!("restarting the system");
+    reboot()
+}
+
+/// reboots the system right away, whatever the boot counter says
+pub fn reboot() -> Result<()> {
+    tracing::info!("restarting the system");
     Command::new("systemctl").arg("reboot").status()?;
     Ok(())
 }
 
-/// Rollback to the previous deployment if the boot counter allows.
-pub fn handle_rollback() -> Result<()> {
-    let boot_counter = get_boot_counter()?;
+/// broadcasts a message to every logged in terminal and the console
+pub fn handle_wall(message: &str) -> Result<()> {
+    let status = Command::new("wall").arg(message).status()?;
+    if !status.success() {
+        bail!("wall exited with {status}");
+    }
+    Ok(())
+}
+
+/// powers the system off instead of rebooting it
+pub fn handle_poweroff() -> Result<()> {
+    tracing::info!("powering off the system");
+    Command::new("systemctl").arg("poweroff").status()?;
+    Ok(())
+}
+
+/// What a rollback may do, from the config.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RollbackPolicy {
+    /// switch of image based dual-root systems
+    pub ab_root: AbRoot,
+    /// ostree deployments below this version are never rolled back to, e.g.
+    /// images with known security issues
+    pub min_version: Option<String>,
+}
+
+/// Rollback to the previous deployment if the boot counter allows. A/B
+/// backends and the configured dual-root handler switch images themselves,
+/// bootc or rpm-ostree are used otherwise.
+pub fn handle_rollback(
+    paths: &Paths,
+    backend: &dyn BootloaderBackend,
+    policy: &RollbackPolicy,
+) -> Result<()> {
+    let boot_counter = backend.boot_counter()?;
 
     match boot_counter {
         // Exit early if boot_counter is not set
@@ -76,33 +217,321 @@ pub fn handle_rollback() -> Result<()> {
         }
         // Proceed with rollback if boot_counter is <= 0
         Some(counter) if counter <= 0 => {
-            log::info!("Greenboot will now attempt to rollback to a previous deployment.");
-            if let Some(deployment_cmd) = detect_os_deployment() {
-                log::info!("Deployment manager '{deployment_cmd}' detected, attempting rollback.");
-                let status = Command::new(deployment_cmd)
-                    .arg("rollback")
-                    .status()
-                    .context(format!("Failed to execute '{deployment_cmd} rollback'"))?;
-
-                if !status.success() {
-                    bail!(
-                        "Rollback with '{}' failed with status: {}",
-                        deployment_cmd,
-                        status
-                    );
-                }
-            } else {
-                bail!("Rollback only supported in bootc or rpm-ostree environment.");
-            }
-            Ok(())
+            tracing::info!("Greenboot will now attempt to rollback to a previous deployment.");
+            rollback_now(paths, backend, policy)
         }
         // Reject if boot_counter is > 0
         Some(counter) => bail!("Rollback not initiated as boot_counter is {}", counter),
     }
 }
 
-/// writes greenboot status to motd.d/boot-status
-pub fn handle_motd(state: &str) -> Result<()> {
-    std::fs::write("/etc/motd.d/boot-status", format!("{state}.").as_bytes())
-        .map_err(|err| anyhow!("Error writing motd: {}", err))
+/// Switches to the previous deployment or image whatever the boot counter
+/// says, the next boot starts it.
+pub fn rollback_now(
+    paths: &Paths,
+    backend: &dyn BootloaderBackend,
+    policy: &RollbackPolicy,
+) -> Result<()> {
+    if backend.rollback()? {
+        tracing::info!("Rollback done by the bootloader backend.");
+        return Ok(());
+    }
+    if policy.ab_root.rollback(paths, backend)? {
+        tracing::info!("Rollback done by the {} handler.", policy.ab_root.handler);
+        return Ok(());
+    }
+    if let Some(deployment_cmd) = detect_os_deployment(paths) {
+        if let Some(floor) = &policy.min_version {
+            let deployments = list_deployments()?;
+            let target = rollback_deployment(&deployments)
+                .context("there is no deployment to roll back to")?;
+            check_version_floor(target, floor)?;
+        }
+        tracing::info!("Deployment manager '{deployment_cmd}' detected, attempting rollback.");
+        let status = Command::new(deployment_cmd)
+            .arg("rollback")
+            .status()
+            .context(format!("Failed to execute '{deployment_cmd} rollback'"))?;
+
+        if !status.success() {
+            bail!(
+                "Rollback with '{}' failed with status: {}",
+                deployment_cmd,
+                status
+            );
+        }
+    } else {
+        bail!("Rollback only supported in bootc or rpm-ostree environment.");
+    }
+    Ok(())
+}
+
+/// ostree deployment as listed by `rpm-ostree status --json`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Deployment {
+    /// `<osname>-<checksum>.<serial>`
+    pub id: String,
+    pub checksum: String,
+    pub serial: u32,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub booted: bool,
+    /// staged by an update, deployed at shutdown
+    #[serde(default)]
+    pub staged: bool,
+    /// kept whatever the updates to come
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+/// the deployments of the system, in boot menu order, the default first
+pub fn list_deployments() -> Result<Vec<Deployment>> {
+    let output = Command::new("rpm-ostree")
+        .args(["status", "--json"])
+        .output()
+        .context("Failed to execute 'rpm-ostree status --json'")?;
+    if !output.status.success() {
+        bail!(
+            "'rpm-ostree status --json' failed with status: {}",
+            output.status
+        );
+    }
+    parse_deployments(&output.stdout)
+}
+
+fn parse_deployments(status: &[u8]) -> Result<Vec<Deployment>> {
+    #[derive(Deserialize)]
+    struct Status {
+        deployments: Vec<Deployment>,
+    }
+    let status: Status =
+        serde_json::from_slice(status).context("Invalid JSON from 'rpm-ostree status --json'")?;
+    Ok(status.deployments)
+}
+
+/// the deployment `rpm-ostree rollback` and `bootc rollback` switch to
+fn rollback_deployment(deployments: &[Deployment]) -> Option<&Deployment> {
+    deployments
+        .iter()
+        .find(|deployment| !deployment.booted && !deployment.staged)
+}
+
+/// refuses a deployment whose version is below `floor`, or unknown
+pub fn check_version_floor(deployment: &Deployment, floor: &str) -> Result<()> {
+    match deployment.version.as_deref() {
+        Some(version) if compare_versions(version, floor).is_lt() => bail!(
+            "{} has version {version}, below GREENBOOT_ROLLBACK_MIN_VERSION {floor}, refusing to roll back to it",
+            deployment.id
+        ),
+        Some(_) => Ok(()),
+        None => bail!(
+            "{} has no version to compare with GREENBOOT_ROLLBACK_MIN_VERSION {floor}, refusing to roll back to it",
+            deployment.id
+        ),
+    }
+}
+
+/// compares ostree versions such as `41.20250101.0` segment by segment,
+/// numbers numerically and anything else as text
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let segments = |version: &str| -> Vec<String> {
+        version
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|segment| !segment.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let (a, b) = (segments(a), segments(b));
+    for (a, b) in a.iter().zip(&b) {
+        let order = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if order.is_ne() {
+            return order;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+/// Finds the deployment `target` names: its index in the deployment list, as
+/// `ostree admin status` numbers them, its id, `<checksum>.<serial>` like the
+/// `ostree=` kernel argument, or a unique prefix of its checksum.
+pub fn find_deployment<'a>(
+    deployments: &'a [Deployment],
+    target: &str,
+) -> Result<(usize, &'a Deployment)> {
+    if let Ok(index) = target.parse::<usize>()
+        && let Some(deployment) = deployments.get(index)
+    {
+        return Ok((index, deployment));
+    }
+    let exact = deployments.iter().position(|deployment| {
+        deployment.id == target
+            || format!("{}.{}", deployment.checksum, deployment.serial) == target
+    });
+    if let Some(index) = exact {
+        return Ok((index, &deployments[index]));
+    }
+    let mut prefixed = deployments
+        .iter()
+        .enumerate()
+        .filter(|(_, deployment)| deployment.checksum.starts_with(target));
+    match (prefixed.next(), prefixed.next()) {
+        (Some(found), None) => Ok(found),
+        (Some(_), Some(_)) => bail!("'{target}' matches several deployments"),
+        (None, _) => bail!("no deployment matches '{target}'"),
+    }
+}
+
+/// Makes the deployment `target` names the default one, for rolling back
+/// past a previous deployment that is known to be bad too.
+pub fn rollback_to(target: &str, policy: &RollbackPolicy) -> Result<Deployment> {
+    let deployments = list_deployments()?;
+    let (index, deployment) = select_rollback_to(&deployments, target, policy)?;
+    tracing::info!("Making deployment {index} ({}) the default", deployment.id);
+    let status = Command::new("ostree")
+        .args(["admin", "set-default", &index.to_string()])
+        .status()
+        .context("Failed to execute 'ostree admin set-default'")?;
+    if !status.success() {
+        bail!("Rollback to {} failed with status: {status}", deployment.id);
+    }
+    Ok(deployment.clone())
+}
+
+/// the deployment `rollback_to` would make the default, with its index
+pub fn select_rollback_to<'a>(
+    deployments: &'a [Deployment],
+    target: &str,
+    policy: &RollbackPolicy,
+) -> Result<(usize, &'a Deployment)> {
+    let (index, deployment) = find_deployment(deployments, target)?;
+    if deployment.booted {
+        bail!("{} is the booted deployment", deployment.id);
+    }
+    if let Some(floor) = &policy.min_version {
+        check_version_floor(deployment, floor)?;
+    }
+    Ok((index, deployment))
+}
+
+/// Pins the booted deployment so that no update ever garbage collects it,
+/// keeping a known-good fallback around.
+pub fn pin_booted_deployment() -> Result<Deployment> {
+    let deployments = list_deployments()?;
+    let (index, deployment) = deployments
+        .iter()
+        .enumerate()
+        .find(|(_, deployment)| deployment.booted)
+        .context("no booted deployment")?;
+    if deployment.pinned {
+        tracing::info!("{} is already pinned", deployment.id);
+        return Ok(deployment.clone());
+    }
+    let status = Command::new("ostree")
+        .args(["admin", "pin", &index.to_string()])
+        .status()
+        .context("Failed to execute 'ostree admin pin'")?;
+    if !status.success() {
+        bail!("Pinning {} failed with status: {status}", deployment.id);
+    }
+    Ok(deployment.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_deployment_manager() {
+        let bootc =
+            br#"{"status":{"booted":{"image":{"image":"quay.io/fedora/fedora-bootc:42"}}}}"#;
+        assert_eq!(deployment_manager(bootc), "bootc");
+        assert_eq!(
+            deployment_manager(br#"{"status":{"booted":{"image":null}}}"#),
+            "rpm-ostree"
+        );
+        assert_eq!(deployment_manager(b"not json"), "rpm-ostree");
+    }
+
+    #[test]
+    fn test_find_deployment() {
+        let deployments = parse_deployments(
+            br#"{"deployments":[
+                {"id":"fedora-iot-3e5f12.0","checksum":"3e5f12","serial":0,"booted":true},
+                {"id":"fedora-iot-9a7b34.0","checksum":"9a7b34","serial":0},
+                {"id":"fedora-iot-9a1c56.1","checksum":"9a1c56","serial":1,"version":"41.1"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(find_deployment(&deployments, "2").unwrap().0, 2);
+        assert_eq!(
+            find_deployment(&deployments, "fedora-iot-9a7b34.0")
+                .unwrap()
+                .0,
+            1
+        );
+        assert_eq!(find_deployment(&deployments, "9a1c56.1").unwrap().0, 2);
+        assert_eq!(find_deployment(&deployments, "9a7").unwrap().0, 1);
+        assert!(find_deployment(&deployments, "9a").is_err());
+        assert!(find_deployment(&deployments, "ffff").is_err());
+
+        let policy = RollbackPolicy {
+            min_version: Some("41".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            select_rollback_to(&deployments, "9a1c56", &policy)
+                .unwrap()
+                .0,
+            2
+        );
+        // booted, then without a version to hold against the floor
+        assert!(select_rollback_to(&deployments, "0", &policy).is_err());
+        assert!(select_rollback_to(&deployments, "1", &policy).is_err());
+    }
+
+    #[test]
+    fn test_version_floor() {
+        assert!(compare_versions("41.20250101.0", "41.20241215.1").is_gt());
+        assert!(compare_versions("40.20250101.0", "41").is_lt());
+        assert!(compare_versions("41.1", "41.1.1").is_lt());
+        assert!(compare_versions("41.rc2", "41.rc10").is_gt());
+
+        let deployments = parse_deployments(
+            br#"{"deployments":[
+                {"id":"fedora-iot-9a7b34.0","checksum":"9a7b34","serial":0,"staged":true,"version":"42.1"},
+                {"id":"fedora-iot-3e5f12.0","checksum":"3e5f12","serial":0,"booted":true,"version":"41.2"},
+                {"id":"fedora-iot-9a1c56.1","checksum":"9a1c56","serial":1,"version":"41.1"}
+            ]}"#,
+        )
+        .unwrap();
+        let target = rollback_deployment(&deployments).unwrap();
+        assert_eq!(target.checksum, "9a1c56");
+        assert!(check_version_floor(target, "41.1").is_ok());
+        assert!(check_version_floor(target, "41.2").is_err());
+        assert!(check_version_floor(&deployments[1], "1").is_ok());
+    }
+
+    #[test]
+    fn test_rollback_target_queries() {
+        let bootc: Value =
+            serde_json::from_str(r#"{"status":{"booted":{},"rollback":{"image":{}}}}"#).unwrap();
+        assert!(bootc_has_rollback(&bootc));
+        let bootc: Value = serde_json::from_str(r#"{"status":{"rollback":null}}"#).unwrap();
+        assert!(!bootc_has_rollback(&bootc));
+
+        let ostree: Value =
+            serde_json::from_str(r#"{"deployments":[{"booted":true},{"booted":false}]}"#).unwrap();
+        assert!(rpm_ostree_has_rollback(&ostree));
+        let ostree: Value = serde_json::from_str(r#"{"deployments":[{"booted":true}]}"#).unwrap();
+        assert!(!rpm_ostree_has_rollback(&ostree));
+        let ostree: Value = serde_json::from_str(
+            r#"{"deployments":[{"booted":false,"staged":true},{"booted":true,"staged":false}]}"#,
+        )
+        .unwrap();
+        assert!(!rpm_ostree_has_rollback(&ostree));
+    }
 }
@@ -0,0 +1,763 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::greenboot::FailedCheck;
+use crate::paths::Paths;
+use crate::status::{HealthStatus, StatusRecord};
+
+const SECS_PER_DAY: u64 = 86400;
+/// the history is trimmed to HISTORY_TRIM_SIZE once it grows past this, the
+/// oldest verdicts are dropped first
+const HISTORY_SIZE_LIMIT: u64 = 1024 * 1024;
+const HISTORY_TRIM_SIZE: usize = 768 * 1024;
+/// bytes read from the end of the history for its last verdict, doubled
+/// until a whole one is found
+const HISTORY_TAIL_SIZE: u64 = 4096;
+/// random id of the running boot, see random(4)
+static BOOT_ID_PATH: &str = "/proc/sys/kernel/random/boot_id";
+
+/// one health-check verdict, a line of the history file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// seconds since the epoch when the verdict was reached
+    pub timestamp: u64,
+    pub status: HealthStatus,
+    pub failed_checks: Vec<String>,
+    /// booted deployment, none outside of rpm-ostree/bootc systems
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    /// the boot followed a rollback
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub after_rollback: bool,
+    /// boot the verdict was reached in, as the _BOOT_ID of the journal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot_id: Option<String>,
+    /// how long the health-check took
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    /// time from boot to the verdict, for GREEN and DEGRADED ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_to_green_ms: Option<u64>,
+}
+
+/// id of the running boot in the format of the journal, without dashes
+pub fn current_boot_id() -> Option<String> {
+    let boot_id = fs::read_to_string(BOOT_ID_PATH).ok()?;
+    Some(boot_id.trim().replace('-', ""))
+}
+
+impl HistoryEntry {
+    pub fn new(record: &StatusRecord, deployment: Option<String>) -> Self {
+        Self {
+            timestamp: record.timestamp,
+            status: record.status,
+            failed_checks: record.failed_checks.clone(),
+            deployment,
+            after_rollback: false,
+            boot_id: current_boot_id(),
+            duration_ms: None,
+            time_to_green_ms: None,
+        }
+    }
+
+    pub fn with_after_rollback(mut self, after_rollback: bool) -> Self {
+        self.after_rollback = after_rollback;
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration_ms = Some(duration.as_millis() as u64);
+        self
+    }
+
+    pub fn with_time_to_green(mut self, time_to_green: Option<Duration>) -> Self {
+        self.time_to_green_ms = time_to_green.map(|elapsed| elapsed.as_millis() as u64);
+        self
+    }
+}
+
+/// appends a verdict to the history file, which unlike the status file
+/// survives reboots and journal rotation, trimming it once it reaches
+/// HISTORY_SIZE_LIMIT
+pub fn append_history(paths: &Paths, entry: &HistoryEntry) -> Result<()> {
+    let path = &paths.history;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Unable to open {}", path.display()))?;
+    // a line torn by a power cut is terminated so it does not swallow this one
+    let mut last = [b'\n'];
+    if file.seek(SeekFrom::End(-1)).is_ok() {
+        file.read_exact(&mut last)?;
+    }
+    let line = serde_json::to_string(entry)?;
+    let line = if last[0] == b'\n' {
+        format!("{line}\n")
+    } else {
+        format!("\n{line}\n")
+    };
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    if file.metadata()?.len() > HISTORY_SIZE_LIMIT {
+        trim_history(path)?;
+    }
+    Ok(())
+}
+
+/// keeps the newest lines within HISTORY_TRIM_SIZE, rewriting the file
+/// through a rename so a reader never sees half of it
+fn trim_history(path: &Path) -> Result<()> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let mut kept = 0;
+    let start = content
+        .lines()
+        .rev()
+        .take_while(|line| {
+            kept += line.len() + 1;
+            kept <= HISTORY_TRIM_SIZE
+        })
+        .count();
+    let lines: Vec<&str> = content.lines().collect();
+    let dropped = lines.len() - start;
+    tracing::info!("history is full, dropping its {dropped} oldest verdict(s)");
+    let trimmed: String = lines[dropped..]
+        .iter()
+        .map(|line| format!("{line}\n"))
+        .collect();
+    let staged = path.with_extension("new");
+    fs::write(&staged, trimmed)
+        .and_then(|_| fs::rename(&staged, path))
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// Details of the last RED boot, overwritten by the next one. They outlive
+/// the failed boot and the journal of it, for the triage after a rollback.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LastFailure {
+    /// seconds since the epoch when the boot was declared RED
+    pub timestamp: u64,
+    /// booted deployment, none outside of rpm-ostree/bootc systems
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    /// commit checksum of the booted deployment
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    pub checks: Vec<FailedCheck>,
+}
+
+pub fn write_last_failure(paths: &Paths, failure: &LastFailure) -> Result<()> {
+    let path = &paths.last_failure;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    fs::write(path, serde_json::to_string_pretty(failure)?)
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// the last RED boot, none if there was none yet
+pub fn read_last_failure(paths: &Paths) -> Result<Option<LastFailure>> {
+    let path = &paths.last_failure;
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map(Some)
+            .with_context(|| format!("Invalid {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Unable to read {}", path.display())),
+    }
+}
+
+/// true if `deployment` passed a health-check before, the current boot is
+/// then not the first one of a newly deployed image
+pub fn passed_before(entries: &[HistoryEntry], deployment: &str) -> bool {
+    entries.iter().any(|entry| {
+        entry.deployment.as_deref() == Some(deployment)
+            && matches!(entry.status, HealthStatus::Green | HealthStatus::Degraded)
+    })
+}
+
+/// the verdicts of the last `boots` boots among `entries`, oldest first; the
+/// verdicts without a boot id count as a boot each
+pub fn last_boots(entries: &[HistoryEntry], boots: usize) -> &[HistoryEntry] {
+    let mut seen = 0;
+    let mut start = entries.len();
+    while start > 0 {
+        let entry = &entries[start - 1];
+        let same_boot = entry.boot_id.is_some()
+            && entries
+                .get(start)
+                .is_some_and(|next| next.boot_id == entry.boot_id);
+        if !same_boot {
+            if seen == boots {
+                break;
+            }
+            seen += 1;
+        }
+        start -= 1;
+    }
+    &entries[start..]
+}
+
+/// every verdict of the history file, oldest first
+pub fn read_history(paths: &Paths) -> Result<Vec<HistoryEntry>> {
+    let path = &paths.history;
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+    };
+    // a line torn by a power cut must not hide the rest of the history
+    Ok(content
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// newest readable verdict of the history, reading only the end of the file
+pub fn read_last_history_entry(paths: &Paths) -> Result<Option<HistoryEntry>> {
+    let path = &paths.history;
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Unable to open {}", path.display())),
+    };
+    let len = file.metadata()?.len();
+    let mut tail_size = HISTORY_TAIL_SIZE;
+    loop {
+        let start = len.saturating_sub(tail_size);
+        let mut tail = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        file.read_to_end(&mut tail)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+        let mut lines: Vec<&[u8]> = tail.split(|byte| *byte == b'\n').collect();
+        // the first line is cut short unless the tail is the whole file
+        if start > 0 {
+            lines.remove(0);
+        }
+        // a line torn by a power cut is skipped as in read_history
+        if let Some(entry) = lines
+            .iter()
+            .rev()
+            .find_map(|line| serde_json::from_slice(line).ok())
+        {
+            return Ok(Some(entry));
+        }
+        if start == 0 {
+            return Ok(None);
+        }
+        tail_size *= 2;
+    }
+}
+
+/// file format of `greenboot history export`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryFormat {
+    #[default]
+    Csv,
+    /// only available when built with the `parquet` feature
+    Parquet,
+}
+
+impl fmt::Display for HistoryFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HistoryFormat::Csv => write!(f, "csv"),
+            HistoryFormat::Parquet => write!(f, "parquet"),
+        }
+    }
+}
+
+impl FromStr for HistoryFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "csv" => Ok(HistoryFormat::Csv),
+            "parquet" => Ok(HistoryFormat::Parquet),
+            other => bail!("unknown history format '{other}'"),
+        }
+    }
+}
+
+/// serializes `entries` in `format`: one row per verdict with the timestamp,
+/// the status, the failed checks separated by spaces and the deployment
+pub fn export_history(entries: &[HistoryEntry], format: HistoryFormat) -> Result<Vec<u8>> {
+    match format {
+        HistoryFormat::Csv => Ok(history_csv(entries).into_bytes()),
+        #[cfg(feature = "parquet")]
+        HistoryFormat::Parquet => history_parquet(entries),
+        #[cfg(not(feature = "parquet"))]
+        HistoryFormat::Parquet => bail!("greenboot was built without parquet support"),
+    }
+}
+
+fn history_csv(entries: &[HistoryEntry]) -> String {
+    let mut csv = String::from("timestamp,time,status,failed_checks,deployment\n");
+    for entry in entries {
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            entry.timestamp,
+            format_utc(entry.timestamp),
+            entry.status,
+            csv_field(&entry.failed_checks.join(" ")),
+            csv_field(entry.deployment.as_deref().unwrap_or_default())
+        ));
+    }
+    csv
+}
+
+/// One line per verdict of `entries`, oldest first: the time, the status,
+/// how long the health-check took, the boot, the deployment and the failed
+/// checks, marking the boots that followed a rollback, then how many verdicts
+/// of each status there were, so the pattern of a flapping device shows at a
+/// glance
+pub fn history_table(entries: &[HistoryEntry]) -> String {
+    let mut table = String::new();
+    for entry in entries {
+        let duration = match entry.duration_ms {
+            Some(ms) => format!("{:.1}s", ms as f64 / 1000.0),
+            None => "-".to_string(),
+        };
+        let boot_id = entry.boot_id.as_deref().unwrap_or("-");
+        let mut line = format!(
+            "{}  {:<8}  {duration:>7}  {:<8}  {}",
+            format_utc(entry.timestamp),
+            entry.status.to_string(),
+            &boot_id[..boot_id.len().min(8)],
+            entry.deployment.as_deref().unwrap_or("-")
+        );
+        if entry.after_rollback {
+            line.push_str("  after rollback");
+        }
+        if !entry.failed_checks.is_empty() {
+            line.push_str(&format!("  failed: {}", entry.failed_checks.join(" ")));
+        }
+        table.push_str(&line);
+        table.push('\n');
+    }
+    let count = |status| {
+        entries
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    };
+    let rollbacks = entries.iter().filter(|entry| entry.after_rollback).count();
+    let boots = entries.len()
+        - entries
+            .windows(2)
+            .filter(|pair| pair[1].boot_id.is_some() && pair[1].boot_id == pair[0].boot_id)
+            .count();
+    table.push_str(&format!(
+        "{} verdicts over {boots} boots: {} GREEN, {} DEGRADED, {} RED, {} ERROR, {rollbacks} after a rollback\n",
+        entries.len(),
+        count(HealthStatus::Green),
+        count(HealthStatus::Degraded),
+        count(HealthStatus::Red),
+        count(HealthStatus::Error),
+    ));
+    table
+}
+
+/// quotes a field holding a separator, a quote or a line break, RFC 4180
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn history_parquet(entries: &[HistoryEntry]) -> Result<Vec<u8>> {
+    use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+    use parquet::file::properties::WriterProperties;
+    use parquet::file::writer::SerializedFileWriter;
+    use parquet::schema::parser::parse_message_type;
+    use std::sync::Arc;
+
+    let schema = parse_message_type(
+        "message greenboot_history {
+            REQUIRED INT64 timestamp (TIMESTAMP(MILLIS, true));
+            REQUIRED BYTE_ARRAY status (UTF8);
+            REQUIRED BYTE_ARRAY failed_checks (UTF8);
+            OPTIONAL BYTE_ARRAY deployment (UTF8);
+        }",
+    )?;
+    let timestamps: Vec<i64> = entries
+        .iter()
+        .map(|entry| entry.timestamp as i64 * 1000)
+        .collect();
+    let statuses: Vec<ByteArray> = entries
+        .iter()
+        .map(|entry| entry.status.to_string().as_str().into())
+        .collect();
+    let failed: Vec<ByteArray> = entries
+        .iter()
+        .map(|entry| entry.failed_checks.join(" ").as_str().into())
+        .collect();
+    let deployments: Vec<ByteArray> = entries
+        .iter()
+        .filter_map(|entry| entry.deployment.as_deref().map(ByteArray::from))
+        .collect();
+    let deployment_levels: Vec<i16> = entries
+        .iter()
+        .map(|entry| i16::from(entry.deployment.is_some()))
+        .collect();
+
+    let mut buf = Vec::new();
+    let mut writer = SerializedFileWriter::new(
+        &mut buf,
+        Arc::new(schema),
+        Arc::new(WriterProperties::builder().build()),
+    )?;
+    let mut row_group = writer.next_row_group()?;
+    let mut column = 0;
+    while let Some(mut writer) = row_group.next_column()? {
+        match column {
+            0 => {
+                writer
+                    .typed::<Int64Type>()
+                    .write_batch(&timestamps, None, None)?;
+            }
+            1 | 2 => {
+                let values = if column == 1 { &statuses } else { &failed };
+                writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, None, None)?;
+            }
+            _ => {
+                writer.typed::<ByteArrayType>().write_batch(
+                    &deployments,
+                    Some(&deployment_levels),
+                    None,
+                )?;
+            }
+        }
+        writer.close()?;
+        column += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(buf)
+}
+
+/// verdicts reached at or after `since`
+pub fn history_since(entries: Vec<HistoryEntry>, since: u64) -> Vec<HistoryEntry> {
+    entries
+        .into_iter()
+        .filter(|entry| entry.timestamp >= since)
+        .collect()
+}
+
+/// Parses the `--since` value relative to `now`: seconds since the epoch, a
+/// duration ago such as `90m`, `12h`, `7d` or `2w`, or a UTC date
+/// `YYYY-MM-DD` optionally followed by `THH:MM:SS`.
+pub fn parse_since(value: &str, now: u64) -> Result<u64> {
+    let value = value.trim();
+    if let Ok(timestamp) = value.parse() {
+        return Ok(timestamp);
+    }
+    let unit = match value.chars().last() {
+        Some('s') => Some(1),
+        Some('m') => Some(60),
+        Some('h') => Some(3600),
+        Some('d') => Some(SECS_PER_DAY),
+        Some('w') => Some(7 * SECS_PER_DAY),
+        _ => None,
+    };
+    if let Some(unit) = unit
+        && let Ok(count) = value[..value.len() - 1].parse::<u64>()
+    {
+        return Ok(now.saturating_sub(count.saturating_mul(unit)));
+    }
+    parse_utc(value).with_context(|| format!("invalid time '{value}'"))
+}
+
+fn parse_utc(value: &str) -> Option<u64> {
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00"));
+    let time = time.strip_suffix('Z').unwrap_or(time);
+    let date: Vec<u64> = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let time: Vec<u64> = time
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day)?;
+    Some(days * SECS_PER_DAY + hour * 3600 + minute * 60 + second.min(60))
+}
+
+/// days since 1970-01-01 of a proleptic Gregorian date, none before the epoch
+fn days_from_civil(year: u64, month: u64, day: u64) -> Option<u64> {
+    let year = if month <= 2 {
+        year.checked_sub(1)?
+    } else {
+        year
+    };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    (era * 146_097 + day_of_era).checked_sub(719_468)
+}
+
+/// RFC 3339 UTC time of `timestamp`
+pub fn format_utc(timestamp: u64) -> String {
+    let days = timestamp / SECS_PER_DAY + 719_468;
+    let secs = timestamp % SECS_PER_DAY;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkreport::{CheckReport, CheckSeverity};
+
+    fn entry(timestamp: u64, status: HealthStatus, failed: &[&str]) -> HistoryEntry {
+        HistoryEntry {
+            timestamp,
+            status,
+            failed_checks: failed.iter().map(|check| check.to_string()).collect(),
+            deployment: Some("fedora-iot:abc.0".to_string()),
+            after_rollback: false,
+            boot_id: None,
+            duration_ms: None,
+            time_to_green_ms: None,
+        }
+    }
+
+    #[test]
+    fn test_history_export_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        assert!(read_history(&paths).unwrap().is_empty());
+
+        append_history(&paths, &entry(1_700_000_000, HealthStatus::Green, &[])).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&paths.history)
+            .unwrap()
+            .write_all(b"{\"timest")
+            .unwrap();
+        append_history(
+            &paths,
+            &entry(1_700_086_400, HealthStatus::Red, &["a.sh", "b,c.sh"]),
+        )
+        .unwrap();
+
+        let entries = history_since(read_history(&paths).unwrap(), 1_700_000_001);
+        assert_eq!(
+            String::from_utf8(export_history(&entries, HistoryFormat::Csv).unwrap()).unwrap(),
+            "timestamp,time,status,failed_checks,deployment\n\
+             1700086400,2023-11-15T22:13:20Z,RED,\"a.sh b,c.sh\",fedora-iot:abc.0\n"
+        );
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_history_export_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let mut entries = vec![entry(1_700_000_000, HealthStatus::Degraded, &["a.sh"])];
+        entries.push(HistoryEntry {
+            deployment: None,
+            ..entry(1_700_000_060, HealthStatus::Green, &[])
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.parquet");
+        fs::write(
+            &path,
+            export_history(&entries, HistoryFormat::Parquet).unwrap(),
+        )
+        .unwrap();
+
+        let reader = SerializedFileReader::new(fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        let rows: Vec<String> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        assert!(rows[0].contains("status: \"DEGRADED\""), "{}", rows[0]);
+        assert!(rows[1].contains("deployment: null"), "{}", rows[1]);
+    }
+
+    #[test]
+    fn test_history_table() {
+        let in_boot = |boot_id: &str, entry: HistoryEntry| HistoryEntry {
+            boot_id: Some(boot_id.to_string()),
+            duration_ms: Some(4210),
+            ..entry
+        };
+        let entries = [
+            entry(1_699_990_000, HealthStatus::Green, &[]),
+            in_boot(
+                "5a1f0e8c9b2d4e6f",
+                entry(1_700_000_000, HealthStatus::Red, &["01_dns.sh"]),
+            ),
+            in_boot(
+                "9c0d7e1a2b3c4d5e",
+                entry(1_700_000_300, HealthStatus::Green, &[]),
+            ),
+            in_boot(
+                "9c0d7e1a2b3c4d5e",
+                HistoryEntry {
+                    after_rollback: true,
+                    ..entry(1_700_000_310, HealthStatus::Error, &[])
+                },
+            ),
+        ];
+        assert_eq!(last_boots(&entries, 1), &entries[2..]);
+        assert_eq!(last_boots(&entries, 2), &entries[1..]);
+        assert_eq!(last_boots(&entries, 5), &entries[..]);
+        assert_eq!(
+            history_table(last_boots(&entries, 2)),
+            "2023-11-14T22:13:20Z  RED          4.2s  5a1f0e8c  fedora-iot:abc.0  failed: 01_dns.sh\n\
+             2023-11-14T22:18:20Z  GREEN        4.2s  9c0d7e1a  fedora-iot:abc.0\n\
+             2023-11-14T22:18:30Z  ERROR        4.2s  9c0d7e1a  fedora-iot:abc.0  after rollback\n\
+             3 verdicts over 2 boots: 1 GREEN, 0 DEGRADED, 1 RED, 1 ERROR, 1 after a rollback\n"
+        );
+    }
+
+    #[test]
+    fn test_history_is_trimmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        let failed = ["x".repeat(1000)];
+        let failed: Vec<&str> = failed.iter().map(String::as_str).collect();
+        for timestamp in 0..1100 {
+            append_history(&paths, &entry(timestamp, HealthStatus::Red, &failed)).unwrap();
+        }
+        let size = fs::metadata(&paths.history).unwrap().len();
+        assert!(size <= HISTORY_SIZE_LIMIT, "{size}");
+        let entries = read_history(&paths).unwrap();
+        assert_eq!(entries.last().unwrap().timestamp, 1099);
+        assert!(entries.len() < 1100);
+        let last = read_last_history_entry(&paths).unwrap().unwrap();
+        assert_eq!(last.timestamp, 1099);
+    }
+
+    #[test]
+    fn test_passed_before() {
+        let entry = |status, deployment: &str| HistoryEntry {
+            timestamp: 0,
+            status,
+            failed_checks: vec![],
+            deployment: Some(deployment.to_string()),
+            after_rollback: false,
+            boot_id: None,
+            duration_ms: None,
+            time_to_green_ms: None,
+        };
+        let entries = [
+            entry(HealthStatus::Green, "abc.0"),
+            entry(HealthStatus::Red, "def.0"),
+            entry(HealthStatus::Degraded, "fed.0"),
+        ];
+        assert!(passed_before(&entries, "abc.0"));
+        assert!(passed_before(&entries, "fed.0"));
+        assert!(!passed_before(&entries, "def.0"));
+        assert!(!passed_before(&entries, "123.0"));
+    }
+
+    #[test]
+    fn test_last_history_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        assert_eq!(read_last_history_entry(&paths).unwrap(), None);
+
+        // longer than the first tail read, followed by a torn line
+        let failed = ["x".repeat(2 * HISTORY_TAIL_SIZE as usize)];
+        let failed: Vec<&str> = failed.iter().map(String::as_str).collect();
+        append_history(&paths, &entry(1, HealthStatus::Green, &[])).unwrap();
+        append_history(&paths, &entry(2, HealthStatus::Red, &failed)).unwrap();
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(&paths.history)
+            .unwrap();
+        file.write_all(b"{\"timestamp\":3,").unwrap();
+        let last = read_last_history_entry(&paths).unwrap().unwrap();
+        assert_eq!(last.timestamp, 2);
+        assert_eq!(last, read_history(&paths).unwrap().pop().unwrap());
+    }
+
+    #[test]
+    fn test_last_failure() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        assert_eq!(read_last_failure(&paths).unwrap(), None);
+
+        let failure = LastFailure {
+            timestamp: 1_700_000_000,
+            deployment: Some("/ostree/boot.1/fedora-iot/c0ffee/0".to_string()),
+            checksum: Some("3e5f12".to_string()),
+            checks: vec![FailedCheck {
+                check: "01_dns.sh".to_string(),
+                exit_code: Some(1),
+                output: "no route to host\n".to_string(),
+                started_at: 1_699_999_990,
+                finished_at: 1_699_999_999,
+                report: Some(CheckReport {
+                    message: Some("cannot resolve fedoraproject.org".to_string()),
+                    severity: Some(CheckSeverity::Error),
+                    ..Default::default()
+                }),
+                selinux_context: Some("system_u:system_r:greenboot_check_t:s0".to_string()),
+            }],
+        };
+        write_last_failure(&paths, &failure).unwrap();
+        assert_eq!(read_last_failure(&paths).unwrap(), Some(failure));
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = 1_700_000_000;
+        assert_eq!(parse_since("1600000000", now).unwrap(), 1_600_000_000);
+        assert_eq!(parse_since("2d", now).unwrap(), now - 2 * SECS_PER_DAY);
+        assert_eq!(parse_since("2023-11-14", now).unwrap(), 1_699_920_000);
+        assert_eq!(
+            parse_since("2023-11-14T22:13:20Z", now).unwrap(),
+            1_700_000_000
+        );
+        assert_eq!(format_utc(1_700_000_000), "2023-11-14T22:13:20Z");
+        for invalid in ["yesterday", "2023-13-01", "1969-12-31", "5x"] {
+            assert!(parse_since(invalid, now).is_err(), "{invalid}");
+        }
+    }
+}
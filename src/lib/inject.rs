@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+use std::fmt;
+
+/// env var that has to be set to 1 for `--inject` to be honored, so the
+/// testing mode can't be enabled by accident on a production device
+pub static INJECT_GUARD_ENV: &str = "GREENBOOT_ALLOW_INJECT";
+
+/// outcome forced on a check by the testing mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InjectedOutcome {
+    Fail,
+    Timeout,
+}
+
+impl fmt::Display for InjectedOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InjectedOutcome::Fail => write!(f, "failed"),
+            InjectedOutcome::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+/// checks whose outcome is forced instead of running them, used by QE to
+/// exercise the red and rollback paths on a healthy image
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Injections {
+    fail: HashSet<String>,
+    timeout: HashSet<String>,
+}
+
+impl Injections {
+    /// parses `fail:<check>,timeout:<check>,...`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut injections = Self::default();
+        for item in spec.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            match item.split_once(':') {
+                Some(("fail", check)) if !check.is_empty() => {
+                    injections.fail.insert(check.to_string());
+                }
+                Some(("timeout", check)) if !check.is_empty() => {
+                    injections.timeout.insert(check.to_string());
+                }
+                _ => bail!("invalid injection '{item}', expected fail:<check> or timeout:<check>"),
+            }
+        }
+        Ok(injections)
+    }
+
+    /// forced outcome of the check, none if it has to run normally
+    pub fn outcome(&self, check: &str) -> Option<InjectedOutcome> {
+        if self.fail.contains(check) {
+            Some(InjectedOutcome::Fail)
+        } else if self.timeout.contains(check) {
+            Some(InjectedOutcome::Timeout)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fail.is_empty() && self.timeout.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_injections() {
+        let injections = Injections::parse("fail:01_dns.sh, timeout:02_watchdog.sh").unwrap();
+        assert_eq!(injections.outcome("01_dns.sh"), Some(InjectedOutcome::Fail));
+        assert_eq!(
+            injections.outcome("02_watchdog.sh"),
+            Some(InjectedOutcome::Timeout)
+        );
+        assert_eq!(injections.outcome("03_other.sh"), None);
+    }
+
+    #[test]
+    fn test_parse_invalid_injection() {
+        assert!(Injections::parse("crash:01_dns.sh").is_err());
+        assert!(Injections::parse("fail:").is_err());
+    }
+}
@@ -0,0 +1,641 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use libloading::Library;
+use nix::sys::stat::fstat;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::ffi::{c_char, c_int, c_void};
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::process::Command;
+use std::{ptr, slice};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing::{Level, Subscriber};
+use tracing_subscriber::Layer;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::history::current_boot_id;
+
+/// journald native protocol socket
+static JOURNAL_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+/// identifier greenboot entries are tagged with in the journal
+static SYSLOG_IDENTIFIER: &str = "greenboot";
+
+/// sd-journal(3) is loaded at runtime, images without libsystemd still get
+/// everything but reading the journal
+static LIBSYSTEMD: &str = "libsystemd.so.0";
+/// sd_journal_open(3) flag leaving out the journals of containers
+const SD_JOURNAL_LOCAL_ONLY: c_int = 1;
+
+/// syslog priorities used for structured entries
+pub const PRIORITY_ERR: u8 = 3;
+pub const PRIORITY_WARNING: u8 = 4;
+pub const PRIORITY_INFO: u8 = 6;
+pub const PRIORITY_DEBUG: u8 = 7;
+
+/// true if journald is running, false on containers and minimal images without it
+pub fn is_available() -> bool {
+    Path::new(JOURNAL_SOCKET_PATH).exists()
+}
+
+/// Lifecycle events logged with a stable MESSAGE_ID, explained by
+/// usr/lib/systemd/catalog/greenboot.catalog, and their name as
+/// GREENBOOT_EVENT, so tooling keys off them rather than the wording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    HealthCheckStarted,
+    Green,
+    Degraded,
+    Red,
+    BootCounterSet,
+    RollbackTriggered,
+    RollbackCompleted,
+}
+
+impl Event {
+    pub const ALL: [Event; 7] = [
+        Event::HealthCheckStarted,
+        Event::Green,
+        Event::Degraded,
+        Event::Red,
+        Event::BootCounterSet,
+        Event::RollbackTriggered,
+        Event::RollbackCompleted,
+    ];
+
+    /// 128-bit id of the event, never to be changed once released
+    pub fn message_id(self) -> &'static str {
+        match self {
+            Event::HealthCheckStarted => "ed6f26312c194591819ca0168e1e094f",
+            Event::Green => "25f6780a1ac94594accb62d5ab83b353",
+            Event::Degraded => "1f13ed71ac3c4aed9452c11b6067dac6",
+            Event::Red => "c3939f4145944f0780f8122d938b9ada",
+            Event::BootCounterSet => "291f4bfbf54b4d5da17d85afcaf78235",
+            Event::RollbackTriggered => "02af5181d8924df491b318bf9496e9cc",
+            Event::RollbackCompleted => "d76920e9fd9b44a196488f0b7a53778b",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Event::HealthCheckStarted => "health-check-started",
+            Event::Green => "green",
+            Event::Degraded => "degraded",
+            Event::Red => "red",
+            Event::BootCounterSet => "boot-counter-set",
+            Event::RollbackTriggered => "rollback-triggered",
+            Event::RollbackCompleted => "rollback-completed",
+        }
+    }
+}
+
+/// sends `event` as a structured entry, its MESSAGE_ID and GREENBOOT_EVENT
+/// before `fields`
+pub fn send_event(
+    event: Event,
+    priority: u8,
+    message: &str,
+    fields: &[(&str, &str)],
+) -> io::Result<()> {
+    let mut event_fields = vec![
+        ("MESSAGE_ID", event.message_id()),
+        ("GREENBOOT_EVENT", event.name()),
+    ];
+    event_fields.extend_from_slice(fields);
+    send(priority, message, &event_fields)
+}
+
+/// `dev:ino` of JOURNAL_STREAM, see systemd.exec(5)
+fn parse_journal_stream(value: &str) -> Option<(u64, u64)> {
+    let (dev, ino) = value.split_once(':')?;
+    Some((dev.parse().ok()?, ino.parse().ok()?))
+}
+
+/// true when systemd runs greenboot: stderr is the stream JOURNAL_STREAM
+/// names, or a NOTIFY_SOCKET was passed, and journald is listening
+pub fn under_systemd() -> bool {
+    let stderr_is_journal = std::env::var("JOURNAL_STREAM")
+        .ok()
+        .and_then(|value| parse_journal_stream(&value))
+        .zip(fstat(io::stderr()).ok())
+        .is_some_and(|((dev, ino), stat)| stat.st_dev == dev && stat.st_ino == ino);
+    (stderr_is_journal || std::env::var_os("NOTIFY_SOCKET").is_some()) && is_available()
+}
+
+/// Sends every tracing event as a journal entry of its own with the
+/// priority of its level, the CODE_FILE and CODE_LINE it was logged at and
+/// the fields of the spans it happened in as GREENBOOT_<FIELD>, e.g.
+/// GREENBOOT_CHECK for what is logged while a check runs, instead of
+/// formatted lines on stderr that journald would timestamp a second time
+#[derive(Debug)]
+pub struct JournalLayer {
+    stream: JournalStream,
+}
+
+impl JournalLayer {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            stream: JournalStream::open(SYSLOG_IDENTIFIER, &[])?,
+        })
+    }
+}
+
+fn level_priority(level: Level) -> u8 {
+    match level {
+        Level::ERROR => PRIORITY_ERR,
+        Level::WARN => PRIORITY_WARNING,
+        Level::INFO => PRIORITY_INFO,
+        _ => PRIORITY_DEBUG,
+    }
+}
+
+/// journal fields of a span, kept in its extensions
+struct SpanFields(Vec<(String, String)>);
+
+/// the message and the other fields of an event or a span
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Vec<(String, String)>,
+}
+
+impl FieldVisitor {
+    fn record(&mut self, field: &Field, value: String) {
+        match field.name() {
+            "message" => self.message = value,
+            name => self
+                .fields
+                .push((format!("GREENBOOT_{}", name.to_ascii_uppercase()), value)),
+        }
+    }
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{value:?}"));
+    }
+}
+
+impl<S> Layer<S> for JournalLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let mut visitor = FieldVisitor::default();
+        attrs.record(&mut visitor);
+        span.extensions_mut().insert(SpanFields(visitor.fields));
+    }
+
+    fn on_event(&self, event: &tracing::Event<'_>, ctx: Context<'_, S>) {
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let mut fields = Vec::new();
+        for span in ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+        {
+            if let Some(span_fields) = span.extensions().get::<SpanFields>() {
+                fields.extend(span_fields.0.iter().cloned());
+            }
+        }
+        fields.append(&mut visitor.fields);
+        let metadata = event.metadata();
+        if let Some(file) = metadata.file() {
+            fields.push(("CODE_FILE".to_string(), file.to_string()));
+        }
+        if let Some(line) = metadata.line() {
+            fields.push(("CODE_LINE".to_string(), line.to_string()));
+        }
+        let fields: Vec<(&str, &str)> = fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let priority = level_priority(*metadata.level());
+        if self
+            .stream
+            .send_with(priority, &visitor.message, &fields)
+            .is_err()
+        {
+            eprintln!("{} {}", metadata.level(), visitor.message);
+        }
+    }
+}
+
+/// sends a structured entry to journald, extra fields must use upper case journal field names
+pub fn send(priority: u8, message: &str, fields: &[(&str, &str)]) -> io::Result<()> {
+    let payload = encode(SYSLOG_IDENTIFIER, priority, message, fields);
+    let socket = UnixDatagram::unbound()?;
+    socket.send_to(&payload, JOURNAL_SOCKET_PATH)?;
+    Ok(())
+}
+
+/// Entries sent one after the other over the same socket, all tagged with
+/// `identifier` and the same fields, e.g. the output of a check line by line
+#[derive(Debug)]
+pub struct JournalStream {
+    socket: UnixDatagram,
+    identifier: String,
+    fields: Vec<(String, String)>,
+}
+
+impl JournalStream {
+    pub fn open(identifier: &str, fields: &[(&str, &str)]) -> io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            identifier: identifier.to_string(),
+            fields: fields
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+    }
+
+    pub fn send(&self, priority: u8, message: &str) -> io::Result<()> {
+        self.send_with(priority, message, &[])
+    }
+
+    /// sends an entry with `extra` fields next to those of the stream
+    pub fn send_with(&self, priority: u8, message: &str, extra: &[(&str, &str)]) -> io::Result<()> {
+        let fields: Vec<(&str, &str)> = self
+            .fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .chain(extra.iter().copied())
+            .collect();
+        let payload = encode(&self.identifier, priority, message, &fields);
+        self.socket.send_to(&payload, JOURNAL_SOCKET_PATH)?;
+        Ok(())
+    }
+}
+
+/// asks journald to write every pending entry to disk, so nothing logged so
+/// far is lost when the system restarts right after
+pub fn sync() -> io::Result<()> {
+    let status = Command::new("journalctl").arg("--sync").status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "journalctl --sync failed: {status}"
+        )));
+    }
+    Ok(())
+}
+
+/// an entry as printed by `journalctl -o json`
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct JournalEntry {
+    fields: Map<String, Value>,
+}
+
+impl JournalEntry {
+    /// value of `name`, none if the entry does not have it or it is binary
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name)?.as_str()
+    }
+}
+
+/// the structured entries greenboot logged during the boot `boot`, as
+/// journalctl -b takes it, oldest first
+pub fn read_entries(boot: i32) -> io::Result<Vec<JournalEntry>> {
+    let mut reader = JournalReader::open()?;
+    let Some(boot_id) = reader.boot_id(boot)? else {
+        return Err(io::Error::other(format!("no boot {boot} in the journal")));
+    };
+    reader.entries(&[
+        ("_BOOT_ID", &boot_id),
+        ("SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER),
+    ])
+}
+
+type SdJournal = c_void;
+
+/// the part of sd-journal(3) the reader uses
+struct SdJournalApi {
+    open: unsafe extern "C" fn(*mut *mut SdJournal, c_int) -> c_int,
+    close: unsafe extern "C" fn(*mut SdJournal),
+    add_match: unsafe extern "C" fn(*mut SdJournal, *const c_void, usize) -> c_int,
+    flush_matches: unsafe extern "C" fn(*mut SdJournal),
+    seek_head: unsafe extern "C" fn(*mut SdJournal) -> c_int,
+    next: unsafe extern "C" fn(*mut SdJournal) -> c_int,
+    get_realtime_usec: unsafe extern "C" fn(*mut SdJournal, *mut u64) -> c_int,
+    restart_data: unsafe extern "C" fn(*mut SdJournal),
+    enumerate_data: unsafe extern "C" fn(*mut SdJournal, *mut *const c_void, *mut usize) -> c_int,
+    query_unique: unsafe extern "C" fn(*mut SdJournal, *const c_char) -> c_int,
+    restart_unique: unsafe extern "C" fn(*mut SdJournal),
+    enumerate_unique: unsafe extern "C" fn(*mut SdJournal, *mut *const c_void, *mut usize) -> c_int,
+    /// keeps the functions above loaded
+    _lib: Library,
+}
+
+impl SdJournalApi {
+    fn load() -> Result<Self, libloading::Error> {
+        // SAFETY: libsystemd has no initialisation routine with side effects
+        let lib = unsafe { Library::new(LIBSYSTEMD)? };
+        // SAFETY: the signatures are those of sd-journal(3), the pointers
+        // stay valid as long as `lib` is kept
+        unsafe {
+            Ok(Self {
+                open: *lib.get(b"sd_journal_open\0")?,
+                close: *lib.get(b"sd_journal_close\0")?,
+                add_match: *lib.get(b"sd_journal_add_match\0")?,
+                flush_matches: *lib.get(b"sd_journal_flush_matches\0")?,
+                seek_head: *lib.get(b"sd_journal_seek_head\0")?,
+                next: *lib.get(b"sd_journal_next\0")?,
+                get_realtime_usec: *lib.get(b"sd_journal_get_realtime_usec\0")?,
+                restart_data: *lib.get(b"sd_journal_restart_data\0")?,
+                enumerate_data: *lib.get(b"sd_journal_enumerate_data\0")?,
+                query_unique: *lib.get(b"sd_journal_query_unique\0")?,
+                restart_unique: *lib.get(b"sd_journal_restart_unique\0")?,
+                enumerate_unique: *lib.get(b"sd_journal_enumerate_unique\0")?,
+                _lib: lib,
+            })
+        }
+    }
+}
+
+/// sd-journal(3) functions return a negative errno on failure
+fn check(ret: c_int) -> io::Result<c_int> {
+    match ret {
+        0.. => Ok(ret),
+        _ => Err(io::Error::from_raw_os_error(-ret)),
+    }
+}
+
+/// `FIELD=value` as the journal stores a field
+fn split_field(data: &[u8]) -> Option<(String, String)> {
+    let equals = data.iter().position(|&byte| byte == b'=')?;
+    Some((
+        String::from_utf8_lossy(&data[..equals]).into_owned(),
+        String::from_utf8_lossy(&data[equals + 1..]).into_owned(),
+    ))
+}
+
+/// index in `boots`, oldest first, of `boot` as journalctl -b takes it: 0
+/// is the current boot, -1 the one before it and 1 the oldest one
+fn boot_index(boots: &[String], current: Option<&str>, boot: i32) -> Option<usize> {
+    if boot > 0 {
+        return Some(boot as usize - 1).filter(|&index| index < boots.len());
+    }
+    // a boot that logged nothing yet comes after every boot of the journal
+    let current = current
+        .and_then(|current| boots.iter().position(|id| id == current))
+        .unwrap_or(boots.len());
+    current
+        .checked_sub(boot.unsigned_abs() as usize)
+        .filter(|&index| index < boots.len())
+}
+
+/// Reads the journal files of this machine through sd-journal(3), as
+/// journalctl would, without depending on its output format
+pub struct JournalReader {
+    api: SdJournalApi,
+    journal: *mut SdJournal,
+    /// boot ids, oldest first, listed on first use
+    boots: Option<Vec<String>>,
+}
+
+impl JournalReader {
+    pub fn open() -> io::Result<Self> {
+        let api = SdJournalApi::load().map_err(io::Error::other)?;
+        let mut journal = ptr::null_mut();
+        // SAFETY: `journal` is only used once set, and closed on drop
+        check(unsafe { (api.open)(&mut journal, SD_JOURNAL_LOCAL_ONLY) })?;
+        Ok(Self {
+            api,
+            journal,
+            boots: None,
+        })
+    }
+
+    /// id of `boot` as journalctl -b takes it, none if the journal does not
+    /// go back that far
+    pub fn boot_id(&mut self, boot: i32) -> io::Result<Option<String>> {
+        let current = current_boot_id();
+        if boot == 0 && current.is_some() {
+            return Ok(current);
+        }
+        if self.boots.is_none() {
+            self.boots = Some(self.list_boots()?);
+        }
+        let boots = self.boots.as_deref().unwrap_or_default();
+        Ok(boot_index(boots, current.as_deref(), boot).map(|index| boots[index].clone()))
+    }
+
+    /// the entries having all of `matches`, oldest first; matches on the
+    /// same field are alternatives
+    pub fn entries(&mut self, matches: &[(&str, &str)]) -> io::Result<Vec<JournalEntry>> {
+        self.seek(matches)?;
+        let mut entries = Vec::new();
+        while self.next()? {
+            entries.push(self.entry()?);
+        }
+        Ok(entries)
+    }
+
+    /// boots ordered by their first entry
+    fn list_boots(&mut self) -> io::Result<Vec<String>> {
+        let mut ids = Vec::new();
+        // SAFETY: the journal is open and the field name nul-terminated
+        check(unsafe { (self.api.query_unique)(self.journal, c"_BOOT_ID".as_ptr()) })?;
+        // SAFETY: query_unique succeeded
+        unsafe { (self.api.restart_unique)(self.journal) };
+        loop {
+            let (mut data, mut len) = (ptr::null(), 0);
+            // SAFETY: data and len are set on success
+            let ret =
+                check(unsafe { (self.api.enumerate_unique)(self.journal, &mut data, &mut len) })?;
+            if ret == 0 {
+                break;
+            }
+            // SAFETY: the data stays valid until the next enumeration
+            let field = unsafe { slice::from_raw_parts(data.cast::<u8>(), len) };
+            if let Some((_, id)) = split_field(field) {
+                ids.push(id);
+            }
+        }
+        let mut boots = Vec::new();
+        for id in ids {
+            self.seek(&[("_BOOT_ID", &id)])?;
+            if self.next()? {
+                boots.push((self.realtime()?, id));
+            }
+        }
+        boots.sort();
+        Ok(boots.into_iter().map(|(_, id)| id).collect())
+    }
+
+    fn seek(&mut self, matches: &[(&str, &str)]) -> io::Result<()> {
+        // SAFETY: the journal is open
+        unsafe { (self.api.flush_matches)(self.journal) };
+        for (field, value) in matches {
+            let field = format!("{field}={value}");
+            // SAFETY: sd_journal_add_match copies the match
+            check(unsafe {
+                (self.api.add_match)(self.journal, field.as_ptr().cast(), field.len())
+            })?;
+        }
+        // SAFETY: the journal is open
+        check(unsafe { (self.api.seek_head)(self.journal) })?;
+        Ok(())
+    }
+
+    /// moves to the next entry, false past the last one
+    fn next(&mut self) -> io::Result<bool> {
+        // SAFETY: the journal is open
+        Ok(check(unsafe { (self.api.next)(self.journal) })? > 0)
+    }
+
+    /// microseconds since the epoch the current entry was logged at
+    fn realtime(&mut self) -> io::Result<u64> {
+        let mut usec = 0;
+        // SAFETY: usec is set on success
+        check(unsafe { (self.api.get_realtime_usec)(self.journal, &mut usec) })?;
+        Ok(usec)
+    }
+
+    /// the current entry with __REALTIME_TIMESTAMP, the first value of
+    /// fields given more than once is kept
+    fn entry(&mut self) -> io::Result<JournalEntry> {
+        let mut fields = Map::new();
+        fields.insert(
+            "__REALTIME_TIMESTAMP".to_string(),
+            Value::String(self.realtime()?.to_string()),
+        );
+        // SAFETY: the journal is on an entry
+        unsafe { (self.api.restart_data)(self.journal) };
+        loop {
+            let (mut data, mut len) = (ptr::null(), 0);
+            // SAFETY: data and len are set on success
+            let ret =
+                check(unsafe { (self.api.enumerate_data)(self.journal, &mut data, &mut len) })?;
+            if ret == 0 {
+                break;
+            }
+            // SAFETY: the data stays valid until the next enumeration
+            let field = unsafe { slice::from_raw_parts(data.cast::<u8>(), len) };
+            if let Some((name, value)) = split_field(field) {
+                fields.entry(name).or_insert(Value::String(value));
+            }
+        }
+        Ok(JournalEntry { fields })
+    }
+}
+
+impl Drop for JournalReader {
+    fn drop(&mut self) {
+        // SAFETY: the journal was opened by open and is not used after
+        unsafe { (self.api.close)(self.journal) };
+    }
+}
+
+fn encode(identifier: &str, priority: u8, message: &str, fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    append_field(&mut payload, "MESSAGE", message);
+    append_field(&mut payload, "PRIORITY", &priority.to_string());
+    append_field(&mut payload, "SYSLOG_IDENTIFIER", identifier);
+    for (key, value) in fields {
+        append_field(&mut payload, key, value);
+    }
+    payload
+}
+
+/// values containing newlines need the explicit length framing of the native protocol
+fn append_field(payload: &mut Vec<u8>, key: &str, value: &str) {
+    payload.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        payload.push(b'\n');
+        payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        payload.extend_from_slice(value.as_bytes());
+    } else {
+        payload.push(b'=');
+        payload.extend_from_slice(value.as_bytes());
+    }
+    payload.push(b'\n');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_simple_fields() {
+        let payload = encode(
+            SYSLOG_IDENTIFIER,
+            PRIORITY_WARNING,
+            "degraded",
+            &[("GREENBOOT_STATUS", "DEGRADED")],
+        );
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            "MESSAGE=degraded\nPRIORITY=4\nSYSLOG_IDENTIFIER=greenboot\nGREENBOOT_STATUS=DEGRADED\n"
+        );
+    }
+
+    #[test]
+    fn test_encode_multiline_value() {
+        let mut payload = Vec::new();
+        append_field(&mut payload, "MESSAGE", "a\nb");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&3u64.to_le_bytes());
+        expected.extend_from_slice(b"a\nb\n");
+        assert_eq!(payload, expected);
+    }
+
+    #[test]
+    fn test_events_are_in_the_catalog() {
+        let catalog = include_str!("../../usr/lib/systemd/catalog/greenboot.catalog");
+        let mut ids = Vec::new();
+        for event in Event::ALL {
+            let id = event.message_id();
+            assert!(id.len() == 32 && id.bytes().all(|byte| byte.is_ascii_hexdigit()));
+            assert!(
+                catalog.contains(&format!("\n-- {id}\n")),
+                "{id} is not in the catalog"
+            );
+            ids.push(id);
+        }
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), Event::ALL.len());
+    }
+
+    #[test]
+    fn test_journal_stream() {
+        assert_eq!(parse_journal_stream("8:263210"), Some((8, 263210)));
+        assert_eq!(parse_journal_stream("8"), None);
+        assert_eq!(parse_journal_stream("8:x"), None);
+        assert_eq!(level_priority(Level::TRACE), PRIORITY_DEBUG);
+        assert_eq!(level_priority(Level::WARN), PRIORITY_WARNING);
+    }
+
+    #[test]
+    fn test_boot_index() {
+        let boots: Vec<String> = ["aa", "bb", "cc"].map(String::from).to_vec();
+        assert_eq!(boot_index(&boots, Some("cc"), 0), Some(2));
+        assert_eq!(boot_index(&boots, Some("cc"), -2), Some(0));
+        assert_eq!(boot_index(&boots, Some("cc"), -3), None);
+        assert_eq!(boot_index(&boots, Some("bb"), -1), Some(0));
+        // nothing logged in the current boot yet
+        assert_eq!(boot_index(&boots, Some("dd"), -1), Some(2));
+        assert_eq!(boot_index(&boots, Some("dd"), 0), None);
+        assert_eq!(boot_index(&boots, None, 1), Some(0));
+        assert_eq!(boot_index(&boots, None, 4), None);
+        assert_eq!(
+            split_field(b"MESSAGE=a=b"),
+            Some(("MESSAGE".to_string(), "a=b".to_string()))
+        );
+    }
+}
@@ -0,0 +1,352 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::condition::Condition;
+use crate::selinux::parse_selinux_context;
+use crate::systemd::{ResourceLimits, parse_cpu_quota, parse_memory_max};
+
+/// comment prefix of the metadata lines in the leading comment block of a check,
+/// e.g. `# greenboot: remediation="restart the modem with mmcli -m 0 -r"`
+static HEADER_PREFIX: &str = "# greenboot:";
+
+/// only the start of a check is read, binaries can be large
+const HEADER_READ_LIMIT: u64 = 8192;
+
+/// metadata declared by a check about itself
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckMetadata {
+    /// operator-facing advice shown when the check keeps failing
+    pub remediation: Option<String>,
+    /// what the check verifies, shown next to its name
+    pub description: Option<String>,
+    /// the check is killed and failed after running this long
+    pub timeout: Option<Duration>,
+    /// runs again after a failure, up to this many times
+    pub retries: u32,
+    /// the check is skipped unless all of them hold
+    pub conditions: Vec<Condition>,
+    /// the check only runs in these profiles, in every profile if empty
+    pub profiles: Vec<String>,
+    /// CPU and memory the check may use
+    pub limits: ResourceLimits,
+    /// account the check runs as, root if none
+    pub user: Option<String>,
+}
+
+impl CheckMetadata {
+    /// how the check asks to be run, before its sidecar is applied
+    pub fn settings(&self) -> CheckSettings {
+        CheckSettings {
+            timeout: self.timeout,
+            retries: self.retries,
+            conditions: self.conditions.clone(),
+            profiles: self.profiles.clone(),
+            limits: self.limits,
+            user: self.user.clone(),
+            ..CheckSettings::default()
+        }
+    }
+}
+
+/// How a check is run, tuned by a `<check>.conf` sidecar next to it so
+/// vendors can adjust third-party checks without patching them. The sidecar
+/// holds `key=value` lines: `timeout` in seconds, `retries`, `required` to
+/// move the check to the other stage, `profiles` it runs in, `memory_max` and
+/// `cpu_quota` limits, the `user` it runs as, `sandbox` to run it sandboxed
+/// or not whatever its stage, the `selinux_context` it runs in, `env.<NAME>`
+/// for extra
+/// environment and systemd-style `Condition*=` settings, added to those of
+/// the header.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckSettings {
+    /// the check is killed and failed after running this long
+    pub timeout: Option<Duration>,
+    /// runs again after a failure, up to this many times
+    pub retries: u32,
+    /// whether a failure makes the boot red, none to go by the check dir
+    pub required: Option<bool>,
+    /// extra environment variables of the check
+    pub env: Vec<(String, String)>,
+    /// the check is skipped unless all of them hold
+    pub conditions: Vec<Condition>,
+    /// the check only runs in these profiles, in every profile if empty
+    pub profiles: Vec<String>,
+    /// CPU and memory the check may use, the global limits where unset
+    pub limits: ResourceLimits,
+    /// account the check runs as, root if none
+    pub user: Option<String>,
+    /// whether the check runs in a sandbox, none to go by its stage
+    pub sandbox: Option<bool>,
+    /// SELinux context the check runs in, the global one if none
+    pub selinux_context: Option<String>,
+}
+
+/// sidecar of `check`, its file name with `.conf` appended
+pub fn sidecar_path(check: &Path) -> PathBuf {
+    let mut name = check.as_os_str().to_os_string();
+    name.push(".conf");
+    PathBuf::from(name)
+}
+
+/// reads the sidecar of `check`, the defaults if there is none
+pub fn read_sidecar(check: &Path) -> Result<CheckSettings> {
+    apply_sidecar(check, CheckSettings::default())
+}
+
+/// overrides `settings` with the sidecar of `check`, if it has one
+pub fn apply_sidecar(check: &Path, settings: CheckSettings) -> Result<CheckSettings> {
+    let path = sidecar_path(check);
+    match fs::read_to_string(&path) {
+        Ok(content) => {
+            parse_sidecar(&content, settings).with_context(|| format!("Invalid {}", path.display()))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(settings),
+        Err(e) => Err(e).with_context(|| format!("Unable to read {}", path.display())),
+    }
+}
+
+fn parse_sidecar(content: &str, mut settings: CheckSettings) -> Result<CheckSettings> {
+    let lines = content
+        .lines()
+        .map(str::trim)
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    for (index, line) in lines {
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected key=value", index + 1);
+        };
+        let (key, value) = (key.trim(), unquote(value.trim()));
+        let invalid = || format!("line {}: invalid {key} '{value}'", index + 1);
+        match key {
+            "timeout" => {
+                let secs: u64 = value.parse().with_context(invalid)?;
+                settings.timeout = (secs > 0).then(|| Duration::from_secs(secs));
+            }
+            "retries" => settings.retries = value.parse().with_context(invalid)?,
+            "required" => settings.required = Some(value.parse().with_context(invalid)?),
+            "sandbox" => settings.sandbox = Some(value.parse().with_context(invalid)?),
+            "selinux_context" => {
+                settings.selinux_context = Some(parse_selinux_context(value).with_context(invalid)?)
+            }
+            "profiles" => settings.profiles = split_profiles(value),
+            "user" => settings.user = (!value.is_empty()).then(|| value.to_string()),
+            "memory_max" => {
+                settings.limits.memory_max = Some(parse_memory_max(value).with_context(invalid)?)
+            }
+            "cpu_quota" => {
+                settings.limits.cpu_quota = Some(parse_cpu_quota(value).with_context(invalid)?)
+            }
+            _ => match key.strip_prefix("env.") {
+                Some(name) if !name.is_empty() => {
+                    settings.env.push((name.to_string(), value.to_string()))
+                }
+                _ => match Condition::parse(key, value)
+                    .with_context(|| format!("line {}", index + 1))?
+                {
+                    Some(condition) => settings.conditions.push(condition),
+                    None => {
+                        tracing::warn!("line {}: ignoring unknown check setting {key}", index + 1)
+                    }
+                },
+            },
+        }
+    }
+    Ok(settings)
+}
+
+/// comma-separated profile names
+fn split_profiles(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|profile| !profile.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// reads the metadata header of a check, empty if it has none or can't be read
+pub fn read_metadata(path: &Path) -> CheckMetadata {
+    let mut head = Vec::new();
+    match File::open(path) {
+        Ok(file) => {
+            if let Err(e) = file.take(HEADER_READ_LIMIT).read_to_end(&mut head) {
+                tracing::debug!("cannot read metadata of {}: {e}", path.display());
+            }
+        }
+        Err(e) => tracing::debug!("cannot read metadata of {}: {e}", path.display()),
+    }
+    parse_header(&String::from_utf8_lossy(&head))
+}
+
+fn parse_header(content: &str) -> CheckMetadata {
+    let mut metadata = CheckMetadata::default();
+    let header = content
+        .lines()
+        .map(str::trim)
+        .take_while(|line| line.is_empty() || line.starts_with('#'));
+    for line in header {
+        let Some(fields) = line.strip_prefix(HEADER_PREFIX) else {
+            continue;
+        };
+        for (key, value) in parse_fields(fields) {
+            match key.as_str() {
+                "remediation" => metadata.remediation = Some(value),
+                "description" => metadata.description = Some(value),
+                "profiles" => metadata.profiles = split_profiles(&value),
+                "user" => metadata.user = (!value.is_empty()).then_some(value),
+                "timeout" => match value.parse::<u64>() {
+                    Ok(secs) => metadata.timeout = (secs > 0).then(|| Duration::from_secs(secs)),
+                    Err(_) => tracing::warn!("ignoring invalid check timeout '{value}'"),
+                },
+                "retries" => match value.parse() {
+                    Ok(retries) => metadata.retries = retries,
+                    Err(_) => tracing::warn!("ignoring invalid check retries '{value}'"),
+                },
+                "memory_max" => match parse_memory_max(&value) {
+                    Ok(bytes) => metadata.limits.memory_max = Some(bytes),
+                    Err(e) => tracing::warn!("ignoring check limit: {e}"),
+                },
+                "cpu_quota" => match parse_cpu_quota(&value) {
+                    Ok(percent) => metadata.limits.cpu_quota = Some(percent),
+                    Err(e) => tracing::warn!("ignoring check limit: {e}"),
+                },
+                _ => match Condition::parse(&key, &value) {
+                    Ok(Some(condition)) => metadata.conditions.push(condition),
+                    Ok(None) => tracing::debug!("ignoring unknown check metadata key {key}"),
+                    Err(e) => tracing::warn!("ignoring check condition: {e}"),
+                },
+            }
+        }
+    }
+    metadata
+}
+
+/// splits `key=value key="quoted value"` pairs
+fn parse_fields(fields: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    let mut rest = fields.trim_start();
+    while let Some((key, tail)) = rest.split_once('=') {
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => tail.split_once(char::is_whitespace).unwrap_or((tail, "")),
+        };
+        parsed.push((key.trim().to_string(), value.to_string()));
+        rest = tail.trim_start();
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_header_remediation() {
+        let script = "#!/bin/bash\n# checks the modem\n# greenboot: remediation=\"restart the modem with mmcli -m 0 -r\"\nset -e\n# greenboot: remediation=ignored\n";
+        assert_eq!(
+            parse_header(script).remediation.as_deref(),
+            Some("restart the modem with mmcli -m 0 -r")
+        );
+    }
+
+    #[test]
+    fn test_parse_header_settings() {
+        let script = "#!/bin/bash\n# greenboot: timeout=30 retries=2 description=\"DNS check\"\n# greenboot: retries=many profiles=factory,field memory_max=64M cpu_quota=lots user=chrony\n";
+        let metadata = parse_header(script);
+        assert_eq!(metadata.description.as_deref(), Some("DNS check"));
+        assert_eq!(metadata.profiles, ["factory", "field"]);
+        assert_eq!(metadata.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(metadata.retries, 2);
+        assert_eq!(metadata.limits.memory_max, Some(64 << 20));
+        assert_eq!(metadata.limits.cpu_quota, None);
+        assert_eq!(metadata.user.as_deref(), Some("chrony"));
+
+        // the sidecar wins over the header
+        let settings = parse_sidecar(
+            "timeout=0\nprofiles=field\ncpu_quota=25%\nuser=\nselinux_context=system_u:system_r:greenboot_check_t:s0\n",
+            metadata.settings(),
+        )
+        .unwrap();
+        assert_eq!(settings.timeout, None);
+        assert_eq!(settings.retries, 2);
+        assert_eq!(settings.profiles, ["field"]);
+        assert_eq!(settings.limits.memory_max, Some(64 << 20));
+        assert_eq!(settings.limits.cpu_quota, Some(25));
+        // an empty user runs the check as root again
+        assert_eq!(settings.user, None);
+        assert_eq!(
+            settings.selinux_context.as_deref(),
+            Some("system_u:system_r:greenboot_check_t:s0")
+        );
+
+        // conditions of the header and the sidecar add up
+        let metadata = parse_header("# greenboot: ConditionVirtualization=!container\n");
+        let settings =
+            parse_sidecar("ConditionPathExists=/dev/ttyUSB0\n", metadata.settings()).unwrap();
+        let conditions: Vec<String> = settings.conditions.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            conditions,
+            [
+                "ConditionVirtualization=!container",
+                "ConditionPathExists=/dev/ttyUSB0"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_sidecar() {
+        let settings = parse_sidecar(
+            "# tuned for the field units\ntimeout=30\nretries = 2\nrequired=false\nsandbox=false\nenv.PROXY=\"http://proxy:3128\"\n",
+            CheckSettings::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            settings,
+            CheckSettings {
+                timeout: Some(Duration::from_secs(30)),
+                retries: 2,
+                required: Some(false),
+                env: vec![("PROXY".to_string(), "http://proxy:3128".to_string())],
+                conditions: Vec::new(),
+                profiles: Vec::new(),
+                limits: ResourceLimits::default(),
+                user: None,
+                sandbox: Some(false),
+                selinux_context: None,
+            }
+        );
+        assert!(parse_sidecar("timeout=soon\n", CheckSettings::default()).is_err());
+        assert!(parse_sidecar("memory_max=64MB\n", CheckSettings::default()).is_err());
+        assert!(parse_sidecar("required\n", CheckSettings::default()).is_err());
+        assert!(parse_sidecar("sandbox=strict\n", CheckSettings::default()).is_err());
+        assert!(parse_sidecar("selinux_context=unconfined_t\n", CheckSettings::default()).is_err());
+        assert!(parse_sidecar("ConditionPathExists=modem\n", CheckSettings::default()).is_err());
+        assert_eq!(
+            sidecar_path(Path::new("/etc/greenboot/check/required.d/01_dns.sh")),
+            Path::new("/etc/greenboot/check/required.d/01_dns.sh.conf")
+        );
+    }
+
+    #[test]
+    fn test_parse_fields_unquoted_and_quoted() {
+        assert_eq!(
+            parse_fields(" remediation=reboot foo=\"a b\""),
+            vec![
+                ("remediation".to_string(), "reboot".to_string()),
+                ("foo".to_string(), "a b".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::paths::Paths;
+use crate::validate::key_kind;
+
+/// units of the bash implementation that greenboot-rs no longer ships, their
+/// work is done by greenboot-healthcheck.service and the greenboot binary
+static LEGACY_UNITS: [&str; 8] = [
+    "greenboot-task-runner.service",
+    "greenboot-grub2-set-counter.service",
+    "greenboot-grub2-set-success.service",
+    "greenboot-rpm-ostree-grub2-check-fallback.service",
+    "greenboot-status.service",
+    "redboot-auto-reboot.service",
+    "redboot-task-runner.service",
+    "redboot.target",
+];
+/// unit dirs, relative to the root, holding the .wants/.requires links
+static UNIT_DIRS: [&str; 2] = ["etc/systemd/system", "usr/lib/systemd/system"];
+/// boot status written by the bash greenboot-status
+static LEGACY_MOTD_PATH: &str = "run/motd.d/boot-status";
+/// suffix of the copy kept of a rewritten legacy config
+static LEGACY_CONFIG_SUFFIX: &str = "legacy";
+
+/// outcome of a migration, `untranslated` lists what needs a manual look
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    pub migrated: Vec<String>,
+    pub untranslated: Vec<String>,
+}
+
+/// converts the bash greenboot artifacts found under `root` to the current
+/// layout: the shell sourced config, the units enabled for the old task
+/// runners and the old MOTD fragment. With `dry_run` nothing is changed and
+/// the report tells what would be.
+pub fn migrate(root: &Path, dry_run: bool) -> Result<MigrationReport> {
+    let paths = Paths::with_root(root);
+    let mut report = MigrationReport::default();
+
+    migrate_config(&paths.config_file, dry_run, &mut report)?;
+    migrate_units(root, dry_run, &mut report)?;
+    migrate_motd(root, &paths, dry_run, &mut report)?;
+    check_loose_scripts(&paths, &mut report);
+
+    Ok(report)
+}
+
+/// the bash implementation sourced the config, so it may hold `export`,
+/// trailing comments and shell logic the INI parser does not understand
+fn migrate_config(config_file: &Path, dry_run: bool, report: &mut MigrationReport) -> Result<()> {
+    let Ok(content) = fs::read_to_string(config_file) else {
+        return Ok(());
+    };
+
+    let mut converted = Vec::new();
+    let mut changed = false;
+    for (index, line) in content.lines().enumerate() {
+        let location = format!("{}:{}", config_file.display(), index + 1);
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            converted.push(line.to_string());
+            continue;
+        }
+
+        let assignment = trimmed.strip_prefix("export ").unwrap_or(trimmed).trim();
+        let Some((key, value)) = assignment.split_once('=') else {
+            report.untranslated.push(format!(
+                "{location}: shell statement `{trimmed}` left as is"
+            ));
+            converted.push(line.to_string());
+            continue;
+        };
+        let key = key.trim();
+        if !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            report.untranslated.push(format!(
+                "{location}: shell statement `{trimmed}` left as is"
+            ));
+            converted.push(line.to_string());
+            continue;
+        }
+        if key_kind(key).is_none() {
+            report
+                .untranslated
+                .push(format!("{location}: unknown key {key} left as is"));
+        }
+
+        let new_line = format!("{key}={}", strip_trailing_comment(value).trim_end());
+        if new_line != line {
+            changed = true;
+            report
+                .migrated
+                .push(format!("{location}: `{trimmed}` -> `{new_line}`"));
+        }
+        converted.push(new_line);
+    }
+
+    if changed && !dry_run {
+        let backup = config_file.with_extension(format!(
+            "{}.{LEGACY_CONFIG_SUFFIX}",
+            config_file
+                .extension()
+                .map(|ext| ext.to_string_lossy())
+                .unwrap_or_default()
+        ));
+        fs::copy(config_file, &backup)
+            .with_context(|| format!("Unable to back up {}", config_file.display()))?;
+        let mut content = converted.join("\n");
+        content.push('\n');
+        fs::write(config_file, content)
+            .with_context(|| format!("Unable to write {}", config_file.display()))?;
+    }
+    Ok(())
+}
+
+/// drops a ` # comment` after the value, a `#` inside quotes is kept
+fn strip_trailing_comment(value: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (index, c) in value.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => return &value[..index],
+            _ => {}
+        }
+        previous = c;
+    }
+    value
+}
+
+/// removes the .wants/.requires links enabling units that no longer ship
+fn migrate_units(root: &Path, dry_run: bool, report: &mut MigrationReport) -> Result<()> {
+    for link in legacy_unit_links(root) {
+        report.migrated.push(format!(
+            "{}: legacy unit disabled, greenboot-healthcheck.service replaces it",
+            link.display()
+        ));
+        if !dry_run {
+            fs::remove_file(&link)
+                .with_context(|| format!("Unable to remove {}", link.display()))?;
+        }
+    }
+    Ok(())
+}
+
+fn legacy_unit_links(root: &Path) -> Vec<PathBuf> {
+    let mut links: Vec<PathBuf> = UNIT_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(root.join(dir)).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(".wants") || name.ends_with(".requires")
+        })
+        .flat_map(|entry| LEGACY_UNITS.iter().map(move |unit| entry.path().join(unit)))
+        .filter(|link| link.symlink_metadata().is_ok())
+        .collect();
+    links.sort();
+    links
+}
+
+/// the boot status now lives in the MOTD fragment of `paths`
+fn migrate_motd(
+    root: &Path,
+    paths: &Paths,
+    dry_run: bool,
+    report: &mut MigrationReport,
+) -> Result<()> {
+    let legacy = root.join(LEGACY_MOTD_PATH);
+    if legacy == paths.motd || !legacy.exists() {
+        return Ok(());
+    }
+    report.migrated.push(format!(
+        "{}: stale boot status removed, it is now written to {}",
+        legacy.display(),
+        paths.motd.display()
+    ));
+    if !dry_run {
+        fs::remove_file(&legacy)
+            .with_context(|| format!("Unable to remove {}", legacy.display()))?;
+    }
+    Ok(())
+}
+
+/// scripts dropped directly in check/ never ran, whether they are required or
+/// wanted is up to the admin
+fn check_loose_scripts(paths: &Paths, report: &mut MigrationReport) {
+    for install_path in &paths.install_paths {
+        let Ok(entries) = fs::read_dir(install_path.join("check")) else {
+            continue;
+        };
+        let mut loose: Vec<PathBuf> = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        loose.sort();
+        for script in loose {
+            report.untranslated.push(format!(
+                "{}: not in required.d or wanted.d and never runs, move it to one of them",
+                script.display()
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn legacy_root() -> (tempfile::TempDir, Paths) {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(
+            &paths.config_file,
+            "# legacy\nexport GREENBOOT_MAX_BOOT_ATTEMPTS=5 # five tries\n\
+             DISABLED_HEALTHCHECKS=(\"a #1.sh\")\n\
+             GREENBOOT_OLD_KNOB=1\n\
+             [ -f /etc/foo ] && source /etc/foo\n",
+        )
+        .unwrap();
+        let wants = root
+            .path()
+            .join("etc/systemd/system/multi-user.target.wants");
+        fs::create_dir_all(&wants).unwrap();
+        symlink(
+            "/usr/lib/systemd/system/greenboot-task-runner.service",
+            wants.join("greenboot-task-runner.service"),
+        )
+        .unwrap();
+        let motd = root.path().join(LEGACY_MOTD_PATH);
+        fs::create_dir_all(motd.parent().unwrap()).unwrap();
+        fs::write(&motd, "Boot Status is GREEN\n").unwrap();
+        (root, paths)
+    }
+
+    #[test]
+    fn test_dry_run_changes_nothing() {
+        let (root, paths) = legacy_root();
+        let before = fs::read_to_string(&paths.config_file).unwrap();
+
+        let report = migrate(root.path(), true).unwrap();
+        assert_eq!(report.migrated.len(), 3);
+        assert_eq!(report.untranslated.len(), 2);
+        assert_eq!(fs::read_to_string(&paths.config_file).unwrap(), before);
+        assert!(root.path().join(LEGACY_MOTD_PATH).exists());
+    }
+
+    #[test]
+    fn test_migrate_legacy_layout() {
+        let (root, paths) = legacy_root();
+        let loose = paths.install_paths[1].join("check/01_loose.sh");
+        fs::create_dir_all(loose.parent().unwrap()).unwrap();
+        fs::write(&loose, "exit 0\n").unwrap();
+
+        let report = migrate(root.path(), false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&paths.config_file).unwrap(),
+            "# legacy\nGREENBOOT_MAX_BOOT_ATTEMPTS=5\n\
+             DISABLED_HEALTHCHECKS=(\"a #1.sh\")\n\
+             GREENBOOT_OLD_KNOB=1\n\
+             [ -f /etc/foo ] && source /etc/foo\n"
+        );
+        assert!(paths.config_file.with_extension("conf.legacy").exists());
+        assert!(legacy_unit_links(root.path()).is_empty());
+        assert!(!root.path().join(LEGACY_MOTD_PATH).exists());
+        assert_eq!(report.untranslated.len(), 3);
+        assert!(report.untranslated[0].contains("GREENBOOT_OLD_KNOB"));
+
+        // a second run has nothing left to convert
+        let report = migrate(root.path(), false).unwrap();
+        assert!(report.migrated.is_empty());
+    }
+}
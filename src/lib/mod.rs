@@ -1,12 +1,168 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
+//! The stable API covers [`Paths`], the MOTD ([`handle_motd`]) and the
+//! boot-status verdict ([`HealthStatus`], [`StatusRecord`], [`RollbackMarker`],
+//! [`read_status`], [`write_status`]) and follows semver. Its types are `#[non_exhaustive]`,
+//! so fields and variants can be added in a minor release. Everything else is
+//! only built with the `unstable` feature and may change in any release.
+
+mod motd;
+mod paths;
+mod status;
+
+#[cfg(feature = "unstable")]
+pub mod abroot;
+#[cfg(feature = "unstable")]
+pub mod android;
+#[cfg(feature = "unstable")]
+pub mod attempts;
+#[cfg(feature = "unstable")]
+pub mod batch;
+#[cfg(feature = "unstable")]
+pub mod bootloader;
+#[cfg(feature = "unstable")]
+pub mod boottime;
+#[cfg(feature = "unstable")]
+pub mod checkfiles;
+#[cfg(feature = "unstable")]
+pub mod checkoutput;
+#[cfg(feature = "unstable")]
+pub mod checkreport;
+#[cfg(feature = "unstable")]
+pub mod cmdline;
+#[cfg(feature = "unstable")]
+pub mod condition;
+#[cfg(feature = "unstable")]
+pub mod container;
+#[cfg(feature = "unstable")]
 pub mod greenboot;
+#[cfg(feature = "unstable")]
 pub mod grub;
+#[cfg(feature = "unstable")]
 pub mod handler;
+#[cfg(feature = "unstable")]
+pub mod history;
+#[cfg(feature = "unstable")]
+pub mod inject;
+#[cfg(feature = "unstable")]
+pub mod journal;
+#[cfg(feature = "unstable")]
+pub mod metadata;
+#[cfg(feature = "unstable")]
+pub mod migrate;
+#[cfg(feature = "unstable")]
 pub mod mount;
+#[cfg(feature = "unstable")]
+pub mod network;
+#[cfg(feature = "unstable")]
+pub mod privileges;
+#[cfg(feature = "unstable")]
+pub mod procgroup;
+#[cfg(feature = "unstable")]
+pub mod progress;
+#[cfg(feature = "unstable")]
+pub mod rauc;
+#[cfg(feature = "unstable")]
+pub mod redact;
+#[cfg(feature = "unstable")]
+pub mod runlog;
+#[cfg(feature = "unstable")]
+pub mod sdboot;
+#[cfg(feature = "unstable")]
+pub mod selinux;
+#[cfg(feature = "unstable")]
+pub mod statefile;
+#[cfg(feature = "unstable")]
+pub mod systemd;
+#[cfg(feature = "unstable")]
+pub mod uboot;
+#[cfg(feature = "unstable")]
+pub mod uefi;
+#[cfg(feature = "unstable")]
+pub mod validate;
+#[cfg(feature = "unstable")]
+pub mod webhook;
+#[cfg(feature = "unstable")]
+pub mod zipl;
 
 // Re-export public API
+pub use motd::handle_motd;
+pub use paths::Paths;
+pub use status::{HealthStatus, RollbackMarker, StatusRecord, read_status, write_status};
+
+#[cfg(feature = "unstable")]
+pub use abroot::*;
+#[cfg(feature = "unstable")]
+pub use android::*;
+#[cfg(feature = "unstable")]
+pub use attempts::*;
+#[cfg(feature = "unstable")]
+pub use batch::*;
+#[cfg(feature = "unstable")]
+pub use bootloader::*;
+#[cfg(feature = "unstable")]
+pub use boottime::*;
+#[cfg(feature = "unstable")]
+pub use checkfiles::*;
+#[cfg(feature = "unstable")]
+pub use checkoutput::*;
+#[cfg(feature = "unstable")]
+pub use checkreport::*;
+#[cfg(feature = "unstable")]
+pub use cmdline::*;
+#[cfg(feature = "unstable")]
+pub use condition::*;
+#[cfg(feature = "unstable")]
+pub use container::*;
+#[cfg(feature = "unstable")]
 pub use greenboot::*;
+#[cfg(feature = "unstable")]
 pub use grub::*;
+#[cfg(feature = "unstable")]
 pub use handler::*;
+#[cfg(feature = "unstable")]
+pub use history::*;
+#[cfg(feature = "unstable")]
+pub use inject::*;
+#[cfg(feature = "unstable")]
+pub use metadata::*;
+#[cfg(feature = "unstable")]
+pub use migrate::*;
+#[cfg(feature = "unstable")]
 pub use mount::*;
+#[cfg(feature = "unstable")]
+pub use network::*;
+#[cfg(feature = "unstable")]
+pub use paths::*;
+#[cfg(feature = "unstable")]
+pub use privileges::*;
+#[cfg(feature = "unstable")]
+pub use procgroup::*;
+#[cfg(feature = "unstable")]
+pub use progress::*;
+#[cfg(feature = "unstable")]
+pub use rauc::*;
+#[cfg(feature = "unstable")]
+pub use redact::*;
+#[cfg(feature = "unstable")]
+pub use runlog::*;
+#[cfg(feature = "unstable")]
+pub use sdboot::*;
+#[cfg(feature = "unstable")]
+pub use selinux::*;
+#[cfg(feature = "unstable")]
+pub use statefile::*;
+#[cfg(feature = "unstable")]
+pub use status::*;
+#[cfg(feature = "unstable")]
+pub use systemd::*;
+#[cfg(feature = "unstable")]
+pub use uboot::*;
+#[cfg(feature = "unstable")]
+pub use uefi::*;
+#[cfg(feature = "unstable")]
+pub use validate::*;
+#[cfg(feature = "unstable")]
+pub use webhook::*;
+#[cfg(feature = "unstable")]
+pub use zipl::*;
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, anyhow};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+use crate::paths::Paths;
+
+/// writes greenboot status to motd.d/boot-status, or to the fallback under
+/// /run when the MOTD path is on a read-only /etc
+pub fn handle_motd(paths: &Paths, state: &str) -> Result<()> {
+    let content = format!("{state}.");
+    match write_motd(&paths.motd, &content) {
+        Ok(()) => Ok(()),
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::ReadOnlyFilesystem | ErrorKind::PermissionDenied
+            ) =>
+        {
+            tracing::info!(
+                "{} is not writable ({err}), writing the boot status to {}",
+                paths.motd.display(),
+                paths.motd_fallback.display()
+            );
+            write_motd(&paths.motd_fallback, &content)
+                .map_err(|err| anyhow!("Error writing motd: {}", err))?;
+            link_fallback(paths);
+            Ok(())
+        }
+        Err(err) => Err(anyhow!("Error writing motd: {}", err)),
+    }
+}
+
+/// a MOTD path symlinked to /run needs the dir of its target created on every boot
+fn write_motd(path: &Path, content: &str) -> io::Result<()> {
+    let target = fs::read_link(path).map_or(path.to_path_buf(), |target| {
+        path.parent()
+            .map_or(target.clone(), |dir| dir.join(&target))
+    });
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(target, content)
+}
+
+/// points a missing MOTD path at the fallback so later writes go there
+/// directly, which only works where its dir is still writable
+fn link_fallback(paths: &Paths) {
+    if paths.motd.symlink_metadata().is_ok() {
+        return;
+    }
+    if let Err(e) = symlink(&paths.motd_fallback, &paths.motd) {
+        tracing::debug!(
+            "cannot link {} to {}: {e}",
+            paths.motd.display(),
+            paths.motd_fallback.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_handle_motd_appends_period() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths {
+            motd: dir.path().join("boot-status"),
+            ..Paths::default()
+        };
+        handle_motd(&paths, "Greenboot healthcheck passed - status is GREEN").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&paths.motd).unwrap(),
+            "Greenboot healthcheck passed - status is GREEN."
+        );
+    }
+
+    #[test]
+    fn test_handle_motd_follows_symlink_to_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        fs::create_dir_all(paths.motd.parent().unwrap()).unwrap();
+        symlink(&paths.motd_fallback, &paths.motd).unwrap();
+
+        // the /run dir does not exist yet on a fresh boot
+        handle_motd(&paths, "Greenboot healthcheck is in progress").unwrap();
+        assert_eq!(
+            fs::read_to_string(&paths.motd_fallback).unwrap(),
+            "Greenboot healthcheck is in progress."
+        );
+    }
+}
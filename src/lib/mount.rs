@@ -1,195 +1,288 @@
 // SPDX-License-Identifier: BSD-3-Clause
 
-use log::{info, warn};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 #[cfg(not(feature = "test-remount"))]
 use std::process::{Command, Stdio};
 use thiserror::Error;
+use tracing::{info, warn};
 
-/// Shared path to mount info used by default helpers
-static MOUNT_INFO_PATH: &str = "/proc/mounts";
+use crate::paths::{DEFAULT_BOOT_DIR, Paths};
 
 #[derive(Debug, Error)]
 pub enum MountError {
-    #[error("Failed to remount /boot: {0}")]
+    #[error("Failed to remount: {0}")]
     RemountFailed(String),
     #[error("Failed to read mount info")]
     MountInfoError,
+    #[error("{0} is not a mount point")]
+    NotMounted(PathBuf),
 }
 
-fn is_boot_rw_at(mounts_path: &Path) -> Result<bool, MountError> {
-    let mounts = fs::read_to_string(mounts_path).map_err(|_| MountError::MountInfoError)?;
-    for line in mounts.lines() {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.len() >= 4 && parts.get(1) == Some(&"/boot") {
-            let options = parts[3];
-            return Ok(options.contains("rw") && !options.contains("ro"));
+/// A mount point that is kept read-only and remounted read-write only for the
+/// time it takes to write to it, /boot unless another target is configured.
+/// Its state is read from a /proc/mounts formatted file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BootMount {
+    target: PathBuf,
+    mount_info: PathBuf,
+}
+
+impl BootMount {
+    /// /boot, with its state read from the mount info of `paths`
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            target: PathBuf::from(DEFAULT_BOOT_DIR),
+            mount_info: paths.mount_info.clone(),
         }
     }
-    Err(MountError::MountInfoError)
-}
 
-/// Default helper: check /boot RW state using shared MOUNT_INFO_PATH
-pub fn is_boot_rw() -> Result<bool, MountError> {
-    is_boot_rw_at(Path::new(MOUNT_INFO_PATH))
-}
+    /// same mount info, different mount point, e.g. /boot/efi
+    pub fn with_target(mut self, target: impl Into<PathBuf>) -> Self {
+        self.target = target.into();
+        self
+    }
 
-#[cfg(not(feature = "test-remount"))]
-fn remount_boot_ro_at(mounts_path: &Path) -> Result<(), MountError> {
-    match is_boot_rw_at(mounts_path)? {
-        true => {
-            let output = Command::new("mount")
-                .arg("-o")
-                .arg("remount,bind,ro")
-                .arg("/boot")
-                .stderr(Stdio::piped()) // Capture stderr for error handling
-                .output();
-
-            match output {
-                Ok(output) => {
-                    if output.status.success() {
-                        Ok(())
-                    } else {
-                        let error_message = String::from_utf8_lossy(&output.stderr);
-                        warn!("Failed to remount /boot as RO using shell: {error_message}");
-                        Err(MountError::RemountFailed(error_message.to_string()))
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// true if the target is currently mounted read-write
+    pub fn is_rw(&self) -> Result<bool, MountError> {
+        let mounts =
+            fs::read_to_string(&self.mount_info).map_err(|_| MountError::MountInfoError)?;
+        // the last entry of a mount point is the one stacked on top and visible
+        mounts
+            .lines()
+            .rev()
+            .find_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                match fields.as_slice() {
+                    [_, mount_point, _, options, ..]
+                        if Path::new(&unescape(mount_point)) == self.target =>
+                    {
+                        Some(options.split(',').any(|option| option == "rw"))
                     }
+                    _ => None,
                 }
-                Err(e) => {
-                    warn!("Failed to execute mount command: {e}");
-                    Err(MountError::RemountFailed(format!(
-                        "Failed to execute mount: {e}"
-                    )))
-                }
-            }
+            })
+            .ok_or_else(|| MountError::NotMounted(self.target.clone()))
+    }
+
+    /// remounts the target read-write, nothing to do if it already is
+    pub fn remount_rw(&self) -> Result<(), MountError> {
+        if self.is_rw()? {
+            info!("{} is already read-write", self.target.display());
+            return Ok(());
         }
-        false => {
-            info!("/boot is already read-only");
-            Ok(())
+        self.remount("remount,rw")
+    }
+
+    /// remounts the target read-only, nothing to do if it already is
+    pub fn remount_ro(&self) -> Result<(), MountError> {
+        if !self.is_rw()? {
+            info!("{} is already read-only", self.target.display());
+            return Ok(());
         }
+        self.remount("remount,bind,ro")
     }
-}
 
-#[cfg(not(feature = "test-remount"))]
-fn remount_boot_rw_at(mounts_path: &Path) -> Result<(), MountError> {
-    match is_boot_rw_at(mounts_path)? {
-        false => {
-            let output = Command::new("mount")
-                .arg("-o")
-                .arg("remount,rw")
-                .arg("/boot")
-                .stderr(Stdio::piped()) // Capture stderr for error handling
-                .output();
-
-            match output {
-                Ok(output) => {
-                    if output.status.success() {
-                        Ok(())
-                    } else {
-                        let error_message = String::from_utf8_lossy(&output.stderr);
-                        warn!("Failed to remount /boot as RW using shell: {error_message}");
-                        Err(MountError::RemountFailed(error_message.to_string()))
-                    }
-                }
-                Err(e) => {
-                    warn!("Failed to execute mount command: {e}");
-                    Err(MountError::RemountFailed(format!(
-                        "Failed to execute mount: {e}"
-                    )))
-                }
-            }
+    /// makes the target writable until the returned guard is released or
+    /// dropped, a target that was read-only is then remounted read-only again
+    pub fn rw_guard(&self) -> Result<BootMountGuard<'_>, MountError> {
+        let was_rw = self.is_rw()?;
+        info!(
+            "Initial {} mount state: {}",
+            self.target.display(),
+            if was_rw { "rw" } else { "ro" }
+        );
+        if !was_rw {
+            info!("Remounting {} as rw for operation", self.target.display());
+            self.remount_rw()?;
         }
-        true => {
-            info!("/boot is already read-write");
-            Ok(())
+        Ok(BootMountGuard {
+            mount: self,
+            restore: !was_rw,
+        })
+    }
+
+    #[cfg(not(feature = "test-remount"))]
+    fn remount(&self, options: &str) -> Result<(), MountError> {
+        let output = Command::new("mount")
+            .arg("-o")
+            .arg(options)
+            .arg(&self.target)
+            .stderr(Stdio::piped()) // Capture stderr for error handling
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => Ok(()),
+            Ok(output) => {
+                let error_message = String::from_utf8_lossy(&output.stderr);
+                warn!(
+                    "Failed to remount {} with {options}: {error_message}",
+                    self.target.display()
+                );
+                Err(MountError::RemountFailed(error_message.to_string()))
+            }
+            Err(e) => {
+                warn!("Failed to execute mount command: {e}");
+                Err(MountError::RemountFailed(format!(
+                    "Failed to execute mount: {e}"
+                )))
+            }
         }
     }
+
+    /// For testing without actually remounting
+    #[cfg(feature = "test-remount")]
+    fn remount(&self, _options: &str) -> Result<(), MountError> {
+        Ok(())
+    }
 }
 
-/// Default helper: remount /boot RO using shared MOUNT_INFO_PATH
-#[cfg(not(feature = "test-remount"))]
-pub fn remount_boot_ro() -> Result<(), MountError> {
-    remount_boot_ro_at(Path::new(MOUNT_INFO_PATH))
+/// Read-write window on a BootMount, see BootMount::rw_guard. Whether the
+/// target has to be restored is state of the guard alone, there is no
+/// process-wide record of the original mount state: a guard taken while
+/// another one holds the target writable finds it read-write and leaves the
+/// restore to the outer one.
+#[derive(Debug)]
+pub struct BootMountGuard<'a> {
+    mount: &'a BootMount,
+    restore: bool,
 }
 
-/// Default helper: remount /boot RW using shared MOUNT_INFO_PATH
-#[cfg(not(feature = "test-remount"))]
-pub fn remount_boot_rw() -> Result<(), MountError> {
-    remount_boot_rw_at(Path::new(MOUNT_INFO_PATH))
+impl BootMountGuard<'_> {
+    /// restores the read-only state now, reporting a failure to do so that
+    /// dropping the guard could only log
+    pub fn release(mut self) -> Result<(), MountError> {
+        self.restore()
+    }
+
+    fn restore(&mut self) -> Result<(), MountError> {
+        if !std::mem::take(&mut self.restore) {
+            return Ok(());
+        }
+        info!("Restoring {} mount to ro", self.mount.target.display());
+        self.mount.remount_ro()
+    }
+}
+
+impl Drop for BootMountGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            warn!(
+                "cannot restore {} to read-only: {e}",
+                self.mount.target.display()
+            );
+        }
+    }
 }
 
-/// For testing without actually remounting /mount
-#[cfg(feature = "test-remount")]
-fn remount_boot_rw_at(_mounts_path: &Path) -> Result<(), MountError> {
-    Ok(())
+/// /proc/mounts escapes spaces, tabs, newlines and backslashes as octal
+fn unescape(field: &str) -> String {
+    field
+        .replace("\\040", " ")
+        .replace("\\011", "\t")
+        .replace("\\012", "\n")
+        .replace("\\134", "\\")
 }
-/// For testing without actually remounting /mount
-#[cfg(feature = "test-remount")]
-fn remount_boot_ro_at(_mounts_path: &Path) -> Result<(), MountError> {
-    Ok(())
+
+/// Default helper: check /boot RW state using the configured mount info
+pub fn is_boot_rw(paths: &Paths) -> Result<bool, MountError> {
+    BootMount::new(paths).is_rw()
 }
 
-/// For testing feature: default helpers no-op
-#[cfg(feature = "test-remount")]
-pub fn remount_boot_rw() -> Result<(), MountError> {
-    Ok(())
+/// Default helper: remount /boot RO using the configured mount info
+pub fn remount_boot_ro(paths: &Paths) -> Result<(), MountError> {
+    BootMount::new(paths).remount_ro()
 }
-#[cfg(feature = "test-remount")]
-pub fn remount_boot_ro() -> Result<(), MountError> {
-    Ok(())
+
+/// Default helper: remount /boot RW using the configured mount info
+pub fn remount_boot_rw(paths: &Paths) -> Result<(), MountError> {
+    BootMount::new(paths).remount_rw()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
-    fn create_mock_file(content: &str) -> std::path::PathBuf {
+    fn mock_mount(content: &str, target: &str) -> (tempfile::TempDir, BootMount) {
         let dir = tempfile::tempdir().unwrap();
-        let path = dir.path().join("mounts");
-        std::fs::write(&path, content).unwrap();
-        Box::leak(Box::new(dir));
-        path
+        let mut paths = Paths::with_root(dir.path());
+        paths.mount_info = dir.path().join("mounts");
+        fs::write(&paths.mount_info, content).unwrap();
+        (dir, BootMount::new(&paths).with_target(target))
     }
 
     #[cfg(not(feature = "test-remount"))]
     #[test]
     fn test_remount_boot_ro_when_already_ro() {
-        let mounts_content = "rootfs / rootfs rw 0 0\n\
-                             none /boot tmpfs ro 0 0\n";
-        let mounts_path = create_mock_file(mounts_content);
-
-        let result = remount_boot_ro_at(&mounts_path);
-        assert!(result.is_ok());
+        let (_dir, mount) =
+            mock_mount("rootfs / rootfs rw 0 0\nnone /boot tmpfs ro 0 0\n", "/boot");
+        assert!(mount.remount_ro().is_ok());
     }
 
     #[cfg(not(feature = "test-remount"))]
     #[test]
     fn test_remount_boot_rw_when_already_rw() {
-        let mounts_content = "rootfs / rootfs rw 0 0\n\
-                             none /boot tmpfs rw 0 0\n";
-        let mounts_path = create_mock_file(mounts_content);
+        let (_dir, mount) =
+            mock_mount("rootfs / rootfs rw 0 0\nnone /boot tmpfs rw 0 0\n", "/boot");
+        assert!(mount.remount_rw().is_ok());
+        // nothing to restore, releasing must not try to remount
+        assert!(mount.rw_guard().unwrap().release().is_ok());
+    }
 
-        let result = remount_boot_rw_at(&mounts_path);
-        assert!(result.is_ok());
+    #[test]
+    fn test_guards_are_independent() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<BootMount>();
+        assert_send_sync::<BootMountGuard<'_>>();
+
+        let (_dir, mount) = mock_mount("none /boot tmpfs rw 0 0\n", "/boot");
+        let outer = mount.rw_guard().unwrap();
+        let inner = mount.rw_guard().unwrap();
+        assert!(!outer.restore && !inner.restore);
+        std::thread::scope(|s| {
+            s.spawn(|| inner.release().unwrap());
+        });
+        outer.release().unwrap();
     }
 
     #[test]
     fn test_is_boot_rw_detection() {
         // Test RW case - should return true
-        let rw_path = create_mock_file("device /boot ext4 rw,relatime 0 0");
-        assert!(is_boot_rw_at(&rw_path).unwrap());
+        let (_dir, rw) = mock_mount("device /boot ext4 rw,relatime 0 0", "/boot");
+        assert!(rw.is_rw().unwrap());
 
         // Test RO case - should return false
-        let ro_path = create_mock_file("device /boot ext4 ro,relatime 0 0");
-        assert!(!is_boot_rw_at(&ro_path).unwrap());
+        let (_dir, ro) = mock_mount("device /boot ext4 ro,relatime 0 0", "/boot");
+        assert!(!ro.is_rw().unwrap());
 
         // Test missing /boot - should error
-        let missing_path = create_mock_file("device /other ext4 rw 0 0");
-        assert!(is_boot_rw_at(&missing_path).is_err());
+        let (_dir, missing) = mock_mount("device /other ext4 rw 0 0", "/boot");
+        assert!(matches!(missing.is_rw(), Err(MountError::NotMounted(_))));
 
         // Test malformed line - should error
-        let malformed_path = create_mock_file("incomplete fields");
-        assert!(is_boot_rw_at(&malformed_path).is_err());
+        let (_dir, malformed) = mock_mount("incomplete fields", "/boot");
+        assert!(malformed.is_rw().is_err());
+    }
+
+    #[test]
+    fn test_configurable_target() {
+        let mounts = fs::read_to_string("testing_assets/mounts").unwrap();
+        let (_dir, boot) = mock_mount(&mounts, "/boot");
+        assert!(!boot.is_rw().unwrap());
+        // errors=remount-ro is not the ro flag
+        let (_dir, efi) = mock_mount(&mounts, "/boot/efi");
+        assert!(efi.is_rw().unwrap());
+
+        // the entry stacked last is the visible one, escaped names are decoded
+        let (_dir, stacked) = mock_mount(
+            "a /mnt/my\\040boot ext4 ro 0 0\nb /mnt/my\\040boot ext4 rw 0 0\n",
+            "/mnt/my boot",
+        );
+        assert!(stacked.is_rw().unwrap());
+        assert_eq!(stacked.target(), Path::new("/mnt/my boot"));
     }
 }
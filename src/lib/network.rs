@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// delay between two connectivity probes
+static NETWORK_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Grace settings for checks that need the network to be online before running
+#[derive(Debug, Default)]
+pub struct NetworkWait {
+    /// script/binary names that depend on network connectivity
+    pub checks: Vec<String>,
+    /// upper bound spent waiting for connectivity
+    pub timeout: Duration,
+    /// outcome of the wait, so only the first network check of a run pays for it
+    online: OnceLock<bool>,
+}
+
+impl NetworkWait {
+    pub fn new(checks: Vec<String>, timeout: Duration) -> Self {
+        Self {
+            checks,
+            timeout,
+            online: OnceLock::new(),
+        }
+    }
+
+    /// true if the given check has been declared as network dependent
+    pub fn applies_to(&self, check: &str) -> bool {
+        self.checks.iter().any(|c| c == check)
+    }
+
+    /// waits for connectivity before running `check` if it needs the network,
+    /// the check is always executed afterwards even if the wait timed out
+    pub fn wait_for(&self, check: &str) {
+        if !self.applies_to(check) {
+            return;
+        }
+        let online = *self.online.get_or_init(|| wait_for_network(self.timeout));
+        if !online {
+            tracing::warn!(
+                "network is still offline after {}s, running {check} anyway",
+                self.timeout.as_secs()
+            );
+        }
+    }
+}
+
+/// polls network-online.target and NetworkManager until one reports connectivity
+/// or the timeout expires
+fn wait_for_network(timeout: Duration) -> bool {
+    tracing::info!(
+        "waiting up to {}s for network connectivity",
+        timeout.as_secs()
+    );
+    let deadline = Instant::now() + timeout;
+    loop {
+        if is_network_online() {
+            tracing::info!("network is online");
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(NETWORK_POLL_INTERVAL);
+    }
+}
+
+fn is_network_online() -> bool {
+    let target_active = Command::new("systemctl")
+        .args(["is-active", "--quiet", "network-online.target"])
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if target_active {
+        return true;
+    }
+
+    // nm-online -x only checks the current state without waiting for startup
+    Command::new("nm-online")
+        .args(["-q", "-x", "-t", "0"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_applies_only_to_listed_checks() {
+        let wait = NetworkWait::new(
+            vec!["01_repository_dns_check.sh".to_string()],
+            Duration::ZERO,
+        );
+        assert!(wait.applies_to("01_repository_dns_check.sh"));
+        assert!(!wait.applies_to("02_watchdog.sh"));
+    }
+
+    #[test]
+    fn test_unlisted_check_does_not_wait() {
+        let wait = NetworkWait::new(vec![], Duration::from_secs(600));
+        let start = Instant::now();
+        wait.wait_for("02_watchdog.sh");
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(wait.online.get().is_none());
+    }
+}
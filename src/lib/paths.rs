@@ -0,0 +1,331 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+#[cfg(feature = "unstable")]
+use std::collections::BTreeMap;
+#[cfg(feature = "unstable")]
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// dirs that greenboot looks for the health check and other scripts
+pub static DEFAULT_INSTALL_PATHS: [&str; 2] = ["/usr/lib/greenboot", "/etc/greenboot"];
+/// greenboot config path
+pub static DEFAULT_CONFIG_FILE: &str = "/etc/greenboot/greenboot.conf";
+/// drop-in dirs merged over the config file, a file in a later dir masks the
+/// same name in an earlier one
+pub static DEFAULT_CONFIG_DROPIN_DIRS: [&str; 2] = [
+    "/usr/lib/greenboot/greenboot.conf.d",
+    "/etc/greenboot/greenboot.conf.d",
+];
+/// GRUB environment block
+pub static DEFAULT_GRUBENV_PATH: &str = "/boot/grub2/grubenv";
+/// flocked while greenboot updates the grubenv, other writers may take it too
+pub static DEFAULT_GRUBENV_LOCK_PATH: &str = "/run/greenboot/grubenv.lock";
+/// how the last grubenv update of this boot was written, native or grub2-editenv
+pub static DEFAULT_GRUBENV_WRITER_PATH: &str = "/run/greenboot/grubenv-writer";
+/// mount info used to check the /boot mount state
+pub static DEFAULT_MOUNT_INFO_PATH: &str = "/proc/mounts";
+/// MOTD fragment holding the boot status
+pub static DEFAULT_MOTD_PATH: &str = "/etc/motd.d/boot-status";
+/// MOTD fragment written instead when the MOTD path is read-only
+pub static DEFAULT_MOTD_FALLBACK_PATH: &str = "/run/motd.d/greenboot-status";
+/// verdict of the current boot's health-check
+pub static DEFAULT_STATUS_FILE_PATH: &str = "/run/greenboot/status.json";
+/// flag file present on ostree based systems
+pub static DEFAULT_OSTREE_BOOTED_PATH: &str = "/run/ostree-booted";
+/// marker left by a rollback for the next boot, used when the journal is unavailable
+pub static DEFAULT_ROLLBACK_MARKER_PATH: &str = "/var/lib/greenboot/rollback-marker";
+/// boot counter and status when the bootloader cannot keep them, e.g. GRUB
+/// without a writable grubenv
+pub static DEFAULT_STATE_FILE_PATH: &str = "/var/lib/greenboot/state";
+/// checks that failed on the previous boots, to spot repeated failures
+pub static DEFAULT_FAILURE_STREAK_PATH: &str = "/var/lib/greenboot/failure-streak.json";
+/// consecutive green boots of the booted deployment, for the auto-pin
+pub static DEFAULT_GREEN_STREAK_PATH: &str = "/var/lib/greenboot/green-streak.json";
+/// failed boots of each deployment, by commit checksum
+pub static DEFAULT_BOOT_ATTEMPTS_PATH: &str = "/var/lib/greenboot/boot-attempts.json";
+/// details of the checks that failed on the last RED boot
+pub static DEFAULT_LAST_FAILURE_PATH: &str = "/var/lib/greenboot/last-failure.json";
+/// files the scripts leave for the triage of the current boot
+pub static DEFAULT_RESULTS_DIR: &str = "/run/greenboot/results";
+/// verdicts of the previous health-checks, one JSON object per line
+pub static DEFAULT_HISTORY_PATH: &str = "/var/lib/greenboot/history.jsonl";
+/// monitor snapshots waiting to be uploaded
+pub static DEFAULT_MONITOR_SPOOL_PATH: &str = "/var/lib/greenboot/monitor-spool.jsonl";
+/// command line of the running kernel
+pub static DEFAULT_CMDLINE_PATH: &str = "/proc/cmdline";
+/// dir holding the kernels and the bootloader files
+pub static DEFAULT_BOOT_DIR: &str = "/boot";
+/// present when the system was booted by UEFI firmware
+pub static DEFAULT_EFI_FIRMWARE_PATH: &str = "/sys/firmware/efi";
+/// efivarfs mount, holding the UEFI variables
+pub static DEFAULT_EFIVARS_PATH: &str = "/sys/firmware/efi/efivars";
+/// zipl configuration, s390x only
+pub static DEFAULT_ZIPL_CONFIG_PATH: &str = "/etc/zipl.conf";
+/// fw_printenv/fw_setenv configuration of U-Boot systems
+pub static DEFAULT_UBOOT_ENV_CONFIG_PATH: &str = "/etc/fw_env.config";
+/// misc partition holding the A/B boot control block of Android bootloaders
+pub static DEFAULT_ANDROID_MISC_PATH: &str = "/dev/disk/by-partlabel/misc";
+/// RAUC system configuration, present when RAUC manages the slots
+pub static DEFAULT_RAUC_SYSTEM_CONF_PATH: &str = "/etc/rauc/system.conf";
+/// links to the partitions by their GPT label
+pub static DEFAULT_PARTLABEL_DIR: &str = "/dev/disk/by-partlabel";
+/// sysfs entries of the block devices, telling partitions from disks
+pub static DEFAULT_SYS_CLASS_BLOCK_PATH: &str = "/sys/class/block";
+/// log file used when journald is unavailable
+#[cfg(feature = "unstable")]
+pub static DEFAULT_LOG_FILE_PATH: &str = "/var/log/greenboot/greenboot.log";
+
+/// format of the structured config files next to the legacy one
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructuredFormat {
+    Toml,
+    Yaml,
+}
+
+/// Every filesystem location greenboot touches, resolved once at startup from
+/// the built-in defaults, the config file and the command line, then passed
+/// through the library instead of each module hard-coding its own path.
+/// Build it with [`Paths::default`] or [`Paths::with_root`] and change the
+/// fields that differ, as fields may be added.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Paths {
+    /// base dirs holding check/, green.d/ and red.d/, in execution order
+    pub install_paths: Vec<PathBuf>,
+    pub config_file: PathBuf,
+    pub config_dropin_dirs: Vec<PathBuf>,
+    pub grubenv: PathBuf,
+    pub grubenv_lock: PathBuf,
+    pub grubenv_writer: PathBuf,
+    pub mount_info: PathBuf,
+    pub motd: PathBuf,
+    pub motd_fallback: PathBuf,
+    pub status_file: PathBuf,
+    pub ostree_booted: PathBuf,
+    pub rollback_marker: PathBuf,
+    pub state_file: PathBuf,
+    pub failure_streak: PathBuf,
+    pub green_streak: PathBuf,
+    pub boot_attempts: PathBuf,
+    pub last_failure: PathBuf,
+    pub results_dir: PathBuf,
+    pub history: PathBuf,
+    pub cmdline: PathBuf,
+    pub monitor_spool: PathBuf,
+    pub boot_dir: PathBuf,
+    pub efi_firmware: PathBuf,
+    pub efivars: PathBuf,
+    pub zipl_config: PathBuf,
+    pub uboot_env_config: PathBuf,
+    pub android_misc: PathBuf,
+    pub rauc_system_conf: PathBuf,
+    pub partlabel_dir: PathBuf,
+    pub sys_class_block: PathBuf,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Self {
+            install_paths: DEFAULT_INSTALL_PATHS.iter().map(PathBuf::from).collect(),
+            config_file: PathBuf::from(DEFAULT_CONFIG_FILE),
+            config_dropin_dirs: DEFAULT_CONFIG_DROPIN_DIRS
+                .iter()
+                .map(PathBuf::from)
+                .collect(),
+            grubenv: PathBuf::from(DEFAULT_GRUBENV_PATH),
+            grubenv_lock: PathBuf::from(DEFAULT_GRUBENV_LOCK_PATH),
+            grubenv_writer: PathBuf::from(DEFAULT_GRUBENV_WRITER_PATH),
+            mount_info: PathBuf::from(DEFAULT_MOUNT_INFO_PATH),
+            motd: PathBuf::from(DEFAULT_MOTD_PATH),
+            motd_fallback: PathBuf::from(DEFAULT_MOTD_FALLBACK_PATH),
+            status_file: PathBuf::from(DEFAULT_STATUS_FILE_PATH),
+            ostree_booted: PathBuf::from(DEFAULT_OSTREE_BOOTED_PATH),
+            rollback_marker: PathBuf::from(DEFAULT_ROLLBACK_MARKER_PATH),
+            state_file: PathBuf::from(DEFAULT_STATE_FILE_PATH),
+            failure_streak: PathBuf::from(DEFAULT_FAILURE_STREAK_PATH),
+            green_streak: PathBuf::from(DEFAULT_GREEN_STREAK_PATH),
+            boot_attempts: PathBuf::from(DEFAULT_BOOT_ATTEMPTS_PATH),
+            last_failure: PathBuf::from(DEFAULT_LAST_FAILURE_PATH),
+            results_dir: PathBuf::from(DEFAULT_RESULTS_DIR),
+            history: PathBuf::from(DEFAULT_HISTORY_PATH),
+            cmdline: PathBuf::from(DEFAULT_CMDLINE_PATH),
+            monitor_spool: PathBuf::from(DEFAULT_MONITOR_SPOOL_PATH),
+            boot_dir: PathBuf::from(DEFAULT_BOOT_DIR),
+            efi_firmware: PathBuf::from(DEFAULT_EFI_FIRMWARE_PATH),
+            efivars: PathBuf::from(DEFAULT_EFIVARS_PATH),
+            zipl_config: PathBuf::from(DEFAULT_ZIPL_CONFIG_PATH),
+            uboot_env_config: PathBuf::from(DEFAULT_UBOOT_ENV_CONFIG_PATH),
+            android_misc: PathBuf::from(DEFAULT_ANDROID_MISC_PATH),
+            rauc_system_conf: PathBuf::from(DEFAULT_RAUC_SYSTEM_CONF_PATH),
+            partlabel_dir: PathBuf::from(DEFAULT_PARTLABEL_DIR),
+            sys_class_block: PathBuf::from(DEFAULT_SYS_CLASS_BLOCK_PATH),
+        }
+    }
+}
+
+impl Paths {
+    /// default layout rebased under `root`, e.g. a composed image tree or a test dir
+    pub fn with_root(root: &Path) -> Self {
+        let rebase = |path: &Path| root.join(path.strip_prefix("/").unwrap_or(path));
+        let defaults = Self::default();
+        Self {
+            install_paths: defaults.install_paths.iter().map(|p| rebase(p)).collect(),
+            config_file: rebase(&defaults.config_file),
+            config_dropin_dirs: defaults
+                .config_dropin_dirs
+                .iter()
+                .map(|p| rebase(p))
+                .collect(),
+            grubenv: rebase(&defaults.grubenv),
+            grubenv_lock: rebase(&defaults.grubenv_lock),
+            grubenv_writer: rebase(&defaults.grubenv_writer),
+            mount_info: rebase(&defaults.mount_info),
+            motd: rebase(&defaults.motd),
+            motd_fallback: rebase(&defaults.motd_fallback),
+            status_file: rebase(&defaults.status_file),
+            ostree_booted: rebase(&defaults.ostree_booted),
+            rollback_marker: rebase(&defaults.rollback_marker),
+            state_file: rebase(&defaults.state_file),
+            failure_streak: rebase(&defaults.failure_streak),
+            green_streak: rebase(&defaults.green_streak),
+            boot_attempts: rebase(&defaults.boot_attempts),
+            last_failure: rebase(&defaults.last_failure),
+            results_dir: rebase(&defaults.results_dir),
+            history: rebase(&defaults.history),
+            cmdline: rebase(&defaults.cmdline),
+            monitor_spool: rebase(&defaults.monitor_spool),
+            boot_dir: rebase(&defaults.boot_dir),
+            efi_firmware: rebase(&defaults.efi_firmware),
+            efivars: rebase(&defaults.efivars),
+            zipl_config: rebase(&defaults.zipl_config),
+            uboot_env_config: rebase(&defaults.uboot_env_config),
+            android_misc: rebase(&defaults.android_misc),
+            rauc_system_conf: rebase(&defaults.rauc_system_conf),
+            partlabel_dir: rebase(&defaults.partlabel_dir),
+            sys_class_block: rebase(&defaults.sys_class_block),
+        }
+    }
+
+    /// check/required.d dirs of every install path
+    pub fn required_dirs(&self) -> Vec<PathBuf> {
+        self.dirs("check/required.d")
+    }
+
+    /// check/wanted.d dirs of every install path
+    pub fn wanted_dirs(&self) -> Vec<PathBuf> {
+        self.dirs("check/wanted.d")
+    }
+
+    /// green.d dirs of every install path
+    pub fn green_dirs(&self) -> Vec<PathBuf> {
+        self.dirs("green.d")
+    }
+
+    /// red.d dirs of every install path
+    pub fn red_dirs(&self) -> Vec<PathBuf> {
+        self.dirs("red.d")
+    }
+
+    /// pre-reboot.d dirs of every install path
+    pub fn pre_reboot_dirs(&self) -> Vec<PathBuf> {
+        self.dirs("pre-reboot.d")
+    }
+
+    /// greenboot.toml and greenboot.yaml next to the config file, the legacy
+    /// config file wins over them
+    #[cfg(feature = "unstable")]
+    pub fn structured_config_files(&self) -> Vec<(PathBuf, StructuredFormat)> {
+        vec![
+            (
+                self.config_file.with_file_name("greenboot.toml"),
+                StructuredFormat::Toml,
+            ),
+            (
+                self.config_file.with_file_name("greenboot.yaml"),
+                StructuredFormat::Yaml,
+            ),
+        ]
+    }
+
+    /// *.conf drop-ins in lexical order of their file name, a drop-in in a
+    /// later dir masks the one with the same name in an earlier dir
+    #[cfg(feature = "unstable")]
+    pub fn config_dropins(&self) -> Vec<PathBuf> {
+        let mut dropins = BTreeMap::new();
+        for dir in &self.config_dropin_dirs {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+                if path.extension().is_some_and(|ext| ext == "conf")
+                    && let Some(name) = path.file_name()
+                {
+                    dropins.insert(name.to_os_string(), path);
+                }
+            }
+        }
+        dropins.into_values().collect()
+    }
+
+    fn dirs(&self, sub_dir: &str) -> Vec<PathBuf> {
+        self.install_paths.iter().map(|p| p.join(sub_dir)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_with_root_rebases_every_path() {
+        let paths = Paths::with_root(Path::new("/sysroot"));
+        assert_eq!(
+            paths.install_paths,
+            vec![
+                PathBuf::from("/sysroot/usr/lib/greenboot"),
+                PathBuf::from("/sysroot/etc/greenboot")
+            ]
+        );
+        assert_eq!(paths.grubenv, PathBuf::from("/sysroot/boot/grub2/grubenv"));
+        assert_eq!(paths.motd, PathBuf::from("/sysroot/etc/motd.d/boot-status"));
+    }
+
+    #[test]
+    fn test_check_dirs_follow_install_paths() {
+        let paths = Paths::default();
+        assert_eq!(
+            paths.required_dirs(),
+            vec![
+                PathBuf::from("/usr/lib/greenboot/check/required.d"),
+                PathBuf::from("/etc/greenboot/check/required.d")
+            ]
+        );
+        assert_eq!(paths.red_dirs()[1], PathBuf::from("/etc/greenboot/red.d"));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_config_dropins_order_and_masking() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let (usr, etc) = (&paths.config_dropin_dirs[0], &paths.config_dropin_dirs[1]);
+        fs::create_dir_all(usr).unwrap();
+        fs::create_dir_all(etc).unwrap();
+        for file in ["20-vendor.conf", "10-image.conf", "README"] {
+            fs::write(usr.join(file), "").unwrap();
+        }
+        for file in ["20-vendor.conf", "30-site.conf"] {
+            fs::write(etc.join(file), "").unwrap();
+        }
+
+        assert_eq!(
+            paths.config_dropins(),
+            vec![
+                usr.join("10-image.conf"),
+                etc.join("20-vendor.conf"),
+                etc.join("30-site.conf")
+            ]
+        );
+    }
+}
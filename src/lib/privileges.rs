@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use nix::unistd::{Gid, Uid, User, getgrouplist, setgid, setgroups, setuid};
+use std::ffi::CString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Account a check runs as instead of root, e.g. a network check that has
+/// no business being privileged. It is resolved before the check is spawned
+/// as looking up users is not possible between fork and exec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckUser {
+    pub name: String,
+    pub uid: Uid,
+    pub gid: Gid,
+    /// supplementary groups of the user, including its primary group
+    pub groups: Vec<Gid>,
+    pub home: PathBuf,
+}
+
+impl CheckUser {
+    pub fn lookup(name: &str) -> io::Result<Self> {
+        let user = User::from_name(name)
+            .map_err(io::Error::from)?
+            .ok_or_else(|| io::Error::other(format!("unknown user {name}")))?;
+        let c_name = CString::new(name).map_err(io::Error::other)?;
+        let groups = getgrouplist(&c_name, user.gid).map_err(io::Error::from)?;
+        Ok(Self {
+            name: user.name,
+            uid: user.uid,
+            gid: user.gid,
+            groups,
+            home: user.dir,
+        })
+    }
+
+    /// makes `command` drop root for this user once spawned, with the
+    /// variables a login would set
+    pub fn apply(&self, command: &mut Command) {
+        command
+            .env("USER", &self.name)
+            .env("LOGNAME", &self.name)
+            .env("HOME", &self.home);
+        let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+        // SAFETY: setgroups, setgid and setuid are async-signal-safe and the
+        // groups were allocated before the fork
+        unsafe {
+            command.pre_exec(move || {
+                setgroups(&groups)?;
+                setgid(gid)?;
+                setuid(uid)?;
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lookup_check_user() {
+        let root = CheckUser::lookup("root").unwrap();
+        assert!(root.uid.is_root());
+        assert!(root.groups.contains(&root.gid));
+        assert!(CheckUser::lookup("no-such-greenboot-user").is_err());
+
+        // dropping root needs root
+        let Ok(nobody) = CheckUser::lookup("nobody") else {
+            return;
+        };
+        if !Uid::current().is_root() {
+            return;
+        }
+        let mut command = Command::new("id");
+        nobody.apply(&mut command);
+        let output = command.output().unwrap();
+        let id = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            id.starts_with(&format!("uid={}(nobody)", nobody.uid)),
+            "{id}"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use nix::errno::Errno;
+use nix::libc::c_int;
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, killpg, raise, sigaction};
+use nix::unistd::Pid;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+/// more scripts than this only run concurrently with many red.d or green.d
+/// actions, the groups beyond it are not killed when greenboot is terminated
+const MAX_TRACKED_GROUPS: usize = 64;
+
+/// process groups of the running scripts, 0 for a free slot
+static GROUPS: [AtomicI32; MAX_TRACKED_GROUPS] = [const { AtomicI32::new(0) }; MAX_TRACKED_GROUPS];
+
+/// The process group a check or action script runs in, so it is killed as a
+/// whole on timeout and once the script exits: children a script left in
+/// the background cannot outlive it and hold sockets or locks. Running
+/// groups are also killed when greenboot is terminated.
+#[derive(Debug)]
+pub struct ProcessGroup {
+    pgid: i32,
+    slot: Option<usize>,
+}
+
+impl ProcessGroup {
+    /// spawns `command` as the leader of a new process group
+    pub fn spawn(command: &mut Command) -> io::Result<(Child, Self)> {
+        let child = command.process_group(0).spawn()?;
+        let pgid = child.id() as i32;
+        let slot = GROUPS.iter().position(|slot| {
+            slot.compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+        });
+        if slot.is_none() {
+            tracing::debug!("too many running scripts, process group {pgid} is not tracked");
+        }
+        Ok((child, Self { pgid, slot }))
+    }
+
+    /// kills every process left in the group
+    pub fn kill(&self) {
+        match killpg(Pid::from_raw(self.pgid), Signal::SIGKILL) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(e) => tracing::warn!("cannot kill process group {}: {e}", self.pgid),
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        self.kill();
+        if let Some(slot) = self.slot {
+            GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// kills the process groups of the running scripts, then lets `signal`
+/// terminate greenboot as it would have without the handler
+extern "C" fn terminate(signal: c_int) {
+    for slot in &GROUPS {
+        let pgid = slot.load(Ordering::SeqCst);
+        if pgid != 0 {
+            let _ = killpg(Pid::from_raw(pgid), Signal::SIGKILL);
+        }
+    }
+    // SA_RESETHAND restored the default action, it is delivered on return
+    if let Ok(signal) = Signal::try_from(signal) {
+        let _ = raise(signal);
+    }
+}
+
+/// kills the scripts still running when greenboot gets SIGTERM or SIGINT,
+/// e.g. when systemd stops the health-check
+pub fn install_termination_handler() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(terminate),
+        SaFlags::SA_RESETHAND,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGTERM, Signal::SIGINT] {
+        // SAFETY: the handler only loads atomics and calls killpg and raise,
+        // which are async-signal-safe
+        unsafe { sigaction(signal, &action) }?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_group_is_killed_with_background_children() {
+        let dir = tempfile::tempdir().unwrap();
+        let pid_file = dir.path().join("pid");
+        let mut command = Command::new("sh");
+        command.args([
+            "-c",
+            &format!("sleep 60 & echo $! > {}", pid_file.display()),
+        ]);
+        let (mut child, group) = ProcessGroup::spawn(&mut command).unwrap();
+        assert!(child.wait().unwrap().success());
+        assert!(
+            GROUPS
+                .iter()
+                .any(|slot| slot.load(Ordering::SeqCst) == group.pgid)
+        );
+        let sleep: i32 = std::fs::read_to_string(&pid_file)
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        let pgid = nix::unistd::getpgid(Some(Pid::from_raw(sleep))).unwrap();
+        assert_eq!(pgid.as_raw(), group.pgid);
+
+        drop(group);
+        // a killed process is a zombie until whoever it was reparented to reaps it
+        let alive = || {
+            std::fs::read_to_string(format!("/proc/{sleep}/stat"))
+                .is_ok_and(|stat| !stat.rsplit(") ").next().unwrap().starts_with('Z'))
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while alive() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!alive());
+    }
+}
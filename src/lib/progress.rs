@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::webhook::CheckOutcome;
+
+/// how often the line of the running check is redrawn
+const TICK: Duration = Duration::from_millis(100);
+static SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+/// moves to the start of the line and erases it
+static CLEAR_LINE: &str = "\r\x1b[2K";
+
+static PROGRESS: OnceLock<Progress> = OnceLock::new();
+
+#[derive(Debug)]
+struct Running {
+    stage: String,
+    check: String,
+    started: Instant,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    running: Option<Running>,
+    frame: usize,
+}
+
+/// Live list of the checks of a health-check run on a terminal: a line per
+/// completed check with a glyph of its outcome and its duration, below which
+/// the running check is redrawn with a spinner and the time it has taken so
+/// far. Logs are written above the list rather than across it.
+#[derive(Debug, Default)]
+pub struct Progress {
+    state: Mutex<State>,
+    /// glyphs are colored
+    color: bool,
+}
+
+/// shows the progress of the checks when stderr is a terminal, otherwise
+/// plain logs are all there is
+pub fn enable_progress(color: bool) -> Option<&'static Progress> {
+    if !io::stderr().is_terminal() || std::env::var("TERM").as_deref() == Ok("dumb") {
+        return None;
+    }
+    let mut enabled = false;
+    let progress = PROGRESS.get_or_init(|| {
+        enabled = true;
+        Progress {
+            color,
+            ..Progress::default()
+        }
+    });
+    if enabled {
+        thread::spawn(|| {
+            loop {
+                thread::sleep(TICK);
+                progress.tick();
+            }
+        });
+    }
+    Some(progress)
+}
+
+/// the progress display, if enable_progress turned it on
+pub fn progress() -> Option<&'static Progress> {
+    PROGRESS.get()
+}
+
+impl Progress {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// `check` of `stage` is being run
+    pub fn started(&self, stage: &str, check: &str) {
+        let mut state = self.lock();
+        state.running = Some(Running {
+            stage: stage.to_string(),
+            check: check.to_string(),
+            started: Instant::now(),
+        });
+        draw(&state, &mut io::stderr().lock());
+    }
+
+    /// `check` of `stage` is done, it moves to the list
+    pub fn finished(&self, stage: &str, check: &str, outcome: CheckOutcome, duration: Duration) {
+        let mut state = self.lock();
+        if state
+            .running
+            .as_ref()
+            .is_some_and(|running| running.check == check)
+        {
+            state.running = None;
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = writeln!(
+            stderr,
+            "{CLEAR_LINE}{}",
+            check_line(stage, check, outcome, duration, self.color)
+        );
+        draw(&state, &mut stderr);
+    }
+
+    /// prints the verdict below the list
+    pub fn verdict(&self, verdict: &str) {
+        let mut state = self.lock();
+        state.running = None;
+        let _ = writeln!(io::stderr().lock(), "{CLEAR_LINE}health-check {verdict}");
+    }
+
+    /// writes a log record above the list
+    pub fn write_log(&self, buf: &[u8]) -> io::Result<()> {
+        let state = self.lock();
+        let mut stderr = io::stderr().lock();
+        write!(stderr, "{CLEAR_LINE}")?;
+        stderr.write_all(buf)?;
+        draw(&state, &mut stderr);
+        Ok(())
+    }
+
+    fn tick(&self) {
+        let mut state = self.lock();
+        if state.running.is_some() {
+            state.frame = (state.frame + 1) % SPINNER.len();
+            draw(&state, &mut io::stderr().lock());
+        }
+    }
+}
+
+/// redraws the line of the running check
+fn draw(state: &State, out: &mut impl Write) {
+    if let Some(running) = &state.running {
+        let _ = write!(
+            out,
+            "{CLEAR_LINE}  {} {} {} {}",
+            SPINNER[state.frame],
+            running.stage,
+            running.check,
+            format_duration(running.started.elapsed())
+        );
+        let _ = out.flush();
+    }
+}
+
+/// line of a completed check: its outcome as a glyph, colored if `color`,
+/// and how long it took
+pub fn check_line(
+    stage: &str,
+    check: &str,
+    outcome: CheckOutcome,
+    duration: Duration,
+    color: bool,
+) -> String {
+    let (glyph, sgr) = match outcome {
+        CheckOutcome::Passed => ("✔", "32"),
+        CheckOutcome::Failed => ("✘", "31"),
+        CheckOutcome::Error => ("!", "33"),
+        CheckOutcome::Skipped => ("-", "2"),
+    };
+    let (start, end) = match color {
+        true => (format!("\x1b[{sgr}m"), "\x1b[0m"),
+        false => (String::new(), ""),
+    };
+    match outcome {
+        CheckOutcome::Skipped => format!("  {start}{glyph} {stage} {check} skipped{end}"),
+        _ => format!(
+            "  {start}{glyph}{end} {stage} {check} {}",
+            format_duration(duration)
+        ),
+    }
+}
+
+/// tenths of a second, minutes and seconds past a minute
+fn format_duration(duration: Duration) -> String {
+    match duration.as_secs() {
+        secs @ 60.. => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{:.1}s", duration.as_secs_f64()),
+    }
+}
+
+/// log target going above the progress list while it is shown
+#[derive(Debug, Default)]
+pub struct ProgressLog;
+
+impl Write for ProgressLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match progress() {
+            Some(progress) => progress.write_log(buf)?,
+            None => io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_progress_lines() {
+        assert_eq!(
+            check_line(
+                "required",
+                "01_dns_check.sh",
+                CheckOutcome::Passed,
+                Duration::from_millis(1240),
+                true
+            ),
+            "  \x1b[32m✔\x1b[0m required 01_dns_check.sh 1.2s"
+        );
+        assert_eq!(
+            check_line(
+                "wanted",
+                "02_mqtt.sh",
+                CheckOutcome::Failed,
+                Duration::from_secs(95),
+                true
+            ),
+            "  \x1b[31m✘\x1b[0m wanted 02_mqtt.sh 1m35s"
+        );
+        assert_eq!(
+            check_line(
+                "wanted",
+                "03_vpn.sh",
+                CheckOutcome::Skipped,
+                Duration::ZERO,
+                false
+            ),
+            "  - wanted 03_vpn.sh skipped"
+        );
+
+        let state = State {
+            running: Some(Running {
+                stage: "required".to_string(),
+                check: "03_disk.sh".to_string(),
+                started: Instant::now(),
+            }),
+            frame: 1,
+        };
+        let mut drawn = Vec::new();
+        draw(&state, &mut drawn);
+        assert_eq!(
+            String::from_utf8(drawn).unwrap(),
+            "\r\x1b[2K  ⠙ required 03_disk.sh 0.0s"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::process::Command;
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+use crate::statefile::StateFileBackend;
+
+/// RAUC already owns the slot selection of the bootloader it drives (barebox,
+/// U-Boot, GRUB or EFI), so greenboot only reports its verdict on the booted
+/// slot with `rauc status mark-good`/`mark-bad`. The boot counter and the
+/// rollback trigger are kept in the state file and the boots counted by
+/// greenboot, and marking the slot bad is the rollback: the bootloader then
+/// boots the other slot of the pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaucBackend {
+    state: StateFileBackend,
+}
+
+impl RaucBackend {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            state: StateFileBackend::new(paths.state_file.clone()),
+        }
+    }
+
+    /// `mark` is mark-good or mark-bad
+    fn mark_booted(&self, mark: &str) -> Result<()> {
+        let output = Command::new("rauc")
+            .args(["status", mark, "booted"])
+            .output()
+            .context("Unable to run rauc")?;
+        if !output.status.success() {
+            bail!(
+                "rauc status {mark} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        tracing::info!("rauc status {mark} booted");
+        Ok(())
+    }
+}
+
+impl BootloaderBackend for RaucBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        self.state.boot_counter()
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        self.state.set_boot_counter(attempts)
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.state.unset_boot_counter()
+    }
+
+    /// a failed boot is left to the attempts of RAUC's bootloader backend,
+    /// the slot is only marked bad by the rollback
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        self.state.set_boot_status(success)?;
+        if success {
+            self.mark_booted("mark-good")?;
+        }
+        Ok(())
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        self.state.rollback_trigger()
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.state.set_rollback_trigger()
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.state.unset_rollback_trigger()
+    }
+
+    fn count_boot(&self) -> Result<()> {
+        self.state.count_boot()
+    }
+
+    fn rollback(&self) -> Result<bool> {
+        self.mark_booted("mark-bad")?;
+        Ok(true)
+    }
+
+    /// RAUC systems always have the other slot of the pair
+    fn has_rollback_target(&self) -> Result<Option<bool>> {
+        Ok(Some(true))
+    }
+
+    fn store(&self) -> String {
+        format!("{} and the RAUC slot status", self.state.store())
+    }
+}
+
+/// true if RAUC manages the slots of this system
+pub fn managed_by_rauc(paths: &Paths) -> bool {
+    paths.rauc_system_conf.exists()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_rauc_counts_in_state_file() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        assert!(!managed_by_rauc(&paths));
+        fs::create_dir_all(paths.rauc_system_conf.parent().unwrap()).unwrap();
+        fs::write(&paths.rauc_system_conf, "[system]\nbootloader=uboot\n").unwrap();
+        assert!(managed_by_rauc(&paths));
+
+        let backend = RaucBackend::new(&paths);
+        backend.set_boot_counter(2).unwrap();
+        // a failure does not go through rauc
+        backend.set_boot_status(false).unwrap();
+        backend.count_boot().unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), Some(1));
+        assert!(backend.store().ends_with("and the RAUC slot status"));
+    }
+}
@@ -0,0 +1,149 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use serde_json::Value;
+use std::time::Duration;
+
+use crate::journal::JournalEntry;
+use crate::status::HealthStatus;
+use crate::webhook::CheckOutcome;
+
+/// result of a check as a health-check logged it to the journal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedCheck {
+    /// required or wanted
+    pub stage: String,
+    pub check: String,
+    pub outcome: CheckOutcome,
+    pub duration: Duration,
+    /// what the check wrote to RESULT_FD
+    pub message: Option<String>,
+}
+
+impl LoggedCheck {
+    fn from_entry(entry: &JournalEntry) -> Option<Self> {
+        Some(Self {
+            stage: entry.field("GREENBOOT_PHASE")?.to_string(),
+            check: entry.field("GREENBOOT_CHECK")?.to_string(),
+            outcome: parse_field(entry, "GREENBOOT_OUTCOME")?,
+            duration: entry
+                .field("GREENBOOT_DURATION_MS")
+                .and_then(|ms| ms.parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or_default(),
+            message: entry.field("GREENBOOT_CHECK_MESSAGE").map(str::to_string),
+        })
+    }
+}
+
+/// A health-check as found in the journal: the result of each of its checks,
+/// in the order they ran, and the verdict it ended with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedRun {
+    /// seconds since the epoch when the verdict was logged
+    pub finished_at: Option<u64>,
+    pub status: HealthStatus,
+    pub checks: Vec<LoggedCheck>,
+    pub failed_checks: Vec<String>,
+    pub messages: Vec<String>,
+    pub hints: Vec<String>,
+}
+
+fn parse_field<T: serde::de::DeserializeOwned>(entry: &JournalEntry, name: &str) -> Option<T> {
+    let value = entry.field(name)?;
+    serde_json::from_value(Value::String(value.to_string())).ok()
+}
+
+fn lines(entry: &JournalEntry, name: &str) -> Vec<String> {
+    entry
+        .field(name)
+        .map(|value| value.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn is_verdict(entry: &JournalEntry) -> bool {
+    parse_field::<HealthStatus>(entry, "GREENBOOT_STATUS").is_some()
+}
+
+/// The last health-check that reached a verdict among `entries`, oldest
+/// first. Its checks are those logged by the same process since its previous
+/// verdict, which leaves out the checks of `greenboot monitor` and remediation
+/// retries of an earlier run.
+pub fn last_run(entries: &[JournalEntry]) -> Option<LoggedRun> {
+    let end = entries.iter().rposition(is_verdict)?;
+    let verdict = &entries[end];
+    let pid = verdict.field("_PID");
+    let same_process = |entry: &JournalEntry| entry.field("_PID") == pid;
+    let start = entries[..end]
+        .iter()
+        .rposition(|entry| is_verdict(entry) && same_process(entry))
+        .map_or(0, |previous| previous + 1);
+    Some(LoggedRun {
+        finished_at: verdict
+            .field("__REALTIME_TIMESTAMP")
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .map(|usec| usec / 1_000_000),
+        status: parse_field(verdict, "GREENBOOT_STATUS")?,
+        checks: entries[start..end]
+            .iter()
+            .filter(|entry| same_process(entry))
+            .filter_map(LoggedCheck::from_entry)
+            .collect(),
+        failed_checks: verdict
+            .field("GREENBOOT_FAILED_CHECKS")
+            .map(|failed| failed.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+        messages: lines(verdict, "GREENBOOT_CHECK_MESSAGES"),
+        hints: lines(verdict, "GREENBOOT_HINTS"),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entries(json: &[&str]) -> Vec<JournalEntry> {
+        json.iter()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_last_run() {
+        let entries = entries(&[
+            r#"{"_PID": "310", "GREENBOOT_PHASE": "required", "GREENBOOT_CHECK": "01_dns.sh", "GREENBOOT_OUTCOME": "failed", "GREENBOOT_DURATION_MS": "40"}"#,
+            r#"{"_PID": "310", "GREENBOOT_STATUS": "RED", "GREENBOOT_FAILED_CHECKS": "01_dns.sh"}"#,
+            r#"{"_PID": "512", "MESSAGE": "running required check 01_dns.sh"}"#,
+            r#"{"_PID": "512", "GREENBOOT_PHASE": "required", "GREENBOOT_CHECK": "01_dns.sh", "GREENBOOT_OUTCOME": "passed", "GREENBOOT_DURATION_MS": "310"}"#,
+            r#"{"_PID": "733", "GREENBOOT_PHASE": "wanted", "GREENBOOT_CHECK": "02_mqtt.sh", "GREENBOOT_OUTCOME": "passed", "GREENBOOT_DURATION_MS": "5"}"#,
+            r#"{"_PID": "512", "GREENBOOT_PHASE": "wanted", "GREENBOOT_CHECK": "02_mqtt.sh", "GREENBOOT_OUTCOME": "failed", "GREENBOOT_DURATION_MS": "5012", "GREENBOOT_CHECK_MESSAGE": "broker unreachable"}"#,
+            r#"{"_PID": "512", "__REALTIME_TIMESTAMP": "1760600000123456", "GREENBOOT_STATUS": "DEGRADED", "GREENBOOT_FAILED_CHECKS": "02_mqtt.sh", "GREENBOOT_CHECK_MESSAGES": "02_mqtt.sh: broker unreachable"}"#,
+        ]);
+        assert_eq!(
+            last_run(&entries),
+            Some(LoggedRun {
+                finished_at: Some(1_760_600_000),
+                status: HealthStatus::Degraded,
+                checks: vec![
+                    LoggedCheck {
+                        stage: "required".to_string(),
+                        check: "01_dns.sh".to_string(),
+                        outcome: CheckOutcome::Passed,
+                        duration: Duration::from_millis(310),
+                        message: None,
+                    },
+                    LoggedCheck {
+                        stage: "wanted".to_string(),
+                        check: "02_mqtt.sh".to_string(),
+                        outcome: CheckOutcome::Failed,
+                        duration: Duration::from_millis(5012),
+                        message: Some("broker unreachable".to_string()),
+                    },
+                ],
+                failed_checks: vec!["02_mqtt.sh".to_string()],
+                messages: vec!["02_mqtt.sh: broker unreachable".to_string()],
+                hints: vec![],
+            })
+        );
+        assert_eq!(last_run(&entries[..1]), None);
+    }
+}
@@ -0,0 +1,260 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+
+/// vendor GUID of the variables systemd-boot exports to the OS
+static LOADER_GUID: &str = "4a67b082-0a4c-41cf-b6c7-440b29bb8c4f";
+/// BLS entry dirs relative to the boot dir: an XBOOTLDR or ESP mounted on
+/// /boot, then an ESP mounted on /boot/efi
+static ENTRIES_CANDIDATES: [&str; 2] = ["loader/entries", "efi/loader/entries"];
+/// flag file next to the entries dir, systemd-boot has no variable for it
+static ROLLBACK_TRIGGER_FILE: &str = "greenboot-rollback-trigger";
+
+/// systemd-boot's boot assessment keeps the counter in the name of the BLS
+/// entry, `<id>+<left>[-<done>].conf`: the bootloader decrements it on every
+/// try and sorts an entry with no tries left last, systemd-bless-boot drops
+/// it once boot-complete.target is reached, which greenboot is required by
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdBootBackend {
+    entries_dir: PathBuf,
+    efivars: PathBuf,
+}
+
+impl SdBootBackend {
+    pub fn new(paths: &Paths) -> Self {
+        let entries_dir = ENTRIES_CANDIDATES
+            .iter()
+            .map(|candidate| paths.boot_dir.join(candidate))
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| paths.boot_dir.join(ENTRIES_CANDIDATES[0]));
+        Self {
+            entries_dir,
+            efivars: paths.efivars.clone(),
+        }
+    }
+
+    /// entry file of the current boot, found through the LoaderEntrySelected
+    /// variable, whose id carries no counter
+    fn booted_entry(&self) -> Result<(PathBuf, BlsEntryName)> {
+        let selected = read_loader_variable(&self.efivars, "LoaderEntrySelected")
+            .context("Unable to find the booted systemd-boot entry")?;
+        let selected = selected.strip_suffix(".conf").unwrap_or(&selected);
+        let entries = fs::read_dir(&self.entries_dir)
+            .with_context(|| format!("Unable to read {}", self.entries_dir.display()))?;
+        entries
+            .filter_map(|entry| entry.ok())
+            .find_map(|entry| {
+                let name = BlsEntryName::parse(&entry.file_name().to_string_lossy())?;
+                (name.id == selected).then(|| (entry.path(), name))
+            })
+            .with_context(|| {
+                format!(
+                    "booted entry {selected} not found in {}",
+                    self.entries_dir.display()
+                )
+            })
+    }
+
+    /// renames the booted entry to carry `counter`, none drops it
+    fn rename_booted(&self, counter: Option<(u32, u32)>) -> Result<()> {
+        let (path, name) = self.booted_entry()?;
+        if name.counter == counter {
+            return Ok(());
+        }
+        let renamed = BlsEntryName { counter, ..name };
+        let target = path.with_file_name(renamed.to_string());
+        fs::rename(&path, &target).with_context(|| {
+            format!(
+                "Unable to rename {} to {}",
+                path.display(),
+                target.display()
+            )
+        })?;
+        tracing::info!("Renamed boot entry to {}", target.display());
+        Ok(())
+    }
+
+    fn rollback_trigger_file(&self) -> PathBuf {
+        self.entries_dir.with_file_name(ROLLBACK_TRIGGER_FILE)
+    }
+}
+
+impl BootloaderBackend for SdBootBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        let (_, name) = self.booted_entry()?;
+        Ok(name.counter.map(|(left, _)| left as i32))
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        let (_, name) = self.booted_entry()?;
+        if let Some((left, _)) = name.counter {
+            bail!("already set boot_counter={left}");
+        }
+        self.rename_booted(Some((attempts as u32, 0)))
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.rename_booted(None)
+    }
+
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        // what systemd-bless-boot good does, a failure leaves the remaining
+        // tries to the counter rather than marking the entry bad right away
+        if success {
+            self.unset_boot_counter()?;
+        }
+        Ok(())
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        Ok(self.rollback_trigger_file().exists())
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        let path = self.rollback_trigger_file();
+        fs::write(&path, "1\n").with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        let path = self.rollback_trigger_file();
+        match fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Unable to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn store(&self) -> String {
+        format!(
+            "boot counting of the entries in {}",
+            self.entries_dir.display()
+        )
+    }
+}
+
+/// true if the firmware says systemd-boot started this boot
+pub fn booted_by_sdboot(paths: &Paths) -> bool {
+    read_loader_variable(&paths.efivars, "LoaderInfo")
+        .is_some_and(|info| info.starts_with("systemd-boot"))
+}
+
+/// file name of a BLS entry split into its id and boot counter (left, done)
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BlsEntryName {
+    id: String,
+    counter: Option<(u32, u32)>,
+}
+
+impl BlsEntryName {
+    fn parse(file_name: &str) -> Option<Self> {
+        let stem = file_name.strip_suffix(".conf")?;
+        let counter = stem.rsplit_once('+').and_then(|(id, counter)| {
+            let (left, done) = counter.split_once('-').unwrap_or((counter, "0"));
+            Some((id, (left.parse().ok()?, done.parse().ok()?)))
+        });
+        Some(match counter {
+            Some((id, counter)) => Self {
+                id: id.to_string(),
+                counter: Some(counter),
+            },
+            None => Self {
+                id: stem.to_string(),
+                counter: None,
+            },
+        })
+    }
+}
+
+impl fmt::Display for BlsEntryName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.counter {
+            Some((left, 0)) => write!(f, "{}+{left}.conf", self.id),
+            Some((left, done)) => write!(f, "{}+{left}-{done}.conf", self.id),
+            None => write!(f, "{}.conf", self.id),
+        }
+    }
+}
+
+/// string variable of the loader, stored as 4 attribute bytes followed by
+/// NUL terminated UTF-16LE
+fn read_loader_variable(efivars: &Path, name: &str) -> Option<String> {
+    let raw = fs::read(efivars.join(format!("{name}-{LOADER_GUID}"))).ok()?;
+    let units: Vec<u16> = raw
+        .get(4..)?
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write_loader_variable(paths: &Paths, name: &str, value: &str) {
+        let efivars = &paths.efivars;
+        fs::create_dir_all(efivars).unwrap();
+        let mut raw = vec![6, 0, 0, 0];
+        for unit in value.encode_utf16().chain([0]) {
+            raw.extend(unit.to_le_bytes());
+        }
+        fs::write(efivars.join(format!("{name}-{LOADER_GUID}")), raw).unwrap();
+    }
+
+    #[test]
+    fn test_bls_entry_name() {
+        for (file_name, id, counter) in [
+            ("fedora-6.8.conf", "fedora-6.8", None),
+            ("fedora-6.8+3.conf", "fedora-6.8", Some((3, 0))),
+            ("fedora-6.8+0-3.conf", "fedora-6.8", Some((0, 3))),
+            ("fedora+rt.conf", "fedora+rt", None),
+        ] {
+            let name = BlsEntryName::parse(file_name).unwrap();
+            assert_eq!((name.id.as_str(), name.counter), (id, counter));
+            assert_eq!(name.to_string(), file_name);
+        }
+        assert_eq!(BlsEntryName::parse("loader.conf.bak"), None);
+    }
+
+    #[test]
+    fn test_sdboot_backend_counter() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let entries = paths.boot_dir.join("loader/entries");
+        fs::create_dir_all(&entries).unwrap();
+        fs::write(entries.join("ostree-1.conf"), "").unwrap();
+        fs::write(entries.join("ostree-2+1-2.conf"), "").unwrap();
+        write_loader_variable(&paths, "LoaderEntrySelected", "ostree-1.conf");
+        write_loader_variable(&paths, "LoaderInfo", "systemd-boot 255.4");
+        assert!(booted_by_sdboot(&paths));
+
+        let backend = SdBootBackend::new(&paths);
+        assert_eq!(backend.boot_counter().unwrap(), None);
+        backend.set_boot_counter(3).unwrap();
+        assert!(entries.join("ostree-1+3.conf").exists());
+        assert_eq!(backend.boot_counter().unwrap(), Some(3));
+        assert!(backend.set_boot_counter(2).is_err());
+
+        // failures leave the counter to systemd-boot, a success blesses the entry
+        backend.set_boot_status(false).unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), Some(3));
+        backend.set_boot_status(true).unwrap();
+        assert!(entries.join("ostree-1.conf").exists());
+        assert!(entries.join("ostree-2+1-2.conf").exists());
+
+        assert!(!backend.rollback_trigger().unwrap());
+        backend.set_rollback_trigger().unwrap();
+        assert!(backend.rollback_trigger().unwrap());
+        backend.unset_rollback_trigger().unwrap();
+        backend.unset_rollback_trigger().unwrap();
+        assert!(!backend.rollback_trigger().unwrap());
+    }
+}
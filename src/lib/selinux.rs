@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Result, bail};
+use nix::fcntl::{OFlag, open};
+use nix::sys::stat::Mode;
+use nix::unistd::write;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::Command;
+
+/// exists when SELinux is enabled, see is_selinux_enabled(3)
+static SELINUX_ENFORCE: &str = "/sys/fs/selinux/enforce";
+/// context the next exec of the calling thread transitions to, see
+/// setexeccon(3)
+static EXEC_ATTR: &str = "/proc/thread-self/attr/exec";
+
+pub fn selinux_enabled() -> bool {
+    Path::new(SELINUX_ENFORCE).exists()
+}
+
+/// a context is `user:role:type` with an optional MLS/MCS level, e.g.
+/// `system_u:system_r:greenboot_check_t:s0`
+pub fn parse_selinux_context(value: &str) -> Result<String> {
+    let value = value.trim();
+    let fields: Vec<&str> = value.splitn(4, ':').collect();
+    if fields.len() < 3 || fields.iter().any(|field| field.is_empty()) {
+        bail!("invalid SELinux context '{value}', expected user:role:type[:level]");
+    }
+    Ok(value.to_string())
+}
+
+/// makes `command` run in `context` once spawned, like setexeccon(3), so a
+/// check lands in a confined domain instead of inheriting the one of
+/// greenboot. The exec fails if the policy does not allow the transition.
+pub fn set_exec_context(command: &mut Command, context: &str) -> io::Result<()> {
+    let path = CString::new(EXEC_ATTR).map_err(io::Error::other)?;
+    let context = CString::new(context).map_err(io::Error::other)?;
+    // SAFETY: open, write and close are async-signal-safe and the strings
+    // were allocated before the fork
+    unsafe {
+        command.pre_exec(move || {
+            let attr = open(
+                path.as_c_str(),
+                OFlag::O_WRONLY | OFlag::O_CLOEXEC,
+                Mode::empty(),
+            )?;
+            write(&attr, context.as_bytes())?;
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+/// context process `pid` runs in, none without SELinux or once it is gone
+pub fn process_context(pid: u32) -> Option<String> {
+    let context = fs::read_to_string(format!("/proc/{pid}/attr/current")).ok()?;
+    let context = context.trim_end_matches(['\0', '\n']);
+    (!context.is_empty()).then(|| context.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_selinux_context() {
+        assert_eq!(
+            parse_selinux_context(" system_u:system_r:greenboot_check_t:s0-s0:c0.c1023").unwrap(),
+            "system_u:system_r:greenboot_check_t:s0-s0:c0.c1023"
+        );
+        assert!(parse_selinux_context("system_u:system_r:greenboot_check_t").is_ok());
+        assert!(parse_selinux_context("greenboot_check_t").is_err());
+        assert!(parse_selinux_context("system_u::greenboot_check_t").is_err());
+    }
+}
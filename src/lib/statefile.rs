@@ -0,0 +1,187 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::bootloader::BootloaderBackend;
+
+/// Keeps the grubenv variables in a `key=value` file for bootloaders that
+/// cannot store them, and counts the boot itself at the start of the
+/// health-check. Once the counter is exhausted the next failure rolls back
+/// through the OS deployment manager rather than the bootloader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateFileBackend {
+    state_file: PathBuf,
+}
+
+impl StateFileBackend {
+    pub fn new(state_file: PathBuf) -> Self {
+        Self { state_file }
+    }
+
+    /// file the variables are kept in
+    pub fn path(&self) -> &Path {
+        &self.state_file
+    }
+
+    fn read_state(&self) -> Result<BTreeMap<String, String>> {
+        let content = match fs::read_to_string(&self.state_file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Unable to read {}", self.state_file.display()));
+            }
+        };
+        Ok(content
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect())
+    }
+
+    /// rewrites the whole file through a rename, a reader never sees half of it
+    fn write_state(&self, state: &BTreeMap<String, String>) -> Result<()> {
+        let content: String = state
+            .iter()
+            .map(|(key, value)| format!("{key}={value}\n"))
+            .collect();
+        let staged = self.state_file.with_extension("new");
+        self.state_file
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&staged, content))
+            .and_then(|_| fs::rename(&staged, &self.state_file))
+            .with_context(|| format!("Unable to write {}", self.state_file.display()))
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BTreeMap<String, String>)) -> Result<()> {
+        let mut state = self.read_state()?;
+        change(&mut state);
+        self.write_state(&state)
+    }
+}
+
+fn counter(state: &BTreeMap<String, String>) -> Result<Option<i32>> {
+    state
+        .get("boot_counter")
+        .map(|value| {
+            value
+                .parse()
+                .with_context(|| format!("boot_counter has invalid value: {value}"))
+        })
+        .transpose()
+}
+
+impl BootloaderBackend for StateFileBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        counter(&self.read_state()?)
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        if let Some(counter) = self.boot_counter()? {
+            bail!("already set boot_counter={counter}");
+        }
+        self.update(|state| {
+            state.insert("boot_counter".to_string(), attempts.to_string());
+        })
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.update(|state| {
+            state.remove("boot_counter");
+        })
+    }
+
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        self.update(|state| {
+            state.insert("boot_success".to_string(), u8::from(success).to_string());
+            if success {
+                state.remove("boot_counter");
+            }
+        })
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        Ok(self
+            .read_state()?
+            .get("greenboot_rollback_trigger")
+            .is_some_and(|value| value == "1"))
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.update(|state| {
+            state.insert("greenboot_rollback_trigger".to_string(), "1".to_string());
+        })
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.update(|state| {
+            state.remove("greenboot_rollback_trigger");
+        })
+    }
+
+    /// what 08_greenboot.cfg does in GRUB: a boot following an unsuccessful
+    /// one uses up an attempt, down to -1 once the countdown has ended
+    fn count_boot(&self) -> Result<()> {
+        let mut state = self.read_state()?;
+        if let Some(counter) = counter(&state)?
+            && state.get("boot_success").map(String::as_str) == Some("0")
+        {
+            let counter = if counter <= 0 { -1 } else { counter - 1 };
+            tracing::info!("boot counter is now {counter}");
+            state.insert("boot_counter".to_string(), counter.to_string());
+        }
+        state.insert("boot_success".to_string(), "0".to_string());
+        self.write_state(&state)
+    }
+
+    fn env_var(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.read_state()?.get(key).cloned())
+    }
+
+    fn set_env_var(&self, key: &str, value: &str) -> Result<()> {
+        self.update(|state| {
+            state.insert(key.to_string(), value.to_string());
+        })
+    }
+
+    fn store(&self) -> String {
+        format!("state file {}", self.state_file.display())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_state_file_counts_boots() {
+        let root = tempfile::tempdir().unwrap();
+        let state_file = root.path().join("lib/greenboot/state");
+        let backend = StateFileBackend::new(state_file.clone());
+
+        backend.count_boot().unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), None);
+        backend.set_boot_status(false).unwrap();
+        backend.set_boot_counter(2).unwrap();
+        assert!(backend.set_boot_counter(2).is_err());
+
+        for expected in [1, 0, -1, -1] {
+            backend.count_boot().unwrap();
+            assert_eq!(backend.boot_counter().unwrap(), Some(expected));
+        }
+
+        backend.set_rollback_trigger().unwrap();
+        assert!(backend.rollback_trigger().unwrap());
+        backend.set_boot_status(true).unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), None);
+        assert_eq!(
+            fs::read_to_string(&state_file).unwrap(),
+            "boot_success=1\ngreenboot_rollback_trigger=1\n"
+        );
+    }
+}
@@ -0,0 +1,529 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result};
+#[cfg(feature = "unstable")]
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "unstable")]
+use std::borrow::Cow;
+use std::fmt;
+use std::fs;
+#[cfg(feature = "unstable")]
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::paths::Paths;
+
+/// verdict of a health-check run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+#[non_exhaustive]
+pub enum HealthStatus {
+    /// required and wanted checks passed
+    Green,
+    /// required checks passed but at least one wanted check failed
+    Degraded,
+    /// a required check failed
+    Red,
+    /// greenboot itself could not complete the health-check
+    Error,
+}
+
+impl fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthStatus::Green => write!(f, "GREEN"),
+            HealthStatus::Degraded => write!(f, "DEGRADED"),
+            HealthStatus::Red => write!(f, "RED"),
+            HealthStatus::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// persisted outcome of the last health-check, consumed by `greenboot status`,
+/// built with [`StatusRecord::new`] as fields may be added
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct StatusRecord {
+    pub status: HealthStatus,
+    /// names of the checks that failed during the run
+    pub failed_checks: Vec<String>,
+    /// seconds since the epoch when the verdict was reached
+    pub timestamp: u64,
+    /// remediation advice for checks that keep failing across boots
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+    /// `<check>: <message>` reported by the failed checks on fd 3
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<String>,
+    /// a red boot with no deployment to roll back to, left running
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_rollback_target: bool,
+    /// the rollback this boot followed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback: Option<RollbackMarker>,
+}
+
+impl StatusRecord {
+    pub fn new(status: HealthStatus, failed_checks: Vec<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            status,
+            failed_checks,
+            timestamp,
+            hints: vec![],
+            messages: vec![],
+            no_rollback_target: false,
+            rollback: None,
+        }
+    }
+
+    pub fn with_hints(mut self, hints: Vec<String>) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    pub fn with_messages(mut self, messages: Vec<String>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    pub fn with_rollback(mut self, rollback: Option<RollbackMarker>) -> Self {
+        self.rollback = rollback;
+        self
+    }
+}
+
+/// StatusRecord borrowing its strings from the buffer the status file was
+/// read into, for pollers that read it often
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[non_exhaustive]
+pub struct StatusView<'a> {
+    pub status: HealthStatus,
+    #[serde(borrow, deserialize_with = "borrowed_strs")]
+    pub failed_checks: Vec<Cow<'a, str>>,
+    pub timestamp: u64,
+    #[serde(default, borrow, deserialize_with = "borrowed_strs")]
+    pub hints: Vec<Cow<'a, str>>,
+    #[serde(default, borrow, deserialize_with = "borrowed_strs")]
+    pub messages: Vec<Cow<'a, str>>,
+    #[serde(default)]
+    pub no_rollback_target: bool,
+    #[serde(default)]
+    pub rollback: Option<RollbackMarker>,
+}
+
+/// serde only borrows a Cow that is a field itself, not one inside a Vec
+#[cfg(feature = "unstable")]
+fn borrowed_strs<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Cow<'de, str>>, D::Error> {
+    let strs: Vec<BorrowedStr<'de>> = Vec::deserialize(deserializer)?;
+    Ok(strs.into_iter().map(|s| s.0).collect())
+}
+
+/// a string borrowed from the input unless it holds escapes
+#[cfg(feature = "unstable")]
+struct BorrowedStr<'a>(Cow<'a, str>);
+
+#[cfg(feature = "unstable")]
+impl<'de> Deserialize<'de> for BorrowedStr<'de> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct StrVisitor;
+
+        impl<'de> Visitor<'de> for StrVisitor {
+            type Value = BorrowedStr<'de>;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a string")
+            }
+
+            fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(BorrowedStr(Cow::Owned(v.to_string())))
+            }
+        }
+
+        deserializer.deserialize_str(StrVisitor)
+    }
+}
+
+/// checks that failed on the last consecutive boots
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailureStreak {
+    /// sorted names of the failed checks
+    pub failed_checks: Vec<String>,
+    /// number of consecutive boots that failed with exactly these checks
+    pub boots: u32,
+}
+
+/// records the checks that failed on this boot, returns for how many
+/// consecutive boots the very same checks failed, 0 when none failed
+#[cfg(feature = "unstable")]
+pub fn update_failure_streak(paths: &Paths, failed_checks: &[String]) -> Result<u32> {
+    let path = &paths.failure_streak;
+    let mut failed = failed_checks.to_vec();
+    failed.sort();
+    failed.dedup();
+
+    let previous: FailureStreak = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
+        Err(_) => FailureStreak::default(),
+    };
+    let boots = if failed.is_empty() {
+        0
+    } else if previous.failed_checks == failed {
+        previous.boots.saturating_add(1)
+    } else {
+        1
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    let streak = FailureStreak {
+        failed_checks: failed,
+        boots,
+    };
+    fs::write(path, serde_json::to_string(&streak)?)
+        .with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok(boots)
+}
+
+/// consecutive GREEN boots of one deployment
+#[cfg(feature = "unstable")]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GreenStreak {
+    /// `ostree=` deployment the boots were counted for
+    pub deployment: Option<String>,
+    pub boots: u32,
+    /// the deployment was pinned by greenboot
+    #[serde(default)]
+    pub pinned: bool,
+}
+
+#[cfg(feature = "unstable")]
+impl GreenStreak {
+    /// the streak after a boot of `deployment`, any verdict but GREEN or
+    /// another deployment starts it over
+    pub fn record(self, deployment: Option<&str>, green: bool) -> Self {
+        if !green {
+            return Self {
+                deployment: deployment.map(str::to_string),
+                ..Self::default()
+            };
+        }
+        if self.deployment.as_deref() == deployment {
+            return Self {
+                boots: self.boots.saturating_add(1),
+                ..self
+            };
+        }
+        Self {
+            deployment: deployment.map(str::to_string),
+            boots: 1,
+            pinned: false,
+        }
+    }
+}
+
+/// the streak so far, empty when it was never recorded or is unreadable
+#[cfg(feature = "unstable")]
+pub fn read_green_streak(paths: &Paths) -> GreenStreak {
+    fs::read_to_string(&paths.green_streak)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "unstable")]
+pub fn write_green_streak(paths: &Paths, streak: &GreenStreak) -> Result<()> {
+    let path = &paths.green_streak;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    fs::write(path, serde_json::to_string(streak)?)
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// writes the verdict of the current run to the status file
+pub fn write_status(paths: &Paths, record: &StatusRecord) -> Result<()> {
+    write_status_at(record, &paths.status_file)
+}
+
+fn write_status_at(record: &StatusRecord, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    let json = serde_json::to_string_pretty(record)?;
+    // renamed over the old one so `greenboot status` never reads half of it
+    let staged = path.with_extension("new");
+    fs::write(&staged, json)
+        .and_then(|_| fs::rename(&staged, path))
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// reads the verdict of the current boot, none if health-check has not finished yet
+pub fn read_status(paths: &Paths) -> Result<Option<StatusRecord>> {
+    read_status_at(&paths.status_file)
+}
+
+fn read_status_at(path: &Path) -> Result<Option<StatusRecord>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Unable to read {}", path.display()))?;
+    let record = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid status file {}", path.display()))?;
+    Ok(Some(record))
+}
+
+/// reads the verdict of the current boot into `buf`, none if health-check has
+/// not finished yet; only the lists of failed checks, hints and messages
+/// allocate, a status file larger than `buf` is an error
+#[cfg(feature = "unstable")]
+pub fn read_status_view<'a>(paths: &Paths, buf: &'a mut [u8]) -> Result<Option<StatusView<'a>>> {
+    read_status_view_at(&paths.status_file, buf)
+}
+
+#[cfg(feature = "unstable")]
+fn read_status_view_at<'a>(path: &Path, buf: &'a mut [u8]) -> Result<Option<StatusView<'a>>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+    };
+    let mut len = 0;
+    loop {
+        if len == buf.len() {
+            anyhow::bail!("{} is larger than {len} bytes", path.display());
+        }
+        match file
+            .read(&mut buf[len..])
+            .with_context(|| format!("Unable to read {}", path.display()))?
+        {
+            0 => break,
+            read => len += read,
+        }
+    }
+    let view = serde_json::from_slice(&buf[..len])
+        .with_context(|| format!("Invalid status file {}", path.display()))?;
+    Ok(Some(view))
+}
+
+/// flags the verdict of the current boot as having no rollback target
+#[cfg(feature = "unstable")]
+pub fn mark_no_rollback_target(paths: &Paths) -> Result<()> {
+    let mut record = read_status(paths)?.context("no status recorded in this boot")?;
+    record.no_rollback_target = true;
+    write_status(paths, &record)
+}
+
+/// A rollback as recorded when it was done, for the boot that follows it to
+/// report without the journal of the previous boot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct RollbackMarker {
+    /// seconds since the epoch when the rollback was done
+    pub timestamp: u64,
+    /// `ostree=` deployment rolled back from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    /// deployment rolled back to, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+impl RollbackMarker {
+    /// a rollback done now
+    pub fn new(from: Option<String>, to: Option<String>) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self::at(timestamp, from, to)
+    }
+
+    /// a rollback done at `timestamp`, seconds since the epoch
+    pub fn at(timestamp: u64, from: Option<String>, to: Option<String>) -> Self {
+        Self {
+            timestamp,
+            from,
+            to,
+        }
+    }
+}
+
+/// leaves a marker telling the next boot that a rollback happened
+#[cfg(feature = "unstable")]
+pub fn write_rollback_marker(paths: &Paths, marker: &RollbackMarker) -> Result<()> {
+    let path = &paths.rollback_marker;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Unable to create {}", parent.display()))?;
+    }
+    fs::write(path, serde_json::to_string(marker)?)
+        .with_context(|| format!("Unable to write {}", path.display()))
+}
+
+/// consumes the rollback marker, some if the previous boot rolled back. A
+/// marker of an older greenboot only holds the timestamp, one that cannot
+/// be read at all still tells that a rollback happened.
+#[cfg(feature = "unstable")]
+pub fn take_rollback_marker(paths: &Paths) -> Result<Option<RollbackMarker>> {
+    let path = &paths.rollback_marker;
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            tracing::warn!("cannot read {}: {e}", path.display());
+            String::new()
+        }
+    };
+    let marker = serde_json::from_str(&content)
+        .unwrap_or_else(|_| RollbackMarker::at(content.trim().parse().unwrap_or(0), None, None));
+    fs::remove_file(path).with_context(|| format!("Unable to remove {}", path.display()))?;
+    Ok(Some(marker))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_status_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("greenboot/status.json");
+
+        assert_eq!(read_status_at(&path).unwrap(), None);
+
+        let record = StatusRecord::new(
+            HealthStatus::Degraded,
+            vec!["01_update_platforms_check.sh".to_string()],
+        );
+        write_status_at(&record, &path).unwrap();
+        assert_eq!(read_status_at(&path).unwrap(), Some(record));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_status_view_borrows_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("status.json");
+        let mut buf = [0; 512];
+        assert_eq!(read_status_view_at(&path, &mut buf).unwrap(), None);
+
+        let record = StatusRecord::new(HealthStatus::Red, vec!["a \"b\".sh".to_string()])
+            .with_hints(vec!["reseat the modem".to_string()])
+            .with_messages(vec!["a \"b\".sh: no carrier".to_string()]);
+        write_status_at(&record, &path).unwrap();
+        let view = read_status_view_at(&path, &mut buf).unwrap().unwrap();
+        assert_eq!(view.status, HealthStatus::Red);
+        assert_eq!(view.failed_checks, ["a \"b\".sh"]);
+        assert!(matches!(view.hints[0], Cow::Borrowed("reseat the modem")));
+        assert_eq!(view.messages, ["a \"b\".sh: no carrier"]);
+
+        assert!(!view.no_rollback_target);
+
+        let mut small = [0; 16];
+        assert!(read_status_view_at(&path, &mut small).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_mark_no_rollback_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        assert!(mark_no_rollback_target(&paths).is_err());
+
+        write_status(&paths, &StatusRecord::new(HealthStatus::Red, vec![])).unwrap();
+        mark_no_rollback_target(&paths).unwrap();
+        let mut buf = [0; 512];
+        let view = read_status_view(&paths, &mut buf).unwrap().unwrap();
+        assert!(view.no_rollback_target);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_rollback_marker_consumed_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+
+        assert_eq!(take_rollback_marker(&paths).unwrap(), None);
+        let marker = RollbackMarker::new(
+            Some("/ostree/boot.1/fedora-iot/3e5f/0".to_string()),
+            Some("fedora-iot-9a1c.0".to_string()),
+        );
+        write_rollback_marker(&paths, &marker).unwrap();
+        assert_eq!(take_rollback_marker(&paths).unwrap(), Some(marker));
+        assert_eq!(take_rollback_marker(&paths).unwrap(), None);
+
+        // left by a greenboot writing only the timestamp
+        fs::create_dir_all(paths.rollback_marker.parent().unwrap()).unwrap();
+        fs::write(&paths.rollback_marker, "1760600000").unwrap();
+        let legacy = take_rollback_marker(&paths).unwrap().unwrap();
+        assert_eq!((legacy.timestamp, legacy.from), (1_760_600_000, None));
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_failure_streak_counts_identical_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        let failed = vec!["b.sh".to_string(), "a.sh".to_string()];
+
+        assert_eq!(update_failure_streak(&paths, &failed).unwrap(), 1);
+        let reordered = vec!["a.sh".to_string(), "b.sh".to_string()];
+        assert_eq!(update_failure_streak(&paths, &reordered).unwrap(), 2);
+        assert_eq!(update_failure_streak(&paths, &failed[..1]).unwrap(), 1);
+        assert_eq!(update_failure_streak(&paths, &[]).unwrap(), 0);
+        assert_eq!(update_failure_streak(&paths, &failed[..1]).unwrap(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "unstable")]
+    fn test_green_streak_per_deployment() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(dir.path());
+        let streak = read_green_streak(&paths)
+            .record(Some("abc.0"), true)
+            .record(Some("abc.0"), true);
+        assert_eq!(streak.boots, 2);
+        write_green_streak(
+            &paths,
+            &GreenStreak {
+                pinned: true,
+                ..streak
+            },
+        )
+        .unwrap();
+
+        let streak = read_green_streak(&paths);
+        assert!(streak.pinned);
+        assert_eq!(streak.clone().record(Some("abc.0"), true).boots, 3);
+        assert!(streak.clone().record(Some("abc.0"), true).pinned);
+        assert_eq!(streak.clone().record(Some("def.0"), true).boots, 1);
+        assert!(!streak.clone().record(Some("def.0"), true).pinned);
+        assert_eq!(streak.record(Some("abc.0"), false).boots, 0);
+    }
+
+    #[test]
+    fn test_status_serialized_upper_case() {
+        let record = StatusRecord::new(HealthStatus::Degraded, vec![]);
+        let json = serde_json::to_string(&record).unwrap();
+        assert!(json.contains("\"status\":\"DEGRADED\""));
+    }
+}
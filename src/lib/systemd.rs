@@ -0,0 +1,600 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::greenboot::{Diagnostics, DiagnosticsError, FailedCheck};
+use crate::metadata::CheckSettings;
+use crate::privileges::CheckUser;
+use crate::selinux::set_exec_context;
+
+/// name the built-in check is reported under, next to the script names
+pub static SYSTEMD_JOBS_CHECK: &str = "systemd-jobs";
+/// greenboot's own units are busy while the health-check runs
+static GREENBOOT_UNITS: [&str; 2] = ["greenboot-healthcheck.service", "greenboot-monitor.service"];
+/// delay between two looks at the systemd job queue
+static SETTLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// exists when systemd is the init system, see sd_booted(3)
+static SYSTEMD_RUNTIME_DIR: &str = "/run/systemd/system";
+/// runs the checks in transient units
+pub static SYSTEMD_RUN: &str = "systemd-run";
+/// unit settings of a sandboxed check: the file system is read-only but for
+/// a private /tmp, and only pseudo devices like /dev/null are available
+static SANDBOX_PROPERTIES: [&str; 5] = [
+    "ProtectSystem=strict",
+    "ProtectHome=read-only",
+    "PrivateTmp=yes",
+    "PrivateDevices=yes",
+    "NoNewPrivileges=yes",
+];
+
+/// how a boot whose systemd jobs do not settle is judged
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum JobsCheck {
+    /// the job queue is not looked at
+    #[default]
+    Off,
+    /// unsettled jobs make the boot degraded
+    Wanted,
+    /// unsettled jobs make the boot red
+    Required,
+}
+
+impl fmt::Display for JobsCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobsCheck::Off => write!(f, "off"),
+            JobsCheck::Wanted => write!(f, "wanted"),
+            JobsCheck::Required => write!(f, "required"),
+        }
+    }
+}
+
+impl FromStr for JobsCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "off" => Ok(JobsCheck::Off),
+            "wanted" => Ok(JobsCheck::Wanted),
+            "required" => Ok(JobsCheck::Required),
+            other => bail!("unknown systemd jobs check '{other}'"),
+        }
+    }
+}
+
+/// Built-in check run once the scripts passed: the boot only counts as
+/// healthy if systemd has no running job and no unit stuck activating, such
+/// as a service in its restart loop, within `settle_timeout`. Jobs merely
+/// waiting are left out as they may be ordered after greenboot itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemdJobs {
+    pub check: JobsCheck,
+    pub settle_timeout: Duration,
+}
+
+impl SystemdJobs {
+    pub fn new(check: JobsCheck, settle_timeout: Duration) -> Self {
+        Self {
+            check,
+            settle_timeout,
+        }
+    }
+
+    /// adds the outcome of the check to the one of the scripts, a red boot
+    /// stays red and the check is not run
+    pub fn apply(&self, result: Result<Diagnostics>) -> Result<Diagnostics> {
+        let mut diagnostics = match (self.check, result) {
+            (JobsCheck::Off, result) | (_, result @ Err(_)) => return result,
+            (_, Ok(diagnostics)) => diagnostics,
+        };
+        let started = SystemTime::now();
+        let unsettled = match self.wait_settled() {
+            Ok(unsettled) => unsettled,
+            Err(e) => {
+                tracing::warn!(
+                    "cannot look at the systemd jobs, skipping {SYSTEMD_JOBS_CHECK}: {e}"
+                );
+                return Ok(diagnostics);
+            }
+        };
+        if unsettled.is_empty() {
+            diagnostics.passed += 1;
+            return Ok(diagnostics);
+        }
+        tracing::error!(
+            "systemd units still not settled after {}s: {}",
+            self.settle_timeout.as_secs(),
+            unsettled.join(", ")
+        );
+        let failed = FailedCheck::new(
+            SYSTEMD_JOBS_CHECK,
+            None,
+            &format!("units not settled: {}", unsettled.join(", ")),
+            started,
+        );
+        match self.check {
+            JobsCheck::Required => Err(DiagnosticsError::RequiredFailed {
+                failed: vec![SYSTEMD_JOBS_CHECK.to_string()],
+                passed: diagnostics.passed,
+                details: vec![failed],
+            }
+            .into()),
+            _ => {
+                diagnostics
+                    .failed_wanted
+                    .push(SYSTEMD_JOBS_CHECK.to_string());
+                diagnostics.wanted_details.push(failed);
+                Ok(diagnostics)
+            }
+        }
+    }
+
+    /// polls systemd until nothing is busy or the timeout expires, and
+    /// returns the units still busy
+    fn wait_settled(&self) -> Result<Vec<String>> {
+        let deadline = Instant::now() + self.settle_timeout;
+        loop {
+            let unsettled = unsettled_units()?;
+            if unsettled.is_empty() || Instant::now() >= deadline {
+                return Ok(unsettled);
+            }
+            tracing::debug!(
+                "waiting for systemd units to settle: {}",
+                unsettled.join(", ")
+            );
+            thread::sleep(SETTLE_POLL_INTERVAL);
+        }
+    }
+}
+
+fn unsettled_units() -> Result<Vec<String>> {
+    let jobs = systemctl(&["list-jobs", "--no-legend", "--plain"])?;
+    let units = systemctl(&[
+        "list-units",
+        "--all",
+        "--state=activating",
+        "--no-legend",
+        "--plain",
+    ])?;
+    let unsettled: BTreeSet<String> = running_jobs(&jobs)
+        .chain(activating_units(&units))
+        .filter(|unit| !GREENBOOT_UNITS.contains(unit))
+        .map(str::to_string)
+        .collect();
+    Ok(unsettled.into_iter().collect())
+}
+
+fn systemctl(args: &[&str]) -> Result<String> {
+    let output = Command::new("systemctl")
+        .args(args)
+        .output()
+        .context("Unable to run systemctl")?;
+    if !output.status.success() {
+        bail!(
+            "systemctl {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// units of the `JOB UNIT TYPE STATE` lines whose job is running
+fn running_jobs(output: &str) -> impl Iterator<Item = &str> {
+    output.lines().filter_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [_, unit, _, "running", ..] => Some(*unit),
+            _ => None,
+        }
+    })
+}
+
+/// first column of the `UNIT LOAD ACTIVE SUB DESCRIPTION` lines
+fn activating_units(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next())
+}
+
+/// how the checks are run, see `GREENBOOT_CHECK_UNIT`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CheckUnit {
+    /// as children of greenboot
+    #[default]
+    Off,
+    /// in a transient scope, still children of greenboot but in their own
+    /// cgroup
+    Scope,
+    /// as a transient service, started by systemd
+    Service,
+}
+
+impl fmt::Display for CheckUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckUnit::Off => write!(f, "off"),
+            CheckUnit::Scope => write!(f, "scope"),
+            CheckUnit::Service => write!(f, "service"),
+        }
+    }
+}
+
+impl FromStr for CheckUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "off" => Ok(CheckUnit::Off),
+            "scope" => Ok(CheckUnit::Scope),
+            "service" => Ok(CheckUnit::Service),
+            other => bail!("unknown check unit '{other}'"),
+        }
+    }
+}
+
+impl CheckUnit {
+    /// `command` run by systemd-run as a transient unit named after the
+    /// `stage` and `check`, so its output is attributed to the check in the
+    /// journal and its resources are accounted. The unit gets the timeout of
+    /// `settings` as RuntimeMaxSec, its limits as cgroup limits and its user,
+    /// a service also the environment set on `command`. Limits need a unit,
+    /// a scope when the checks are not run in one. A sandboxed check always
+    /// runs as a service, only `results_dir` stays writable for it. The
+    /// SELinux context is the SELinuxContext of a service and set by runcon
+    /// in a scope. Without systemd the command is returned as it is, only
+    /// switching to the user and the context, which fails if the user does
+    /// not exist.
+    pub fn wrap(
+        self,
+        mut command: Command,
+        stage: &str,
+        check: &str,
+        settings: &CheckSettings,
+        results_dir: &Path,
+    ) -> io::Result<Command> {
+        let limits = settings.limits;
+        let sandbox = settings.sandbox == Some(true);
+        let unit = match self {
+            // systemd only sandboxes the processes it starts itself
+            _ if sandbox => Some(CheckUnit::Service),
+            CheckUnit::Off if limits.is_empty() => None,
+            CheckUnit::Off => Some(CheckUnit::Scope),
+            unit => Some(unit),
+        };
+        let unit = unit.filter(|unit| {
+            let systemd = Path::new(SYSTEMD_RUNTIME_DIR).exists();
+            if !systemd && sandbox {
+                tracing::warn!("systemd is not running, {check} is not sandboxed");
+            } else if !systemd && !limits.is_empty() {
+                tracing::warn!("systemd is not running, the limits of {check} are not enforced");
+            } else if !systemd {
+                tracing::warn!("systemd is not running, running {check} without a {unit} unit");
+            }
+            systemd
+        });
+        let Some(unit) = unit else {
+            if let Some(context) = &settings.selinux_context {
+                set_exec_context(&mut command, context)?;
+            }
+            if let Some(user) = &settings.user {
+                CheckUser::lookup(user)?.apply(&mut command);
+            }
+            return Ok(command);
+        };
+        let mut wrapped = Command::new(SYSTEMD_RUN);
+        wrapped
+            .args(unit.run_args(&command, stage, check, settings, results_dir))
+            .envs(
+                command
+                    .get_envs()
+                    .filter_map(|(key, value)| Some((key, value?))),
+            );
+        Ok(wrapped)
+    }
+
+    fn run_args(
+        self,
+        command: &Command,
+        stage: &str,
+        check: &str,
+        settings: &CheckSettings,
+        results_dir: &Path,
+    ) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "--quiet".into(),
+            "--collect".into(),
+            format!("--unit={}", unit_name(stage, check, std::process::id())).into(),
+            format!("--description=greenboot {stage} check {check}").into(),
+        ];
+        if let Some(timeout) = settings.timeout {
+            args.push(format!("--property=RuntimeMaxSec={}", timeout.as_secs()).into());
+        }
+        if let Some(bytes) = settings.limits.memory_max {
+            args.push(format!("--property=MemoryMax={bytes}").into());
+        }
+        if let Some(percent) = settings.limits.cpu_quota {
+            args.push(format!("--property=CPUQuota={percent}%").into());
+        }
+        if let Some(user) = &settings.user {
+            args.push(format!("--uid={user}").into());
+        }
+        if settings.sandbox == Some(true) {
+            args.extend(
+                SANDBOX_PROPERTIES
+                    .iter()
+                    .map(|property| format!("--property={property}").into()),
+            );
+            // the leading - keeps a missing directory from failing the unit
+            let mut writable = OsString::from("--property=ReadWritePaths=-");
+            writable.push(results_dir);
+            args.push(writable);
+        }
+        match self {
+            CheckUnit::Scope => args.push("--scope".into()),
+            _ => {
+                if let Some(context) = &settings.selinux_context {
+                    args.push(format!("--property=SELinuxContext={context}").into());
+                }
+                // the exit code and output of a service only come back this way
+                args.extend(["--wait".into(), "--pipe".into()]);
+                for (key, value) in command.get_envs() {
+                    if let Some(value) = value {
+                        let mut setenv = OsString::from("--setenv=");
+                        setenv.push(key);
+                        setenv.push("=");
+                        setenv.push(value);
+                        args.push(setenv);
+                    }
+                }
+            }
+        }
+        args.push("--".into());
+        if let (CheckUnit::Scope, Some(context)) = (self, &settings.selinux_context) {
+            // a scope execs the command itself, the transition is left to runcon
+            args.extend(["runcon".into(), context.into()]);
+        }
+        args.push(command.get_program().into());
+        args.extend(command.get_args().map(OsString::from));
+        args
+    }
+}
+
+/// CPU and memory a check may use, enforced by the cgroup of its unit so a
+/// misbehaving check cannot starve or OOM the device while it boots
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// bytes, beyond which the check is OOM killed
+    pub memory_max: Option<u64>,
+    /// percent of a CPU, above 100 for more than one
+    pub cpu_quota: Option<u32>,
+}
+
+impl ResourceLimits {
+    pub fn is_empty(&self) -> bool {
+        self.memory_max.is_none() && self.cpu_quota.is_none()
+    }
+
+    /// these limits, those of `defaults` where there are none
+    pub fn or(self, defaults: Self) -> Self {
+        Self {
+            memory_max: self.memory_max.or(defaults.memory_max),
+            cpu_quota: self.cpu_quota.or(defaults.cpu_quota),
+        }
+    }
+}
+
+/// bytes of a size with an optional K, M, G or T suffix for powers of 1024,
+/// like systemd's MemoryMax=
+pub fn parse_memory_max(value: &str) -> Result<u64> {
+    let value = value.trim();
+    let (digits, shift) = match value.char_indices().last() {
+        Some((i, 'K')) => (&value[..i], 10),
+        Some((i, 'M')) => (&value[..i], 20),
+        Some((i, 'G')) => (&value[..i], 30),
+        Some((i, 'T')) => (&value[..i], 40),
+        _ => (value, 0),
+    };
+    let size: u64 = digits
+        .parse()
+        .with_context(|| format!("invalid memory size '{value}'"))?;
+    match size.checked_mul(1 << shift) {
+        Some(bytes) if bytes > 0 => Ok(bytes),
+        _ => bail!("invalid memory size '{value}'"),
+    }
+}
+
+/// percent of a CPU, e.g. `50%`, like systemd's CPUQuota=
+pub fn parse_cpu_quota(value: &str) -> Result<u32> {
+    let value = value.trim();
+    match value.strip_suffix('%').map(str::parse) {
+        Some(Ok(percent)) if percent > 0 => Ok(percent),
+        _ => bail!("invalid CPU quota '{value}', expected a percentage like 50%"),
+    }
+}
+
+/// `greenboot-<stage>-<check>-<pid>`, unique to the run so concurrent
+/// health-checks do not collide, with the characters systemd does not
+/// allow in unit names replaced
+fn unit_name(stage: &str, check: &str, pid: u32) -> String {
+    let check: String = check
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | ':' | '_' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect();
+    format!("greenboot-{stage}-{check}-{pid}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unsettled_units_parsing() {
+        let jobs = "\
+148 greenboot-healthcheck.service start running
+151 boot-complete.target          start waiting
+163 flaky.service                 start running
+";
+        assert_eq!(
+            running_jobs(jobs).collect::<Vec<_>>(),
+            vec!["greenboot-healthcheck.service", "flaky.service"]
+        );
+        let units = "flaky.service loaded activating auto-restart Flaky service\n";
+        assert_eq!(
+            activating_units(units).collect::<Vec<_>>(),
+            vec!["flaky.service"]
+        );
+
+        assert!("sometimes".parse::<JobsCheck>().is_err());
+        assert_eq!("wanted".parse::<JobsCheck>().unwrap(), JobsCheck::Wanted);
+    }
+
+    #[test]
+    fn test_check_unit_args() {
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("/etc/greenboot/check/required.d/01 dns.sh")
+            .env("GREENBOOT_PHASE", "required");
+        let results_dir = Path::new("/run/greenboot/results");
+        let args = |unit: CheckUnit, timeout| {
+            let settings = CheckSettings {
+                timeout,
+                ..Default::default()
+            };
+            unit.run_args(&command, "required", "01 dns.sh", &settings, results_dir)
+                .into_iter()
+                .map(|arg| arg.into_string().unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let scope = args(CheckUnit::Scope, Some(Duration::from_secs(30)));
+        assert_eq!(
+            scope[2],
+            format!("--unit=greenboot-required-01_dns.sh-{}", std::process::id())
+        );
+        assert_eq!(
+            scope[4..],
+            [
+                "--property=RuntimeMaxSec=30",
+                "--scope",
+                "--",
+                "/bin/sh",
+                "/etc/greenboot/check/required.d/01 dns.sh"
+            ]
+        );
+        let service = args(CheckUnit::Service, None);
+        assert_eq!(
+            service[4..7],
+            ["--wait", "--pipe", "--setenv=GREENBOOT_PHASE=required"]
+        );
+
+        let settings = CheckSettings {
+            limits: ResourceLimits {
+                memory_max: Some(parse_memory_max("64M").unwrap()),
+                cpu_quota: Some(parse_cpu_quota("50%").unwrap()),
+            },
+            user: Some("chrony".to_string()),
+            ..Default::default()
+        };
+        let limited =
+            CheckUnit::Scope.run_args(&command, "wanted", "02_ntp.sh", &settings, results_dir);
+        assert_eq!(
+            limited[4..7],
+            [
+                "--property=MemoryMax=67108864",
+                "--property=CPUQuota=50%",
+                "--uid=chrony"
+            ]
+        );
+
+        let settings = CheckSettings {
+            selinux_context: Some("system_u:system_r:greenboot_check_t:s0".to_string()),
+            ..Default::default()
+        };
+        let confined =
+            CheckUnit::Scope.run_args(&command, "wanted", "02_ntp.sh", &settings, results_dir);
+        assert_eq!(
+            confined[4..8],
+            [
+                "--scope",
+                "--",
+                "runcon",
+                "system_u:system_r:greenboot_check_t:s0"
+            ]
+        );
+        let confined =
+            CheckUnit::Service.run_args(&command, "wanted", "02_ntp.sh", &settings, results_dir);
+        assert_eq!(
+            confined[4],
+            "--property=SELinuxContext=system_u:system_r:greenboot_check_t:s0"
+        );
+
+        let settings = CheckSettings {
+            sandbox: Some(true),
+            ..Default::default()
+        };
+        let sandboxed =
+            CheckUnit::Service.run_args(&command, "wanted", "02_ntp.sh", &settings, results_dir);
+        assert_eq!(
+            sandboxed[4..10],
+            [
+                "--property=ProtectSystem=strict",
+                "--property=ProtectHome=read-only",
+                "--property=PrivateTmp=yes",
+                "--property=PrivateDevices=yes",
+                "--property=NoNewPrivileges=yes",
+                "--property=ReadWritePaths=-/run/greenboot/results"
+            ]
+        );
+
+        assert_eq!("scope".parse::<CheckUnit>().unwrap(), CheckUnit::Scope);
+        assert!("slice".parse::<CheckUnit>().is_err());
+    }
+
+    #[test]
+    fn test_resource_limits() {
+        assert_eq!(parse_memory_max("512K").unwrap(), 512 * 1024);
+        assert_eq!(parse_memory_max("1048576").unwrap(), 1 << 20);
+        assert!(parse_memory_max("0").is_err());
+        assert!(parse_memory_max("lots").is_err());
+        assert!(parse_memory_max("99999999T").is_err());
+        assert_eq!(parse_cpu_quota("200%").unwrap(), 200);
+        assert!(parse_cpu_quota("50").is_err());
+
+        let check = ResourceLimits {
+            cpu_quota: Some(20),
+            ..Default::default()
+        };
+        let global = ResourceLimits {
+            memory_max: Some(1 << 20),
+            cpu_quota: Some(50),
+        };
+        assert_eq!(
+            check.or(global),
+            ResourceLimits {
+                memory_max: Some(1 << 20),
+                cpu_quota: Some(20),
+            }
+        );
+    }
+
+    #[test]
+    fn test_off_keeps_the_diagnostics() {
+        let jobs = SystemdJobs::default();
+        let diagnostics = jobs.apply(Ok(Diagnostics::default())).unwrap();
+        assert_eq!(diagnostics.passed, 0);
+        assert!(diagnostics.failed_wanted.is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+
+/// U-Boot's bootcount feature: while `upgrade_available` is 1 every boot
+/// increments `bootcount`, and once it exceeds `bootlimit` U-Boot runs
+/// `altbootcmd` instead of `bootcmd`. The environment is read and written
+/// with fw_printenv/fw_setenv from libubootenv or u-boot-tools.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UBootBackend {
+    env_config: PathBuf,
+}
+
+impl UBootBackend {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            env_config: paths.uboot_env_config.clone(),
+        }
+    }
+
+    fn printenv(&self) -> Result<String> {
+        let output = Command::new("fw_printenv")
+            .arg("-c")
+            .arg(&self.env_config)
+            .output()
+            .context("Unable to run fw_printenv")?;
+        if !output.status.success() {
+            bail!(
+                "fw_printenv failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// sets the variables in a single write of the environment, an empty
+    /// value deletes the variable
+    fn setenv(&self, vars: &[(&str, &str)]) -> Result<()> {
+        let script: String = vars
+            .iter()
+            .map(|(key, value)| format!("{key} {value}\n"))
+            .collect();
+        let mut script_file = tempfile::NamedTempFile::new()?;
+        script_file
+            .write_all(script.as_bytes())
+            .context("Unable to write the fw_setenv script")?;
+        let status = Command::new("fw_setenv")
+            .arg("-c")
+            .arg(&self.env_config)
+            .arg("-s")
+            .arg(script_file.path())
+            .status()
+            .context("Unable to run fw_setenv")?;
+        if !status.success() {
+            bail!("Failed to set U-Boot environment: {}", script.trim());
+        }
+        tracing::info!(
+            "Set U-Boot environment: {}",
+            script.trim().replace('\n', ", ")
+        );
+        Ok(())
+    }
+}
+
+impl BootloaderBackend for UBootBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        UBootEnv::parse(&self.printenv()?).boot_counter()
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        if let Some(counter) = UBootEnv::parse(&self.printenv()?).boot_counter()? {
+            bail!("already set boot_counter={counter}");
+        }
+        self.setenv(&[
+            ("bootlimit", &attempts.to_string()),
+            ("bootcount", "0"),
+            ("upgrade_available", "1"),
+        ])
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.setenv(&[("upgrade_available", "0"), ("bootcount", "0")])
+    }
+
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        // a failed boot keeps counting until U-Boot falls back to altbootcmd
+        if success {
+            self.unset_boot_counter()?;
+        }
+        Ok(())
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        Ok(UBootEnv::parse(&self.printenv()?).get("greenboot_rollback_trigger") == Some("1"))
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.setenv(&[("greenboot_rollback_trigger", "1")])
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.setenv(&[("greenboot_rollback_trigger", "")])
+    }
+
+    fn env_var(&self, key: &str) -> Result<Option<String>> {
+        Ok(UBootEnv::parse(&self.printenv()?)
+            .get(key)
+            .map(String::from))
+    }
+
+    fn set_env_var(&self, key: &str, value: &str) -> Result<()> {
+        self.setenv(&[(key, value)])
+    }
+
+    fn store(&self) -> String {
+        format!("U-Boot environment of {}", self.env_config.display())
+    }
+}
+
+/// fw_printenv output
+struct UBootEnv<'a>(HashMap<&'a str, &'a str>);
+
+impl<'a> UBootEnv<'a> {
+    fn parse(output: &'a str) -> Self {
+        Self(
+            output
+                .lines()
+                .filter_map(|line| line.split_once('='))
+                .collect(),
+        )
+    }
+
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.0.get(key).copied()
+    }
+
+    fn int(&self, key: &str) -> Result<Option<i32>> {
+        self.get(key)
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("{key} has invalid value: {value}"))
+            })
+            .transpose()
+    }
+
+    /// boot attempts left, none unless an upgrade is being tried
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        if self.get("upgrade_available") != Some("1") {
+            return Ok(None);
+        }
+        let limit = self.int("bootlimit")?.unwrap_or(0);
+        let count = self.int("bootcount")?.unwrap_or(0);
+        Ok(Some(limit - count))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uboot_boot_counter() {
+        let env = UBootEnv::parse("bootcmd=run distro_bootcmd\nbootlimit=3\nbootcount=1\n");
+        assert_eq!(env.boot_counter().unwrap(), None);
+
+        let env = UBootEnv::parse("upgrade_available=1\nbootlimit=3\nbootcount=1\n");
+        assert_eq!(env.boot_counter().unwrap(), Some(2));
+
+        let env = UBootEnv::parse("upgrade_available=1\nbootlimit=3\nbootcount=4\n");
+        assert_eq!(env.boot_counter().unwrap(), Some(-1));
+
+        let env = UBootEnv::parse("upgrade_available=1\nbootlimit=three\n");
+        assert!(env.boot_counter().is_err());
+    }
+}
@@ -0,0 +1,235 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::bootloader::BootloaderBackend;
+use crate::paths::Paths;
+use crate::statefile::StateFileBackend;
+
+/// vendor GUID of the variables defined by the UEFI specification
+static EFI_GLOBAL_VARIABLE: &str = "8be4df61-93ca-11d0-9d2b-00a0c93ec4c3";
+/// NON_VOLATILE | BOOTSERVICE_ACCESS | RUNTIME_ACCESS, as the firmware sets
+/// them on the boot variables
+const EFI_VARIABLE_ATTRIBUTES: u32 = 0x7;
+
+/// Dual-image appliances not using ostree install each image behind its own
+/// `Boot####` entry. The firmware counts nothing, so the boot counter and the
+/// rollback trigger are kept in the state file and the boots counted by
+/// greenboot like with zipl, while the image booted next is picked through
+/// the UEFI variables: a failed boot retries the same entry through
+/// `BootNext`, a healthy one moves it to the front of `BootOrder` and the
+/// rollback moves the other image there instead. The other image is the
+/// first entry of `BootOrder` which is not `BootCurrent`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UefiAbBackend {
+    efivars: PathBuf,
+    state: StateFileBackend,
+}
+
+impl UefiAbBackend {
+    pub fn new(paths: &Paths) -> Self {
+        Self {
+            efivars: paths.efivars.clone(),
+            state: StateFileBackend::new(paths.state_file.clone()),
+        }
+    }
+
+    fn var_path(&self, name: &str) -> PathBuf {
+        self.efivars.join(format!("{name}-{EFI_GLOBAL_VARIABLE}"))
+    }
+
+    /// entries held by a variable, none if it does not exist
+    fn read_entries(&self, name: &str) -> Result<Option<Vec<u16>>> {
+        let path = self.var_path(name);
+        let raw = match fs::read(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Unable to read {}", path.display())),
+        };
+        // efivarfs prefixes the value with its attributes
+        match raw.get(4..) {
+            Some(data) if data.len() % 2 == 0 => Ok(Some(
+                data.chunks_exact(2)
+                    .map(|entry| u16::from_le_bytes([entry[0], entry[1]]))
+                    .collect(),
+            )),
+            _ => bail!("{name} has an invalid size of {} bytes", raw.len()),
+        }
+    }
+
+    fn write_entries(&self, name: &str, entries: &[u16]) -> Result<()> {
+        let path = self.var_path(name);
+        let raw: Vec<u8> = EFI_VARIABLE_ATTRIBUTES
+            .to_le_bytes()
+            .into_iter()
+            .chain(entries.iter().flat_map(|entry| entry.to_le_bytes()))
+            .collect();
+        // efivarfs takes a variable in a single write
+        let write = || fs::write(&path, &raw);
+        match write() {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                clear_immutable(&path);
+                write()
+            }
+            written => written,
+        }
+        .with_context(|| format!("Unable to write {}", path.display()))
+    }
+
+    fn remove_var(&self, name: &str) -> Result<()> {
+        let path = self.var_path(name);
+        let remove = || fs::remove_file(&path);
+        match remove() {
+            Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+                clear_immutable(&path);
+                remove()
+            }
+            removed => removed,
+        }
+        .or_else(|e| match e.kind() {
+            ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        })
+        .with_context(|| format!("Unable to remove {}", path.display()))
+    }
+
+    fn boot_current(&self) -> Result<u16> {
+        match self.read_entries("BootCurrent")?.as_deref() {
+            Some(&[entry]) => Ok(entry),
+            _ => bail!("no BootCurrent in {}", self.efivars.display()),
+        }
+    }
+
+    fn boot_order(&self) -> Result<Vec<u16>> {
+        self.read_entries("BootOrder")?
+            .with_context(|| format!("no BootOrder in {}", self.efivars.display()))
+    }
+
+    /// puts `entry` at the front of BootOrder, keeping the other entries
+    fn boot_first(&self, entry: u16) -> Result<()> {
+        let mut order = self.boot_order()?;
+        if order.first() == Some(&entry) {
+            return Ok(());
+        }
+        order.retain(|other| *other != entry);
+        order.insert(0, entry);
+        tracing::info!("moving Boot{entry:04X} to the front of BootOrder");
+        self.write_entries("BootOrder", &order)
+    }
+
+    /// entry of the image the rollback switches to
+    fn other_entry(&self) -> Result<u16> {
+        let current = self.boot_current()?;
+        self.boot_order()?
+            .into_iter()
+            .find(|entry| *entry != current)
+            .with_context(|| format!("BootOrder has no other entry than Boot{current:04X}"))
+    }
+}
+
+/// efivarfs marks the variables immutable, like efibootmgr the flag is
+/// cleared before changing one
+fn clear_immutable(path: &Path) {
+    match Command::new("chattr").arg("-i").arg(path).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => tracing::debug!("chattr -i {} exited with {status}", path.display()),
+        Err(e) => tracing::debug!("cannot run chattr: {e}"),
+    }
+}
+
+impl BootloaderBackend for UefiAbBackend {
+    fn boot_counter(&self) -> Result<Option<i32>> {
+        self.state.boot_counter()
+    }
+
+    fn set_boot_counter(&self, attempts: u16) -> Result<()> {
+        self.state.set_boot_counter(attempts)
+    }
+
+    fn unset_boot_counter(&self) -> Result<()> {
+        self.state.unset_boot_counter()
+    }
+
+    /// a healthy image becomes the default one, a failed one is booted again
+    /// once through BootNext, whatever BootOrder says
+    fn set_boot_status(&self, success: bool) -> Result<()> {
+        self.state.set_boot_status(success)?;
+        let current = self.boot_current()?;
+        if success {
+            self.boot_first(current)
+        } else {
+            self.write_entries("BootNext", &[current])
+        }
+    }
+
+    fn rollback_trigger(&self) -> Result<bool> {
+        self.state.rollback_trigger()
+    }
+
+    fn set_rollback_trigger(&self) -> Result<()> {
+        self.state.set_rollback_trigger()
+    }
+
+    fn unset_rollback_trigger(&self) -> Result<()> {
+        self.state.unset_rollback_trigger()
+    }
+
+    fn count_boot(&self) -> Result<()> {
+        self.state.count_boot()
+    }
+
+    fn rollback(&self) -> Result<bool> {
+        let other = self.other_entry()?;
+        self.boot_first(other)?;
+        // a BootNext left by the failed boot would win over BootOrder
+        self.remove_var("BootNext")?;
+        Ok(true)
+    }
+
+    fn has_rollback_target(&self) -> Result<Option<bool>> {
+        Ok(Some(self.other_entry().is_ok()))
+    }
+
+    fn store(&self) -> String {
+        format!("{} and the UEFI BootOrder", self.state.store())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_uefi_ab_backend() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        fs::create_dir_all(&paths.efivars).unwrap();
+        let backend = UefiAbBackend::new(&paths);
+        backend.write_entries("BootCurrent", &[0x0002]).unwrap();
+        backend
+            .write_entries("BootOrder", &[0x0001, 0x0002, 0x0003])
+            .unwrap();
+        assert_eq!(
+            fs::read(backend.var_path("BootCurrent")).unwrap(),
+            [7, 0, 0, 0, 2, 0]
+        );
+
+        backend.set_boot_status(false).unwrap();
+        assert_eq!(
+            backend.read_entries("BootNext").unwrap(),
+            Some(vec![0x0002])
+        );
+        backend.set_boot_status(true).unwrap();
+        assert_eq!(backend.boot_order().unwrap(), vec![0x0002, 0x0001, 0x0003]);
+
+        backend.set_boot_status(false).unwrap();
+        assert_eq!(backend.has_rollback_target().unwrap(), Some(true));
+        assert!(backend.rollback().unwrap());
+        assert_eq!(backend.boot_order().unwrap(), vec![0x0001, 0x0002, 0x0003]);
+        assert_eq!(backend.read_entries("BootNext").unwrap(), None);
+    }
+}
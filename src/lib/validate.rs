@@ -0,0 +1,714 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use config::{Config, File, FileFormat, Map, Value};
+use std::fmt;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::checkfiles::{CheckFiles, UnknownFiles, is_masked, shebang};
+use crate::container::{ContainerCheck, is_container};
+use crate::metadata::read_sidecar;
+use crate::paths::{Paths, StructuredFormat};
+
+/// unit that runs the health-check, it has to ship and be enabled in the image
+static HEALTHCHECK_UNIT: &str = "greenboot-healthcheck.service";
+/// greenboot binary the units execute
+static GREENBOOT_BINARY: &str = "usr/libexec/greenboot/greenboot";
+/// unit dirs, relative to the image root, looked up for the healthcheck unit
+static UNIT_DIRS: [&str; 2] = ["usr/lib/systemd/system", "etc/systemd/system"];
+/// preset dirs, relative to the image root, that may enable the healthcheck unit
+static PRESET_DIRS: [&str; 2] = ["usr/lib/systemd/system-preset", "etc/systemd/system-preset"];
+/// every key greenboot or the shipped checks read from the config
+pub static CONFIG_KEYS: [(&str, KeyKind); 48] = [
+    (
+        "GREENBOOT_MAX_BOOT_ATTEMPTS",
+        KeyKind::Integer(u16::MAX as u64),
+    ),
+    ("GREENBOOT_GRUBENV_PATH", KeyKind::Text),
+    ("GREENBOOT_MOTD_PATH", KeyKind::Text),
+    ("GREENBOOT_BOOTLOADER", KeyKind::Choice(&BOOTLOADERS)),
+    (
+        "GREENBOOT_ROLLBACK_HANDLER",
+        KeyKind::Choice(&ROLLBACK_HANDLERS),
+    ),
+    ("GREENBOOT_AB_ENV_VAR", KeyKind::Text),
+    ("GREENBOOT_AB_ROOT_PARTLABELS", KeyKind::List),
+    ("GREENBOOT_ROLLBACK_MIN_VERSION", KeyKind::Text),
+    (
+        "GREENBOOT_AUTO_PIN_AFTER",
+        KeyKind::Integer(u32::MAX as u64),
+    ),
+    ("GREENBOOT_ARM_ONLY_ON_UPDATE", KeyKind::Bool),
+    ("DISABLED_HEALTHCHECKS", KeyKind::List),
+    ("GREENBOOT_NETWORK_CHECKS", KeyKind::List),
+    ("GREENBOOT_NETWORK_WAIT_TIMEOUT", KeyKind::Integer(u64::MAX)),
+    (
+        "GREENBOOT_SYSTEMD_JOBS_CHECK",
+        KeyKind::Choice(&SYSTEMD_JOBS_CHECKS),
+    ),
+    (
+        "GREENBOOT_SYSTEMD_SETTLE_TIMEOUT",
+        KeyKind::Integer(u64::MAX),
+    ),
+    ("GREENBOOT_BOOT_TIME_BUDGET", KeyKind::Integer(u64::MAX)),
+    (
+        "GREENBOOT_BOOT_TIME_CHECK",
+        KeyKind::Choice(&BOOT_TIME_CHECKS),
+    ),
+    ("GREENBOOT_CHECK_UNIT", KeyKind::Choice(&CHECK_UNITS)),
+    ("GREENBOOT_CHECK_MEMORY_MAX", KeyKind::Text),
+    ("GREENBOOT_CHECK_CPU_QUOTA", KeyKind::Text),
+    ("GREENBOOT_SANDBOX_WANTED", KeyKind::Bool),
+    ("GREENBOOT_CHECK_SELINUX_CONTEXT", KeyKind::Text),
+    ("GREENBOOT_OUTPUT_LIMIT", KeyKind::Text),
+    ("GREENBOOT_REDACT_PATTERNS", KeyKind::List),
+    ("GREENBOOT_ERROR_CONSUMES_BOOT_ATTEMPT", KeyKind::Bool),
+    (
+        "GREENBOOT_REMEDIATION_RETRIES",
+        KeyKind::Integer(u16::MAX as u64),
+    ),
+    ("GREENBOOT_REBOOT_DEFER_SECONDS", KeyKind::Integer(u64::MAX)),
+    ("GREENBOOT_ACTION_TIMEOUT", KeyKind::Integer(u64::MAX)),
+    (
+        "GREENBOOT_HEALTHCHECK_HARD_LIMIT",
+        KeyKind::Integer(u64::MAX),
+    ),
+    ("GREENBOOT_RED_ACTION", KeyKind::Choice(&RED_ACTIONS)),
+    ("GREENBOOT_REBOOT_DELAY", KeyKind::Integer(u64::MAX)),
+    ("GREENBOOT_MONITOR_INTERVAL", KeyKind::Integer(u64::MAX)),
+    ("GREENBOOT_MONITOR_REQUIRED", KeyKind::Bool),
+    ("GREENBOOT_MONITOR_ESCALATE", KeyKind::Bool),
+    ("GREENBOOT_MONITOR_UPLOAD_URL", KeyKind::Text),
+    ("GREENBOOT_RESULT_WEBHOOK_URL", KeyKind::Text),
+    ("GREENBOOT_PROFILE", KeyKind::Text),
+    (
+        "GREENBOOT_MONITOR_BATCH_SIZE",
+        KeyKind::Integer(u32::MAX as u64),
+    ),
+    (
+        "GREENBOOT_MONITOR_BATCH_MAX_AGE",
+        KeyKind::Integer(u64::MAX),
+    ),
+    ("GREENBOOT_ROLLBACK_JOURNAL_UNIT", KeyKind::Text),
+    (
+        "GREENBOOT_ROLLBACK_JOURNAL_OFFSET",
+        KeyKind::Integer(u32::MAX as u64),
+    ),
+    (
+        "GREENBOOT_ROLLBACK_JOURNAL_BOOTS",
+        KeyKind::Integer(u32::MAX as u64),
+    ),
+    ("GREENBOOT_ROLLBACK_MARKERS", KeyKind::List),
+    ("GREENBOOT_FALLBACK_BANNER", KeyKind::Text),
+    (
+        "GREENBOOT_UNKNOWN_FILES",
+        KeyKind::Choice(&UNKNOWN_FILES_POLICIES),
+    ),
+    ("GREENBOOT_IGNORE_PATTERNS", KeyKind::List),
+    ("GREENBOOT_WATCHDOG_CHECK_ENABLED", KeyKind::Bool),
+    (
+        "GREENBOOT_WATCHDOG_GRACE_PERIOD",
+        KeyKind::Integer(u64::MAX),
+    ),
+];
+static BOOTLOADERS: [&str; 9] = [
+    "auto",
+    "grub-efi",
+    "grub-bios",
+    "systemd-boot",
+    "u-boot",
+    "zipl",
+    "android-ab",
+    "uefi-ab",
+    "rauc",
+];
+static ROLLBACK_HANDLERS: [&str; 3] = ["deployment", "bootloader-env", "gpt"];
+static RED_ACTIONS: [&str; 4] = ["reboot", "rollback-now", "poweroff", "none"];
+static UNKNOWN_FILES_POLICIES: [&str; 3] = ["ignore", "warn", "fail"];
+static SYSTEMD_JOBS_CHECKS: [&str; 3] = ["off", "wanted", "required"];
+static BOOT_TIME_CHECKS: [&str; 3] = ["warn", "wanted", "required"];
+static CHECK_UNITS: [&str; 3] = ["off", "scope", "service"];
+/// spellings the config crate reads as booleans
+static BOOL_VALUES: [&str; 8] = ["true", "false", "yes", "no", "on", "off", "1", "0"];
+
+/// type of the value a config key holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// non-negative integer up to the given maximum
+    Integer(u64),
+    Bool,
+    /// bash array in the INI files, native array in TOML/YAML
+    List,
+    Text,
+    /// one of the given words
+    Choice(&'static [&'static str]),
+}
+
+/// kind of a known config key, none for an unknown one
+pub fn key_kind(key: &str) -> Option<KeyKind> {
+    CONFIG_KEYS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, kind)| *kind)
+}
+
+/// problem in one of the config files, `line` is unknown for TOML/YAML
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}: {}", self.file.display(), self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// the image would not be protected by greenboot as intended
+    Error,
+    /// likely a mistake, but greenboot still works
+    Warning,
+}
+
+/// misconfiguration found in an image tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for ImageIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: {}", self.message),
+            Severity::Warning => write!(f, "warning: {}", self.message),
+        }
+    }
+}
+
+/// inspects a composed image tree at `root` before it is ever booted: check
+/// dirs and scripts, the config file and the presence of the greenboot units.
+/// `disabled` are the DISABLED_HEALTHCHECKS of the image config, `files` its
+/// policy for the files of the check dirs that are not checks.
+pub fn validate_image(root: &Path, disabled: &[String], files: &CheckFiles) -> Vec<ImageIssue> {
+    let paths = Paths::with_root(root);
+    let mut issues = Vec::new();
+
+    check_scripts(&paths, disabled, files, &mut issues);
+    check_config(&paths, &mut issues);
+    check_units(root, &mut issues);
+
+    issues
+}
+
+fn error(issues: &mut Vec<ImageIssue>, message: String) {
+    issues.push(ImageIssue {
+        severity: Severity::Error,
+        message,
+    });
+}
+
+fn warning(issues: &mut Vec<ImageIssue>, message: String) {
+    issues.push(ImageIssue {
+        severity: Severity::Warning,
+        message,
+    });
+}
+
+fn check_scripts(
+    paths: &Paths,
+    disabled: &[String],
+    files: &CheckFiles,
+    issues: &mut Vec<ImageIssue>,
+) {
+    let required_dirs = paths.required_dirs();
+    if !required_dirs.iter().any(|dir| dir.is_dir()) {
+        error(issues, "no check/required.d directory found".to_string());
+    }
+
+    // the policy only covers the check dirs, actions keep warning; the
+    // subdirs of a check dir hold the checks of a profile
+    let check_dirs = required_dirs
+        .into_iter()
+        .chain(paths.wanted_dirs())
+        .flat_map(|dir| {
+            let profiles = subdirs(&dir);
+            std::iter::once(dir).chain(profiles)
+        })
+        .map(|dir| (dir, Some(files)));
+    let action_dirs = paths
+        .green_dirs()
+        .into_iter()
+        .chain(paths.red_dirs())
+        .chain(paths.pre_reboot_dirs())
+        .map(|dir| (dir, None));
+    let mut found = Vec::new();
+    for (dir, files) in check_dirs.chain(action_dirs) {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            if files.is_some_and(|files| files.is_ignored(&path) || path.is_dir())
+                || is_masked(&path)
+            {
+                continue;
+            }
+            if files.is_some() && is_container(&path) {
+                if let Err(e) = ContainerCheck::read(&path) {
+                    error(issues, format!("{e:#}"));
+                }
+            } else {
+                check_script(
+                    &path,
+                    files.map_or(UnknownFiles::Warn, |files| files.unknown),
+                    issues,
+                );
+            }
+            if files.is_some()
+                && let Err(e) = read_sidecar(&path)
+            {
+                error(issues, format!("{e:#}"));
+            }
+            found.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+
+    for check in disabled.iter().filter(|check| !found.contains(check)) {
+        warning(
+            issues,
+            format!("DISABLED_HEALTHCHECKS lists {check}, which is not shipped in the image"),
+        );
+    }
+}
+
+/// shell a .sh script runs with, none when its shebang names another
+/// interpreter whose syntax cannot be checked
+fn syntax_checker(path: &Path) -> Option<&'static str> {
+    let Some(interpreter) = shebang(path) else {
+        return Some("sh");
+    };
+    // `#!/usr/bin/env bash` names the shell in its argument
+    let program = match interpreter.as_slice() {
+        [env, program, ..] if env.ends_with("/env") => program.as_str(),
+        [program, ..] => program.rsplit('/').next().unwrap_or(program),
+        [] => return None,
+    };
+    match program {
+        "bash" => Some("bash"),
+        "sh" => Some("sh"),
+        _ => None,
+    }
+}
+
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut dirs: Vec<PathBuf> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+fn check_script(path: &Path, unknown: UnknownFiles, issues: &mut Vec<ImageIssue>) {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            error(issues, format!("{} cannot be read: {e}", path.display()));
+            return;
+        }
+    };
+    if !metadata.is_file() {
+        warning(
+            issues,
+            format!("{} is not a regular file and is ignored", path.display()),
+        );
+        return;
+    }
+
+    let is_shell = path.extension().and_then(|ext| ext.to_str()) == Some("sh");
+    if !is_shell && metadata.permissions().mode() & 0o111 == 0 {
+        let message = format!(
+            "{} is neither a .sh script nor executable and is ignored",
+            path.display()
+        );
+        match unknown {
+            UnknownFiles::Ignore => {}
+            UnknownFiles::Warn => warning(issues, message),
+            UnknownFiles::Fail => error(issues, message),
+        }
+        return;
+    }
+
+    // syntax check only, `sh -n` does not run anything
+    if is_shell
+        && let Some(shell) = syntax_checker(path)
+        && let Ok(output) = Command::new(shell).arg("-n").arg(path).output()
+        && !output.status.success()
+    {
+        error(
+            issues,
+            format!(
+                "{} has a syntax error: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            ),
+        );
+    }
+}
+
+fn check_config(paths: &Paths, issues: &mut Vec<ImageIssue>) {
+    if !paths.config_file.exists() {
+        warning(
+            issues,
+            format!("{} not found, defaults apply", paths.config_file.display()),
+        );
+    }
+    for issue in validate_config(paths) {
+        error(issues, issue.to_string());
+    }
+}
+
+/// checks the TOML/YAML config, the config file and its drop-ins for unknown
+/// keys and values of the wrong type or out of range
+pub fn validate_config(paths: &Paths) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    for (structured, format) in paths.structured_config_files() {
+        if structured.exists() {
+            check_structured_file(&structured, format, &mut issues);
+        }
+    }
+    if paths.config_file.exists() {
+        check_ini_file(&paths.config_file, &mut issues);
+    }
+    for dropin in paths.config_dropins() {
+        check_ini_file(&dropin, &mut issues);
+    }
+    issues
+}
+
+fn config_issue(issues: &mut Vec<ConfigIssue>, file: &Path, line: Option<usize>, message: String) {
+    issues.push(ConfigIssue {
+        file: file.to_path_buf(),
+        line,
+        message,
+    });
+}
+
+/// parsed line by line, unlike the config crate this knows where a key is
+fn check_ini_file(config_file: &Path, issues: &mut Vec<ConfigIssue>) {
+    let content = match fs::read_to_string(config_file) {
+        Ok(content) => content,
+        Err(e) => {
+            config_issue(issues, config_file, None, format!("cannot be read: {e}"));
+            return;
+        }
+    };
+    for (index, line) in content.lines().enumerate() {
+        let line_number = Some(index + 1);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            config_issue(
+                issues,
+                config_file,
+                line_number,
+                format!("`{line}` is not a KEY=value assignment"),
+            );
+            continue;
+        };
+        if let Err(message) = check_value(key.trim(), unquote(value.trim())) {
+            config_issue(issues, config_file, line_number, message);
+        }
+    }
+}
+
+fn check_structured_file(
+    config_file: &Path,
+    format: StructuredFormat,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    let format = match format {
+        StructuredFormat::Toml => FileFormat::Toml,
+        StructuredFormat::Yaml => FileFormat::Yaml,
+    };
+    let values = match Config::builder()
+        .add_source(File::new(&config_file.to_string_lossy(), format))
+        .build()
+        .and_then(|parsed| parsed.try_deserialize::<Map<String, Value>>())
+    {
+        Ok(values) => values,
+        Err(e) => {
+            config_issue(issues, config_file, None, format!("cannot be parsed: {e}"));
+            return;
+        }
+    };
+    let mut values: Vec<_> = values.into_iter().collect();
+    values.sort_by(|a, b| a.0.cmp(&b.0));
+    for (key, value) in values {
+        let result = match (key_kind(&key), value.clone().into_array()) {
+            (Some(KeyKind::List), Ok(_)) => Ok(()),
+            (_, Ok(_)) if key_kind(&key).is_some() => Err(format!("{key} must not be a list")),
+            _ => match value.into_string() {
+                Ok(value) => check_value(&key, &value),
+                Err(_) => Err(format!("{key} must be a plain value")),
+            },
+        };
+        if let Err(message) = result {
+            config_issue(issues, config_file, None, message);
+        }
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    ['"', '\'']
+        .iter()
+        .find_map(|quote| {
+            value
+                .strip_prefix(*quote)
+                .and_then(|value| value.strip_suffix(*quote))
+        })
+        .unwrap_or(value)
+}
+
+fn check_value(key: &str, value: &str) -> Result<(), String> {
+    let Some(kind) = key_kind(key) else {
+        return Err(format!("unknown key {key}"));
+    };
+    match kind {
+        KeyKind::Integer(max) => match value.parse::<u64>() {
+            Ok(number) if number > max => {
+                Err(format!("{key}={value} is out of range, at most {max}"))
+            }
+            Ok(_) => Ok(()),
+            Err(_) => Err(format!("{key}={value} is not a non-negative integer")),
+        },
+        KeyKind::Bool if !BOOL_VALUES.contains(&value.to_ascii_lowercase().as_str()) => {
+            Err(format!("{key}={value} is not a boolean"))
+        }
+        KeyKind::List if value.starts_with('(') != value.ends_with(')') => {
+            Err(format!("{key}={value} has unbalanced parentheses"))
+        }
+        KeyKind::Choice(choices) if !choices.contains(&value) => Err(format!(
+            "{key}={value} is not one of {}",
+            choices.join(", ")
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn check_units(root: &Path, issues: &mut Vec<ImageIssue>) {
+    if !root.join(GREENBOOT_BINARY).is_file() {
+        error(issues, format!("/{GREENBOOT_BINARY} is missing"));
+    }
+
+    let unit_dirs: Vec<PathBuf> = UNIT_DIRS.iter().map(|dir| root.join(dir)).collect();
+    if !unit_dirs
+        .iter()
+        .any(|dir| dir.join(HEALTHCHECK_UNIT).exists())
+    {
+        error(issues, format!("{HEALTHCHECK_UNIT} is missing"));
+        return;
+    }
+    if !is_unit_enabled(root, &unit_dirs) {
+        warning(
+            issues,
+            format!("{HEALTHCHECK_UNIT} is neither enabled nor enabled by a preset"),
+        );
+    }
+}
+
+/// enabled through a .wants/.requires symlink or an `enable` preset
+fn is_unit_enabled(root: &Path, unit_dirs: &[PathBuf]) -> bool {
+    let linked = unit_dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            name.ends_with(".wants") || name.ends_with(".requires")
+        })
+        .any(|entry| {
+            entry
+                .path()
+                .join(HEALTHCHECK_UNIT)
+                .symlink_metadata()
+                .is_ok()
+        });
+
+    let preset = PRESET_DIRS
+        .iter()
+        .filter_map(|dir| fs::read_dir(root.join(dir)).ok())
+        .flat_map(|entries| entries.filter_map(Result::ok))
+        .filter_map(|entry| fs::read_to_string(entry.path()).ok())
+        .any(|content| {
+            content.lines().any(|line| {
+                let mut words = line.split_whitespace();
+                words.next() == Some("enable")
+                    && words
+                        .next()
+                        .is_some_and(|unit| unit == HEALTHCHECK_UNIT || unit == "greenboot-*")
+            })
+        });
+
+    linked || preset
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::checkfiles::DEFAULT_IGNORE_PATTERNS;
+    use std::os::unix::fs::symlink;
+
+    fn messages(issues: &[ImageIssue]) -> Vec<String> {
+        issues.iter().map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn test_empty_image_fails() {
+        let root = tempfile::tempdir().unwrap();
+        let issues = validate_image(root.path(), &[], &CheckFiles::default());
+        let messages = messages(&issues);
+        assert!(messages.contains(&"error: no check/required.d directory found".to_string()));
+        assert!(messages.contains(&format!("error: {HEALTHCHECK_UNIT} is missing")));
+    }
+
+    #[test]
+    fn test_valid_image_passes() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let required = &paths.required_dirs()[0];
+        fs::create_dir_all(required).unwrap();
+        fs::write(required.join("01_check.sh"), "exit 0\n").unwrap();
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=3\n").unwrap();
+
+        let binary = root.path().join(GREENBOOT_BINARY);
+        fs::create_dir_all(binary.parent().unwrap()).unwrap();
+        fs::write(&binary, "").unwrap();
+        let units = root.path().join(UNIT_DIRS[0]);
+        fs::create_dir_all(&units).unwrap();
+        fs::write(units.join(HEALTHCHECK_UNIT), "").unwrap();
+        let wants = root
+            .path()
+            .join("etc/systemd/system/multi-user.target.wants");
+        fs::create_dir_all(&wants).unwrap();
+        symlink(
+            format!("/{}/{HEALTHCHECK_UNIT}", UNIT_DIRS[0]),
+            wants.join(HEALTHCHECK_UNIT),
+        )
+        .unwrap();
+
+        fs::write(required.join("README"), "not a check\n").unwrap();
+        fs::write(
+            required.join("03_app.container"),
+            "image=quay.io/example/app-health:1.4\n",
+        )
+        .unwrap();
+        let files = CheckFiles::default();
+        assert_eq!(validate_image(root.path(), &[], &files), vec![]);
+
+        fs::write(required.join("02_broken.sh"), "if then fi\n").unwrap();
+        fs::write(required.join("notes.txt"), "not a check\n").unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=three\n").unwrap();
+        let issues = validate_image(root.path(), &["99_gone.sh".to_string()], &files);
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|i| i.severity == Severity::Error)
+                .count(),
+            2
+        );
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|i| i.severity == Severity::Warning)
+                .count(),
+            2
+        );
+        let files = CheckFiles::new(UnknownFiles::Fail, &DEFAULT_IGNORE_PATTERNS);
+        let issues = validate_image(root.path(), &[], &files);
+        assert!(messages(&issues).contains(&format!(
+            "error: {} is neither a .sh script nor executable and is ignored",
+            required.join("notes.txt").display()
+        )));
+
+        fs::write(required.join("01_check.sh.conf"), "timeout=soon\n").unwrap();
+        let issues = validate_image(root.path(), &[], &files);
+        assert!(messages(&issues).contains(&format!(
+            "error: Invalid {}: line 1: invalid timeout 'soon': invalid digit found in string",
+            required.join("01_check.sh.conf").display()
+        )));
+
+        fs::write(required.join("04_app.container"), "args=--verbose\n").unwrap();
+        let issues = validate_image(root.path(), &[], &files);
+        assert!(messages(&issues).contains(&format!(
+            "error: Invalid {}: no image",
+            required.join("04_app.container").display()
+        )));
+    }
+
+    #[test]
+    fn test_validate_config_reports_location() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(
+            &paths.config_file,
+            "# comment\nGREENBOOT_MAX_BOOT_ATTEMPT=3\nGREENBOOT_MAX_BOOT_ATTEMPTS=\"70000\"\n\
+             GREENBOOT_RED_ACTION=reboot\nDISABLED_HEALTHCHECKS=(\"a.sh\")\n",
+        )
+        .unwrap();
+        let dropins = &paths.config_dropin_dirs[1];
+        fs::create_dir_all(dropins).unwrap();
+        fs::write(
+            dropins.join("10-site.conf"),
+            "GREENBOOT_MONITOR_ESCALATE=maybe\n",
+        )
+        .unwrap();
+        let (toml, _) = &paths.structured_config_files()[0];
+        fs::write(
+            toml,
+            "GREENBOOT_RED_ACTION = \"explode\"\nGREENBOOT_NETWORK_CHECKS = [\"a.sh\"]\n",
+        )
+        .unwrap();
+
+        let issues: Vec<String> = validate_config(&paths)
+            .iter()
+            .map(|issue| issue.to_string())
+            .collect();
+        let config_file = paths.config_file.display();
+        assert_eq!(
+            issues,
+            vec![
+                format!(
+                    "{}: GREENBOOT_RED_ACTION=explode is not one of reboot, rollback-now, poweroff, none",
+                    toml.display()
+                ),
+                format!("{config_file}:2: unknown key GREENBOOT_MAX_BOOT_ATTEMPT"),
+                format!(
+                    "{config_file}:3: GREENBOOT_MAX_BOOT_ATTEMPTS=70000 is out of range, at most 65535"
+                ),
+                format!(
+                    "{}:1: GREENBOOT_MONITOR_ESCALATE=maybe is not a boolean",
+                    dropins.join("10-site.conf").display()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::batch::post_json;
+use crate::checkreport::CheckReport;
+
+/// a check result must not hold up the next check for long
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// outcome of a single check as streamed to the webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckOutcome {
+    Passed,
+    Failed,
+    /// the check could not be executed at all
+    Error,
+    Skipped,
+}
+
+impl fmt::Display for CheckOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckOutcome::Passed => write!(f, "passed"),
+            CheckOutcome::Failed => write!(f, "failed"),
+            CheckOutcome::Error => write!(f, "error"),
+            CheckOutcome::Skipped => write!(f, "skipped"),
+        }
+    }
+}
+
+/// JSON object POSTed for each check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckEvent<'a> {
+    /// required or wanted
+    pub stage: &'a str,
+    pub check: &'a str,
+    pub outcome: CheckOutcome,
+    pub duration_ms: u64,
+    /// seconds since the epoch when the check completed
+    pub timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<&'a str>,
+    /// what the check wrote to RESULT_FD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<&'a CheckReport>,
+}
+
+/// Streams each check result to an endpoint as soon as the check completes,
+/// so the progress of a device stuck mid-boot can be followed from the
+/// fleet side. Results are best effort: once a POST fails the endpoint is
+/// considered unreachable and the rest of the run is not streamed, rather
+/// than delaying every remaining check by the upload timeout.
+#[derive(Debug)]
+pub struct ResultWebhook {
+    pub url: String,
+    /// booted deployment the results are reported for
+    pub deployment: Option<String>,
+    unreachable: AtomicBool,
+}
+
+impl ResultWebhook {
+    pub fn new(url: String, deployment: Option<String>) -> Self {
+        Self {
+            url,
+            deployment,
+            unreachable: AtomicBool::new(false),
+        }
+    }
+
+    pub fn send(
+        &self,
+        stage: &str,
+        check: &str,
+        outcome: CheckOutcome,
+        duration: Duration,
+        report: Option<&CheckReport>,
+    ) {
+        self.send_with(stage, check, outcome, duration, report, |body| {
+            post_json(&self.url, body, WEBHOOK_TIMEOUT)
+        });
+    }
+
+    fn send_with(
+        &self,
+        stage: &str,
+        check: &str,
+        outcome: CheckOutcome,
+        duration: Duration,
+        report: Option<&CheckReport>,
+        post: impl FnOnce(&str) -> Result<()>,
+    ) {
+        if self.unreachable.load(Ordering::Relaxed) {
+            return;
+        }
+        let event = CheckEvent {
+            stage,
+            check,
+            outcome,
+            duration_ms: duration.as_millis() as u64,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            deployment: self.deployment.as_deref(),
+            report,
+        };
+        let sent = serde_json::to_string(&event)
+            .map_err(anyhow::Error::from)
+            .and_then(|body| post(&body));
+        if let Err(e) = sent {
+            tracing::warn!("cannot stream check results, stopping for this run: {e}");
+            self.unreachable.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::bail;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_stops_streaming_once_unreachable() {
+        let webhook =
+            ResultWebhook::new("http://localhost/".to_string(), Some("abc.0".to_string()));
+        let sent = RefCell::new(vec![]);
+        let record = |body: &str| {
+            sent.borrow_mut().push(body.to_string());
+            Ok(())
+        };
+
+        let report = CheckReport {
+            message: Some("disk 60% full".to_string()),
+            ..Default::default()
+        };
+        webhook.send_with(
+            "required",
+            "01_disk.sh",
+            CheckOutcome::Passed,
+            Duration::from_millis(1500),
+            Some(&report),
+            record,
+        );
+        let event: serde_json::Value = serde_json::from_str(&sent.borrow()[0]).unwrap();
+        assert_eq!(event["check"], "01_disk.sh");
+        assert_eq!(event["outcome"], "passed");
+        assert_eq!(event["duration_ms"], 1500);
+        assert_eq!(event["deployment"], "abc.0");
+        assert_eq!(event["report"]["message"], "disk 60% full");
+
+        webhook.send_with(
+            "wanted",
+            "02_net.sh",
+            CheckOutcome::Failed,
+            Duration::ZERO,
+            None,
+            |_| bail!("connection refused"),
+        );
+        webhook.send_with(
+            "wanted",
+            "03_time.sh",
+            CheckOutcome::Passed,
+            Duration::ZERO,
+            None,
+            record,
+        );
+        assert_eq!(sent.borrow().len(), 1);
+    }
+}
@@ -0,0 +1,37 @@
+// SPDX-License-Identifier: BSD-3-Clause
+
+use crate::paths::Paths;
+use crate::statefile::StateFileBackend;
+
+/// environment file in the boot dir, next to zipl.conf's BLS entries
+static ZIPL_ENV_FILE: &str = "greenboot.env";
+
+/// zipl has no environment and runs no script at IPL, so greenboot keeps the
+/// grubenv variables in a state file in /boot, where the tools regenerating
+/// the IPL configuration can read them, and counts the boot itself. Once the
+/// counter is exhausted the rollback is done by rpm-ostree, which rewrites
+/// the zipl configuration.
+pub fn zipl_backend(paths: &Paths) -> StateFileBackend {
+    StateFileBackend::new(paths.boot_dir.join(ZIPL_ENV_FILE))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bootloader::BootloaderBackend;
+    use std::fs;
+
+    #[test]
+    fn test_zipl_env_file_in_boot_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        fs::create_dir_all(&paths.boot_dir).unwrap();
+        let backend = zipl_backend(&paths);
+
+        backend.set_rollback_trigger().unwrap();
+        assert_eq!(
+            fs::read_to_string(paths.boot_dir.join(ZIPL_ENV_FILE)).unwrap(),
+            "greenboot_rollback_trigger=1\n"
+        );
+    }
+}
@@ -2,71 +2,1156 @@
 
 use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand, ValueEnum};
-use config::{Config, File, FileFormat};
-use greenboot::detect_os_deployment;
+use config::{Case, Config, ConfigError, Environment, File, FileFormat, Map, Source};
+use greenboot::journal::{Event, JournalLayer};
 use greenboot::{
-    get_boot_counter, get_rollback_trigger, handle_motd, handle_reboot, handle_rollback,
-    run_diagnostics, run_green, run_red, set_boot_counter, set_boot_status, set_rollback_trigger,
-    unset_boot_counter, unset_rollback_trigger,
+    AbRoot, BatchUploader, BootAttempts, BootMount, BootTimeBudget, Bootloader, BootloaderBackend,
+    BudgetCheck, CONFIG_KEYS, CheckFiles, CheckUnit, DEFAULT_IGNORE_PATTERNS,
+    DEFAULT_LOG_FILE_PATH, Diagnostics, DiagnosticsError, FailedCheck, FailureContext, GreenStreak,
+    HealthStatus, HistoryEntry, HistoryFormat, INJECT_GUARD_ENV, Injections, JobsCheck,
+    KernelOverrides, LastFailure, NetworkWait, Paths, ResourceLimits, ResultWebhook,
+    RollbackHandler, RollbackMarker, RollbackPolicy, ScriptEnv, Severity, StatusRecord,
+    StructuredFormat, SystemdJobs, UnknownFiles, append_history, backend_for, booted_checksum,
+    booted_deployment, detect_bootloader, detect_os_deployment, discover_grubenv, export_history,
+    format_utc, history_since, history_table, install_termination_handler, journal, last_boots,
+    migrate, parse_since, passed_before, read_green_streak, read_grubenv_writer, read_history,
+    read_kernel_overrides, read_last_history_entry, read_status_view, remediation_hints,
+    take_rollback_marker, time_since_boot, update_failure_streak, validate_config, validate_image,
+    write_green_streak, write_last_failure, write_rollback_marker, write_status,
 };
-use greenboot::{is_boot_rw, remount_boot_ro, remount_boot_rw};
-use std::{process::Command, sync::OnceLock};
+use greenboot::{
+    DEFAULT_OUTPUT_LIMIT, ProgressLog, check_line, describe_rollback, enable_progress, handle_motd,
+    handle_poweroff, handle_reboot, handle_rollback, handle_wall, has_rollback_target, last_run,
+    list_deployments, mark_no_rollback_target, parse_cpu_quota, parse_memory_max,
+    parse_selinux_context, pin_booted_deployment, plan_run, progress, reboot, rollback_now,
+    rollback_to, run_diagnostics, run_green, run_pre_reboot, run_red, run_required, run_wanted,
+    select_rollback_to, set_redactions,
+};
+use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, sigaction};
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    sync::mpsc::{self, RecvTimeoutError},
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::Layer;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 
-/// greenboot config path
-static GREENBOOT_CONFIG_FILE: &str = "/etc/greenboot/greenboot.conf";
+/// exit code when greenboot itself failed, whatever the command
+const INTERNAL_ERROR_EXIT_CODE: u8 = 1;
+/// exit code of health-check when a required check failed
+const RED_EXIT_CODE: u8 = 2;
+/// exit code of health-check when required checks passed but wanted checks failed
+const DEGRADED_EXIT_CODE: u8 = 3;
+/// exit code of health-check when it hit GREENBOOT_HEALTHCHECK_HARD_LIMIT
+const TIMED_OUT_EXIT_CODE: u8 = 4;
+/// exit code of `rollback --dry-run` when the rollback would go ahead
+const DRY_RUN_EXIT_CODE: u8 = 5;
+/// exit code of invalid command line arguments, EX_USAGE of sysexits.h, as
+/// the 2 clap exits with is taken by RED
+const USAGE_EXIT_CODE: u8 = 64;
+/// unit logging the RollbackCompleted event when greenboot rolled back
+const DEFAULT_ROLLBACK_JOURNAL_UNIT: &str = "greenboot-healthcheck.service";
+/// bootloader variable naming the root slot for GREENBOOT_ROLLBACK_HANDLER=bootloader-env
+const DEFAULT_AB_ENV_VAR: &str = "greenboot_root_slot";
+/// partition labels of the a and b roots for GREENBOOT_ROLLBACK_HANDLER=gpt
+const DEFAULT_AB_ROOT_PARTLABELS: [&str; 2] = ["root_a", "root_b"];
+/// logged by rollback_and_reboot along with the RollbackCompleted event
+const ROLLBACK_SUCCESS_MESSAGE: &str = "Rollback successful";
+/// MOTD line announcing a fallback boot, `{manager}` is rpm-ostree or bootc
+const DEFAULT_FALLBACK_BANNER: &str =
+    "FALLBACK BOOT DETECTED! Default {manager} deployment has been rolled back.";
+/// stack buffer `status` reads the status file into
+const STATUS_BUF_SIZE: usize = 16 * 1024;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
 #[clap(propagate_version = true)]
 /// cli parameters for greenboot
 struct Cli {
-    #[clap(value_enum, short, long, default_value_t = LogLevel::Info)]
-    log_level: LogLevel,
+    /// info, or warn while the progress of a health-check is shown on a
+    /// terminal
+    #[clap(value_enum, short, long)]
+    log_level: Option<LogLevel>,
+    /// only log errors and show no progress, e.g. in kickstart %post
+    /// scripts; overrides --log-level
+    #[clap(short, long, global = true)]
+    quiet: bool,
+    /// when logs and the progress of a health-check are colored, auto
+    /// honors NO_COLOR
+    #[clap(value_enum, long, global = true, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// format of the logs on stderr, json writes an object per line for log
+    /// collectors and shows no progress
+    #[clap(value_enum, long, global = true, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+    /// config file to read instead of /etc/greenboot/greenboot.conf
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// grubenv to use, overrides GREENBOOT_GRUBENV_PATH
+    #[clap(long, global = true)]
+    grubenv: Option<PathBuf>,
+    /// MOTD file to write the boot status to, overrides GREENBOOT_MOTD_PATH
+    #[clap(long, global = true)]
+    motd: Option<PathBuf>,
+    /// file logs are appended to when journald is unavailable
+    #[clap(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// testing only: force check outcomes, e.g. fail:<check>,timeout:<check>;
+    /// requires GREENBOOT_ALLOW_INJECT=1
+    #[clap(long, global = true, hide = true)]
+    inject: Option<String>,
     #[clap(subcommand)]
     command: Commands,
 }
+/// what health_check does once a boot has been declared RED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RedAction {
+    /// reboot until the boot counter is exhausted, then roll back
+    Reboot,
+    /// roll back right away without retrying the boot
+    RollbackNow,
+    Poweroff,
+    /// leave the system running
+    None,
+}
+
+impl FromStr for RedAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "reboot" => Ok(RedAction::Reboot),
+            "rollback-now" => Ok(RedAction::RollbackNow),
+            "poweroff" => Ok(RedAction::Poweroff),
+            "none" => Ok(RedAction::None),
+            other => bail!("unknown red action '{other}'"),
+        }
+    }
+}
+
+impl fmt::Display for RedAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RedAction::Reboot => write!(f, "reboot"),
+            RedAction::RollbackNow => write!(f, "rollback-now"),
+            RedAction::Poweroff => write!(f, "poweroff"),
+            RedAction::None => write!(f, "none"),
+        }
+    }
+}
+
 #[derive(Debug)]
 /// config params for greenboot
 struct GreenbootConfig {
     max_reboot: u16,
     disabled_healthchecks: Vec<String>,
+    monitor_interval: u64,
+    monitor_required: bool,
+    monitor_escalate: bool,
+    monitor_upload_url: Option<String>,
+    monitor_batch_size: usize,
+    monitor_batch_max_age: u64,
+    /// endpoint each check result is POSTed to during the health-check
+    result_webhook_url: Option<String>,
+    /// check profile, selects the profile subdirs and tagged checks
+    profile: Option<String>,
+    /// unit whose journal tells whether the previous boots rolled back
+    rollback_journal_unit: String,
+    /// first previous boot searched, 1 being the boot right before this one
+    rollback_journal_offset: u32,
+    /// how many previous boots are searched for the last greenboot run
+    rollback_journal_boots: u32,
+    /// (unit, message) pairs telling another agent rolled back
+    rollback_markers: Vec<(String, String)>,
+    /// template of the MOTD line announcing a fallback boot
+    fallback_banner: String,
+    unknown_files: UnknownFiles,
+    /// file names in the check dirs that are never run
+    ignore_patterns: Vec<String>,
+    network_checks: Vec<String>,
+    network_wait_timeout: u64,
+    systemd_jobs_check: JobsCheck,
+    /// seconds systemd jobs get to settle once the scripts passed
+    systemd_settle_timeout: u64,
+    /// seconds from boot to green a boot may take, 0 for no budget
+    boot_time_budget: u64,
+    boot_time_check: BudgetCheck,
+    check_unit: CheckUnit,
+    /// CPU and memory limits of every check
+    check_limits: ResourceLimits,
+    /// run the wanted.d checks sandboxed
+    sandbox_wanted: bool,
+    /// SELinux context of the checks, none to leave it to the policy
+    check_selinux_context: Option<String>,
+    /// bytes of output kept of each script
+    output_limit: usize,
+    /// secrets redacted from the output of the scripts, on top of the defaults
+    redact_patterns: Vec<String>,
+    error_consumes_attempt: bool,
+    remediation_retries: u16,
+    reboot_defer_seconds: u64,
+    action_timeout: u64,
+    hard_limit: u64,
+    red_action: RedAction,
+    reboot_delay: u64,
+    injections: Injections,
+    /// greenboot.disable on the kernel command line
+    disabled: bool,
+    /// GREENBOOT_BOOTLOADER, none to autodetect
+    bootloader: Option<Bootloader>,
+    rollback_handler: RollbackHandler,
+    ab_env_var: String,
+    ab_root_partlabels: Vec<String>,
+    rollback_min_version: Option<String>,
+    /// consecutive GREEN boots after which the deployment is pinned, 0 never
+    auto_pin_after: u32,
+    /// a deployment that already passed is not rolled back on a failure
+    arm_only_on_update: bool,
+    /// boot of the booted deployment this is, taken when the config is
+    /// loaded so the failure of this boot being recorded does not change it
+    boot_attempt: u16,
+    paths: Paths,
+}
+
+/// environment variables named like config keys, i.e. GREENBOOT_* and
+/// DISABLED_HEALTHCHECKS; the others are left out so unrelated variables
+/// with non UTF-8 values can't break loading the config
+fn config_env_vars() -> Map<String, String> {
+    std::env::vars_os()
+        .filter_map(|(key, value)| Some((key.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(key, _)| key.starts_with("GREENBOOT_") || key == "DISABLED_HEALTHCHECKS")
+        .collect()
+}
+
+/// list keys are bash arrays in the INI file and native arrays in TOML/YAML
+fn get_list(parsed_config: &Config, key: &str) -> Option<Vec<String>> {
+    if let Ok(values) = parsed_config.get_array(key) {
+        return Some(
+            values
+                .into_iter()
+                .filter_map(|value| value.into_string().ok())
+                .collect(),
+        );
+    }
+    parsed_config
+        .get_string(key)
+        .ok()
+        .map(|raw| parse_bash_array_string(&raw))
+}
+
+/// config sources from the lowest to the highest precedence, labelled with
+/// where they come from for `config print`
+fn config_sources(paths: &Paths) -> Vec<(String, Box<dyn Source + Send + Sync>)> {
+    let mut sources: Vec<(String, Box<dyn Source + Send + Sync>)> = Vec::new();
+    for (structured, format) in paths.structured_config_files() {
+        sources.push((
+            structured.display().to_string(),
+            Box::new(File::new(&structured.to_string_lossy(), format_of(format)).required(false)),
+        ));
+    }
+    sources.push((
+        paths.config_file.display().to_string(),
+        Box::new(File::new(&paths.config_file.to_string_lossy(), FileFormat::Ini).required(false)),
+    ));
+    for dropin in paths.config_dropins() {
+        sources.push((
+            dropin.display().to_string(),
+            Box::new(File::new(&dropin.to_string_lossy(), FileFormat::Ini)),
+        ));
+    }
+    sources.push((
+        "environment".to_string(),
+        Box::new(Environment::with_convert_case(Case::UpperSnake).source(Some(config_env_vars()))),
+    ));
+    sources
+}
+
+/// every config source built on its own, from the lowest to the highest
+/// precedence, so a file that cannot be parsed only loses its own keys
+fn config_layers(paths: &Paths) -> Vec<(String, Result<Config, ConfigError>)> {
+    config_sources(paths)
+        .into_iter()
+        .map(|(label, source)| (label, Config::builder().add_source(vec![source]).build()))
+        .collect()
+}
+
+/// the layers that could be parsed merged into one config, the later ones
+/// winning; the others are logged and skipped
+fn merge_config_layers(layers: &[(String, Result<Config, ConfigError>)]) -> Config {
+    let valid: Vec<Config> = layers
+        .iter()
+        .filter_map(|(label, layer)| match layer {
+            Ok(layer) => Some(layer.clone()),
+            Err(e) => {
+                tracing::error!("ignoring the config in {label}: {e}");
+                None
+            }
+        })
+        .collect();
+    Config::builder()
+        .add_source(valid)
+        .build()
+        .unwrap_or_else(|e| {
+            tracing::error!("cannot merge the config files, using the defaults: {e}");
+            Config::default()
+        })
+}
+
+/// list values printed back as the bash arrays of the INI file
+fn format_list(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|value| format!("\"{value}\"")).collect();
+    format!("({})", quoted.join(" "))
+}
+
+fn format_of(format: StructuredFormat) -> FileFormat {
+    match format {
+        StructuredFormat::Toml => FileFormat::Toml,
+        StructuredFormat::Yaml => FileFormat::Yaml,
+    }
 }
 
 impl GreenbootConfig {
-    pub fn get_config() -> Self {
+    /// loads the TOML/YAML config, `paths.config_file`, its drop-ins and the environment, whose path keys override the given paths
+    pub fn get_config(paths: Paths) -> Self {
         let mut config = Self {
             max_reboot: 3,                 // Default value
             disabled_healthchecks: vec![], //empty list
+            monitor_interval: 300,
+            monitor_required: false,
+            monitor_escalate: false,
+            monitor_upload_url: None,
+            monitor_batch_size: 12,
+            monitor_batch_max_age: 86400,
+            result_webhook_url: None,
+            profile: None,
+            rollback_journal_unit: DEFAULT_ROLLBACK_JOURNAL_UNIT.to_string(),
+            rollback_journal_offset: 1,
+            rollback_journal_boots: 1,
+            rollback_markers: vec![],
+            fallback_banner: DEFAULT_FALLBACK_BANNER.to_string(),
+            unknown_files: UnknownFiles::Warn,
+            ignore_patterns: DEFAULT_IGNORE_PATTERNS.map(String::from).to_vec(),
+            network_checks: vec![],
+            network_wait_timeout: 60,
+            systemd_jobs_check: JobsCheck::Off,
+            systemd_settle_timeout: 120,
+            boot_time_budget: 0,
+            boot_time_check: BudgetCheck::Warn,
+            check_unit: CheckUnit::Off,
+            check_limits: ResourceLimits::default(),
+            sandbox_wanted: false,
+            check_selinux_context: None,
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+            redact_patterns: Vec::new(),
+            error_consumes_attempt: true,
+            remediation_retries: 0,
+            reboot_defer_seconds: 0,
+            action_timeout: 300,
+            hard_limit: 3600,
+            red_action: RedAction::Reboot,
+            reboot_delay: 0,
+            injections: Injections::default(),
+            disabled: false,
+            bootloader: None,
+            rollback_handler: RollbackHandler::Deployment,
+            ab_env_var: DEFAULT_AB_ENV_VAR.to_string(),
+            ab_root_partlabels: DEFAULT_AB_ROOT_PARTLABELS.map(String::from).to_vec(),
+            rollback_min_version: None,
+            auto_pin_after: 0,
+            arm_only_on_update: true,
+            boot_attempt: 1,
+            paths,
+        };
+
+        // Try to load from the TOML/YAML config, then the legacy config file, which wins for
+        // compatibility, and its drop-ins, environment variables take precedence
+        let parsed_config = merge_config_layers(&config_layers(&config.paths));
+        let mut grubenv_configured = false;
+        config.max_reboot = match parsed_config.get_int("GREENBOOT_MAX_BOOT_ATTEMPTS") {
+            Ok(max) => max as u16,
+            Err(_) => {
+                tracing::debug!(
+                    "GREENBOOT_MAX_BOOT_ATTEMPTS not found in config using default value : 3"
+                );
+                3_u16
+            }
+        };
+
+        config.disabled_healthchecks = match get_list(&parsed_config, "DISABLED_HEALTHCHECKS") {
+            Some(disabled) => disabled,
+            None => {
+                tracing::debug!(
+                    "DISABLED_HEALTHCHECKS key not found in config, using default empty list."
+                );
+                vec![]
+            }
+        };
+
+        config.monitor_interval = match parsed_config.get_int("GREENBOOT_MONITOR_INTERVAL") {
+            Ok(interval) if interval > 0 => interval as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_MONITOR_INTERVAL not found or invalid, using default value : 300"
+                );
+                300
+            }
         };
 
-        // Try to load from config file
-        if let Ok(parsed_config) = Config::builder()
-            .add_source(File::new(GREENBOOT_CONFIG_FILE, FileFormat::Ini))
-            .build()
+        config.monitor_required = parsed_config
+            .get_bool("GREENBOOT_MONITOR_REQUIRED")
+            .unwrap_or(false);
+
+        config.monitor_escalate = parsed_config
+            .get_bool("GREENBOOT_MONITOR_ESCALATE")
+            .unwrap_or(false);
+
+        config.network_checks =
+            get_list(&parsed_config, "GREENBOOT_NETWORK_CHECKS").unwrap_or_default();
+
+        config.network_wait_timeout = match parsed_config.get_int("GREENBOOT_NETWORK_WAIT_TIMEOUT")
         {
-            config.max_reboot = match parsed_config.get_int("GREENBOOT_MAX_BOOT_ATTEMPTS") {
-                Ok(max) => max as u16,
-                Err(_) => {
-                    log::debug!(
-                        "GREENBOOT_MAX_BOOT_ATTEMPTS not found in config using default value : 3"
-                    );
-                    3_u16
+            Ok(timeout) if timeout >= 0 => timeout as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_NETWORK_WAIT_TIMEOUT not found or invalid, using default value : 60"
+                );
+                60
+            }
+        };
+
+        config.systemd_jobs_check = match parsed_config
+            .get_string("GREENBOOT_SYSTEMD_JOBS_CHECK")
+            .map_err(anyhow::Error::from)
+            .and_then(|check| check.parse())
+        {
+            Ok(check) => check,
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_SYSTEMD_JOBS_CHECK not usable ({e}), using default value : off"
+                );
+                JobsCheck::Off
+            }
+        };
+
+        config.systemd_settle_timeout = match parsed_config
+            .get_int("GREENBOOT_SYSTEMD_SETTLE_TIMEOUT")
+        {
+            Ok(timeout) if timeout >= 0 => timeout as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_SYSTEMD_SETTLE_TIMEOUT not found or invalid, using default value : 120"
+                );
+                120
+            }
+        };
+
+        config.boot_time_budget = match parsed_config.get_int("GREENBOOT_BOOT_TIME_BUDGET") {
+            Ok(budget) if budget >= 0 => budget as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_BOOT_TIME_BUDGET not found or invalid, using default value : 0"
+                );
+                0
+            }
+        };
+
+        config.boot_time_check = match parsed_config
+            .get_string("GREENBOOT_BOOT_TIME_CHECK")
+            .map_err(anyhow::Error::from)
+            .and_then(|check| check.parse())
+        {
+            Ok(check) => check,
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_BOOT_TIME_CHECK not usable ({e}), using default value : warn"
+                );
+                BudgetCheck::Warn
+            }
+        };
+
+        config.check_unit = match parsed_config
+            .get_string("GREENBOOT_CHECK_UNIT")
+            .map_err(anyhow::Error::from)
+            .and_then(|unit| unit.parse())
+        {
+            Ok(unit) => unit,
+            Err(e) => {
+                tracing::debug!("GREENBOOT_CHECK_UNIT not usable ({e}), using default value : off");
+                CheckUnit::Off
+            }
+        };
+
+        config.check_limits.memory_max = match parsed_config
+            .get_string("GREENBOOT_CHECK_MEMORY_MAX")
+            .map_err(anyhow::Error::from)
+            .and_then(|max| parse_memory_max(&max))
+        {
+            Ok(max) => Some(max),
+            Err(e) => {
+                tracing::debug!("GREENBOOT_CHECK_MEMORY_MAX not usable ({e}), using no limit");
+                None
+            }
+        };
+
+        config.check_limits.cpu_quota = match parsed_config
+            .get_string("GREENBOOT_CHECK_CPU_QUOTA")
+            .map_err(anyhow::Error::from)
+            .and_then(|quota| parse_cpu_quota(&quota))
+        {
+            Ok(quota) => Some(quota),
+            Err(e) => {
+                tracing::debug!("GREENBOOT_CHECK_CPU_QUOTA not usable ({e}), using no limit");
+                None
+            }
+        };
+
+        config.sandbox_wanted = match parsed_config.get_bool("GREENBOOT_SANDBOX_WANTED") {
+            Ok(sandbox) => sandbox,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_SANDBOX_WANTED not found or invalid, using default value : false"
+                );
+                false
+            }
+        };
+
+        config.check_selinux_context = match parsed_config
+            .get_string("GREENBOOT_CHECK_SELINUX_CONTEXT")
+            .map_err(anyhow::Error::from)
+            .and_then(|context| parse_selinux_context(&context))
+        {
+            Ok(context) => Some(context),
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_CHECK_SELINUX_CONTEXT not usable ({e}), leaving it to the policy"
+                );
+                None
+            }
+        };
+
+        config.output_limit = match parsed_config
+            .get_string("GREENBOOT_OUTPUT_LIMIT")
+            .map_err(anyhow::Error::from)
+            .and_then(|limit| parse_memory_max(&limit))
+        {
+            Ok(limit) => usize::try_from(limit).unwrap_or(usize::MAX),
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_OUTPUT_LIMIT not usable ({e}), using default value : {DEFAULT_OUTPUT_LIMIT}"
+                );
+                DEFAULT_OUTPUT_LIMIT
+            }
+        };
+
+        config.error_consumes_attempt = parsed_config
+            .get_bool("GREENBOOT_ERROR_CONSUMES_BOOT_ATTEMPT")
+            .unwrap_or(true);
+
+        config.remediation_retries = match parsed_config.get_int("GREENBOOT_REMEDIATION_RETRIES") {
+            Ok(retries) if retries >= 0 => retries as u16,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_REMEDIATION_RETRIES not found or invalid, using default value : 0"
+                );
+                0
+            }
+        };
+
+        config.reboot_defer_seconds = match parsed_config.get_int("GREENBOOT_REBOOT_DEFER_SECONDS")
+        {
+            Ok(seconds) if seconds >= 0 => seconds as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_REBOOT_DEFER_SECONDS not found or invalid, using default value : 0"
+                );
+                0
+            }
+        };
+
+        config.action_timeout = match parsed_config.get_int("GREENBOOT_ACTION_TIMEOUT") {
+            Ok(seconds) if seconds >= 0 => seconds as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_ACTION_TIMEOUT not found or invalid, using default value : 300"
+                );
+                300
+            }
+        };
+
+        config.hard_limit = match parsed_config.get_int("GREENBOOT_HEALTHCHECK_HARD_LIMIT") {
+            Ok(seconds) if seconds >= 0 => seconds as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_HEALTHCHECK_HARD_LIMIT not found or invalid, using default value : 3600"
+                );
+                3600
+            }
+        };
+
+        config.red_action = match parsed_config
+            .get_string("GREENBOOT_RED_ACTION")
+            .map_err(anyhow::Error::from)
+            .and_then(|action| action.parse())
+        {
+            Ok(action) => action,
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_RED_ACTION not usable ({e}), using default value : reboot"
+                );
+                RedAction::Reboot
+            }
+        };
+
+        config.reboot_delay = match parsed_config.get_int("GREENBOOT_REBOOT_DELAY") {
+            Ok(seconds) if seconds >= 0 => seconds as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_REBOOT_DELAY not found or invalid, using default value : 0"
+                );
+                0
+            }
+        };
+
+        config.bootloader = match parsed_config.get_string("GREENBOOT_BOOTLOADER") {
+            Ok(value) if value.trim() == "auto" => None,
+            Ok(value) => match value.parse() {
+                Ok(bootloader) => Some(bootloader),
+                Err(e) => {
+                    tracing::warn!("{e} in GREENBOOT_BOOTLOADER, detecting it instead");
+                    None
                 }
-            };
+            },
+            Err(_) => None,
+        };
+
+        config.rollback_handler = match parsed_config
+            .get_string("GREENBOOT_ROLLBACK_HANDLER")
+            .map_err(anyhow::Error::from)
+            .and_then(|handler| handler.parse())
+        {
+            Ok(handler) => handler,
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_ROLLBACK_HANDLER not usable ({e}), using default value : deployment"
+                );
+                RollbackHandler::Deployment
+            }
+        };
+
+        config.ab_env_var = match parsed_config.get_string("GREENBOOT_AB_ENV_VAR") {
+            Ok(var) if !var.trim().is_empty() => var.trim().to_string(),
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_AB_ENV_VAR not found or invalid, using default value : {DEFAULT_AB_ENV_VAR}"
+                );
+                DEFAULT_AB_ENV_VAR.to_string()
+            }
+        };
+
+        config.ab_root_partlabels = match get_list(&parsed_config, "GREENBOOT_AB_ROOT_PARTLABELS") {
+            Some(labels) if labels.len() == 2 => labels,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_AB_ROOT_PARTLABELS not found or invalid, using default value : {}",
+                    format_list(&DEFAULT_AB_ROOT_PARTLABELS.map(String::from))
+                );
+                DEFAULT_AB_ROOT_PARTLABELS.map(String::from).to_vec()
+            }
+        };
+
+        config.rollback_min_version = parsed_config
+            .get_string("GREENBOOT_ROLLBACK_MIN_VERSION")
+            .ok()
+            .map(|version| version.trim().to_string())
+            .filter(|version| !version.is_empty());
+
+        config.auto_pin_after = match parsed_config.get_int("GREENBOOT_AUTO_PIN_AFTER") {
+            Ok(boots) if (0..=u32::MAX as i64).contains(&boots) => boots as u32,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_AUTO_PIN_AFTER not found or invalid, using default value : 0"
+                );
+                0
+            }
+        };
+
+        config.arm_only_on_update = match parsed_config.get_bool("GREENBOOT_ARM_ONLY_ON_UPDATE") {
+            Ok(arm) => arm,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_ARM_ONLY_ON_UPDATE not found or invalid, using default value : true"
+                );
+                true
+            }
+        };
+
+        config.monitor_upload_url = parsed_config
+            .get_string("GREENBOOT_MONITOR_UPLOAD_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty());
+
+        config.result_webhook_url = parsed_config
+            .get_string("GREENBOOT_RESULT_WEBHOOK_URL")
+            .ok()
+            .filter(|url| !url.trim().is_empty());
+
+        config.profile = parsed_config
+            .get_string("GREENBOOT_PROFILE")
+            .ok()
+            .and_then(|profile| valid_profile(profile.trim()));
+
+        config.monitor_batch_size = match parsed_config.get_int("GREENBOOT_MONITOR_BATCH_SIZE") {
+            Ok(size) if size > 0 => size as usize,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_MONITOR_BATCH_SIZE not found or invalid, using default value : 12"
+                );
+                12
+            }
+        };
+
+        config.monitor_batch_max_age = match parsed_config
+            .get_int("GREENBOOT_MONITOR_BATCH_MAX_AGE")
+        {
+            Ok(age) if age >= 0 => age as u64,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_MONITOR_BATCH_MAX_AGE not found or invalid, using default value : 86400"
+                );
+                86400
+            }
+        };
+
+        config.rollback_journal_unit = match parsed_config
+            .get_string("GREENBOOT_ROLLBACK_JOURNAL_UNIT")
+        {
+            Ok(unit) if !unit.trim().is_empty() => unit.trim().to_string(),
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_ROLLBACK_JOURNAL_UNIT not found or invalid, using default value : {DEFAULT_ROLLBACK_JOURNAL_UNIT}"
+                );
+                DEFAULT_ROLLBACK_JOURNAL_UNIT.to_string()
+            }
+        };
 
-            config.disabled_healthchecks = match parsed_config.get_string("DISABLED_HEALTHCHECKS") {
-                Ok(raw_disabled_str) => parse_bash_array_string(&raw_disabled_str),
-                Err(_) => {
-                    log::debug!(
-                        "DISABLED_HEALTHCHECKS key not found in config, using default empty list."
+        config.rollback_journal_offset = match parsed_config
+            .get_int("GREENBOOT_ROLLBACK_JOURNAL_OFFSET")
+        {
+            Ok(offset) if offset > 0 && offset <= u32::MAX as i64 => offset as u32,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_ROLLBACK_JOURNAL_OFFSET not found or invalid, using default value : 1"
+                );
+                1
+            }
+        };
+
+        config.rollback_journal_boots = match parsed_config
+            .get_int("GREENBOOT_ROLLBACK_JOURNAL_BOOTS")
+        {
+            Ok(boots) if boots > 0 && boots <= u32::MAX as i64 => boots as u32,
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_ROLLBACK_JOURNAL_BOOTS not found or invalid, using default value : 1"
+                );
+                1
+            }
+        };
+
+        config.rollback_markers = get_list(&parsed_config, "GREENBOOT_ROLLBACK_MARKERS")
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|marker| match marker.split_once(':') {
+                Some((unit, message)) if !unit.is_empty() && !message.is_empty() => {
+                    Some((unit.to_string(), message.to_string()))
+                }
+                _ => {
+                    tracing::warn!(
+                        "GREENBOOT_ROLLBACK_MARKERS entry '{marker}' is not <unit>:<message>, ignoring it"
                     );
-                    vec![]
+                    None
                 }
-            };
+            })
+            .collect();
+
+        config.fallback_banner = match parsed_config.get_string("GREENBOOT_FALLBACK_BANNER") {
+            Ok(banner) if !banner.trim().trim_matches('"').is_empty() => {
+                banner.trim().trim_matches('"').to_string()
+            }
+            _ => {
+                tracing::debug!(
+                    "GREENBOOT_FALLBACK_BANNER not found or invalid, using default value : {DEFAULT_FALLBACK_BANNER}"
+                );
+                DEFAULT_FALLBACK_BANNER.to_string()
+            }
+        };
+
+        config.unknown_files = match parsed_config
+            .get_string("GREENBOOT_UNKNOWN_FILES")
+            .map_err(anyhow::Error::from)
+            .and_then(|policy| policy.parse())
+        {
+            Ok(policy) => policy,
+            Err(e) => {
+                tracing::debug!(
+                    "GREENBOOT_UNKNOWN_FILES not usable ({e}), using default value : warn"
+                );
+                UnknownFiles::Warn
+            }
+        };
+
+        if let Some(patterns) = get_list(&parsed_config, "GREENBOOT_IGNORE_PATTERNS") {
+            config.ignore_patterns = patterns;
+        }
+
+        if let Some(patterns) = get_list(&parsed_config, "GREENBOOT_REDACT_PATTERNS") {
+            config.redact_patterns = patterns;
+        }
+
+        if let Ok(grubenv) = parsed_config.get_string("GREENBOOT_GRUBENV_PATH") {
+            config.paths.grubenv = PathBuf::from(grubenv);
+            grubenv_configured = true;
+        }
+
+        if let Ok(motd) = parsed_config.get_string("GREENBOOT_MOTD_PATH") {
+            config.paths.motd = PathBuf::from(motd);
+        }
+
+        if !grubenv_configured {
+            match discover_grubenv(&config.paths.boot_dir) {
+                Some(grubenv) => config.paths.grubenv = grubenv,
+                None => tracing::debug!(
+                    "no grubenv found, using default path : {}",
+                    config.paths.grubenv.display()
+                ),
+            }
         }
 
         config
     }
+
+    /// effective value of the keys greenboot itself reads, as they would be
+    /// written in the config file
+    fn effective_values(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("GREENBOOT_MAX_BOOT_ATTEMPTS", self.max_reboot.to_string()),
+            (
+                "GREENBOOT_GRUBENV_PATH",
+                self.paths.grubenv.display().to_string(),
+            ),
+            ("GREENBOOT_MOTD_PATH", self.paths.motd.display().to_string()),
+            (
+                "GREENBOOT_BOOTLOADER",
+                self.bootloader
+                    .map_or("auto".to_string(), |bootloader| bootloader.to_string()),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_HANDLER",
+                self.rollback_handler.to_string(),
+            ),
+            ("GREENBOOT_AB_ENV_VAR", self.ab_env_var.clone()),
+            (
+                "GREENBOOT_AB_ROOT_PARTLABELS",
+                format_list(&self.ab_root_partlabels),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_MIN_VERSION",
+                self.rollback_min_version.clone().unwrap_or_default(),
+            ),
+            ("GREENBOOT_AUTO_PIN_AFTER", self.auto_pin_after.to_string()),
+            (
+                "GREENBOOT_ARM_ONLY_ON_UPDATE",
+                self.arm_only_on_update.to_string(),
+            ),
+            (
+                "DISABLED_HEALTHCHECKS",
+                format_list(&self.disabled_healthchecks),
+            ),
+            (
+                "GREENBOOT_NETWORK_CHECKS",
+                format_list(&self.network_checks),
+            ),
+            (
+                "GREENBOOT_NETWORK_WAIT_TIMEOUT",
+                self.network_wait_timeout.to_string(),
+            ),
+            (
+                "GREENBOOT_SYSTEMD_JOBS_CHECK",
+                self.systemd_jobs_check.to_string(),
+            ),
+            (
+                "GREENBOOT_SYSTEMD_SETTLE_TIMEOUT",
+                self.systemd_settle_timeout.to_string(),
+            ),
+            (
+                "GREENBOOT_BOOT_TIME_BUDGET",
+                self.boot_time_budget.to_string(),
+            ),
+            (
+                "GREENBOOT_BOOT_TIME_CHECK",
+                self.boot_time_check.to_string(),
+            ),
+            ("GREENBOOT_CHECK_UNIT", self.check_unit.to_string()),
+            (
+                "GREENBOOT_CHECK_MEMORY_MAX",
+                self.check_limits
+                    .memory_max
+                    .map(|max| max.to_string())
+                    .unwrap_or_default(),
+            ),
+            (
+                "GREENBOOT_CHECK_CPU_QUOTA",
+                self.check_limits
+                    .cpu_quota
+                    .map(|quota| format!("{quota}%"))
+                    .unwrap_or_default(),
+            ),
+            ("GREENBOOT_SANDBOX_WANTED", self.sandbox_wanted.to_string()),
+            (
+                "GREENBOOT_CHECK_SELINUX_CONTEXT",
+                self.check_selinux_context.clone().unwrap_or_default(),
+            ),
+            ("GREENBOOT_OUTPUT_LIMIT", self.output_limit.to_string()),
+            (
+                "GREENBOOT_REDACT_PATTERNS",
+                format_list(&self.redact_patterns),
+            ),
+            (
+                "GREENBOOT_ERROR_CONSUMES_BOOT_ATTEMPT",
+                self.error_consumes_attempt.to_string(),
+            ),
+            (
+                "GREENBOOT_REMEDIATION_RETRIES",
+                self.remediation_retries.to_string(),
+            ),
+            (
+                "GREENBOOT_REBOOT_DEFER_SECONDS",
+                self.reboot_defer_seconds.to_string(),
+            ),
+            ("GREENBOOT_ACTION_TIMEOUT", self.action_timeout.to_string()),
+            (
+                "GREENBOOT_HEALTHCHECK_HARD_LIMIT",
+                self.hard_limit.to_string(),
+            ),
+            ("GREENBOOT_RED_ACTION", self.red_action.to_string()),
+            ("GREENBOOT_REBOOT_DELAY", self.reboot_delay.to_string()),
+            (
+                "GREENBOOT_MONITOR_INTERVAL",
+                self.monitor_interval.to_string(),
+            ),
+            (
+                "GREENBOOT_MONITOR_REQUIRED",
+                self.monitor_required.to_string(),
+            ),
+            (
+                "GREENBOOT_MONITOR_ESCALATE",
+                self.monitor_escalate.to_string(),
+            ),
+            (
+                "GREENBOOT_MONITOR_UPLOAD_URL",
+                self.monitor_upload_url.clone().unwrap_or_default(),
+            ),
+            (
+                "GREENBOOT_MONITOR_BATCH_SIZE",
+                self.monitor_batch_size.to_string(),
+            ),
+            (
+                "GREENBOOT_MONITOR_BATCH_MAX_AGE",
+                self.monitor_batch_max_age.to_string(),
+            ),
+            (
+                "GREENBOOT_RESULT_WEBHOOK_URL",
+                self.result_webhook_url.clone().unwrap_or_default(),
+            ),
+            (
+                "GREENBOOT_PROFILE",
+                self.profile.clone().unwrap_or_default(),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_JOURNAL_UNIT",
+                self.rollback_journal_unit.clone(),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_JOURNAL_OFFSET",
+                self.rollback_journal_offset.to_string(),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_JOURNAL_BOOTS",
+                self.rollback_journal_boots.to_string(),
+            ),
+            (
+                "GREENBOOT_ROLLBACK_MARKERS",
+                format_list(
+                    &self
+                        .rollback_markers
+                        .iter()
+                        .map(|(unit, message)| format!("{unit}:{message}"))
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+            ("GREENBOOT_FALLBACK_BANNER", self.fallback_banner.clone()),
+            ("GREENBOOT_UNKNOWN_FILES", self.unknown_files.to_string()),
+            (
+                "GREENBOOT_IGNORE_PATTERNS",
+                format_list(&self.ignore_patterns),
+            ),
+        ]
+    }
+
+    /// GREENBOOT_FALLBACK_BANNER with its placeholders filled in, `\n` in
+    /// the template starts a new line
+    fn fallback_banner(&self, manager: &str) -> String {
+        let deployment = booted_deployment(&self.paths);
+        self.fallback_banner
+            .replace("{manager}", manager)
+            .replace("{deployment}", deployment.as_deref().unwrap_or("unknown"))
+            .replace("\\n", "\n")
+    }
+
+    /// messages proving a rollback grouped by the unit logging them, greenboot's
+    /// own unit first, which logs the RollbackCompleted event instead, then those of
+    /// GREENBOOT_ROLLBACK_MARKERS
+    fn rollback_markers(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut markers: Vec<(&str, Vec<&str>)> =
+            vec![(self.rollback_journal_unit.as_str(), Vec::new())];
+        for (unit, message) in &self.rollback_markers {
+            match markers.iter_mut().find(|(known, _)| known == unit) {
+                Some((_, messages)) => messages.push(message),
+                None => markers.push((unit, vec![message])),
+            }
+        }
+        markers
+    }
+
+    /// spools and uploads the monitor snapshots when GREENBOOT_MONITOR_UPLOAD_URL is set
+    fn batch_uploader(&self) -> Option<BatchUploader> {
+        self.monitor_upload_url.as_ref().map(|url| BatchUploader {
+            spool: self.paths.monitor_spool.clone(),
+            url: url.clone(),
+            batch_size: self.monitor_batch_size,
+            max_age: Duration::from_secs(self.monitor_batch_max_age),
+        })
+    }
+
+    /// streams the check results when GREENBOOT_RESULT_WEBHOOK_URL is set
+    fn result_webhook(&self) -> Option<ResultWebhook> {
+        self.result_webhook_url
+            .as_ref()
+            .map(|url| ResultWebhook::new(url.clone(), booted_deployment(&self.paths)))
+    }
+
+    /// backend keeping the boot counter of the configured or detected bootloader
+    fn bootloader_backend(&self) -> Option<Box<dyn BootloaderBackend>> {
+        let bootloader = resolve_bootloader(self.bootloader, &self.paths);
+        let backend = backend_for(bootloader, &self.paths);
+        match &backend {
+            Some(backend) => tracing::info!(
+                "using the {bootloader} bootloader backend, state kept in the {}",
+                backend.store()
+            ),
+            None => tracing::error!(
+                "{bootloader} bootloader is not supported yet, skipping boot counter handling"
+            ),
+        }
+        backend
+    }
+
+    /// layers the `greenboot.*` kernel parameters over the config file
+    fn apply_kernel_overrides(&mut self, overrides: KernelOverrides) {
+        if overrides.is_empty() {
+            return;
+        }
+        tracing::info!("applying kernel command line overrides: {overrides:?}");
+        self.disabled = overrides.disable;
+        if let Some(attempts) = overrides.max_boot_attempts {
+            self.max_reboot = attempts;
+        }
+        self.disabled_healthchecks.extend(overrides.skip);
+        if let Some(profile) = overrides.profile.as_deref().and_then(valid_profile) {
+            self.profile = Some(profile);
+        }
+    }
+
+    /// network-online grace settings for the network dependent checks
+    fn network_wait(&self) -> NetworkWait {
+        NetworkWait::new(
+            self.network_checks.clone(),
+            Duration::from_secs(self.network_wait_timeout),
+        )
+    }
+
+    /// environment of the check and action scripts
+    fn script_env(&self) -> ScriptEnv {
+        let results_dir = self.paths.results_dir.clone();
+        fs::create_dir_all(&results_dir)
+            .unwrap_or_else(|e| tracing::warn!("cannot create {}: {e}", results_dir.display()));
+        ScriptEnv {
+            max_boot_attempts: self.max_reboot,
+            boot_attempt: self.boot_attempt,
+            dry_run: !self.injections.is_empty(),
+            results_dir,
+            profile: self.profile.clone(),
+            check_unit: self.check_unit,
+            check_limits: self.check_limits,
+            sandbox_wanted: self.sandbox_wanted,
+            selinux_context: self.check_selinux_context.clone(),
+            output_limit: Some(self.output_limit),
+        }
+    }
+
+    /// how a rollback switches roots and which deployments it may go to
+    fn rollback_policy(&self) -> RollbackPolicy {
+        RollbackPolicy {
+            ab_root: AbRoot::new(
+                self.rollback_handler,
+                self.ab_env_var.clone(),
+                self.ab_root_partlabels.clone(),
+            ),
+            min_version: self.rollback_min_version.clone(),
+        }
+    }
+
+    /// built-in check of the systemd job queue
+    fn systemd_jobs(&self) -> SystemdJobs {
+        SystemdJobs::new(
+            self.systemd_jobs_check,
+            Duration::from_secs(self.systemd_settle_timeout),
+        )
+    }
+
+    /// built-in check of the time from boot to green
+    fn boot_time_budget(&self) -> BootTimeBudget {
+        BootTimeBudget::new(
+            (self.boot_time_budget > 0).then(|| Duration::from_secs(self.boot_time_budget)),
+            self.boot_time_check,
+        )
+    }
+
+    /// files of the check dirs that are not checks
+    fn check_files(&self) -> CheckFiles {
+        CheckFiles::new(self.unknown_files, &self.ignore_patterns)
+    }
+
+    /// per-run limit for green.d/red.d scripts, none when set to 0
+    fn action_timeout(&self) -> Option<Duration> {
+        (self.action_timeout > 0).then(|| Duration::from_secs(self.action_timeout))
+    }
+
+    /// time after which a stalled health-check is declared RED, none when set to 0
+    fn hard_limit(&self) -> Option<Duration> {
+        (self.hard_limit > 0).then(|| Duration::from_secs(self.hard_limit))
+    }
 }
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 /// log level for journald logging
@@ -80,14 +1165,45 @@ enum LogLevel {
 }
 
 impl LogLevel {
-    fn to_log(self) -> log::LevelFilter {
+    fn to_filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Trace => LevelFilter::TRACE,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Off => LevelFilter::OFF,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// format of the logs on stderr
+enum LogFormat {
+    Text,
+    /// an object per line with the timestamp, level, message, target and
+    /// the spans the record was logged in
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+/// coloring of the terminal output
+enum ColorChoice {
+    /// when writing to a terminal
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// whether output is colored, `terminal` if it goes to one
+    fn enabled(self, terminal: bool) -> bool {
         match self {
-            LogLevel::Trace => log::LevelFilter::Trace,
-            LogLevel::Debug => log::LevelFilter::Debug,
-            LogLevel::Info => log::LevelFilter::Info,
-            LogLevel::Warn => log::LevelFilter::Warn,
-            LogLevel::Error => log::LevelFilter::Error,
-            LogLevel::Off => log::LevelFilter::Off,
+            ColorChoice::Auto => {
+                terminal && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
         }
     }
 }
@@ -98,9 +1214,100 @@ impl LogLevel {
 /// greenboot health-check -> runs the custom health checks
 ///
 /// greenboot set-rollback-trigger -> sets rollback trigger flag for next boot
+///
+/// greenboot rollback -> switches to the previous deployment for next boot
+///
+/// greenboot monitor -> periodically re-runs the health checks after a green boot
+///
+/// greenboot status -> prints the health-check verdict of the current boot
+///
+/// greenboot plan -> prints what a health-check would run without running it
 enum Commands {
     HealthCheck,
     SetRollbackTrigger,
+    /// switches to the previous deployment, or the other image of an A/B
+    /// system, which the next boot starts
+    Rollback {
+        /// ostree deployment to make the default instead: its index, id,
+        /// checksum prefix or `<checksum>.<serial>`
+        #[clap(long)]
+        to: Option<String>,
+        /// prints what the rollback would do, changing nothing, and exits
+        /// with 5 when it would go ahead
+        #[clap(long)]
+        dry_run: bool,
+    },
+    Status,
+    Monitor {
+        /// seconds between two monitoring runs, overrides GREENBOOT_MONITOR_INTERVAL
+        #[clap(long)]
+        interval: Option<u64>,
+    },
+    /// checks a composed image tree for misconfiguration, fails on any error
+    ValidateImage {
+        /// root of the image tree
+        #[clap(long)]
+        root: PathBuf,
+    },
+    /// prints the checks and actions a health-check would run, in order,
+    /// without executing anything
+    Plan,
+    /// inspects the configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// converts bash greenboot leftovers to the current layout, fails when
+    /// some of them need a manual migration
+    Migrate {
+        /// root of the system or image tree to migrate
+        #[clap(long, default_value = "/")]
+        root: PathBuf,
+        /// only report what would be converted
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// prints the checks and the verdict of the last health-check from the
+    /// journal
+    Logs {
+        /// boot to read, 0 for the current one and -1 for the previous one
+        #[clap(long, default_value_t = 0, allow_negative_numbers = true)]
+        boot: i32,
+    },
+    /// the verdicts of the previous health-checks
+    History {
+        #[command(subcommand)]
+        command: HistoryCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommands {
+    /// writes the verdicts to stdout for offline analysis
+    Export {
+        /// csv, or parquet when built with the parquet feature
+        #[clap(long, default_value = "csv")]
+        format: HistoryFormat,
+        /// only verdicts since this time: seconds since the epoch, a duration
+        /// ago such as 7d, or a UTC date YYYY-MM-DD[THH:MM:SS]
+        #[clap(long)]
+        since: Option<String>,
+    },
+    /// summarizes the verdicts, failed checks and rollbacks of the last boots
+    Show {
+        /// number of boots whose verdicts are shown
+        #[clap(long, default_value_t = 10)]
+        boots: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// prints the effective configuration and where each value comes from
+    Print,
+    /// checks the config files and drop-ins for unknown keys and invalid
+    /// values, fails on any issue
+    Validate,
 }
 
 /// Determine if we're executing inside a containerized environment.
@@ -113,256 +1320,1190 @@ fn running_in_container() -> bool {
         {
             Ok(status) => {
                 if status.success() {
-                    log::debug!("systemd-detect-virt detected container environment ({status})");
+                    tracing::debug!(
+                        "systemd-detect-virt detected container environment ({status})"
+                    );
                     true
                 } else {
-                    log::debug!("systemd-detect-virt reported non-container context ({status})");
+                    tracing::debug!(
+                        "systemd-detect-virt reported non-container context ({status})"
+                    );
                     false
                 }
             }
             Err(err) => {
-                log::debug!("Unable to determine container state via systemd-detect-virt: {err}");
+                tracing::debug!(
+                    "Unable to determine container state via systemd-detect-virt: {err}"
+                );
                 false
             }
         }
     })
 }
 
+/// the configured bootloader, or the detected one; an undetected bootloader
+/// is assumed to be GRUB as before autodetection existed
+fn resolve_bootloader(configured: Option<Bootloader>, paths: &Paths) -> Bootloader {
+    configured
+        .or_else(|| detect_bootloader(paths))
+        .unwrap_or_else(|| {
+            tracing::warn!("cannot detect the bootloader, assuming GRUB");
+            Bootloader::GrubBios
+        })
+}
+
 /// Execute a mutating GRUB operation while ensuring /boot is temporarily remounted RW if needed
-fn with_boot_rw<F>(f: F) -> Result<()>
+fn with_boot_rw<F>(paths: &Paths, f: F) -> Result<()>
 where
     F: FnOnce() -> Result<()>,
 {
     if running_in_container() {
-        log::info!("Container environment detected; skipping /boot remounts");
+        tracing::info!("Container environment detected; skipping /boot remounts");
         return f();
     }
 
-    let was_rw =
-        is_boot_rw().map_err(|e| anyhow::anyhow!("Failed to check boot mount state: {}", e))?;
-
-    log::info!(
-        "Initial /boot mount state: {}",
-        if was_rw { "rw" } else { "ro" }
-    );
-
-    if !was_rw {
-        log::info!("Remounting /boot as rw for operation");
-        remount_boot_rw().context("Failed to remount /boot as rw")?;
-    } else {
-        log::info!("/boot is already rw; no remount needed");
-    }
-
+    let boot = BootMount::new(paths);
+    let guard = boot.rw_guard().context("Failed to make /boot writable")?;
     let op_result = f();
-
-    if !was_rw {
-        log::info!("Restoring /boot mount to ro");
-        remount_boot_ro().context("Failed to remount /boot as ro")?;
-    }
-
+    guard.release().context("Failed to remount /boot as ro")?;
     op_result
 }
 
-/// Check if greenboot-rollback.service successfully ran in the previous boot
-fn check_previous_rollback() -> Result<bool> {
-    log::debug!("Checking journalctl for previous rollback attempts...");
-
-    let output = Command::new("journalctl")
-        .arg("-b")
-        .arg("-1")
-        .arg("-u")
-        .arg("greenboot-healthcheck.service")
-        .arg("--no-pager")
-        .output()
-        .context("Failed to execute journalctl command to check rollback status")?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        log::warn!(
-            "journalctl command failed with status: {}. Error: {}",
-            output.status,
-            stderr.trim()
-        );
-        return Ok(false);
+/// The rollback the previous boots ended with, if any. The rollback marker
+/// greenboot leaves when it rolls back is relied on first, so a fallback
+/// boot is reported even when the journal of the previous boot is gone. The
+/// journal is searched otherwise, for the rollbacks of the agents of
+/// GREENBOOT_ROLLBACK_MARKERS; boots where none of the units logged anything,
+/// e.g. a reboot before greenboot ran, are skipped up to
+/// GREENBOOT_ROLLBACK_JOURNAL_BOOTS boots back.
+fn previous_rollback(config: &GreenbootConfig) -> Result<Option<RollbackMarker>> {
+    // always consume the marker so it cannot outlive the boot that follows the rollback
+    let marker = take_rollback_marker(&config.paths).unwrap_or_else(|e| {
+        tracing::warn!("cannot read rollback marker: {e}");
+        None
+    });
+    if let Some(marker) = marker {
+        if marker.from.is_some() && marker.from == booted_deployment(&config.paths) {
+            tracing::warn!(
+                "the rollback from {} did not take effect, it is still booted",
+                marker.from.as_deref().unwrap_or_default()
+            );
+            return Ok(None);
+        }
+        tracing::debug!("rollback marker found: {marker:?}");
+        return Ok(Some(marker));
     }
-
-    let journal_output =
-        String::from_utf8(output.stdout).context("Failed to parse journalctl output as UTF-8")?;
-
-    if journal_output.trim().is_empty() {
-        log::debug!("No rollback service logs found in previous boot");
-        return Ok(false);
+    if !journal::is_available() {
+        tracing::debug!("journald is not available and there is no rollback marker");
+        return Ok(None);
     }
 
-    // Check for specific success indicators
-    let success = journal_output.contains("Rollback successful");
+    tracing::debug!("Checking the journal for previous rollback attempts...");
 
-    log::debug!("Rollback detection result: {success}");
-    Ok(success)
-}
-
-/// Generate appropriate MOTD message with optional fallback prefix
-/// Generate MOTD message using pre-checked rollback status
-fn generate_motd_message(base_msg: &str, previous_rollback: bool) -> Result<String> {
-    let prefix = if previous_rollback {
-        match detect_os_deployment() {
-            Some(manager) => {
-                format!(
-                    "FALLBACK BOOT DETECTED! Default {manager} deployment has been rolled back.\n"
-                )
-            }
-            None => String::from(""),
+    let mut reader = match journal::JournalReader::open() {
+        Ok(reader) => reader,
+        Err(e) => {
+            tracing::warn!("cannot read the journal ({e}), relying on the rollback marker only");
+            return Ok(None);
         }
-    } else {
-        String::from("")
     };
-    Ok(format!("{prefix}{base_msg}"))
-}
+    // an unreadable or rotated journal only means no rollback can be found
+    match journal_rollback(config, &mut reader) {
+        Ok(rollback) => Ok(rollback),
+        Err(e) => {
+            tracing::warn!("cannot search the journal for a previous rollback: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// the rollback logged in the journal of the previous boots, see previous_rollback
+fn journal_rollback(
+    config: &GreenbootConfig,
+    reader: &mut journal::JournalReader,
+) -> Result<Option<RollbackMarker>> {
+    let markers = config.rollback_markers();
+    let rollback_completed = Event::RollbackCompleted.message_id();
+    let first = config.rollback_journal_offset;
+    let last = first.saturating_add(config.rollback_journal_boots - 1);
+    for offset in first..=last {
+        let boot = i32::try_from(offset).map_or(i32::MIN, |offset| -offset);
+        let Some(boot_id) = reader.boot_id(boot)? else {
+            tracing::debug!("The journal does not go back to boot -{offset}");
+            return Ok(None);
+        };
+        let mut logged = false;
+        let mut rollback = None;
+        for (unit, messages) in &markers {
+            let entries = reader.entries(&[("_BOOT_ID", &boot_id), ("_SYSTEMD_UNIT", unit)])?;
+            if entries.is_empty() {
+                tracing::debug!("No {unit} logs found in boot -{offset}");
+                continue;
+            }
+            logged = true;
+
+            let is_rollback = |entry: &&journal::JournalEntry| {
+                let message = entry.field("MESSAGE").unwrap_or_default();
+                entry.field("MESSAGE_ID") == Some(rollback_completed)
+                    || messages.iter().any(|marker| message.contains(marker))
+            };
+            if let Some(entry) = entries.iter().find(is_rollback) {
+                tracing::debug!(
+                    "{unit} logged a rollback in boot -{offset}: {}",
+                    entry.field("MESSAGE").unwrap_or_default()
+                );
+                let logged_at = entry
+                    .field("__REALTIME_TIMESTAMP")
+                    .and_then(|usec| usec.parse::<u64>().ok())
+                    .map_or(0, |usec| usec / 1_000_000);
+                rollback = Some(RollbackMarker::at(logged_at, None, None));
+            }
+        }
+        if logged {
+            tracing::debug!(
+                "Rollback detection result in boot -{offset}: {}",
+                rollback.is_some()
+            );
+            return Ok(rollback);
+        }
+    }
+    tracing::debug!("No rollback unit logs found in boots -{first} to -{last}");
+    Ok(None)
+}
+
+/// MOTD message, preceded by the fallback banner when the previous boot rolled back
+fn generate_motd_message(base_msg: &str, fallback_banner: Option<&str>) -> String {
+    match fallback_banner {
+        Some(banner) => format!("{banner}\n{base_msg}"),
+        None => base_msg.to_string(),
+    }
+}
+
+/// persists the verdict for `greenboot status` along with the messages the
+/// checks of `details` reported and tags it in the journal, returns
+/// remediation hints when the same checks failed on consecutive boots
+fn record_status(
+    paths: &Paths,
+    summary: &RunSummary,
+    status: HealthStatus,
+    failed_checks: Vec<String>,
+    details: &[FailedCheck],
+) -> Vec<String> {
+    let (event, priority) = match status {
+        HealthStatus::Green => (Event::Green, journal::PRIORITY_INFO),
+        HealthStatus::Degraded => (Event::Degraded, journal::PRIORITY_WARNING),
+        _ => (Event::Red, journal::PRIORITY_ERR),
+    };
+    // an ERROR says nothing about the checks, keep the streak as it is
+    let hints = match status {
+        HealthStatus::Error => vec![],
+        _ => repeated_failure_hints(paths, &failed_checks),
+    };
+    let failed = failed_checks.join(" ");
+    let status_field = status.to_string();
+    let hints_field = hints.join("\n");
+    let messages: Vec<String> = details.iter().filter_map(FailedCheck::message).collect();
+    let messages_field = messages.join("\n");
+    let mut fields = vec![
+        ("GREENBOOT_STATUS", status_field.as_str()),
+        ("GREENBOOT_FAILED_CHECKS", failed.as_str()),
+    ];
+    if !hints.is_empty() {
+        fields.push(("GREENBOOT_HINTS", hints_field.as_str()));
+    }
+    if !messages.is_empty() {
+        fields.push(("GREENBOOT_CHECK_MESSAGES", messages_field.as_str()));
+    }
+    let time_to_green = matches!(status, HealthStatus::Green | HealthStatus::Degraded)
+        .then(time_since_boot)
+        .and_then(Result::ok);
+    let time_to_green_field = time_to_green.map(|elapsed| elapsed.as_millis().to_string());
+    if let Some(elapsed) = time_to_green {
+        tracing::info!(
+            "boot reached {status} {}s after it started",
+            elapsed.as_secs()
+        );
+    }
+    if let Some(field) = &time_to_green_field {
+        fields.push(("GREENBOOT_TIME_TO_GREEN_MS", field.as_str()));
+    }
+    journal::send_event(
+        event,
+        priority,
+        &format!("greenboot health-check finished with status {status}"),
+        &fields,
+    )
+    .unwrap_or_else(|e| tracing::debug!("cannot send status to journald: {e}"));
+
+    let deployment = summary.deployment.clone();
+    let streak =
+        read_green_streak(paths).record(deployment.as_deref(), status == HealthStatus::Green);
+    write_green_streak(paths, &streak)
+        .unwrap_or_else(|e| tracing::warn!("cannot update green streak: {e}"));
+
+    let record = StatusRecord::new(status, failed_checks)
+        .with_hints(hints.clone())
+        .with_messages(messages)
+        .with_rollback(summary.rollback.clone());
+    write_status(paths, &record)
+        .unwrap_or_else(|e| tracing::error!("cannot write status file: {e}"));
+    let entry = HistoryEntry::new(&record, deployment)
+        .with_after_rollback(summary.rollback.is_some())
+        .with_duration(summary.started.elapsed())
+        .with_time_to_green(time_to_green);
+    append_history(paths, &entry)
+        .unwrap_or_else(|e| tracing::error!("cannot append to the history: {e}"));
+    hints
+}
+
+/// remediation advice of the failed checks once they failed identically on
+/// at least two consecutive boots
+fn repeated_failure_hints(paths: &Paths, failed_checks: &[String]) -> Vec<String> {
+    let boots = match update_failure_streak(paths, failed_checks) {
+        Ok(boots) => boots,
+        Err(e) => {
+            tracing::warn!("cannot update failure streak: {e}");
+            return vec![];
+        }
+    };
+    if boots < 2 {
+        return vec![];
+    }
+    tracing::warn!("the same checks failed on {boots} consecutive boots: {failed_checks:?}");
+    let hints = remediation_hints(paths, failed_checks);
+    hints
+        .iter()
+        .for_each(|hint| tracing::warn!("remediation hint: {hint}"));
+    hints
+}
+
+/// pins the booted deployment once it was GREEN for GREENBOOT_AUTO_PIN_AFTER
+/// consecutive boots, only once per streak
+fn auto_pin(config: &GreenbootConfig) {
+    let paths = &config.paths;
+    if config.auto_pin_after == 0 || !paths.ostree_booted.exists() {
+        return;
+    }
+    let streak = read_green_streak(paths);
+    if streak.pinned || streak.boots < config.auto_pin_after {
+        return;
+    }
+    match pin_booted_deployment() {
+        Ok(deployment) => {
+            tracing::info!(
+                "{} was GREEN for {} consecutive boots, pinned it as a known-good fallback",
+                deployment.id,
+                streak.boots
+            );
+            write_green_streak(
+                paths,
+                &GreenStreak {
+                    pinned: true,
+                    ..streak
+                },
+            )
+            .unwrap_or_else(|e| tracing::warn!("cannot update green streak: {e}"));
+        }
+        Err(e) => tracing::error!("cannot pin the booted deployment: {e}"),
+    }
+}
+
+/// exit codes and output of the required checks a health-check failed on
+fn failed_details(result: &Result<Diagnostics>) -> Vec<FailedCheck> {
+    match result
+        .as_ref()
+        .map_err(|e| e.downcast_ref::<DiagnosticsError>())
+    {
+        Err(Some(DiagnosticsError::RequiredFailed { details, .. })) => details.clone(),
+        _ => vec![],
+    }
+}
+
+/// appends the remediation hints to a MOTD message
+fn motd_with_hints(message: &str, hints: &[String]) -> String {
+    hints.iter().fold(message.to_string(), |motd, hint| {
+        format!("{motd}\nHint: {hint}")
+    })
+}
+
+/// runs red.d with the `failed` checks in its environment, returns true if a
+/// script asked to defer the reboot
+fn run_red_scripts(config: &GreenbootConfig, failed: Vec<FailedCheck>) -> bool {
+    let report = run_red(
+        &config.paths,
+        config.action_timeout(),
+        &config.script_env(),
+        &FailureContext { failed },
+    );
+    if report.failures().next().is_some() {
+        tracing::error!("There is a problem with red script runner");
+    }
+    report.defer_reboot()
+}
+
+/// runs pre-reboot.d, flushes the logs and issues the failure reboot after
+/// GREENBOOT_REBOOT_DELAY, unless a red.d script asked to defer it, then the
+/// reboot waits GREENBOOT_REBOOT_DEFER_SECONDS more or is skipped for this attempt
+fn failure_reboot(
+    config: &GreenbootConfig,
+    backend: &dyn BootloaderBackend,
+    force: bool,
+    defer: bool,
+) {
+    if defer {
+        if config.reboot_defer_seconds == 0 {
+            tracing::warn!(
+                "red.d script requested to defer the reboot, skipping it for this attempt"
+            );
+            return;
+        }
+        tracing::warn!(
+            "red.d script requested to defer the reboot, rebooting in {}s",
+            config.reboot_defer_seconds
+        );
+        thread::sleep(Duration::from_secs(config.reboot_defer_seconds));
+    }
+    if config.reboot_delay > 0 {
+        let message = format!(
+            "greenboot: health-check failed, rebooting in {}s. Stop greenboot-healthcheck.service to intervene.",
+            config.reboot_delay
+        );
+        tracing::warn!("{message}");
+        handle_wall(&message).unwrap_or_else(|e| tracing::warn!("cannot send wall message: {e}"));
+        thread::sleep(Duration::from_secs(config.reboot_delay));
+    }
+    let report = run_pre_reboot(&config.paths, config.action_timeout(), &config.script_env());
+    if report.failures().next().is_some() {
+        tracing::error!("There is a problem with pre-reboot script runner");
+    }
+    flush_logs();
+    handle_reboot(backend, force).unwrap_or_else(|e| tracing::error!("cannot reboot: {e}"));
+}
+
+/// true if the booted ostree deployment was GREEN or DEGRADED before, i.e.
+/// this is not the first boot of a new image. Without a deployment or a
+/// history it is assumed to be one.
+fn deployment_passed_before(paths: &Paths) -> bool {
+    let Some(deployment) = booted_deployment(paths) else {
+        return false;
+    };
+    match read_history(paths) {
+        Ok(entries) => passed_before(&entries, &deployment),
+        Err(e) => {
+            tracing::warn!("cannot read the history: {e}");
+            false
+        }
+    }
+}
+
+/// keeps the details of the failed checks in /var/lib/greenboot, where they
+/// survive the reboot and the rollback
+fn record_last_failure(paths: &Paths, checks: Vec<FailedCheck>) {
+    let failure = LastFailure {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        deployment: booted_deployment(paths),
+        checksum: booted_checksum(paths),
+        checks,
+    };
+    write_last_failure(paths, &failure)
+        .unwrap_or_else(|e| tracing::error!("cannot save the failure details: {e}"));
+}
+
+/// leaves `attempts` boots of the booted deployment before a rollback
+fn arm_boot_counter(paths: &Paths, backend: &dyn BootloaderBackend, attempts: u16) {
+    if let Err(e) = with_boot_rw(paths, || backend.set_boot_counter(attempts)) {
+        tracing::error!("cannot set boot_counter: {e}");
+        return;
+    }
+    let counter = attempts.to_string();
+    journal::send_event(
+        Event::BootCounterSet,
+        journal::PRIORITY_INFO,
+        &format!("greenboot set the boot counter to {attempts}"),
+        &[("GREENBOOT_BOOT_COUNTER", &counter)],
+    )
+    .unwrap_or_else(|e| tracing::debug!("cannot log the boot counter to journald: {e}"));
+}
+
+/// counts a failed boot of the booted deployment in /var/lib/greenboot, a
+/// healthy one clears its count. Without ostree there is nothing to count.
+fn record_boot_attempt(paths: &Paths, green: bool) {
+    let Some(checksum) = booted_checksum(paths) else {
+        return;
+    };
+    let mut attempts = BootAttempts::read(paths);
+    if green {
+        attempts.passed(&checksum);
+    } else {
+        let failed = attempts.failed(&checksum);
+        tracing::debug!("deployment {checksum} failed {failed} boot(s)");
+    }
+    attempts
+        .write(paths)
+        .unwrap_or_else(|e| tracing::error!("cannot save the boot attempts: {e}"));
+}
+
+/// a profile names a subdir of the check dirs, none for an empty or
+/// invalid name
+fn valid_profile(profile: &str) -> Option<String> {
+    if profile.is_empty() {
+        return None;
+    }
+    if profile.starts_with('.') || profile.contains('/') {
+        tracing::warn!("ignoring invalid check profile '{profile}'");
+        return None;
+    }
+    Some(profile.to_string())
+}
+
+/// boot of the booted deployment this is, counting its failed boots so far
+fn current_boot_attempt(paths: &Paths) -> u16 {
+    booted_checksum(paths)
+        .map(|checksum| BootAttempts::read(paths).get(&checksum))
+        .unwrap_or(0)
+        .saturating_add(1)
+}
+
+/// boot counter to arm on the first failure: GREENBOOT_MAX_BOOT_ATTEMPTS
+/// less the boots the deployment already failed before this one, so a
+/// re-staged image or a reset grubenv does not start over
+fn remaining_boot_attempts(paths: &Paths, max_reboot: u16) -> u16 {
+    let Some(checksum) = booted_checksum(paths) else {
+        return max_reboot;
+    };
+    // this boot is already counted
+    let failed_before = BootAttempts::read(paths).get(&checksum).saturating_sub(1);
+    if failed_before > 0 {
+        tracing::info!(
+            "deployment {checksum} already failed {failed_before} boot(s) before this one"
+        );
+    }
+    max_reboot.saturating_sub(failed_before)
+}
+
+/// false when a rollback would have nowhere to go: the boot counter is not
+/// armed and the red boot is left running, flagged in the status file. A
+/// failed query does not stop the reboots.
+fn preflight_rollback(config: &GreenbootConfig, backend: &dyn BootloaderBackend) -> bool {
+    let paths = &config.paths;
+    match has_rollback_target(paths, backend, &config.rollback_policy()) {
+        Ok(true) => true,
+        Ok(false) => {
+            tracing::error!(
+                "No rollback target: there is no other deployment to roll back to, skipping the boot retries - manual intervention required"
+            );
+            mark_no_rollback_target(paths)
+                .unwrap_or_else(|e| tracing::error!("cannot flag the status: {e}"));
+            handle_motd(
+                paths,
+                "Greenboot healthcheck failed - status is RED, no rollback target - manual intervention required",
+            )
+            .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+            false
+        }
+        Err(e) => {
+            tracing::warn!("cannot check for a rollback target, retrying the boot anyway: {e}");
+            true
+        }
+    }
+}
+
+/// default red action: reboots while the boot counter allows, then rolls back
+fn reboot_until_rollback(
+    config: &GreenbootConfig,
+    backend: &dyn BootloaderBackend,
+    defer_reboot: bool,
+) -> Result<()> {
+    let paths = &config.paths;
+    // Check if boot_counter is 0 (exhausted retries) or if no counter is set
+    match backend.boot_counter()? {
+        Some(counter) if counter > 0 => {
+            // Still have retries left, just reboot
+            tracing::info!("Boot counter is {counter}, rebooting to try again");
+            failure_reboot(config, backend, false, defer_reboot);
+        }
+        Some(_) => {
+            // Boot counter reached 0 (or negative) - roll back
+            rollback_and_reboot(config, backend, defer_reboot)?;
+        }
+        None => {
+            // No boot counter set - this is the first failure, set it and reboot
+            if config.arm_only_on_update && deployment_passed_before(paths) {
+                tracing::warn!(
+                    "The booted deployment passed the health-check on a previous boot, not arming the boot counter for a failure that is not caused by an update"
+                );
+                return Ok(());
+            }
+            if !preflight_rollback(config, backend) {
+                return Ok(());
+            }
+            let attempts = remaining_boot_attempts(paths, config.max_reboot);
+            if attempts == 0 {
+                if config.max_reboot == 0 {
+                    tracing::info!(
+                        "GREENBOOT_MAX_BOOT_ATTEMPTS is 0, rolling back on the first RED boot"
+                    );
+                } else {
+                    tracing::warn!(
+                        "The booted deployment already used up its boot attempts, rolling back without retrying"
+                    );
+                }
+                arm_boot_counter(paths, backend, 0);
+                return rollback_and_reboot(config, backend, defer_reboot);
+            }
+            tracing::info!("First health check failure, setting boot counter to {attempts}");
+            arm_boot_counter(paths, backend, attempts);
+            failure_reboot(config, backend, false, defer_reboot);
+        }
+    }
+    Ok(())
+}
+
+/// rolls back to the previous deployment if the rollback trigger is set and
+/// reboots into it, expects the boot counter to be exhausted
+fn rollback_and_reboot(
+    config: &GreenbootConfig,
+    backend: &dyn BootloaderBackend,
+    defer_reboot: bool,
+) -> Result<()> {
+    let paths = &config.paths;
+    if !backend.rollback_trigger().unwrap_or(false) {
+        tracing::warn!(
+            "Boot counter exhausted but no rollback trigger set - manual intervention required"
+        );
+        bail!("Manual intervention required - no rollback trigger");
+    }
+    tracing::info!("Boot counter exhausted and rollback trigger is set - initiating rollback");
+    journal::send_event(
+        Event::RollbackTriggered,
+        journal::PRIORITY_WARNING,
+        "greenboot is rolling back to the previous deployment",
+        &[],
+    )
+    .unwrap_or_else(|e| tracing::debug!("cannot log the rollback to journald: {e}"));
+    match handle_rollback(paths, backend, &config.rollback_policy()) {
+        Ok(()) => {
+            tracing::info!("{ROLLBACK_SUCCESS_MESSAGE}");
+            journal::send_event(
+                Event::RollbackCompleted,
+                journal::PRIORITY_INFO,
+                ROLLBACK_SUCCESS_MESSAGE,
+                &[],
+            )
+            .unwrap_or_else(|e| tracing::warn!("cannot log the rollback to journald: {e}"));
+            let marker = RollbackMarker::new(booted_deployment(paths), None);
+            write_rollback_marker(paths, &marker)
+                .unwrap_or_else(|e| tracing::error!("cannot write rollback marker: {e}"));
+            with_boot_rw(paths, || {
+                backend.unset_boot_counter()?;
+                backend.unset_rollback_trigger()?;
+                Ok(())
+            })
+            .unwrap_or_else(|e| tracing::error!("Failed to clear boot counter vars: {e}"));
+            failure_reboot(config, backend, true, defer_reboot);
+            Ok(())
+        }
+        Err(rollback_err) => {
+            tracing::error!("Rollback failed: {rollback_err}");
+            bail!("Manual intervention required - rollback failed");
+        }
+    }
+}
 
 /// triggers the diagnostics followed by the action on the outcome
 /// this also handles setting the grub variables and system restart
-fn health_check() -> Result<()> {
-    let config = GreenbootConfig::get_config();
-    log::debug!("{config:?}");
+fn health_check(config: &GreenbootConfig) -> Result<HealthStatus> {
+    let _run = tracing::info_span!("health_check").entered();
+    let paths = &config.paths;
+    let mut summary = RunSummary::start(paths);
+
+    if config.disabled {
+        tracing::warn!(
+            "greenboot.disable is set on the kernel command line, skipping the health-check"
+        );
+        handle_motd(
+            paths,
+            "Greenboot disabled on the kernel command line - health-check skipped",
+        )
+        .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+        summary.emit("DISABLED", 0, 0, None);
+        return Ok(HealthStatus::Green);
+    }
 
     let container_mode = running_in_container();
     if container_mode {
-        log::info!("Container environment detected; skipping reboot and rollback handling");
+        tracing::info!("Container environment detected; skipping reboot and rollback handling");
+    }
+    let deployment: Vec<(&str, &str)> = summary
+        .deployment
+        .iter()
+        .map(|deployment| ("GREENBOOT_DEPLOYMENT", deployment.as_str()))
+        .collect();
+    journal::send_event(
+        Event::HealthCheckStarted,
+        journal::PRIORITY_INFO,
+        "greenboot health-check started",
+        &deployment,
+    )
+    .unwrap_or_else(|e| tracing::debug!("cannot log the start to journald: {e}"));
+    // without a bootloader backend there is nothing to count or roll back either
+    let backend = if container_mode {
+        None
+    } else {
+        config.bootloader_backend()
+    };
+    let _backstop = config.hard_limit().map(|limit| {
+        let reboot = backend.is_some().then(|| BackstopReboot::new(config));
+        Backstop::arm(paths.clone(), limit, reboot)
+    });
+    if let Some(backend) = &backend {
+        with_boot_rw(paths, || backend.count_boot())
+            .unwrap_or_else(|e| tracing::error!("cannot count this boot: {e}"));
     }
 
     // Check rollback status with graceful error handling
-    let previous_rollback = match check_previous_rollback() {
-        Ok(status) => {
-            if status {
-                match detect_os_deployment() {
-                    Some(manager) => log::info!(
-                        "FALLBACK BOOT DETECTED! Default {manager} deployment has been rolled back."
-                    ),
-                    None => log::info!(
-                        "FALLBACK BOOT DETECTED! Cannot rollback as its available only on rpm-ostree or bootc system."
-                    ),
-                }
+    summary.rollback = previous_rollback(config).unwrap_or_else(|e| {
+        tracing::warn!("Failed to check previous rollback status: {e}. Defaulting to false.");
+        None
+    });
+    let fallback_banner = match summary.rollback.is_some() {
+        true => match detect_os_deployment(paths) {
+            Some(manager) => {
+                let banner = config.fallback_banner(manager);
+                tracing::info!("{banner}");
+                Some(banner)
             }
-            status
-        }
-        Err(e) => {
-            log::warn!("Failed to check previous rollback status: {e}. Defaulting to false.");
-            false
-        }
+            None => {
+                tracing::info!(
+                    "FALLBACK BOOT DETECTED! Cannot rollback as its available only on rpm-ostree or bootc system."
+                );
+                None
+            }
+        },
+        false => None,
     };
 
     // Rest of the function remains the same...
-    handle_motd(&generate_motd_message(
-        "Greenboot healthcheck is in progress",
-        previous_rollback,
-    )?)?;
+    handle_motd(
+        paths,
+        &generate_motd_message(
+            "Greenboot healthcheck is in progress",
+            fallback_banner.as_deref(),
+        ),
+    )?;
+
+    let check_files = config.check_files();
+    let script_env = config.script_env();
+    let systemd_jobs = config.systemd_jobs();
+    let webhook = config.result_webhook();
+    let mut result = systemd_jobs.apply(run_diagnostics(
+        paths,
+        config.disabled_healthchecks.clone(),
+        &config.network_wait(),
+        &config.injections,
+        &check_files,
+        webhook.as_ref(),
+        &script_env,
+    ));
+
+    // red.d scripts often fix transient issues, give them a chance before rebooting
+    let mut remediated = false;
+    let mut defer_reboot = false;
+    for retry in 1..=config.remediation_retries {
+        let remediable = match &result {
+            Ok(_) => false,
+            Err(e) => e
+                .downcast_ref::<DiagnosticsError>()
+                .is_some_and(|e| !e.is_infrastructure()),
+        };
+        if !remediable {
+            break;
+        }
+        tracing::warn!(
+            "health-check failed, running red.d remediation before retry {retry}/{}",
+            config.remediation_retries
+        );
+        defer_reboot = run_red_scripts(config, failed_details(&result));
+        remediated = true;
+        result = systemd_jobs.apply(run_diagnostics(
+            paths,
+            config.disabled_healthchecks.clone(),
+            &config.network_wait(),
+            &config.injections,
+            &check_files,
+            webhook.as_ref(),
+            &script_env,
+        ));
+    }
+
+    // red.d cannot make the boot any faster, the budget is judged last
+    match config.boot_time_budget().apply(result) {
+        Ok(diagnostics) => {
+            if remediated {
+                tracing::info!("health-check recovered after red.d remediation");
+            }
+            let status = diagnostics.status();
+            let (passed, failed) = (diagnostics.passed, diagnostics.failed_wanted.len());
+            let motd = match status {
+                HealthStatus::Degraded => {
+                    tracing::warn!(
+                        "greenboot health-check passed with failing wanted checks: {:?}",
+                        diagnostics.failed_wanted
+                    );
+                    "Greenboot healthcheck passed with failing wanted checks - status is DEGRADED"
+                }
+                _ => {
+                    tracing::info!("greenboot health-check passed.");
+                    "Greenboot healthcheck passed - status is GREEN"
+                }
+            };
+            let hints = record_status(
+                paths,
+                &summary,
+                status,
+                diagnostics.failed_wanted,
+                &diagnostics.wanted_details,
+            );
 
-    match run_diagnostics(config.disabled_healthchecks) {
-        Ok(_) => {
-            log::info!("greenboot health-check passed.");
-            let errors = run_green();
-            if !errors.is_empty() {
-                log::error!("There is a problem with green script runner");
-                errors.iter().for_each(|e| log::error!("{e}"));
+            let report = run_green(paths, config.action_timeout(), &config.script_env());
+            if report.failures().next().is_some() {
+                tracing::error!("There is a problem with green script runner");
             }
 
-            handle_motd(&generate_motd_message(
-                "Greenboot healthcheck passed - status is GREEN",
-                previous_rollback,
-            )?)
-            .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
+            handle_motd(
+                paths,
+                &generate_motd_message(&motd_with_hints(motd, &hints), fallback_banner.as_deref()),
+            )
+            .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
 
-            if !container_mode {
-                with_boot_rw(|| set_boot_status(true))?;
+            if let Some(backend) = &backend {
+                if let Err(e) = with_boot_rw(paths, || backend.set_boot_status(true)) {
+                    record_status(paths, &summary, HealthStatus::Error, vec![], &[]);
+                    summary.emit(HealthStatus::Error, passed, failed, Some(backend.as_ref()));
+                    return Err(e.context("health-check passed but boot status cannot be saved"));
+                }
 
                 // Unset rollback trigger on successful health check
-                if get_rollback_trigger().unwrap_or(false) {
-                    with_boot_rw(unset_rollback_trigger)
-                        .unwrap_or_else(|e| log::error!("Failed to unset rollback trigger: {e}"));
+                if backend.rollback_trigger().unwrap_or(false) {
+                    with_boot_rw(paths, || backend.unset_rollback_trigger()).unwrap_or_else(|e| {
+                        tracing::error!("Failed to unset rollback trigger: {e}")
+                    });
                 }
+                record_boot_attempt(paths, true);
+                auto_pin(config);
             }
 
-            Ok(())
+            summary.emit(status, passed, failed, backend.as_deref());
+            Ok(status)
         }
         Err(e) => {
-            log::error!("Greenboot error: {e}");
-
-            handle_motd(&generate_motd_message(
-                "Greenboot healthcheck failed - status is RED",
-                previous_rollback,
-            )?)
-            .unwrap_or_else(|e| log::error!("cannot set motd: {e}"));
-            let errors = run_red();
-            if !errors.is_empty() {
-                log::error!("There is a problem with red script runner");
-                errors.iter().for_each(|e| log::error!("{e}"));
-            }
-
-            if !container_mode {
-                with_boot_rw(|| set_boot_status(false))
-                    .unwrap_or_else(|e| log::error!("cannot set boot_status: {e}"));
-
-                // Check if boot_counter is 0 (exhausted retries) or if no counter is set
-                match get_boot_counter()? {
-                    Some(counter) if counter > 0 => {
-                        // Still have retries left, just reboot
-                        log::info!("Boot counter is {counter}, rebooting to try again");
-                        handle_reboot(false).unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
+            let diagnostics_error = e.downcast_ref::<DiagnosticsError>();
+            let failed_checks = match diagnostics_error {
+                Some(DiagnosticsError::RequiredFailed { details, .. }) => details.clone(),
+                _ => vec![],
+            };
+            let status = match diagnostics_error.is_none_or(DiagnosticsError::is_infrastructure) {
+                true => HealthStatus::Error,
+                false => HealthStatus::Red,
+            };
+            if status == HealthStatus::Error {
+                tracing::error!("Greenboot could not complete the health-check: {e}");
+                record_status(paths, &summary, HealthStatus::Error, vec![], &[]);
+                handle_motd(
+                    paths,
+                    &generate_motd_message(
+                        "Greenboot healthcheck could not complete - status is ERROR",
+                        fallback_banner.as_deref(),
+                    ),
+                )
+                .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+                summary.emit(HealthStatus::Error, 0, 0, backend.as_deref());
+
+                if !config.error_consumes_attempt {
+                    tracing::warn!(
+                        "GREENBOOT_ERROR_CONSUMES_BOOT_ATTEMPT is false, leaving boot counter untouched"
+                    );
+                    return Ok(status);
+                }
+            } else {
+                tracing::error!("Greenboot error: {e}");
+                let (failed, passed) = match diagnostics_error {
+                    Some(DiagnosticsError::RequiredFailed { failed, passed, .. }) => {
+                        (failed.clone(), *passed)
                     }
-                    Some(_) => {
-                        // Boot counter reached 0 (or negative) - check rollback trigger
-                        if get_rollback_trigger().unwrap_or(false) {
-                            log::info!(
-                                "Boot counter exhausted and rollback trigger is set - initiating rollback"
-                            );
-                            match handle_rollback() {
-                                Ok(()) => {
-                                    log::info!("Rollback successful");
-                                    with_boot_rw(|| {
-                                        unset_boot_counter()?;
-                                        unset_rollback_trigger()?;
-                                        Ok(())
-                                    })
-                                    .unwrap_or_else(|e| {
-                                        log::error!("Failed to clear grub vars: {e}")
-                                    });
-                                    handle_reboot(true)
-                                        .unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
-                                }
-                                Err(rollback_err) => {
-                                    log::error!("Rollback failed: {rollback_err}");
-                                    bail!("Manual intervention required - rollback failed");
-                                }
-                            }
-                        } else {
-                            log::warn!(
-                                "Boot counter exhausted but no rollback trigger set - manual intervention required"
-                            );
-                            bail!("Manual intervention required - no rollback trigger");
+                    _ => (vec![], 0),
+                };
+                // the summary goes out before the red action, which may reboot
+                summary.emit(HealthStatus::Red, passed, failed.len(), backend.as_deref());
+                let hints =
+                    record_status(paths, &summary, HealthStatus::Red, failed, &failed_checks);
+                record_last_failure(paths, failed_checks.clone());
+
+                handle_motd(
+                    paths,
+                    &generate_motd_message(
+                        &motd_with_hints("Greenboot healthcheck failed - status is RED", &hints),
+                        fallback_banner.as_deref(),
+                    ),
+                )
+                .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+            }
+            // red.d already ran right before the last failed attempt
+            if !remediated {
+                defer_reboot = run_red_scripts(config, failed_checks);
+            }
+
+            if let Some(backend) = &backend {
+                let backend = backend.as_ref();
+                with_boot_rw(paths, || backend.set_boot_status(false))
+                    .unwrap_or_else(|e| tracing::error!("cannot set boot_status: {e}"));
+                record_boot_attempt(paths, false);
+
+                match config.red_action {
+                    RedAction::Reboot => reboot_until_rollback(config, backend, defer_reboot)?,
+                    RedAction::RollbackNow => {
+                        tracing::info!(
+                            "GREENBOOT_RED_ACTION is rollback-now, skipping boot retries"
+                        );
+                        if !preflight_rollback(config, backend) {
+                            return Ok(status);
                         }
+                        arm_boot_counter(paths, backend, 0);
+                        rollback_and_reboot(config, backend, defer_reboot)?;
                     }
-                    None => {
-                        // No boot counter set - this is the first failure, set it and reboot
-                        log::info!(
-                            "First health check failure, setting boot counter to {}",
-                            config.max_reboot
-                        );
-                        with_boot_rw(|| set_boot_counter(config.max_reboot))
-                            .unwrap_or_else(|e| log::error!("cannot set boot_counter: {e}"));
-                        handle_reboot(false).unwrap_or_else(|e| log::error!("cannot reboot: {e}"));
+                    RedAction::Poweroff => {
+                        flush_logs();
+                        handle_poweroff()
+                            .unwrap_or_else(|e| tracing::error!("cannot power off: {e}"))
+                    }
+                    RedAction::None => {
+                        tracing::warn!("GREENBOOT_RED_ACTION is none, leaving the system running")
+                    }
+                }
+            }
+
+            Ok(status)
+        }
+    }
+}
+
+/// Last line of a health-check run, meant for fleet pipelines that only grep
+/// the logs: `GREENBOOT_SUMMARY` followed by `key=value` fields whose names,
+/// order and spelling stay stable across releases
+struct RunSummary {
+    started: Instant,
+    deployment: Option<String>,
+    /// the rollback the boot followed
+    rollback: Option<RollbackMarker>,
+}
+
+impl RunSummary {
+    fn start(paths: &Paths) -> Self {
+        Self {
+            started: Instant::now(),
+            deployment: booted_deployment(paths),
+            rollback: None,
+        }
+    }
+
+    /// `boot_counter` is its value when the verdict was reached, before any
+    /// red action changes it
+    fn emit(
+        &self,
+        verdict: impl fmt::Display,
+        passed: usize,
+        failed: usize,
+        backend: Option<&dyn BootloaderBackend>,
+    ) {
+        let boot_counter = match backend.map(|backend| backend.boot_counter()) {
+            Some(Ok(Some(counter))) => counter.to_string(),
+            Some(Ok(None)) => "unset".to_string(),
+            Some(Err(_)) => "unknown".to_string(),
+            None => "none".to_string(),
+        };
+        tracing::info!(
+            "GREENBOOT_SUMMARY verdict={verdict} passed={passed} failed={failed} duration_ms={} boot_counter={boot_counter} deployment={}",
+            self.started.elapsed().as_millis(),
+            self.deployment.as_deref().unwrap_or("none")
+        );
+    }
+}
+
+/// Watchdog of the health-check itself: unless disarmed by dropping it before
+/// the hard limit, it declares the boot RED and reboots, so a stalled run
+/// never leaves the device in "healthcheck in progress" forever
+struct Backstop {
+    _disarm: mpsc::Sender<()>,
+}
+
+impl Backstop {
+    /// reboots when it fires if `reboot` is set, i.e. there is a bootloader
+    /// backend to count the boots
+    fn arm(paths: Paths, limit: Duration, reboot: Option<BackstopReboot>) -> Self {
+        let (disarm, armed) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            // dropping the sender disconnects the channel, only a timeout fires
+            if armed.recv_timeout(limit) == Err(RecvTimeoutError::Timeout) {
+                fire_backstop(&paths, limit, reboot.as_ref());
+            }
+        });
+        Self { _disarm: disarm }
+    }
+}
+
+/// what the backstop needs from the config to arm the boot counter before it
+/// reboots, the backend is opened again on its own thread
+#[derive(Debug, Clone)]
+struct BackstopReboot {
+    /// GREENBOOT_BOOTLOADER, none to autodetect
+    bootloader: Option<Bootloader>,
+    max_reboot: u16,
+    arm_only_on_update: bool,
+}
+
+impl BackstopReboot {
+    fn new(config: &GreenbootConfig) -> Self {
+        Self {
+            bootloader: config.bootloader,
+            max_reboot: config.max_reboot,
+            arm_only_on_update: config.arm_only_on_update,
+        }
+    }
+
+    /// Arms the boot counter on the first failure as reboot_until_rollback
+    /// does, as the bootloader only counts down a counter that is set: a
+    /// check hanging on every boot would reboot the device forever otherwise.
+    /// The rollback target is not queried, the query may be what hangs.
+    fn arm_boot_counter(&self, paths: &Paths) {
+        let Some(backend) = backend_for(resolve_bootloader(self.bootloader, paths), paths) else {
+            return;
+        };
+        match backend.boot_counter() {
+            Ok(None) => {}
+            Ok(Some(_)) => return,
+            Err(e) => {
+                tracing::error!("cannot read boot_counter: {e}");
+                return;
+            }
+        }
+        if self.arm_only_on_update && deployment_passed_before(paths) {
+            tracing::warn!(
+                "The booted deployment passed the health-check on a previous boot, not arming the boot counter for a failure that is not caused by an update"
+            );
+            return;
+        }
+        let attempts = remaining_boot_attempts(paths, self.max_reboot);
+        tracing::info!("First health check failure, setting boot counter to {attempts}");
+        arm_boot_counter(paths, backend.as_ref(), attempts);
+    }
+}
+
+fn fire_backstop(paths: &Paths, limit: Duration, counter: Option<&BackstopReboot>) -> ! {
+    tracing::error!(
+        "health-check still running after {}s (GREENBOOT_HEALTHCHECK_HARD_LIMIT), declaring the boot RED",
+        limit.as_secs()
+    );
+    // the run started `limit` ago
+    let summary = RunSummary {
+        started: Instant::now()
+            .checked_sub(limit)
+            .unwrap_or_else(Instant::now),
+        ..RunSummary::start(paths)
+    };
+    record_status(paths, &summary, HealthStatus::Red, vec![], &[]);
+    handle_motd(
+        paths,
+        "Greenboot healthcheck did not finish in time - status is RED",
+    )
+    .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+    // boot_success is still unset, the boot counter armed on the first
+    // failure counts this boot down to a rollback
+    if let Some(counter) = counter {
+        counter.arm_boot_counter(paths);
+    }
+    flush_logs();
+    if counter.is_some() {
+        reboot().unwrap_or_else(|e| tracing::error!("cannot reboot: {e}"));
+    }
+    std::process::exit(TIMED_OUT_EXIT_CODE.into());
+}
+
+/// keeps re-running the health checks on a fixed interval once the boot is green,
+/// logging degradations and optionally escalating them to the red actions
+fn monitor(
+    mut config: GreenbootConfig,
+    interval_override: Option<u64>,
+    reload: impl Fn() -> Result<GreenbootConfig>,
+) -> Result<()> {
+    if let Err(e) = install_reload_handler() {
+        tracing::warn!("cannot install the SIGHUP handler, config reload disabled: {e}");
+    }
+
+    let mut interval = Duration::from_secs(1);
+    let mut uploader = None;
+    // last status the monitor reported, escalations happen on a change
+    let mut reported = HealthStatus::Green;
+    let mut starting = true;
+    loop {
+        if starting || RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+            if !starting {
+                match reload() {
+                    Ok(reloaded) => {
+                        config = reloaded;
+                        tracing::info!("greenboot monitor: configuration reloaded");
+                    }
+                    Err(e) => {
+                        tracing::error!("keeping the current configuration, reload failed: {e}")
                     }
                 }
             }
+            starting = false;
+            if config.disabled {
+                tracing::warn!(
+                    "greenboot.disable is set on the kernel command line, not monitoring"
+                );
+                return Ok(());
+            }
+            interval =
+                Duration::from_secs(interval_override.unwrap_or(config.monitor_interval).max(1));
+            uploader = config.batch_uploader();
+            tracing::info!(
+                "Starting greenboot monitor, checks run every {}s",
+                interval.as_secs()
+            );
+        }
+
+        let paths = &config.paths;
+        let network = config.network_wait();
+        let check_files = config.check_files();
+        let script_env = config.script_env();
+        let mut errors = Vec::new();
+        let mut failed_checks = Vec::new();
+        let mut failures = Vec::new();
+        let mut status = HealthStatus::Green;
+        if config.monitor_required
+            && let Err(e) = run_required(
+                paths,
+                &config.disabled_healthchecks,
+                &network,
+                &config.injections,
+                &check_files,
+                None,
+                &script_env,
+            )
+        {
+            status = HealthStatus::Red;
+            if let Some(DiagnosticsError::RequiredFailed {
+                failed, details, ..
+            }) = e.downcast_ref()
+            {
+                failed_checks.extend(failed.iter().cloned());
+                failures.extend(details.iter().cloned());
+            }
+            errors.push(e.to_string());
+        }
+        let wanted = run_wanted(
+            paths,
+            &config.disabled_healthchecks,
+            &network,
+            &config.injections,
+            &check_files,
+            None,
+            &script_env,
+        );
+        if !wanted.failed.is_empty() && status == HealthStatus::Green {
+            status = HealthStatus::Degraded;
+        }
+        failures.extend(wanted.details);
+        failed_checks.extend(wanted.failed);
+        errors.extend(wanted.errors.iter().map(|e| e.to_string()));
+
+        if let Some(uploader) = &uploader {
+            uploader
+                .record(&StatusRecord::new(status, failed_checks))
+                .unwrap_or_else(|e| tracing::error!("cannot spool monitor snapshot: {e}"));
+            match uploader.flush_if_due() {
+                Ok(0) => {}
+                Ok(uploaded) => tracing::info!("uploaded {uploaded} monitor snapshot(s)"),
+                Err(e) => tracing::debug!("monitor snapshots kept for later: {e}"),
+            }
+        }
+
+        if errors.is_empty() {
+            let recovered = reported != HealthStatus::Green;
+            if recovered {
+                tracing::info!("greenboot monitor: health checks recovered");
+            }
+            if recovered && config.monitor_escalate {
+                handle_motd(
+                    paths,
+                    "Greenboot monitor: health checks recovered - status is GREEN",
+                )
+                .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+            }
+            reported = HealthStatus::Green;
+        } else {
+            tracing::warn!("greenboot monitor detected degradation:");
+            errors.iter().for_each(|e| tracing::warn!("{e}"));
+
+            // only escalate on the transition so red.d doesn't fire every interval
+            if config.monitor_escalate && status != reported && status != HealthStatus::Green {
+                handle_motd(
+                    paths,
+                    &format!("Greenboot monitor detected degradation - status is {status}"),
+                )
+                .unwrap_or_else(|e| tracing::error!("cannot set motd: {e}"));
+                // a failed wanted check leaves the system usable, as at boot
+                if status == HealthStatus::Red {
+                    run_red_scripts(&config, failures);
+                }
+            }
+            reported = status;
+        }
+
+        sleep_unless_reload(interval);
+    }
+}
 
-            bail!("greenboot healthcheck failed")
+/// set by the SIGHUP handler, the monitor reloads its config when it sees it
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// how often a sleeping monitor looks for a reload request
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+extern "C" fn request_reload(_: nix::libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+fn install_reload_handler() -> nix::Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(request_reload),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe { sigaction(Signal::SIGHUP, &action) }.map(|_| ())
+}
+
+/// sleeps for `duration`, returning early when a reload is requested so the
+/// new config takes effect right away
+fn sleep_unless_reload(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !RELOAD_REQUESTED.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if now >= deadline {
+            return;
         }
+        thread::sleep(RELOAD_POLL_INTERVAL.min(deadline - now));
     }
 }
 
 // This function parses a string expected in bash-array format like
 // `( "item1" "item2" ... )` into a Vec<String>.
 fn parse_bash_array_string(raw_str: &str) -> Vec<String> {
-    log::debug!("Attempting to parse raw bash-array string: '{raw_str}'");
+    tracing::debug!("Attempting to parse raw bash-array string: '{raw_str}'");
 
     if raw_str.starts_with('(') && raw_str.ends_with(')') {
         // Remove the outer parentheses
@@ -375,40 +2516,798 @@ fn parse_bash_array_string(raw_str: &str) -> Vec<String> {
             .filter(|s| !s.is_empty())
             .collect();
 
-        log::debug!("Parsed list from bash-array string: {parsed_list:?}");
+        tracing::debug!("Parsed list from bash-array string: {parsed_list:?}");
         parsed_list
     } else if !raw_str.trim().is_empty() {
         // If the string is not empty but doesn't match the expected format,
         // log a warning and return an empty list.
-        log::warn!(
+        tracing::warn!(
             "String ('{raw_str}') is not in the expected bash-array format '( \"item1\" ... )'. Treating as empty list."
         );
         vec![]
     } else {
         // If the string is empty (e.g., "DISABLED_HEALTHCHECKS=" or "DISABLED_HEALTHCHECKS=()"),
         // it correctly results in an empty list.
-        log::debug!("Bash-array string is empty or effectively empty, resulting in an empty list.");
+        tracing::debug!(
+            "Bash-array string is empty or effectively empty, resulting in an empty list."
+        );
         vec![]
     }
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
-    pretty_env_logger::formatted_builder()
-        .filter_level(cli.log_level.to_log())
+/// prints what a health-check run would execute with the current config
+fn plan(config: &GreenbootConfig) {
+    if config.disabled {
+        println!("greenboot is disabled on the kernel command line, nothing would run");
+        return;
+    }
+    let plan = plan_run(
+        &config.paths,
+        &config.disabled_healthchecks,
+        &config.network_wait(),
+        &config.injections,
+        &config.check_files(),
+        config.action_timeout(),
+        config.profile.as_deref(),
+    );
+    if plan.is_empty() {
+        println!("no checks or actions found");
+    }
+    plan.iter().for_each(|planned| println!("{planned}"));
+}
+
+/// prints the health-check verdict of the current boot and the boot counter,
+/// both files are read directly as fleet agents poll this frequently
+fn status(config: &GreenbootConfig) -> Result<()> {
+    let paths = &config.paths;
+    let mut buf = [0; STATUS_BUF_SIZE];
+    let mut out = io::stdout().lock();
+    match read_status_view(paths, &mut buf)? {
+        Some(view) => {
+            writeln!(out, "status: {}", view.status)?;
+            if !view.failed_checks.is_empty() {
+                write!(out, "failed checks:")?;
+                for check in &view.failed_checks {
+                    write!(out, " {check}")?;
+                }
+                writeln!(out)?;
+            }
+            for message in &view.messages {
+                writeln!(out, "message: {message}")?;
+            }
+            for hint in &view.hints {
+                writeln!(out, "hint: {hint}")?;
+            }
+            if view.no_rollback_target {
+                writeln!(out, "rollback target: none")?;
+            }
+            if let Some(rollback) = &view.rollback {
+                write!(out, "after rollback: {}", format_utc(rollback.timestamp))?;
+                if let Some(from) = &rollback.from {
+                    write!(out, " from {from}")?;
+                }
+                if let Some(to) = &rollback.to {
+                    write!(out, " to {to}")?;
+                }
+                writeln!(out)?;
+            }
+        }
+        None => {
+            writeln!(
+                out,
+                "status: UNKNOWN (health-check has not completed in this boot)"
+            )?;
+            // the history survives the reboot, unlike the status file
+            if let Some(last) = read_last_history_entry(paths).ok().flatten() {
+                writeln!(
+                    out,
+                    "last verdict: {} at {}",
+                    last.status,
+                    format_utc(last.timestamp)
+                )?;
+            }
+        }
+    }
+    let bootloader = resolve_bootloader(config.bootloader, paths);
+    if let Some(backend) = backend_for(bootloader, paths)
+        && let Ok(counter) = backend.boot_counter()
+    {
+        match counter {
+            Some(counter) => writeln!(out, "boot counter: {counter}")?,
+            None => writeln!(out, "boot counter: unset")?,
+        }
+        writeln!(out, "boot counter store: {}", backend.store())?;
+        if bootloader.is_grub()
+            && let Some(writer) = read_grubenv_writer(paths)
+        {
+            writeln!(out, "grubenv written by: {writer}")?;
+        }
+    }
+    Ok(())
+}
+
+/// operator requested rollback, to the previous deployment or to `to`
+fn rollback(config: &GreenbootConfig, to: Option<&str>) -> Result<()> {
+    if running_in_container() {
+        bail!("Container environment detected, there is nothing to roll back");
+    }
+    let paths = &config.paths;
+    let rolled_back_to = match to {
+        Some(target) => {
+            let deployment = rollback_to(target, &config.rollback_policy())?;
+            tracing::info!("Deployment {} is now the default", deployment.id);
+            Some(deployment.id)
+        }
+        None => {
+            let backend = config
+                .bootloader_backend()
+                .context("no bootloader backend to roll back with")?;
+            rollback_now(paths, backend.as_ref(), &config.rollback_policy())?;
+            with_boot_rw(paths, || {
+                backend.unset_boot_counter()?;
+                backend.unset_rollback_trigger()
+            })
+            .unwrap_or_else(|e| tracing::error!("Failed to clear boot counter vars: {e}"));
+            None
+        }
+    };
+    let marker = RollbackMarker::new(booted_deployment(paths), rolled_back_to);
+    write_rollback_marker(paths, &marker)
+        .unwrap_or_else(|e| tracing::error!("cannot write rollback marker: {e}"));
+    tracing::info!("Rollback done, it takes effect on the next boot");
+    Ok(())
+}
+
+/// prints the state `rollback` starts from and what it would do, fails with
+/// the reason when it would not go ahead
+fn rollback_dry_run(config: &GreenbootConfig, to: Option<&str>) -> Result<()> {
+    if running_in_container() {
+        bail!("Container environment detected, there is nothing to roll back");
+    }
+    let paths = &config.paths;
+    let policy = config.rollback_policy();
+    let backend = config.bootloader_backend();
+    if let Some(backend) = &backend {
+        println!("bootloader: {}", backend.store());
+        match backend.boot_counter()? {
+            Some(counter) => println!("boot counter: {counter}"),
+            None => println!("boot counter: not armed"),
+        }
+        let trigger = backend.rollback_trigger()?;
+        println!(
+            "rollback trigger: {}",
+            if trigger { "set" } else { "not set" }
+        );
+    }
+    let action = match to {
+        Some(target) => {
+            let deployments = list_deployments()?;
+            let (index, deployment) = select_rollback_to(&deployments, target, &policy)?;
+            format!("run 'ostree admin set-default {index}' ({})", deployment.id)
+        }
+        None => {
+            let backend = backend.context("no bootloader backend to roll back with")?;
+            let action = describe_rollback(paths, backend.as_ref(), &policy)?;
+            format!("{action}, then clear the boot counter and the rollback trigger")
+        }
+    };
+    println!("would: {action}");
+    println!("dry run, nothing was changed");
+    Ok(())
+}
+
+/// writes the history, optionally from `since` on, to stdout
+fn export(paths: &Paths, format: HistoryFormat, since: Option<&str>) -> Result<()> {
+    let mut entries = read_history(paths)?;
+    if let Some(since) = since {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        entries = history_since(entries, parse_since(since, now)?);
+    }
+    let exported = export_history(&entries, format)?;
+    let mut out = io::stdout().lock();
+    out.write_all(&exported)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn show_history(paths: &Paths, boots: usize) -> Result<()> {
+    let entries = read_history(paths)?;
+    print!("{}", history_table(last_boots(&entries, boots)));
+    Ok(())
+}
+
+/// pretty-prints the last health-check of `boot` found in the journal
+fn logs(boot: i32, color: bool) -> Result<()> {
+    let entries = journal::read_entries(boot).context("cannot read the journal")?;
+    let Some(run) = last_run(&entries) else {
+        bail!("no health-check verdict in the journal of boot {boot}");
+    };
+    let mut out = io::stdout().lock();
+    match run.finished_at {
+        Some(finished_at) => writeln!(
+            out,
+            "health-check finished at {}: {}",
+            format_utc(finished_at),
+            run.status
+        )?,
+        None => writeln!(out, "health-check finished: {}", run.status)?,
+    }
+    for check in &run.checks {
+        write!(
+            out,
+            "{}",
+            check_line(
+                &check.stage,
+                &check.check,
+                check.outcome,
+                check.duration,
+                color
+            )
+        )?;
+        match &check.message {
+            Some(message) => writeln!(out, ": {message}")?,
+            None => writeln!(out)?,
+        }
+    }
+    if !run.failed_checks.is_empty() {
+        writeln!(out, "failed checks: {}", run.failed_checks.join(" "))?;
+    }
+    for message in &run.messages {
+        writeln!(out, "message: {message}")?;
+    }
+    for hint in &run.hints {
+        writeln!(out, "hint: {hint}")?;
+    }
+    Ok(())
+}
+
+/// paths used by `status`, which skips the config files: the grubenv comes
+/// from --grubenv or the discovery
+/// prints every issue of the image tree at `root`, fails if any is an error
+fn validate(root: &Path) -> Result<()> {
+    let image_config = GreenbootConfig::get_config(Paths::with_root(root));
+    let issues = validate_image(
+        root,
+        &image_config.disabled_healthchecks,
+        &image_config.check_files(),
+    );
+    issues.iter().for_each(|issue| println!("{issue}"));
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count();
+    if errors > 0 {
+        bail!("image validation failed with {errors} error(s)");
+    }
+    println!("image at {} is valid", root.display());
+    Ok(())
+}
+
+/// prints every config key with its effective value and the source it comes
+/// from: a command line option, the kernel command line, the environment, a
+/// config file or drop-in, or the built-in default
+fn print_config(config: &GreenbootConfig, cli_keys: &[&str]) {
+    config_lines(config, cli_keys)
+        .iter()
+        .for_each(|line| println!("{line}"));
+}
+
+/// lines of `config print`, the sources are looked up in the same layers
+/// get_config merged, skipping the same broken files
+fn config_lines(config: &GreenbootConfig, cli_keys: &[&str]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let layers: Vec<(String, Config)> = config_layers(&config.paths)
+        .into_iter()
+        .filter_map(|(label, layer)| match layer {
+            Ok(layer) => Some((label, layer)),
+            Err(e) => {
+                lines.push(format!(
+                    "# {label} cannot be parsed, its values are ignored: {e}"
+                ));
+                None
+            }
+        })
+        .collect();
+    let kernel = read_kernel_overrides(&config.paths).unwrap_or_default();
+    let effective = config.effective_values();
+
+    for (key, _) in CONFIG_KEYS {
+        let layer = layers
+            .iter()
+            .rev()
+            .find(|(_, layer)| layer.get_string(key).is_ok() || layer.get_array(key).is_ok());
+        let from_kernel = (key == "GREENBOOT_MAX_BOOT_ATTEMPTS"
+            && kernel.max_boot_attempts.is_some())
+            || (key == "DISABLED_HEALTHCHECKS" && !kernel.skip.is_empty());
+        let source = if cli_keys.contains(&key) {
+            "command line option".to_string()
+        } else if from_kernel {
+            "kernel command line".to_string()
+        } else if let Some((label, _)) = layer {
+            label.clone()
+        } else {
+            "default".to_string()
+        };
+
+        let value = match effective.iter().find(|(name, _)| *name == key) {
+            Some((_, value)) => value.clone(),
+            // read by the shipped checks only, greenboot has no default for them
+            None => match layer.and_then(|(_, layer)| layer.get_string(key).ok()) {
+                Some(value) => value,
+                None => continue,
+            },
+        };
+        lines.push(format!("{key}={value}  # {source}"));
+    }
+    if config.disabled {
+        lines.push(
+            "# greenboot.disable=1 is set on the kernel command line, the health-check is skipped"
+                .to_string(),
+        );
+    }
+    lines
+}
+
+fn validate_config_files(paths: &Paths) -> Result<()> {
+    let issues = validate_config(paths);
+    issues.iter().for_each(|issue| println!("{issue}"));
+    if !issues.is_empty() {
+        bail!("config validation failed with {} issue(s)", issues.len());
+    }
+    println!("config is valid");
+    Ok(())
+}
+
+fn migrate_legacy(root: &Path, dry_run: bool) -> Result<()> {
+    let report = migrate(root, dry_run)?;
+    let verb = if dry_run { "would migrate" } else { "migrated" };
+    report
+        .migrated
+        .iter()
+        .for_each(|item| println!("{verb}: {item}"));
+    report
+        .untranslated
+        .iter()
+        .for_each(|item| println!("manual: {item}"));
+
+    if !report.untranslated.is_empty() {
+        bail!(
+            "{} item(s) could not be migrated automatically",
+            report.untranslated.len()
+        );
+    }
+    if report.migrated.is_empty() {
+        println!("nothing to migrate under {}", root.display());
+    }
+    Ok(())
+}
+
+/// duplicates log output to stderr and a log file
+struct TeeWriter {
+    file: fs::File,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        ProgressLog.write_all(buf)?;
+        self.file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()?;
+        self.file.flush()
+    }
+}
+
+/// handle on the fallback log file, kept to sync it to disk before a reboot
+static LOG_FILE: OnceLock<fs::File> = OnceLock::new();
+
+/// makes sure everything logged so far survives the upcoming reboot or poweroff
+fn flush_logs() {
+    if journal::is_available() {
+        journal::sync().unwrap_or_else(|e| tracing::warn!("cannot flush the journal: {e}"));
+    } else if let Some(file) = LOG_FILE.get() {
+        file.sync_data()
+            .unwrap_or_else(|e| tracing::warn!("cannot flush the log file: {e}"));
+    }
+}
+
+fn open_log_file(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// logs to stderr, which systemd forwards to the journal, and also to a log file
+/// when journald is missing so the output isn't lost on containers and minimal images.
+/// On a terminal a health-check shows the progress of its checks instead of
+/// the info logs.
+fn init_logging(cli: &Cli) {
+    let text = cli.log_format == LogFormat::Text;
+    let progress = (text && !cli.quiet && matches!(cli.command, Commands::HealthCheck))
+        .then(|| enable_progress(cli.color.enabled(true)))
+        .flatten();
+    let level = match (cli.quiet, cli.log_level, progress) {
+        (true, _, _) => LogLevel::Error,
+        (false, Some(level), _) => level,
+        (false, None, Some(_)) => LogLevel::Warn,
+        (false, None, None) => LogLevel::Info,
+    };
+    // progress is only shown on a terminal, never to journald, and JSON is
+    // asked for to be collected from stderr
+    let journald = match text && progress.is_none() && journal::under_systemd() {
+        true => JournalLayer::new()
+            .inspect_err(|e| eprintln!("cannot log to journald: {e}"))
+            .ok(),
+        false => None,
+    };
+    let mut writer = BoxMakeWriter::new(|| ProgressLog);
+    let mut ansi = cli.color.enabled(io::stderr().is_terminal());
+
+    let mut file_error = None;
+    if !journal::is_available() {
+        let path = cli
+            .log_file
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG_FILE_PATH));
+        match open_log_file(&path) {
+            Ok(file) => {
+                if let Ok(handle) = file.try_clone() {
+                    let _ = LOG_FILE.set(handle);
+                }
+                writer = BoxMakeWriter::new(Mutex::new(TeeWriter { file }));
+                ansi = false;
+            }
+            Err(e) => file_error = Some(format!("cannot open {}: {e}", path.display())),
+        }
+    }
+    let stderr = journald.is_none().then(|| match cli.log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .without_time()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+    });
+    tracing_subscriber::registry()
+        .with(level.to_filter())
+        .with(journald)
+        .with(stderr)
         .init();
 
-    match cli.command {
-        Commands::HealthCheck => health_check(),
+    if let Some(e) = file_error {
+        tracing::warn!("journald is not available and logging to file failed, {e}");
+    }
+}
+
+/// builds the config from the files, the environment and the command line
+/// options without running anything, also returns the keys overridden by
+/// command line options
+fn cli_config(cli: &Cli) -> (GreenbootConfig, Vec<&'static str>) {
+    let mut paths = Paths::default();
+    if let Some(config_file) = &cli.config {
+        paths.config_file = config_file.clone();
+    }
+    let mut config = GreenbootConfig::get_config(paths);
+    let mut cli_keys = Vec::new();
+    if let Some(grubenv) = &cli.grubenv {
+        config.paths.grubenv = grubenv.clone();
+        cli_keys.push("GREENBOOT_GRUBENV_PATH");
+    }
+    if let Some(motd) = &cli.motd {
+        config.paths.motd = motd.clone();
+        cli_keys.push("GREENBOOT_MOTD_PATH");
+    }
+    (config, cli_keys)
+}
+
+/// builds the config from the files, the command line options and the kernel
+/// command line, also returns the keys overridden by command line options
+fn load_config(cli: &Cli) -> Result<(GreenbootConfig, Vec<&'static str>)> {
+    let (mut config, cli_keys) = cli_config(cli);
+    match read_kernel_overrides(&config.paths) {
+        Ok(overrides) => config.apply_kernel_overrides(overrides),
+        Err(e) => tracing::debug!("no kernel command line overrides: {e}"),
+    }
+    config.boot_attempt = current_boot_attempt(&config.paths);
+    if let Some(spec) = &cli.inject {
+        if std::env::var(INJECT_GUARD_ENV).as_deref() != Ok("1") {
+            bail!("--inject is a testing mode and requires {INJECT_GUARD_ENV}=1");
+        }
+        config.injections = Injections::parse(spec)?;
+        tracing::warn!("testing mode, forcing check outcomes: {spec}");
+    }
+    tracing::debug!("{config:?}");
+    Ok((config, cli_keys))
+}
+
+/// exit code of the verdict of a health-check
+fn health_exit_code(status: HealthStatus) -> ExitCode {
+    match status {
+        HealthStatus::Green => ExitCode::SUCCESS,
+        HealthStatus::Degraded => ExitCode::from(DEGRADED_EXIT_CODE),
+        HealthStatus::Red => ExitCode::from(RED_EXIT_CODE),
+        _ => ExitCode::from(INTERNAL_ERROR_EXIT_CODE),
+    }
+}
+
+/// exit code once clap printed the usage error, the help or the version
+fn usage_exit_code(error: &clap::Error) -> ExitCode {
+    match error.use_stderr() {
+        true => ExitCode::from(USAGE_EXIT_CODE),
+        false => ExitCode::SUCCESS,
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            return usage_exit_code(&e);
+        }
+    };
+    run(&cli).unwrap_or_else(|e| {
+        eprintln!("Error: {e:?}");
+        ExitCode::from(INTERNAL_ERROR_EXIT_CODE)
+    })
+}
+
+fn run(cli: &Cli) -> Result<ExitCode> {
+    // polled by fleet agents, so it skips the logging and config setup
+    if let Commands::Status = cli.command {
+        return status(&cli_config(cli).0).map(|_| ExitCode::SUCCESS);
+    }
+    init_logging(cli);
+    if let Err(e) = install_termination_handler() {
+        tracing::warn!("cannot install the SIGTERM handler, scripts may outlive greenboot: {e}");
+    }
+
+    let (config, cli_keys) = load_config(cli)?;
+    set_redactions(&config.redact_patterns);
+
+    match &cli.command {
+        Commands::HealthCheck => {
+            let status = health_check(&config)?;
+            if let Some(progress) = progress() {
+                progress.verdict(&status.to_string());
+            }
+            Ok(health_exit_code(status))
+        }
         Commands::SetRollbackTrigger => {
             if running_in_container() {
-                log::info!("Container environment detected; skipping rollback trigger updates");
-                return Ok(());
+                tracing::info!("Container environment detected; skipping rollback trigger updates");
+                return Ok(ExitCode::SUCCESS);
             }
-            log::info!("Setting rollback trigger for next boot...");
-            with_boot_rw(set_rollback_trigger)?;
-            log::info!("Rollback trigger set successfully.");
-            Ok(())
+            let Some(backend) = config.bootloader_backend() else {
+                return Ok(ExitCode::SUCCESS);
+            };
+            tracing::info!("Setting rollback trigger for next boot...");
+            with_boot_rw(&config.paths, || backend.set_rollback_trigger())?;
+            tracing::info!("Rollback trigger set successfully.");
+            Ok(ExitCode::SUCCESS)
+        }
+        Commands::Rollback { to, dry_run: true } => {
+            rollback_dry_run(&config, to.as_deref()).map(|_| ExitCode::from(DRY_RUN_EXIT_CODE))
         }
+        Commands::Rollback { to, .. } => {
+            rollback(&config, to.as_deref()).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Status => unreachable!("status is handled before the config is loaded"),
+        Commands::Monitor { interval } => monitor(config, *interval, || {
+            load_config(cli).map(|(config, _)| config)
+        })
+        .map(|_| ExitCode::SUCCESS),
+        Commands::ValidateImage { root } => validate(root).map(|_| ExitCode::SUCCESS),
+        Commands::Plan => {
+            plan(&config);
+            Ok(ExitCode::SUCCESS)
+        }
+        Commands::Config {
+            command: ConfigCommands::Print,
+        } => {
+            print_config(&config, &cli_keys);
+            Ok(ExitCode::SUCCESS)
+        }
+        Commands::Config {
+            command: ConfigCommands::Validate,
+        } => validate_config_files(&config.paths).map(|_| ExitCode::SUCCESS),
+        Commands::Migrate { root, dry_run } => {
+            migrate_legacy(root, *dry_run).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::Logs { boot } => {
+            logs(*boot, cli.color.enabled(io::stdout().is_terminal())).map(|_| ExitCode::SUCCESS)
+        }
+        Commands::History {
+            command: HistoryCommands::Export { format, since },
+        } => export(&config.paths, *format, since.as_deref()).map(|_| ExitCode::SUCCESS),
+        Commands::History {
+            command: HistoryCommands::Show { boots },
+        } => show_history(&config.paths, *boots).map(|_| ExitCode::SUCCESS),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// layout under a temp dir with /boot mounted read-write
+    fn test_paths(root: &Path) -> Paths {
+        let paths = Paths::with_root(root);
+        fs::create_dir_all(paths.mount_info.parent().unwrap()).unwrap();
+        fs::write(&paths.mount_info, "/dev/vda1 /boot xfs rw,relatime 0 0\n").unwrap();
+        paths
+    }
+
+    #[test]
+    fn test_exit_codes() {
+        assert_eq!(health_exit_code(HealthStatus::Green), ExitCode::SUCCESS);
+        assert_eq!(health_exit_code(HealthStatus::Error), ExitCode::from(1));
+        assert_eq!(health_exit_code(HealthStatus::Red), ExitCode::from(2));
+        assert_eq!(health_exit_code(HealthStatus::Degraded), ExitCode::from(3));
+        assert_eq!(TIMED_OUT_EXIT_CODE, 4);
+        assert_eq!(DRY_RUN_EXIT_CODE, 5);
+
+        let exit_code = |args: &[&str]| match Cli::try_parse_from(args) {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => usage_exit_code(&e),
+        };
+        assert_eq!(exit_code(&["greenboot", "health-check"]), ExitCode::SUCCESS);
+        assert_eq!(
+            exit_code(&["greenboot", "--bogus", "health-check"]),
+            ExitCode::from(64)
+        );
+        assert_eq!(
+            exit_code(&["greenboot", "no-such-command"]),
+            ExitCode::from(64)
+        );
+        assert_eq!(exit_code(&["greenboot", "--help"]), ExitCode::SUCCESS);
+    }
+
+    #[test]
+    fn test_broken_dropin_keeps_the_other_layers() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let dropins = &paths.config_dropin_dirs[1];
+        fs::create_dir_all(dropins).unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=7\n").unwrap();
+        fs::write(
+            dropins.join("10-monitor.conf"),
+            "GREENBOOT_MONITOR_INTERVAL=60\n",
+        )
+        .unwrap();
+        fs::write(dropins.join("20-broken.conf"), "[unclosed\n").unwrap();
+
+        let layers = config_layers(&paths);
+        assert!(
+            layers
+                .iter()
+                .any(|(label, layer)| label.ends_with("20-broken.conf") && layer.is_err())
+        );
+        let config = GreenbootConfig::get_config(paths);
+        assert_eq!(config.max_reboot, 7);
+        assert_eq!(config.monitor_interval, 60);
+    }
+
+    #[test]
+    fn test_broken_structured_config_keeps_the_legacy_file() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=7\n").unwrap();
+        let (yaml, _) = &paths.structured_config_files()[1];
+        fs::write(yaml, "GREENBOOT_MONITOR_INTERVAL: [60\n").unwrap();
+        assert!(config_layers(&paths)[1].1.is_err());
+
+        let config = GreenbootConfig::get_config(paths);
+        assert_eq!(config.max_reboot, 7);
+        assert_eq!(config.monitor_interval, 300);
+    }
+
+    #[test]
+    fn test_config_sources() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = Paths::with_root(root.path());
+        let dropins = &paths.config_dropin_dirs[1];
+        fs::create_dir_all(dropins).unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=7\n").unwrap();
+        let dropin = dropins.join("10-monitor.conf");
+        fs::write(&dropin, "GREENBOOT_MONITOR_INTERVAL=60\n").unwrap();
+        let (yaml, _) = paths.structured_config_files()[1].clone();
+        fs::write(&yaml, "GREENBOOT_MONITOR_INTERVAL: [60\n").unwrap();
+
+        let mut config = GreenbootConfig::get_config(paths.clone());
+        config.paths.motd = root.path().join("motd");
+        let lines = config_lines(&config, &["GREENBOOT_MOTD_PATH"]);
+        let line = |key: &str| {
+            lines
+                .iter()
+                .find(|line| line.starts_with(&format!("{key}=")))
+                .unwrap()
+                .clone()
+        };
+        assert!(lines[0].starts_with(&format!(
+            "# {} cannot be parsed, its values are ignored: ",
+            yaml.display()
+        )));
+        assert_eq!(
+            line("GREENBOOT_MAX_BOOT_ATTEMPTS"),
+            format!(
+                "GREENBOOT_MAX_BOOT_ATTEMPTS=7  # {}",
+                paths.config_file.display()
+            )
+        );
+        assert_eq!(
+            line("GREENBOOT_MONITOR_INTERVAL"),
+            format!("GREENBOOT_MONITOR_INTERVAL=60  # {}", dropin.display())
+        );
+        assert_eq!(
+            line("GREENBOOT_ACTION_TIMEOUT"),
+            "GREENBOOT_ACTION_TIMEOUT=300  # default"
+        );
+        assert_eq!(
+            line("GREENBOOT_MOTD_PATH"),
+            format!(
+                "GREENBOOT_MOTD_PATH={}  # command line option",
+                root.path().join("motd").display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_no_boot_attempts_arms_counter_at_zero() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = test_paths(root.path());
+        fs::create_dir_all(paths.config_file.parent().unwrap()).unwrap();
+        fs::write(&paths.config_file, "GREENBOOT_MAX_BOOT_ATTEMPTS=0\n").unwrap();
+        let mut config = GreenbootConfig::get_config(paths.clone());
+        // a dual-root handler always has a rollback target
+        config.rollback_handler = RollbackHandler::BootloaderEnv;
+        let backend = backend_for(Bootloader::GrubBios, &paths).unwrap();
+
+        // the first RED boot arms the counter at 0 and goes straight to the
+        // rollback, which stops before rebooting without a rollback trigger
+        let rollback = reboot_until_rollback(&config, backend.as_ref(), false);
+        assert!(
+            rollback
+                .unwrap_err()
+                .to_string()
+                .contains("no rollback trigger")
+        );
+        assert_eq!(backend.boot_counter().unwrap(), Some(0));
+    }
+
+    #[test]
+    fn test_backstop_arms_boot_counter() {
+        let root = tempfile::tempdir().unwrap();
+        let paths = test_paths(root.path());
+        let counter = BackstopReboot {
+            bootloader: Some(Bootloader::GrubBios),
+            max_reboot: 3,
+            arm_only_on_update: true,
+        };
+        // without a grubenv the counter is kept in the state file
+        let backend = backend_for(Bootloader::GrubBios, &paths).unwrap();
+        assert_eq!(backend.boot_counter().unwrap(), None);
+        counter.arm_boot_counter(&paths);
+        assert_eq!(backend.boot_counter().unwrap(), Some(3));
+
+        // an armed counter keeps counting down
+        backend.unset_boot_counter().unwrap();
+        backend.set_boot_counter(1).unwrap();
+        counter.arm_boot_counter(&paths);
+        assert_eq!(backend.boot_counter().unwrap(), Some(1));
     }
 }
@@ -1,4 +1,5 @@
 #!/bin/bash
+# greenboot: description="repository DNS check"
 set -e
 
 REPOS_DIRECTORY=/etc/ostree/remotes.d
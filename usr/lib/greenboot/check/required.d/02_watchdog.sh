@@ -1,4 +1,5 @@
 #!/bin/bash
+# greenboot: description="watchdog-triggered boot check"
 set -eo pipefail
 
 source_configuration_file() {